@@ -0,0 +1,3 @@
+//! Packages the `wasm` feature of incremental-png for the browser, see index.html.
+
+pub use incremental_png::wasm::Decoder;
@@ -0,0 +1,20 @@
+//! Arbitrary bytes, split into pieces of arbitrary sizes, with or without resynchronization.
+
+#![no_main]
+
+use incremental_png::{dechunker::Dechunker, Error};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|input: (u8, bool, &[u8])| {
+    let (piece_size, resync, data) = input;
+    let mut dechunker = Dechunker::new().with_crc_events();
+    if resync {
+        dechunker = dechunker.with_resync();
+    }
+    for piece in data.chunks(piece_size.max(1) as usize) {
+        if dechunker.update_all(piece, |_| Ok::<_, Error>(())).is_err() {
+            return;
+        }
+    }
+    let _ = dechunker.eof();
+});
@@ -0,0 +1,47 @@
+//! An arbitrary image header followed by arbitrary image data, split into pieces.
+
+#![no_main]
+
+use arbitrary::Arbitrary;
+use incremental_png::{
+    inflater::Inflater,
+    stream_decoder::{Event, ImageHeader},
+};
+use libfuzzer_sys::fuzz_target;
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    width: u32,
+    height: u32,
+    bit_depth: u8,
+    colour_type: u8,
+    interlace_method: u8,
+    image_data: Vec<Vec<u8>>,
+    /// Limit passed to `update_bounded`
+    max_output_bytes: u16,
+}
+
+fuzz_target!(|input: Input| {
+    let mut inflater = Inflater::<1024>::new();
+    let header = ImageHeader {
+        width: input.width,
+        height: input.height,
+        bit_depth: input.bit_depth,
+        colour_type: input.colour_type,
+        compression_method: 0,
+        filter_method: 0,
+        interlace_method: input.interlace_method,
+    };
+    let events = core::iter::once(Event::ImageHeader(header))
+        .chain(input.image_data.iter().map(|data| Event::ImageData(data)))
+        .chain(core::iter::once(Event::End));
+    for e in events {
+        let mut event = Some(e);
+        while let Some(e) = event {
+            match inflater.update_bounded(e, input.max_output_bytes as usize) {
+                Ok((leftover, _)) => event = leftover,
+                Err(_) => return,
+            }
+        }
+    }
+});
@@ -0,0 +1,42 @@
+//! The whole decoder, from file bytes to unfiltered rows. None of the stages allocate, so
+//! memory use is fixed by the stage types; run with `-rss_limit_mb` to check nothing else does.
+
+#![no_main]
+
+use incremental_png::{
+    dechunker::Dechunker, inflater::Inflater, stream_decoder::StreamDecoder, unfilter::Unfilterer,
+    Error,
+};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|input: (u8, &[u8])| {
+    let (piece_size, data) = input;
+    let mut dechunker = Dechunker::new();
+    let mut sd = StreamDecoder::new();
+    let mut inflater = Inflater::<1024>::new();
+    let mut unfilterer = Unfilterer::<1024>::new();
+
+    for piece in data.chunks(piece_size.max(1) as usize) {
+        let result = dechunker.update_all(piece, |e| {
+            let mut dc_event = Some(e);
+            while let Some(e) = dc_event {
+                let (leftover, mut sd_event) = sd.update(e)?;
+                while let Some(e) = sd_event {
+                    let (leftover, mut i_event) = inflater.update(e)?;
+                    while let Some(e) = i_event {
+                        let (leftover, _) = unfilterer.update(e)?;
+                        i_event = leftover;
+                    }
+                    sd_event = leftover;
+                }
+                dc_event = leftover;
+            }
+            Ok::<_, Error>(())
+        });
+        if result.is_err() {
+            return;
+        }
+    }
+    let _ = dechunker.eof();
+    let _ = sd.eof();
+});
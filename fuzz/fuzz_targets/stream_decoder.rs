@@ -0,0 +1,79 @@
+//! Chunks with arbitrary types and contents, without the Dechunker in front. The events are
+//! well-formed (the data adds up to the length in the header), like the Dechunker's, but the
+//! chunks needn't be valid, and the data is split at arbitrary points.
+
+#![no_main]
+
+use arbitrary::Arbitrary;
+use incremental_png::{
+    dechunker::{ChunkHeader, ChunkType, Event},
+    stream_decoder::StreamDecoder,
+};
+use libfuzzer_sys::fuzz_target;
+
+#[derive(Arbitrary, Debug)]
+struct Chunk {
+    type_: Type,
+    data: Vec<u8>,
+    /// Sizes of the pieces the data is split into
+    pieces: Vec<u8>,
+    crc: Option<u32>,
+}
+
+#[derive(Arbitrary, Debug, Clone, Copy)]
+enum Type {
+    Ihdr,
+    Plte,
+    Trns,
+    Idat,
+    Iend,
+    Other([u8; 4]),
+}
+
+impl Type {
+    fn chunk_type(self) -> ChunkType {
+        ChunkType(match self {
+            Type::Ihdr => *b"IHDR",
+            Type::Plte => *b"PLTE",
+            Type::Trns => *b"tRNS",
+            Type::Idat => *b"IDAT",
+            Type::Iend => *b"IEND",
+            Type::Other(t) => t,
+        })
+    }
+}
+
+impl Chunk {
+    fn events(&self) -> Vec<Event<'_>> {
+        let mut events = vec![Event::BeginChunk(ChunkHeader {
+            len: self.data.len() as u32,
+            type_: self.type_.chunk_type(),
+        })];
+        let mut data = &self.data[..];
+        let mut pieces = self.pieces.iter().map(|&n| n.max(1) as usize);
+        while !data.is_empty() {
+            let n = pieces.next().unwrap_or(data.len()).min(data.len());
+            events.push(Event::Data(&data[..n]));
+            data = &data[n..];
+        }
+        events.extend(self.crc.map(Event::Crc));
+        events.push(Event::EndChunk);
+        events
+    }
+}
+
+fuzz_target!(|chunks: Vec<Chunk>| {
+    let mut sd = StreamDecoder::new();
+    for chunk in &chunks {
+        for e in chunk.events() {
+            let mut event = Some(e);
+            while let Some(e) = event {
+                match sd.update(e) {
+                    Ok((leftover, _)) => event = leftover,
+                    Err(_) => return,
+                }
+            }
+        }
+    }
+    let _ = sd.eof();
+});
@@ -0,0 +1,175 @@
+#ifndef INCREMENTAL_PNG_H
+#define INCREMENTAL_PNG_H
+
+/* Generated by cbindgen from src/ffi.rs, don't edit. */
+
+#include <stddef.h>
+#include <stdint.h>
+
+/*
+ Longest supported scanline in bytes, including the filter type byte.
+ */
+#define INC_PNG_MAX_SCANLINE 4096
+
+/*
+ Result of the `inc_png_*` functions. Besides `Ok`, `Aborted` and `InvalidArgument`, these
+ correspond to the variants of [`Error`].
+ */
+typedef enum IncPngStatus {
+  INC_PNG_STATUS_OK = 0,
+  /*
+   The callback returned non-zero
+   */
+  INC_PNG_STATUS_ABORTED,
+  /*
+   Null pointer, or memory too small or misaligned
+   */
+  INC_PNG_STATUS_INVALID_ARGUMENT,
+  INC_PNG_STATUS_INVALID_PNG_SIGNATURE,
+  INC_PNG_STATUS_UNFINISHED_CHUNK,
+  INC_PNG_STATUS_INVALID_IMAGE_HEADER_LENGTH,
+  INC_PNG_STATUS_NO_IMAGE_HEADER,
+  INC_PNG_STATUS_INVALID_DEFLATE_STREAM,
+  INC_PNG_STATUS_CHECKSUM_MISMATCH,
+  INC_PNG_STATUS_INVALID_END_CHUNK_SIZE,
+  INC_PNG_STATUS_INVALID_PALETTE_CHUNK_SIZE,
+  INC_PNG_STATUS_INVALID_TRANSPARENCY_CHUNK_SIZE,
+  INC_PNG_STATUS_PALETTE_INDEX_OUT_OF_RANGE,
+  INC_PNG_STATUS_CHUNK_TOO_LARGE,
+  INC_PNG_STATUS_DUPLICATE_CHUNK,
+  INC_PNG_STATUS_PALETTE_NOT_ALLOWED,
+  INC_PNG_STATUS_UNSUPPORTED_COMPRESSION_METHOD,
+  INC_PNG_STATUS_UNSUPPORTED_FILTER_METHOD,
+  INC_PNG_STATUS_INTERLACE_NOT_SUPPORTED,
+  INC_PNG_STATUS_INVALID_INTERLACE_METHOD,
+  INC_PNG_STATUS_CHUNK_LENGTH_MISMATCH,
+  INC_PNG_STATUS_INVALID_COLOUR_TYPE,
+  INC_PNG_STATUS_ROW_TOO_LARGE,
+  INC_PNG_STATUS_INVALID_ROW_LENGTH,
+  INC_PNG_STATUS_ROW_COUNT_MISMATCH,
+  INC_PNG_STATUS_MISSING_PALETTE,
+  INC_PNG_STATUS_CHUNK_OUT_OF_ORDER,
+  INC_PNG_STATUS_INVALID_BIT_DEPTH,
+  INC_PNG_STATUS_INVALID_TEXT_CHUNK,
+  INC_PNG_STATUS_INVALID_FRAME_CONTROL,
+  INC_PNG_STATUS_FRAME_COUNT_MISMATCH,
+  INC_PNG_STATUS_INVALID_ANIMATION_CONTROL,
+  INC_PNG_STATUS_INVALID_FILTER_TYPE,
+  INC_PNG_STATUS_UNEXPECTED_EVENT,
+  INC_PNG_STATUS_MISSING_END_CHUNK,
+  INC_PNG_STATUS_UNFINISHED_IMAGE_DATA,
+  INC_PNG_STATUS_DATASTREAM_TOO_LONG,
+  INC_PNG_STATUS_INVALID_ICO,
+  INC_PNG_STATUS_INVALID_EXTENSION_CHUNK,
+  INC_PNG_STATUS_ARITHMETIC_OVERFLOW,
+  INC_PNG_STATUS_EVENT_TOO_LARGE,
+  INC_PNG_STATUS_FRAMEBUFFER_TOO_SMALL,
+  INC_PNG_STATUS_TRUNCATED_IMAGE_DATA,
+  INC_PNG_STATUS_TRAILING_COMPRESSED_DATA,
+  INC_PNG_STATUS_INVALID_IMAGE_DIMENSIONS,
+  INC_PNG_STATUS_PALETTE_TOO_LARGE,
+  INC_PNG_STATUS_COMPRESSION_FAILED,
+} IncPngStatus;
+
+typedef enum IncPngEventKind {
+  /*
+   `header` is valid
+   */
+  INC_PNG_EVENT_KIND_HEADER,
+  /*
+   `data` points to `len` bytes of an unfiltered row, without the filter type byte
+   */
+  INC_PNG_EVENT_KIND_ROW,
+  /*
+   IEND reached
+   */
+  INC_PNG_EVENT_KIND_END,
+} IncPngEventKind;
+
+/*
+ Opaque decoder handle.
+ */
+typedef struct IncPng IncPng;
+
+/*
+ Fields of the IHDR chunk which matter for interpreting the rows.
+ */
+typedef struct IncPngHeader {
+  uint32_t width;
+  uint32_t height;
+  uint8_t bit_depth;
+  uint8_t colour_type;
+  uint8_t interlace_method;
+} IncPngHeader;
+
+typedef struct IncPngEvent {
+  enum IncPngEventKind kind;
+  struct IncPngHeader header;
+  const uint8_t *data;
+  size_t len;
+} IncPngEvent;
+
+/*
+ Called with each event. Returning non-zero stops decoding, and the `inc_png_update` call
+ returns `Aborted`. The event is only valid during the call.
+ */
+typedef int (*IncPngCallback)(void *user_data, const struct IncPngEvent *event);
+
+/*
+ Number of bytes `inc_png_new` needs.
+ */
+size_t inc_png_size(void);
+
+/*
+ Required alignment of the memory passed to `inc_png_new`.
+ */
+size_t inc_png_align(void);
+
+/*
+ Creates a decoder in `mem`, which must hold at least `inc_png_size()` bytes aligned to
+ `inc_png_align()`. Returns null if it doesn't.
+
+ # Safety
+
+ `mem` must be null or valid for writes of `size` bytes, and not be used for anything else
+ until `inc_png_free`.
+ */
+struct IncPng *inc_png_new(void *mem, size_t size);
+
+/*
+ Feeds `len` bytes of the file to the decoder, calling `callback` with each event.
+
+ After an error (including `Aborted`) the decoder is in an unspecified state and should
+ only be freed.
+
+ # Safety
+
+ `png` must come from `inc_png_new`, and `data` must be valid for reads of `len` bytes.
+ */
+enum IncPngStatus inc_png_update(struct IncPng *png,
+                                 const uint8_t *data,
+                                 size_t len,
+                                 IncPngCallback callback,
+                                 void *user_data);
+
+/*
+ Checks that the whole image was decoded, like `Decoder::finish`: the file didn't end in the
+ middle of a chunk or before IEND, the deflate stream ended, and as many rows as the header says
+ were decoded.
+
+ # Safety
+
+ `png` must come from `inc_png_new`.
+ */
+enum IncPngStatus inc_png_eof(const struct IncPng *png);
+
+/*
+ Destroys the decoder. The memory passed to `inc_png_new` can be reused afterwards.
+
+ # Safety
+
+ `png` must be null or come from `inc_png_new`, and not be used afterwards.
+ */
+void inc_png_free(struct IncPng *png);
+
+#endif  /* INCREMENTAL_PNG_H */
@@ -0,0 +1,40 @@
+#!/bin/sh
+# Checks the composed Decoder, including the Inflater, for reachable panics.
+#
+# miniz_oxide has bounds checks which aren't optimized out, so with the `inflater` feature the
+# binary can't link without a panic handler. Instead it's linked with the handler left
+# undefined, and every call into the panic machinery is traced back to the source with
+# addr2line. The check fails if a call doesn't come from within miniz_oxide.
+#
+# Needs objdump and addr2line from binutils.
+
+set -eu
+cd "$(dirname "$0")"
+
+target_dir=target/check-inflater
+CARGO_TARGET_DIR=$target_dir \
+CARGO_PROFILE_RELEASE_DEBUG=true \
+CARGO_PROFILE_RELEASE_STRIP=none \
+RUSTFLAGS="-C link-arg=-nostartfiles -C link-arg=-lc -C link-arg=-Wl,--unresolved-symbols=ignore-all" \
+    cargo build --release --features inflater
+bin=$target_dir/x86_64-unknown-linux-gnu/release/incremental-png-panic-check
+
+# Addresses of calls to the panic functions
+sites=$(objdump -d --no-show-raw-insn -C "$bin" |
+    grep -E 'call .*<(core::panicking::|core::slice::.*fail|core::option::unwrap_failed|core::result::unwrap_failed|__rustc::rust_begin_unwind)' |
+    sed -E 's/^ *([0-9a-f]+):.*/0x\1/')
+
+status=0
+for site in $sites; do
+    # Innermost frame outside the standard library, `??` if there is none
+    frame=$(addr2line -f -i -C -e "$bin" "$site" | paste - - | grep -v '/rustc/' | head -n 1 || true)
+    case $frame in
+        '' | *'??'*) ;; # Within core, e.g. `slice_index_fail` calling `panic_fmt`
+        *miniz_oxide*) ;;
+        *)
+            echo "panic reachable at $site: $frame"
+            status=1
+            ;;
+    esac
+done
+exit $status
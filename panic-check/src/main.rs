@@ -0,0 +1,161 @@
+#![no_std]
+#![no_main]
+
+use core::panic::PanicInfo;
+
+#[cfg(feature = "inflater")]
+use incremental_png::decoder::{Decoder, DecoderConfig};
+#[cfg(not(feature = "inflater"))]
+use incremental_png::{
+    dechunker::Dechunker, inflater, stream_decoder as sd, stream_decoder::StreamDecoder,
+    unfilter::Unfilterer,
+};
+use incremental_png::{
+    double_buffer::DoubleBuffer, stream_decoder::ImageHeader, unfilter, Error, Palette, Strictness,
+};
+
+#[panic_handler]
+fn panic(_: &PanicInfo) -> ! {
+    extern "Rust" {
+        fn this_code_path_can_panic() -> !;
+    }
+    unsafe { this_code_path_can_panic() }
+}
+
+/// Stands in for the file; read with volatile loads so the optimizer can't know its contents.
+static mut INPUT: [u8; 4096] = [0; 4096];
+static mut INPUT_LEN: usize = 0;
+static mut PIECE_SIZE: usize = 1;
+
+/// Passes the image data through as if it was already decompressed. miniz_oxide's inflate has
+/// bounds checks which aren't optimized out, so the Inflater is checked separately, see
+/// `check-inflater.sh`.
+#[cfg(not(feature = "inflater"))]
+struct Decompressor;
+
+#[cfg(not(feature = "inflater"))]
+impl Decompressor {
+    fn new() -> Self {
+        Self
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn update<'a>(
+        &mut self,
+        input: sd::Event<'a>,
+    ) -> Result<(Option<sd::Event<'a>>, Option<inflater::Event<'a>>), Error> {
+        let event = match input {
+            sd::Event::ImageHeader(header) => inflater::Event::ImageHeader(header),
+            sd::Event::ImageData(data) => inflater::Event::ImageData(data),
+            sd::Event::Extension(e) => inflater::Event::Extension(e),
+            sd::Event::ImageInfo(i) => inflater::Event::ImageInfo(i),
+            sd::Event::Warning(w) => inflater::Event::Warning(w),
+            sd::Event::End => inflater::Event::End,
+        };
+        Ok((None, Some(event)))
+    }
+}
+
+/// Where the rows go: the DoubleBuffer, and palette expansion for indexed images.
+struct Output<'b> {
+    double_buffer: DoubleBuffer,
+    buffers: &'b mut [[u8; 64]; 2],
+    rgb: [[u8; 3]; 16],
+    rows: u64,
+}
+
+impl Output<'_> {
+    fn update(
+        &mut self,
+        e: unfilter::Event,
+        header: Option<&ImageHeader>,
+        palette: &Palette,
+    ) -> Result<(), Error> {
+        if let (unfilter::Event::Row(row), Some(header)) = (&e, header) {
+            self.rows += 1;
+            if header.colour_type == 3 {
+                let width = (header.width as usize).min(self.rgb.len());
+                let out = self.rgb.get_mut(..width).unwrap_or_default();
+                palette.expand_row(row, header.bit_depth, out, Strictness::Strict)?;
+            }
+        }
+        let mut event = Some(e);
+        while let Some(e) = event {
+            let buf = &mut self.buffers[self.double_buffer.current() & 1];
+            (event, _) = self.double_buffer.update(e, buf)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(not(feature = "inflater"))]
+fn decode(data: &[u8], piece_size: usize, output: &mut Output) -> Result<(), Error> {
+    let mut dechunker = Dechunker::new().with_crc_events();
+    let mut sd = StreamDecoder::new();
+    let mut decompressor = Decompressor::new();
+    let mut unfilterer = Unfilterer::<1024>::new();
+
+    for piece in data.chunks(piece_size.max(1)) {
+        dechunker.update_all(piece, |e| {
+            let mut dc_event = Some(e);
+            while let Some(e) = dc_event {
+                let (leftover, mut sd_event) = sd.update(e)?;
+                while let Some(e) = sd_event {
+                    let (leftover, mut i_event) = decompressor.update(e)?;
+                    while let Some(e) = i_event {
+                        let (leftover, u_event) = unfilterer.update(e)?;
+                        if let Some(e) = u_event {
+                            output.update(e, sd.header(), sd.palette())?;
+                        }
+                        i_event = leftover;
+                    }
+                    sd_event = leftover;
+                }
+                dc_event = leftover;
+            }
+            Ok::<_, Error>(())
+        })?;
+    }
+    dechunker.eof()?;
+    sd.eof()?;
+    unfilterer.eof()
+}
+
+/// The composed Decoder, with the real Inflater.
+#[cfg(feature = "inflater")]
+fn decode(data: &[u8], piece_size: usize, output: &mut Output) -> Result<(), Error> {
+    let mut decoder: Decoder = DecoderConfig::new()
+        .verify_crc(true)
+        .adam7_pass_limit(7)
+        .build();
+    for piece in data.chunks(piece_size.max(1)) {
+        decoder.update_all(piece, |e, context| {
+            output.update(e, context.header(), context.palette())
+        })?;
+    }
+    decoder.finish()
+}
+
+#[no_mangle]
+pub extern "C" fn _start() -> ! {
+    let (input, len, piece_size) = unsafe {
+        (
+            core::ptr::read_volatile(&raw const INPUT),
+            core::ptr::read_volatile(&raw const INPUT_LEN),
+            core::ptr::read_volatile(&raw const PIECE_SIZE),
+        )
+    };
+    let mut buffers = [[0; 64]; 2];
+    let mut output = Output {
+        double_buffer: DoubleBuffer::new(),
+        buffers: &mut buffers,
+        rgb: [[0; 3]; 16],
+        rows: 0,
+    };
+    let result = decode(&input[..len.min(input.len())], piece_size, &mut output);
+    let rows = result.map_or(0, |()| output.rows as usize);
+    unsafe { core::ptr::write_volatile(&raw mut INPUT_LEN, rows) };
+    loop {
+        core::hint::spin_loop();
+    }
+}
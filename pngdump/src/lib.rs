@@ -0,0 +1,681 @@
+//! Building blocks of `pngdump`: walking chunks, tracing the decoder stages, checking files
+//! and decoding whole images, for host tools that want them without the command line.
+
+use std::fmt;
+use std::io::Write;
+
+use incremental_png::{
+    adler32::Adler32,
+    crc32::Crc32,
+    dechunker,
+    dechunker::{ChunkType, Dechunker},
+    encoder::Chunker,
+    inflater,
+    inflater::Inflater,
+    stream_decoder as sd,
+    stream_decoder::{ImageHeader, StreamDecoder},
+    unfilter::{self, Unfilterer},
+    Palette, Strictness,
+};
+
+/// Reads the zlib header of the image data and returns the LZ77 window size it declares, or
+/// `None` if the file has no image data.
+pub fn window_size(mut file: impl std::io::Read, buf: &mut [u8]) -> anyhow::Result<Option<u32>> {
+    let mut dechunker = Dechunker::new();
+    let mut sd = StreamDecoder::new();
+
+    loop {
+        let n = file.read(buf)?;
+        if n == 0 {
+            break;
+        }
+        let mut input = &buf[..n];
+
+        while !input.is_empty() {
+            let (consumed, mut dc_event) = dechunker.update(input)?;
+
+            while let Some(e) = dc_event {
+                let (leftover, sd_event) = sd.update(e)?;
+
+                if let Some(sd::Event::ImageData(data)) = sd_event {
+                    return Ok(Some(1 << ((data[0] as u32 >> 4) + 8)));
+                }
+
+                dc_event = leftover;
+            }
+
+            input = &input[consumed..];
+        }
+    }
+    Ok(None)
+}
+
+/// An event produced by one of the decoder stages, see [`trace`].
+#[derive(Debug)]
+pub enum Traced<'a, 'b> {
+    Dechunker(&'b dechunker::Event<'a>),
+    StreamDecoder(&'b sd::Event<'a>),
+    /// The Inflater doesn't always produce an event for its input
+    Inflater(Option<&'b inflater::Event<'a>>),
+}
+
+impl Traced<'_, '_> {
+    /// The payload carried by the event, with the name of its variant.
+    pub fn data(&self) -> Option<(&'static str, &[u8])> {
+        match self {
+            Traced::Dechunker(dechunker::Event::Data(data)) => Some(("Data", data)),
+            Traced::StreamDecoder(sd::Event::ImageData(data)) => Some(("ImageData", data)),
+            Traced::Inflater(Some(inflater::Event::ImageData(data))) => Some(("ImageData", data)),
+            _ => None,
+        }
+    }
+}
+
+/// Runs the Dechunker, StreamDecoder and Inflater, calling `f` with every event in the order
+/// they are produced.
+pub fn trace(
+    mut file: impl std::io::Read,
+    buf: &mut [u8],
+    mut f: impl FnMut(Traced),
+) -> anyhow::Result<()> {
+    let mut dechunker = Dechunker::new();
+    let mut sd = StreamDecoder::new();
+    let mut inflater = Inflater::<1024>::new();
+
+    loop {
+        let n = file.read(buf)?;
+        if n == 0 {
+            break;
+        }
+        let mut input = &buf[..n];
+
+        while !input.is_empty() {
+            let (consumed, mut dc_event) = dechunker.update(input)?;
+
+            while let Some(e) = dc_event {
+                f(Traced::Dechunker(&e));
+
+                let (leftover, mut sd_event) = sd.update(e)?;
+
+                while let Some(e) = sd_event {
+                    f(Traced::StreamDecoder(&e));
+                    let (leftover, i_event) = inflater.update(e)?;
+
+                    f(Traced::Inflater(i_event.as_ref()));
+
+                    sd_event = leftover;
+                }
+
+                dc_event = leftover;
+            }
+
+            input = &input[consumed..];
+        }
+    }
+
+    Ok(())
+}
+
+/// Something wrong with a file, found by [`verify`].
+#[derive(Debug)]
+pub enum Problem {
+    CrcMismatch {
+        chunk: ChunkType,
+        stored: u32,
+        computed: u32,
+    },
+    /// The decoder stopped with an error. Nothing after it was checked.
+    DecodingFailed(anyhow::Error),
+    MissingIend,
+    ImageDataSize {
+        expected: u64,
+        actual: u64,
+    },
+    MissingHeader,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Problem::CrcMismatch {
+                chunk,
+                stored,
+                computed,
+            } => write!(
+                f,
+                "CRC mismatch in {}: stored {:08x}, computed {:08x}",
+                chunk, stored, computed
+            ),
+            Problem::DecodingFailed(e) => write!(f, "Decoding failed: {}", e),
+            Problem::MissingIend => write!(f, "Missing IEND chunk"),
+            Problem::ImageDataSize { expected, actual } => write!(
+                f,
+                "Image data size mismatch: expected {} bytes, got {}",
+                expected, actual
+            ),
+            Problem::MissingHeader => write!(f, "Missing or invalid image header"),
+        }
+    }
+}
+
+/// Checks the signature, chunk order, CRCs, image data integrity and size, calling `report`
+/// with each problem found. Returns the number of problems.
+pub fn verify(
+    mut file: impl std::io::Read,
+    buf: &mut [u8],
+    mut report: impl FnMut(Problem),
+) -> usize {
+    let mut dechunker = Dechunker::new().with_crc_events();
+    let mut sd = StreamDecoder::new();
+    let mut inflater = Inflater::<1024>::new();
+    let mut problems = 0;
+    let mut crc = Crc32::new();
+    let mut chunk_type = None;
+
+    let mut decode = |report: &mut dyn FnMut(Problem)| -> anyhow::Result<()> {
+        loop {
+            let n = file.read(buf)?;
+            if n == 0 {
+                break;
+            }
+            dechunker.update_all(&buf[..n], |e| {
+                match &e {
+                    dechunker::Event::BeginChunk(h) => {
+                        crc = Crc32::new();
+                        crc.update(&h.type_.0);
+                        chunk_type = Some(h.type_);
+                    }
+                    dechunker::Event::Data(data) => crc.update(data),
+                    dechunker::Event::Crc(stored) => {
+                        let computed = crc.clone().finish();
+                        if *stored != computed {
+                            report(Problem::CrcMismatch {
+                                chunk: chunk_type.unwrap(),
+                                stored: *stored,
+                                computed,
+                            });
+                        }
+                    }
+                    dechunker::Event::EndChunk | dechunker::Event::Resync(_) => {}
+                }
+                let mut dc_event = Some(e);
+                while let Some(e) = dc_event {
+                    let (leftover, mut sd_event) = sd.update(e)?;
+                    while let Some(e) = sd_event {
+                        let (leftover, _) = inflater.update(e)?;
+                        sd_event = leftover;
+                    }
+                    dc_event = leftover;
+                }
+                Ok::<_, incremental_png::Error>(())
+            })?;
+        }
+        dechunker.eof()?;
+        sd.eof()?;
+        Ok(())
+    };
+    let mut report = |problem| {
+        report(problem);
+        problems += 1;
+    };
+    if let Err(e) = decode(&mut report) {
+        report(Problem::DecodingFailed(e));
+        return problems;
+    }
+
+    if chunk_type.map(|t| t.0) != Some(*b"IEND") {
+        report(Problem::MissingIend);
+    }
+    let progress = inflater.progress(&dechunker);
+    match progress.expected_image_data {
+        Some(expected) if expected != progress.image_data_out => {
+            report(Problem::ImageDataSize {
+                expected,
+                actual: progress.image_data_out,
+            });
+        }
+        Some(_) => {}
+        None => report(Problem::MissingHeader),
+    }
+    problems
+}
+
+/// Parses a chunk type given as a 4-character string, like `tEXt`.
+pub fn parse_chunk_type(s: &str) -> anyhow::Result<ChunkType> {
+    let type_: [u8; 4] = s
+        .as_bytes()
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("chunk type must be 4 bytes: {:?}", s))?;
+    Ok(ChunkType(type_))
+}
+
+/// A decoded row, see [`decode_image`].
+pub struct Row<'a> {
+    /// Index of the row, from the top
+    pub y: u32,
+    /// Unfiltered data, in the PNG's sample format
+    pub data: &'a [u8],
+}
+
+/// Where to stop decoding early. The default decodes the whole image.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Limit {
+    pub rows: Option<u32>,
+    /// Bytes of unfiltered row data, rounded up to a whole row
+    pub bytes: Option<u64>,
+}
+
+impl Limit {
+    /// Header describing the part of the image that will be decoded
+    pub fn apply(&self, header: &ImageHeader) -> ImageHeader {
+        let mut height = header.height;
+        if let Some(rows) = self.rows {
+            height = height.min(rows);
+        }
+        // Without the filter type byte, zero for a width of zero
+        let row_len = header
+            .scanline_len()
+            .and_then(|len| len.checked_sub(1))
+            .filter(|&len| len > 0);
+        if let (Some(bytes), Some(row_len)) = (self.bytes, row_len) {
+            let rows = bytes.div_ceil(row_len);
+            height = height.min(rows.try_into().unwrap_or(u32::MAX));
+        }
+        ImageHeader { height, ..*header }
+    }
+}
+
+/// Runs the whole decoder, calling `f` with each unfiltered row. With a [`Limit`], `f` gets a
+/// header with the reduced height and the rest of the file is not read.
+pub fn decode_image(
+    file: impl std::io::Read,
+    buf: &mut [u8],
+    limit: Limit,
+    f: impl FnMut(&ImageHeader, &Palette, Row) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    decode_image_with_progress(file, buf, limit, |_| {}, f)
+}
+
+/// Like [`decode_image`], also calling `on_progress` after each buffer read from `file`.
+pub fn decode_image_with_progress(
+    mut file: impl std::io::Read,
+    buf: &mut [u8],
+    limit: Limit,
+    mut on_progress: impl FnMut(&inflater::Progress),
+    mut f: impl FnMut(&ImageHeader, &Palette, Row) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let mut dechunker = Dechunker::new();
+    let mut sd = StreamDecoder::new();
+    let mut inflater = Inflater::<1024>::new();
+    let mut unfilterer = Box::new(Unfilterer::<{ 1 << 16 }>::new());
+    let mut header = None;
+    let mut y = 0;
+    let done = |header: &Option<ImageHeader>, y| matches!(header, Some(h) if y >= h.height);
+
+    loop {
+        let n = file.read(buf)?;
+        if n == 0 {
+            break;
+        }
+        dechunker.update_all(&buf[..n], |e| {
+            if done(&header, y) {
+                return Ok(());
+            }
+            let mut dc_event = Some(e);
+            while let Some(e) = dc_event {
+                let (leftover, mut sd_event) = sd.update(e)?;
+                while let Some(e) = sd_event {
+                    let (leftover, mut i_event) = inflater.update(e)?;
+                    while let Some(e) = i_event {
+                        let (leftover, u_event) = unfilterer.update(e)?;
+                        match u_event {
+                            Some(unfilter::Event::ImageHeader(h)) => header = Some(limit.apply(&h)),
+                            Some(unfilter::Event::Row(_)) if done(&header, y) => {}
+                            Some(unfilter::Event::Row(data)) => {
+                                f(header.as_ref().unwrap(), sd.palette(), Row { y, data })?;
+                                y += 1;
+                            }
+                            _ => {}
+                        }
+                        i_event = leftover;
+                    }
+                    sd_event = leftover;
+                }
+                dc_event = leftover;
+            }
+            Ok::<_, anyhow::Error>(())
+        })?;
+        on_progress(&inflater.progress(&dechunker));
+        if done(&header, y) {
+            return Ok(());
+        }
+    }
+    dechunker.eof()?;
+    sd.eof()?;
+    Ok(())
+}
+
+/// Decompresses a zlib stream embedded in a chunk, using the image data [`Inflater`].
+pub fn inflate(data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut inflater = Inflater::<1024>::new();
+    let mut output = vec![];
+    let mut event = Some(sd::Event::ImageData(data));
+    while let Some(e) = event {
+        let (leftover, i_event) = inflater.update(e)?;
+        if let Some(inflater::Event::ImageData(data)) = i_event {
+            output.extend_from_slice(data);
+        }
+        event = leftover;
+    }
+    Ok(output)
+}
+
+/// Collects the complete data of the chunks accepted by `select`, calling `f` with each.
+pub fn for_each_chunk(
+    mut file: impl std::io::Read,
+    buf: &mut [u8],
+    select: impl Fn(ChunkType) -> bool,
+    mut f: impl FnMut(ChunkType, &[u8]) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let mut dechunker = Dechunker::new();
+    let mut chunk: Option<(ChunkType, Vec<u8>)> = None;
+
+    loop {
+        let n = file.read(buf)?;
+        if n == 0 {
+            break;
+        }
+        dechunker.update_all(&buf[..n], |e| {
+            match e {
+                dechunker::Event::BeginChunk(h) if select(h.type_) => {
+                    chunk = Some((h.type_, Vec::with_capacity(h.len as usize)));
+                }
+                dechunker::Event::Data(data) => {
+                    if let Some((_, contents)) = &mut chunk {
+                        contents.extend_from_slice(data);
+                    }
+                }
+                dechunker::Event::EndChunk => {
+                    if let Some((type_, contents)) = chunk.take() {
+                        f(type_, &contents)?;
+                    }
+                }
+                _ => {}
+            }
+            Ok::<_, anyhow::Error>(())
+        })?;
+    }
+    dechunker.eof()?;
+    Ok(())
+}
+
+/// Splits at the first null byte.
+pub fn split_null(data: &[u8]) -> anyhow::Result<(&[u8], &[u8])> {
+    let i = data
+        .iter()
+        .position(|&b| b == 0)
+        .ok_or_else(|| anyhow::anyhow!("missing null separator"))?;
+    Ok((&data[..i], &data[i + 1..]))
+}
+
+/// Decodes Latin-1 text, as used by tEXt and zTXt chunks.
+pub fn latin1(s: &[u8]) -> String {
+    s.iter().map(|&b| b as char).collect()
+}
+
+/// Finds the Adler-32 trailer of the zlib stream in the IDAT chunks. Returns its offset in the
+/// concatenated IDAT data, and the correct checksum if it's wrong.
+pub fn find_adler32(data: &[u8], buf: &mut [u8]) -> anyhow::Result<Option<(u64, [u8; 4])>> {
+    use miniz_oxide::inflate::stream::{inflate, InflateState};
+    use miniz_oxide::{DataFormat, MZFlush, MZStatus};
+
+    let mut image_data = vec![];
+    for_each_chunk(
+        data,
+        buf,
+        |t| t.0 == *b"IDAT",
+        |_, data| {
+            image_data.extend_from_slice(data);
+            Ok(())
+        },
+    )?;
+
+    // Inflate the raw deflate stream after the 2-byte zlib header, so that a bad checksum
+    // doesn't stop decompression.
+    let deflate = image_data
+        .get(2..)
+        .ok_or_else(|| anyhow::anyhow!("image data too short"))?;
+    let mut state = InflateState::new_boxed(DataFormat::Raw);
+    let mut adler = Adler32::new();
+    let mut output = vec![0; 1 << 16];
+    let mut consumed = 0;
+    loop {
+        let result = inflate(&mut state, &deflate[consumed..], &mut output, MZFlush::None);
+        consumed += result.bytes_consumed;
+        adler.update(&output[..result.bytes_written]);
+        match result.status {
+            Ok(MZStatus::StreamEnd) => break,
+            Ok(_) if result.bytes_consumed > 0 || result.bytes_written > 0 => {}
+            _ => anyhow::bail!("invalid deflate stream"),
+        }
+    }
+
+    let offset = 2 + consumed;
+    let expected = adler.finish().to_be_bytes();
+    match image_data.get(offset..offset + 4) {
+        Some(stored) if stored == expected => Ok(None),
+        _ if image_data.len() < offset + 4 => anyhow::bail!("missing Adler-32 checksum"),
+        _ => Ok(Some((offset as u64, expected))),
+    }
+}
+
+/// Copies the PNG to `output`, recomputing CRCs and replacing the Adler-32 checksum in the
+/// image data if `adler` is given. Returns the number of checksums that changed.
+pub fn fix_crc(
+    mut file: impl std::io::Read,
+    buf: &mut [u8],
+    adler: Option<(u64, [u8; 4])>,
+    output: &mut impl Write,
+) -> anyhow::Result<usize> {
+    let mut dechunker = Dechunker::new().with_crc_events();
+    let mut chunker = Chunker::new();
+    let mut out_buf = [0; 4096];
+    let mut crc = Crc32::new();
+    let mut fixed = adler.iter().count();
+    let mut in_idat = false;
+    // Position in the concatenated IDAT data
+    let mut idat_pos = 0u64;
+    let mut patched = vec![];
+
+    loop {
+        let n = file.read(buf)?;
+        if n == 0 {
+            break;
+        }
+        dechunker.update_all(&buf[..n], |e| {
+            let e = match e {
+                dechunker::Event::BeginChunk(ref h) => {
+                    crc = Crc32::new();
+                    crc.update(&h.type_.0);
+                    in_idat = h.type_.0 == *b"IDAT";
+                    e
+                }
+                dechunker::Event::Data(data) => {
+                    crc.update(data);
+                    let start = idat_pos;
+                    if in_idat {
+                        idat_pos += data.len() as u64;
+                    }
+                    match adler {
+                        Some((offset, checksum))
+                            if in_idat && offset < idat_pos && offset + 4 > start =>
+                        {
+                            patched.clear();
+                            patched.extend_from_slice(data);
+                            for (i, &b) in checksum.iter().enumerate() {
+                                let pos = offset + i as u64;
+                                if (start..idat_pos).contains(&pos) {
+                                    patched[(pos - start) as usize] = b;
+                                }
+                            }
+                            dechunker::Event::Data(&patched)
+                        }
+                        _ => e,
+                    }
+                }
+                dechunker::Event::Crc(stored) => {
+                    if stored != crc.clone().finish() {
+                        fixed += 1;
+                    }
+                    e
+                }
+                dechunker::Event::EndChunk | dechunker::Event::Resync(_) => e,
+            };
+            let mut event = Some(e);
+            while let Some(e) = event {
+                let (leftover, n) = chunker.update(e, &mut out_buf)?;
+                output.write_all(&out_buf[..n])?;
+                event = leftover;
+            }
+            Ok::<_, anyhow::Error>(())
+        })?;
+    }
+    dechunker.eof()?;
+    chunker.eof()?;
+    Ok(fixed)
+}
+
+/// Copies the critical chunks and the ancillary chunks listed in `keep` to `output`. Returns
+/// the number of chunks removed.
+pub fn strip(
+    mut file: impl std::io::Read,
+    buf: &mut [u8],
+    keep: &[ChunkType],
+    output: &mut impl Write,
+) -> anyhow::Result<usize> {
+    let mut dechunker = Dechunker::new();
+    let mut chunker = Chunker::new();
+    let mut out_buf = [0; 4096];
+    let mut skipping = false;
+    let mut stripped = 0;
+
+    loop {
+        let n = file.read(buf)?;
+        if n == 0 {
+            break;
+        }
+        dechunker.update_all(&buf[..n], |e| {
+            if let dechunker::Event::BeginChunk(h) = &e {
+                skipping = h.type_.is_ancillary() && !keep.contains(&h.type_);
+                if skipping {
+                    stripped += 1;
+                }
+            }
+            if skipping {
+                return Ok(());
+            }
+            let mut event = Some(e);
+            while let Some(e) = event {
+                let (leftover, n) = chunker.update(e, &mut out_buf)?;
+                output.write_all(&out_buf[..n])?;
+                event = leftover;
+            }
+            Ok::<_, anyhow::Error>(())
+        })?;
+    }
+    dechunker.eof()?;
+    chunker.eof()?;
+    Ok(stripped)
+}
+
+/// Extracts the samples of a decoded row, one per element, expanding palette indices to RGB.
+/// Returns the maximum sample value.
+pub fn row_samples(header: &ImageHeader, palette: &Palette, row: &[u8], out: &mut Vec<u16>) -> u16 {
+    out.clear();
+    let width = header.width as usize;
+    if header.colour_type == 3 {
+        let mut colours = vec![[0; 3]; width];
+        // Out-of-range indices show up as black
+        let _ = palette.expand_row(row, header.bit_depth, &mut colours, Strictness::Lenient);
+        out.extend(colours.as_flattened().iter().map(|&s| s as u16));
+        return 255;
+    }
+    let channels = header.channels().unwrap_or(1) as usize;
+    match header.bit_depth {
+        16 => out.extend(
+            row.chunks_exact(2)
+                .map(|s| u16::from_be_bytes([s[0], s[1]])),
+        ),
+        8 => out.extend(row.iter().map(|&s| s as u16)),
+        bit_depth => {
+            let bit_depth = bit_depth as usize;
+            let mask = (1u8 << bit_depth) - 1;
+            out.extend((0..width * channels).map(|i| {
+                let bit = i * bit_depth;
+                ((row[bit / 8] >> (8 - bit_depth - bit % 8)) & mask) as u16
+            }));
+        }
+    }
+    ((1u32 << header.bit_depth) - 1) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IMAGE: &[u8] = include_bytes!("../pngsuite/basn2c08.png");
+
+    #[test]
+    fn verify_ok() {
+        let mut buf = [0; 100];
+        assert_eq!(verify(IMAGE, &mut buf, |p| panic!("{}", p)), 0);
+    }
+
+    #[test]
+    fn verify_crc_mismatch() {
+        let mut image = IMAGE.to_vec();
+        // First byte of the IHDR CRC
+        image[8 + 8 + 13] ^= 1;
+        let mut problems = vec![];
+        let count = verify(&image[..], &mut [0; 100], |p| problems.push(p));
+        assert_eq!(count, 1);
+        assert!(matches!(
+            problems[0],
+            Problem::CrcMismatch { chunk: ChunkType(t), .. } if t == *b"IHDR"
+        ));
+    }
+
+    #[test]
+    fn decode_with_limit() {
+        let limit = Limit {
+            rows: None,
+            bytes: Some(100),
+        };
+        let mut rows = vec![];
+        decode_image(IMAGE, &mut [0; 100], limit, |header, _, row| {
+            assert_eq!(header.height, 2);
+            rows.push(row.y);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(rows, [0, 1]);
+    }
+
+    #[test]
+    fn limit_zero_width() {
+        let header = ImageHeader {
+            width: 0,
+            height: 5,
+            bit_depth: 8,
+            colour_type: 0,
+            compression_method: 0,
+            filter_method: 0,
+            interlace_method: 0,
+        };
+        let limit = Limit {
+            rows: Some(3),
+            bytes: Some(100),
+        };
+        assert_eq!(limit.apply(&header).height, 3);
+    }
+}
@@ -1,88 +1,961 @@
-use std::io::Read;
-use std::{fs::File, path::PathBuf};
+use std::io::{BufWriter, Write};
+use std::{fs::File, path::Path, path::PathBuf};
 
-use clap::Parser;
+use clap::{error::ErrorKind, ArgGroup, CommandFactory, Parser};
 use incremental_png::{
-    dechunker::Dechunker, inflater::Inflater, stream_decoder as sd, stream_decoder::StreamDecoder,
+    apng::{AnimationControl, FrameControl},
+    dechunker,
+    dechunker::{ChunkType, Dechunker},
+    footprint,
+    inflater::{Inflater, Progress},
+    stream_decoder as sd,
+    stream_decoder::{ImageHeader, StreamDecoder},
+    unfilter::Unfilterer,
+    Palette, Strictness,
 };
+use png_tools::{
+    decode_image, decode_image_with_progress, find_adler32, fix_crc, for_each_chunk, inflate,
+    latin1, parse_chunk_type, row_samples, split_null, strip, Limit, Row, Traced,
+};
+use serde_json::json;
 
+/// Exactly one mode has to be selected, e.g. `--events` for the event trace.
 #[derive(Parser, Debug)]
+#[command(group(ArgGroup::new("mode").required(true)))]
 struct Args {
-    #[arg()]
-    input_file: PathBuf,
+    /// Input files, `-` for stdin. Only one with the modes writing an output file
+    #[arg(required_unless_present = "print_sizes")]
+    input_files: Vec<PathBuf>,
 
-    #[arg(long, default_value_t = 1024)]
+    #[arg(
+        long,
+        default_value_t = 1024,
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+    )]
     input_buffer_size: usize,
 
-    #[arg(long)]
+    /// Print the events produced by each decoder stage
+    #[arg(long, group = "mode")]
+    events: bool,
+
+    /// Print the memory footprint of the decoder stages
+    #[arg(long, group = "mode")]
     print_sizes: bool,
 
-    #[arg(long)]
+    /// Print the LZ77 window size declared by the zlib header of the image data
+    #[arg(long, group = "mode")]
     print_window_size: bool,
+
+    /// Print the chunk list, header, palette size, text metadata and errors as JSON
+    #[arg(long, group = "mode")]
+    json: bool,
+
+    /// Check signature, chunk order, CRCs, image data integrity and size. Exits with status 1
+    /// if any check fails.
+    #[arg(long, group = "mode")]
+    verify: bool,
+
+    /// Write the data of all chunks of this type to files named `TYPE.N` in `--out-dir`
+    #[arg(long, group = "mode", value_name = "TYPE")]
+    extract_chunk: Option<String>,
+
+    #[arg(long, default_value = ".")]
+    out_dir: PathBuf,
+
+    /// Write the decoded image as PPM (PGM for grayscale, PAM with alpha)
+    #[arg(long, group = "mode", value_name = "FILE")]
+    to_ppm: Option<PathBuf>,
+
+    /// Write the decoded rows as raw bytes, in the PNG's own sample format
+    #[arg(long, group = "mode", value_name = "FILE")]
+    to_raw: Option<PathBuf>,
+
+    /// Re-encode the decoded image with the `png` crate, then read it back and compare the
+    /// pixels. Exits with status 1 if they differ.
+    #[arg(long, group = "mode", value_name = "FILE")]
+    to_png: Option<PathBuf>,
+
+    /// Decode the file this many times, feeding `--input-buffer-size` bytes at a time, and
+    /// report the throughput of each stage
+    #[arg(long, group = "mode", value_name = "N")]
+    bench: Option<u32>,
+
+    /// Print tEXt, zTXt and iTXt metadata
+    #[arg(long, group = "mode")]
+    text: bool,
+
+    /// Decompress the embedded iCCP colour profile and write it to this file
+    #[arg(long, group = "mode", value_name = "FILE")]
+    icc: Option<PathBuf>,
+
+    /// List APNG animation parameters and frames
+    #[arg(long, group = "mode")]
+    apng: bool,
+
+    /// Write a copy of the file with all chunk CRCs recomputed
+    #[arg(long, group = "mode", value_name = "FILE")]
+    fix_crc: Option<PathBuf>,
+
+    /// With `--fix-crc`, also recompute the Adler-32 checksum of the image data
+    #[arg(long, requires = "fix_crc")]
+    fix_adler: bool,
+
+    /// Write a copy of the file with only critical chunks
+    #[arg(long, group = "mode", value_name = "FILE")]
+    strip: Option<PathBuf>,
+
+    /// With `--strip`, ancillary chunk types to keep, e.g. `--keep tRNS,gAMA`
+    #[arg(long, value_delimiter = ',', requires = "strip")]
+    keep: Vec<String>,
+
+    /// Print per-channel min/max/mean and a coarse histogram of the decoded pixels
+    #[arg(long, group = "mode")]
+    histogram: bool,
+
+    /// Print the file offset, type and length of every chunk
+    #[arg(long, group = "mode")]
+    offsets: bool,
+
+    /// Decode with both this crate and the `png` crate and report the first difference. Exits
+    /// with status 1 if the images differ.
+    #[arg(long, group = "mode")]
+    compare: bool,
+
+    /// With the modes that decode pixels, stop after this many rows
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    max_rows: Option<u32>,
+
+    /// With the modes that decode pixels, stop after the row that brings the output to this
+    /// many bytes
+    #[arg(long, value_name = "N")]
+    max_bytes: Option<u64>,
+
+    /// With `--events`, print data payloads as a hexdump of their first K bytes
+    #[arg(long, value_name = "K")]
+    hexdump: Option<usize>,
+
+    /// Print one line per file: dimensions, format, chunk count, image data size and
+    /// compression ratio
+    #[arg(long, group = "mode")]
+    summary: bool,
+
+    /// With `--to-ppm` and `--to-raw`, show the decoding progress on stderr, updated after each
+    /// input buffer
+    #[arg(long)]
+    progress: bool,
+}
+
+impl Args {
+    fn limit(&self) -> Limit {
+        Limit {
+            rows: self.max_rows,
+            bytes: self.max_bytes,
+        }
+    }
+
+    /// Option of the selected mode if it writes to a single output, which several input files
+    /// would overwrite.
+    fn single_output_mode(&self) -> Option<&'static str> {
+        [
+            ("--extract-chunk", self.extract_chunk.is_some()),
+            ("--to-ppm", self.to_ppm.is_some()),
+            ("--to-raw", self.to_raw.is_some()),
+            ("--to-png", self.to_png.is_some()),
+            ("--icc", self.icc.is_some()),
+            ("--fix-crc", self.fix_crc.is_some()),
+            ("--strip", self.strip.is_some()),
+        ]
+        .into_iter()
+        .find_map(|(option, selected)| selected.then_some(option))
+    }
 }
 
 fn main() -> anyhow::Result<()> {
     let args = Args::parse();
-    let mut file = File::open(args.input_file)?;
-
-    let mut dechunker = Dechunker::new();
-    let mut sd = StreamDecoder::new();
-    let mut inflater = Inflater::<1024>::new();
+    if let (Some(option), [_, _, ..]) = (args.single_output_mode(), &args.input_files[..]) {
+        Args::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                format!("{option} writes a single output, so it takes only one input file"),
+            )
+            .exit();
+    }
 
     if args.print_sizes {
         println!("Memory usage:");
-        println!("  Dechunker: {}", std::mem::size_of::<Dechunker>());
-        println!("  StreamDecoder: {}", std::mem::size_of::<StreamDecoder>());
+        println!("  Dechunker: {}", Dechunker::MEMORY_FOOTPRINT);
+        println!(
+            "  StreamDecoder: {}",
+            StreamDecoder::<256>::MEMORY_FOOTPRINT
+        );
         println!(
             "  Inflater (output buffer=1024): {}",
-            std::mem::size_of::<Inflater<1024>>()
+            Inflater::<1024>::MEMORY_FOOTPRINT
         );
+        println!(
+            "  Unfilterer (max scanline=1024): {}",
+            Unfilterer::<1024>::MEMORY_FOOTPRINT
+        );
+        println!("  Total: {}", footprint::decoder::<256, 1024, 1024>());
+        return Ok(());
     }
 
     let mut buf = vec![0u8; args.input_buffer_size];
+    let mut all_ok = true;
+
+    for (i, path) in args.input_files.iter().enumerate() {
+        if args.input_files.len() > 1 && !args.summary {
+            if i > 0 {
+                println!();
+            }
+            println!("==> {} <==", path.display());
+        }
+        let result = if path.as_os_str() == "-" {
+            process_file(&args, path, std::io::stdin().lock(), &mut buf)
+        } else {
+            File::open(path)
+                .map_err(anyhow::Error::from)
+                .and_then(|file| process_file(&args, path, file, &mut buf))
+        };
+        match result {
+            Ok(ok) => all_ok &= ok,
+            Err(e) => {
+                eprintln!("{}: {}", path.display(), e);
+                all_ok = false;
+            }
+        }
+    }
+
+    if !all_ok {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Writes `path` through a temporary file in the same directory, which replaces it once `f`
+/// succeeded. So the output can be the input file, e.g. to fix its CRCs in place, and a failed
+/// run leaves an existing file untouched.
+fn write_output<T>(
+    path: &Path,
+    f: impl FnOnce(&mut BufWriter<File>) -> anyhow::Result<T>,
+) -> anyhow::Result<T> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| anyhow::anyhow!("{}: not a file name", path.display()))?;
+    let temp = path.with_file_name(format!(".{}.tmp", name.to_string_lossy()));
+    let result = File::create(&temp)
+        .map_err(anyhow::Error::from)
+        .and_then(|file| {
+            let mut output = BufWriter::new(file);
+            let value = f(&mut output)?;
+            output.flush()?;
+            Ok(value)
+        });
+    match result {
+        Ok(value) => {
+            std::fs::rename(&temp, path)?;
+            Ok(value)
+        }
+        Err(e) => {
+            let _ = std::fs::remove_file(&temp);
+            Err(e)
+        }
+    }
+}
+
+/// [`decode_image`] with `args.limit()`, printing a progress line on stderr with `--progress`.
+fn decode_with_progress(
+    args: &Args,
+    file: impl std::io::Read,
+    buf: &mut [u8],
+    f: impl FnMut(&ImageHeader, &Palette, Row) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let print = |progress: &Progress| {
+        if !args.progress {
+            return;
+        }
+        match progress.percent() {
+            Some(percent) => eprint!("\r{percent:3}% ({} bytes read)", progress.input_bytes),
+            None => eprint!("\r  ?% ({} bytes read)", progress.input_bytes),
+        }
+    };
+    let result = decode_image_with_progress(file, buf, args.limit(), print, f);
+    if args.progress {
+        eprintln!();
+    }
+    result
+}
+
+/// Runs the selected mode on one input. Returns false if a check failed.
+fn process_file(
+    args: &Args,
+    path: &Path,
+    mut file: impl std::io::Read,
+    buf: &mut [u8],
+) -> anyhow::Result<bool> {
+    if args.summary {
+        print_summary(path, file, buf)?;
+        return Ok(true);
+    }
 
     if args.print_window_size {
-        print_window_size(file, &mut buf)?;
-        return Ok(());
+        print_window_size(file, buf)?;
+        return Ok(true);
+    }
+
+    if args.json {
+        return print_json(path, file, buf);
+    }
+
+    if let Some(type_) = &args.extract_chunk {
+        let count = extract_chunk(file, buf, parse_chunk_type(type_)?, &args.out_dir)?;
+        println!("Extracted {} chunk(s)", count);
+        return Ok(true);
+    }
+
+    if let Some(path) = &args.to_ppm {
+        let mut pixels = vec![];
+        write_output(path, |output| {
+            decode_with_progress(args, file, buf, |header, palette, row| {
+                write_pnm_row(output, header, palette, row, &mut pixels)
+            })
+        })?;
+        return Ok(true);
+    }
+
+    if let Some(path) = &args.to_raw {
+        write_output(path, |output| {
+            decode_with_progress(args, file, buf, |_, _, row| Ok(output.write_all(row.data)?))
+        })?;
+        return Ok(true);
+    }
+
+    if let Some(path) = &args.to_png {
+        if !roundtrip(file, buf, path, args.limit())? {
+            println!("Pixels differ after re-encoding");
+            return Ok(false);
+        }
+        println!("Pixels match");
+        return Ok(true);
+    }
+
+    if let Some(iterations) = args.bench {
+        let mut data = vec![];
+        file.read_to_end(&mut data)?;
+        bench(&data, buf.len(), iterations)?;
+        return Ok(true);
+    }
+
+    if args.text {
+        print_text(file, buf)?;
+        return Ok(true);
+    }
+
+    if let Some(path) = &args.icc {
+        let mut found = false;
+        for_each_chunk(
+            file,
+            buf,
+            |t| t.0 == *b"iCCP",
+            |_, data| {
+                let (name, rest) = split_null(data)?;
+                // Compression method byte, always 0
+                let profile = inflate(rest.get(1..).unwrap_or_default())?;
+                write_output(path, |output| Ok(output.write_all(&profile)?))?;
+                println!("{}: {} bytes", latin1(name), profile.len());
+                found = true;
+                Ok(())
+            },
+        )?;
+        if !found {
+            println!("No iCCP chunk");
+        }
+        return Ok(found);
+    }
+
+    if args.apng {
+        print_apng(file, buf)?;
+        return Ok(true);
     }
 
+    if let Some(path) = &args.fix_crc {
+        let fixed = write_output(path, |output| {
+            if args.fix_adler {
+                let mut data = vec![];
+                file.read_to_end(&mut data)?;
+                let adler = find_adler32(&data, buf)?;
+                fix_crc(&data[..], buf, adler, output)
+            } else {
+                fix_crc(file, buf, None, output)
+            }
+        })?;
+        println!("{} checksum(s) corrected", fixed);
+        return Ok(true);
+    }
+
+    if let Some(path) = &args.strip {
+        let keep = args
+            .keep
+            .iter()
+            .map(|t| parse_chunk_type(t))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let stripped = write_output(path, |output| strip(file, buf, &keep, output))?;
+        println!("{} chunk(s) removed", stripped);
+        return Ok(true);
+    }
+
+    if args.histogram {
+        print_histogram(file, buf, args.limit())?;
+        return Ok(true);
+    }
+
+    if args.offsets {
+        print_offsets(file, buf)?;
+        return Ok(true);
+    }
+
+    if args.compare {
+        let mut data = vec![];
+        file.read_to_end(&mut data)?;
+        return compare(&data, buf, args.limit());
+    }
+
+    if args.verify {
+        let problems = png_tools::verify(file, buf, |problem| println!("{}", problem));
+        if problems > 0 {
+            println!("{}: {} problem(s)", path.display(), problems);
+            return Ok(false);
+        }
+        println!("{}: OK", path.display());
+        return Ok(true);
+    }
+
+    // --events
+    print_events(file, buf, args.hexdump)?;
+    Ok(true)
+}
+
+fn print_events(
+    file: impl std::io::Read,
+    buf: &mut [u8],
+    hexdump: Option<usize>,
+) -> anyhow::Result<()> {
+    png_tools::trace(file, buf, |e| {
+        let data = e.data();
+        match e {
+            Traced::Dechunker(e) => print_event("c: ", e, data, hexdump),
+            Traced::StreamDecoder(e) => print_event(" s: ", e, data, hexdump),
+            Traced::Inflater(e) => print_event("  i: ", &e, data, hexdump),
+        }
+    })
+}
+
+/// Prints one line of the event trace. With `hexdump`, an event carrying `data` (variant name
+/// and payload) is printed as its length followed by a hexdump of the first `hexdump` bytes.
+fn print_event(
+    prefix: &str,
+    event: &dyn std::fmt::Debug,
+    data: Option<(&str, &[u8])>,
+    hexdump: Option<usize>,
+) {
+    let (Some((name, data)), Some(limit)) = (data, hexdump) else {
+        println!("{}{:?}", prefix, event);
+        return;
+    };
+    println!("{}{}({} bytes)", prefix, name, data.len());
+    let indent = " ".repeat(prefix.len());
+    for (i, line) in data[..limit.min(data.len())].chunks(16).enumerate() {
+        let hex: Vec<_> = line.iter().map(|b| format!("{:02x}", b)).collect();
+        let ascii: String = line
+            .iter()
+            .map(|&b| {
+                if b.is_ascii_graphic() || b == b' ' {
+                    b as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        println!(
+            "{}{:08x}  {:<47}  |{}|",
+            indent,
+            i * 16,
+            hex.join(" "),
+            ascii
+        );
+    }
+    if data.len() > limit {
+        println!("{}...", indent);
+    }
+}
+
+fn print_window_size(file: impl std::io::Read, buf: &mut [u8]) -> anyhow::Result<()> {
+    if let Some(size) = png_tools::window_size(file, buf)? {
+        println!("{}", size);
+    }
+    Ok(())
+}
+
+/// Returns false if decoding failed.
+fn print_json(path: &Path, mut file: impl std::io::Read, buf: &mut [u8]) -> anyhow::Result<bool> {
+    let mut dechunker = Dechunker::new();
+    let mut sd = StreamDecoder::new();
+    let mut chunks = vec![];
+    let mut header = None;
+    let mut texts = vec![];
+    // Contents of the current tEXt chunk
+    let mut text: Option<Vec<u8>> = None;
+
+    let mut decode = || -> anyhow::Result<()> {
+        loop {
+            let n = file.read(buf)?;
+            if n == 0 {
+                break;
+            }
+            dechunker.update_all(&buf[..n], |e| {
+                match &e {
+                    dechunker::Event::BeginChunk(h) => {
+                        chunks.push(json!({ "type": h.type_.to_string(), "length": h.len }));
+                        text = (h.type_.0 == *b"tEXt").then(Vec::new);
+                    }
+                    dechunker::Event::Data(data) => {
+                        if let Some(text) = &mut text {
+                            text.extend_from_slice(data);
+                        }
+                    }
+                    dechunker::Event::EndChunk => {
+                        if let Some(text) = text.take() {
+                            let (keyword, value) = match text.iter().position(|&b| b == 0) {
+                                Some(i) => (&text[..i], &text[i + 1..]),
+                                None => (&text[..], &[][..]),
+                            };
+                            texts
+                                .push(json!({ "keyword": latin1(keyword), "text": latin1(value) }));
+                        }
+                    }
+                    dechunker::Event::Crc(_) | dechunker::Event::Resync(_) => {}
+                }
+                let mut event = Some(e);
+                while let Some(e) = event {
+                    let (leftover, sd_event) = sd.update(e)?;
+                    if let Some(sd::Event::ImageHeader(h)) = sd_event {
+                        header = Some(h);
+                    }
+                    event = leftover;
+                }
+                Ok::<_, incremental_png::Error>(())
+            })?;
+        }
+        dechunker.eof()?;
+        Ok(())
+    };
+    let error = decode().err();
+
+    let palette = sd.palette();
+    let output = json!({
+        "file": path.display().to_string(),
+        "chunks": chunks,
+        "header": header.map(|h| json!({
+            "width": h.width,
+            "height": h.height,
+            "bit_depth": h.bit_depth,
+            "colour_type": h.colour_type,
+            "compression_method": h.compression_method,
+            "filter_method": h.filter_method,
+            "interlace_method": h.interlace_method,
+        })),
+        "palette_size": (!palette.is_empty()).then(|| palette.len()),
+        "text": texts,
+        "error": error.as_ref().map(|e| e.to_string()),
+    });
+    println!("{}", serde_json::to_string_pretty(&output)?);
+
+    Ok(error.is_none())
+}
+
+/// Writes the data of each chunk of type `type_` to a file in `out_dir`. Returns the number of
+/// chunks found.
+fn extract_chunk(
+    mut file: impl std::io::Read,
+    buf: &mut [u8],
+    type_: ChunkType,
+    out_dir: &Path,
+) -> anyhow::Result<usize> {
+    std::fs::create_dir_all(out_dir)?;
+    let mut dechunker = Dechunker::new();
+    let mut count = 0;
+    let mut output: Option<File> = None;
+
     loop {
-        let n = file.read(&mut buf)?;
+        let n = file.read(buf)?;
         if n == 0 {
             break;
         }
-        let mut input = &buf[..n];
+        dechunker.update_all(&buf[..n], |e| {
+            match e {
+                dechunker::Event::BeginChunk(h) if h.type_ == type_ => {
+                    let path = out_dir.join(format!("{}.{}", type_, count));
+                    output = Some(File::create(&path)?);
+                    count += 1;
+                }
+                dechunker::Event::Data(data) => {
+                    if let Some(output) = &mut output {
+                        output.write_all(data)?;
+                    }
+                }
+                dechunker::Event::EndChunk => output = None,
+                _ => {}
+            }
+            Ok::<_, anyhow::Error>(())
+        })?;
+    }
+    dechunker.eof()?;
+    Ok(count)
+}
 
-        while !input.is_empty() {
-            let (consumed, mut dc_event) = dechunker.update(&input).unwrap();
+/// Writes a row of a PNM image, preceded by the PNM header for the first row. `pixels` is
+/// scratch space for converting the row.
+fn write_pnm_row(
+    output: &mut impl Write,
+    header: &ImageHeader,
+    palette: &Palette,
+    row: Row,
+    pixels: &mut Vec<u8>,
+) -> anyhow::Result<()> {
+    let maxval = (1u32 << header.bit_depth) - 1;
+    let maxval = if header.colour_type == 3 { 255 } else { maxval };
+    let (width, height) = (header.width, header.height);
+    if row.y == 0 {
+        match header.colour_type {
+            0 => write!(output, "P5\n{} {}\n{}\n", width, height, maxval)?,
+            2 | 3 => write!(output, "P6\n{} {}\n{}\n", width, height, maxval)?,
+            4 | 6 => {
+                let (depth, tupltype) = if header.colour_type == 4 {
+                    (2, "GRAYSCALE_ALPHA")
+                } else {
+                    (4, "RGB_ALPHA")
+                };
+                write!(
+                    output,
+                    "P7\nWIDTH {}\nHEIGHT {}\nDEPTH {}\nMAXVAL {}\nTUPLTYPE {}\nENDHDR\n",
+                    width, height, depth, maxval, tupltype
+                )?
+            }
+            _ => unreachable!("rejected by the decoder"),
+        }
+    }
 
-            while let Some(e) = dc_event {
-                println!("c: {:?}", e);
+    if header.colour_type == 3 {
+        let mut colours = vec![[0; 3]; width as usize];
+        palette.expand_row(
+            row.data,
+            header.bit_depth,
+            &mut colours,
+            Strictness::Lenient,
+        )?;
+        output.write_all(colours.as_flattened())?;
+    } else if header.bit_depth < 8 {
+        // One byte per sample
+        let bit_depth = header.bit_depth as usize;
+        let mask = maxval as u8;
+        pixels.clear();
+        pixels.extend((0..width as usize).map(|i| {
+            let bit = i * bit_depth;
+            (row.data[bit / 8] >> (8 - bit_depth - bit % 8)) & mask
+        }));
+        output.write_all(pixels)?;
+    } else {
+        // PNM samples are big-endian too
+        output.write_all(row.data)?;
+    }
+    Ok(())
+}
 
-                let (leftover, mut sd_event) = sd.update(e).unwrap();
+/// Decodes the image, writes it to `path` using the `png` crate, and reads it back. Returns
+/// whether the pixels are the same.
+fn roundtrip(
+    file: impl std::io::Read,
+    buf: &mut [u8],
+    path: &Path,
+    limit: Limit,
+) -> anyhow::Result<bool> {
+    let mut header = None;
+    let mut palette = vec![];
+    let mut pixels = vec![];
+    decode_image(file, buf, limit, |h, p, row| {
+        if row.y == 0 {
+            header = Some(*h);
+            palette = p.as_rgb_slice().to_vec();
+        }
+        pixels.extend_from_slice(row.data);
+        Ok(())
+    })?;
+    let header = header.ok_or_else(|| anyhow::anyhow!("no image data"))?;
 
-                while let Some(e) = sd_event {
-                    println!(" s: {:?}", e);
-                    let (leftover, i_event) = inflater.update(e).unwrap();
+    write_output(path, |output| {
+        let mut encoder = png::Encoder::new(output, header.width, header.height);
+        encoder.set_color(match header.colour_type {
+            0 => png::ColorType::Grayscale,
+            2 => png::ColorType::Rgb,
+            3 => png::ColorType::Indexed,
+            4 => png::ColorType::GrayscaleAlpha,
+            _ => png::ColorType::Rgba,
+        });
+        encoder.set_depth(
+            png::BitDepth::from_u8(header.bit_depth)
+                .ok_or_else(|| anyhow::anyhow!("invalid bit depth"))?,
+        );
+        if !palette.is_empty() {
+            encoder.set_palette(palette);
+        }
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&pixels)?;
+        Ok(writer.finish()?)
+    })?;
+
+    let mut decoder = png::Decoder::new(std::io::BufReader::new(File::open(path)?));
+    decoder.set_transformations(png::Transformations::IDENTITY);
+    let mut reader = decoder.read_info()?;
+    let mut reread = vec![0; reader.output_buffer_size().unwrap_or(0)];
+    let info = reader.next_frame(&mut reread)?;
+    Ok(reread[..info.buffer_size()] == pixels[..])
+}
 
-                    println!("  i: {:?}", i_event);
+/// Decoder stages, in pipeline order
+const STAGES: [&str; 4] = ["Dechunker", "StreamDecoder", "Inflater", "Unfilterer"];
 
+/// Runs the first `depth` stages on `data`, fed in pieces of `chunk_size` bytes. The last
+/// stage (the Unfilterer) is end-to-end decoding.
+fn run_stages(data: &[u8], chunk_size: usize, depth: usize) -> anyhow::Result<()> {
+    let mut dechunker = Dechunker::new();
+    let mut sd = StreamDecoder::new();
+    let mut inflater = Inflater::<1024>::new();
+    let mut unfilterer = Box::new(Unfilterer::<{ 1 << 16 }>::new());
+
+    for piece in data.chunks(chunk_size) {
+        dechunker.update_all(piece, |e| {
+            let mut dc_event = (depth > 1).then_some(e);
+            while let Some(e) = dc_event {
+                let (leftover, sd_event) = sd.update(e)?;
+                let mut sd_event = sd_event.filter(|_| depth > 2);
+                while let Some(e) = sd_event {
+                    let (leftover, i_event) = inflater.update(e)?;
+                    let mut i_event = i_event.filter(|_| depth > 3);
+                    while let Some(e) = i_event {
+                        let (leftover, _) = unfilterer.update(e)?;
+                        i_event = leftover;
+                    }
                     sd_event = leftover;
                 }
-
                 dc_event = leftover;
             }
+            Ok::<_, incremental_png::Error>(())
+        })?;
+    }
+    Ok(())
+}
 
-            input = &input[consumed..];
+fn bench(data: &[u8], chunk_size: usize, iterations: u32) -> anyhow::Result<()> {
+    let mb = data.len() as f64 * iterations as f64 / 1e6;
+    println!(
+        "{} bytes, {} iterations, input buffer size {}",
+        data.len(),
+        iterations,
+        chunk_size
+    );
+
+    // Stages run interleaved, so time the pipeline cut off after each stage instead
+    println!("  Stages up to:");
+    for depth in 1..=STAGES.len() {
+        let start = std::time::Instant::now();
+        for _ in 0..iterations {
+            run_stages(data, chunk_size, depth)?;
         }
+        let elapsed = start.elapsed().as_secs_f64();
+        let end_to_end = if depth == STAGES.len() {
+            " (end-to-end)"
+        } else {
+            ""
+        };
+        println!(
+            "    {:<14} {:>10.1} MB/s{}",
+            STAGES[depth - 1],
+            mb / elapsed,
+            end_to_end
+        );
     }
+    Ok(())
+}
+
+fn print_text(file: impl std::io::Read, buf: &mut [u8]) -> anyhow::Result<()> {
+    let text_chunks = [b"tEXt", b"zTXt", b"iTXt"].map(|t| ChunkType(*t));
+    for_each_chunk(
+        file,
+        buf,
+        |t| text_chunks.contains(&t),
+        |type_, data| {
+            let (keyword, rest) = split_null(data)?;
+            match &type_.0 {
+                b"tEXt" => println!("{}: {}", latin1(keyword), latin1(rest)),
+                b"zTXt" => {
+                    // Compression method byte, always 0
+                    let text = inflate(rest.get(1..).unwrap_or_default())?;
+                    println!("{}: {}", latin1(keyword), latin1(&text));
+                }
+                _ => {
+                    let (flags, rest) = rest.split_at(2.min(rest.len()));
+                    let (language, rest) = split_null(rest)?;
+                    let (translated, text) = split_null(rest)?;
+                    let text = if flags.first() == Some(&1) {
+                        inflate(text)?
+                    } else {
+                        text.to_vec()
+                    };
+                    println!(
+                        "{} [{}] ({}): {}",
+                        latin1(keyword),
+                        String::from_utf8_lossy(language),
+                        String::from_utf8_lossy(translated),
+                        String::from_utf8_lossy(&text)
+                    );
+                }
+            }
+            Ok(())
+        },
+    )
+}
 
+fn print_apng(file: impl std::io::Read, buf: &mut [u8]) -> anyhow::Result<()> {
+    let seen_idat = std::cell::Cell::new(false);
+    let mut animated = false;
+    let mut frames = 0;
+    for_each_chunk(
+        file,
+        buf,
+        // IDAT is only needed to tell whether the default image is a frame
+        |t| matches!(&t.0, b"acTL" | b"fcTL") || (t.0 == *b"IDAT" && !seen_idat.get()),
+        |type_, data| {
+            match &type_.0 {
+                b"acTL" => {
+                    let actl = AnimationControl::parse(data)?;
+                    let plays = match actl.num_plays {
+                        0 => "infinite".to_string(),
+                        n => n.to_string(),
+                    };
+                    println!("Frames: {}, plays: {}", actl.num_frames, plays);
+                    animated = true;
+                }
+                b"fcTL" => {
+                    let (sequence_number, frame) = FrameControl::parse(data)?;
+                    println!(
+                        "Frame {} (seq {}): {}x{} at ({}, {}), delay {}/{} ({} ms), dispose {:?}, blend {:?}{}",
+                        frames,
+                        sequence_number,
+                        frame.width,
+                        frame.height,
+                        frame.x_offset,
+                        frame.y_offset,
+                        frame.delay_num,
+                        frame.delay_den,
+                        frame.delay_ms(),
+                        frame.dispose_op,
+                        frame.blend_op,
+                        if seen_idat.get() {
+                            ""
+                        } else {
+                            ", default image"
+                        },
+                    );
+                    frames += 1;
+                }
+                _ => seen_idat.set(true),
+            }
+            Ok(())
+        },
+    )?;
+    if !animated {
+        println!("Not an animated PNG");
+    }
     Ok(())
 }
 
-fn print_window_size(mut file: impl std::io::Read, buf: &mut [u8]) -> anyhow::Result<()> {
+const HISTOGRAM_BUCKETS: usize = 8;
+
+#[derive(Clone)]
+struct ChannelStats {
+    min: u16,
+    max: u16,
+    sum: u64,
+    histogram: [u64; HISTOGRAM_BUCKETS],
+}
+
+fn print_histogram(file: impl std::io::Read, buf: &mut [u8], limit: Limit) -> anyhow::Result<()> {
+    let mut names = "";
+    let mut maxval = 0;
+    let mut stats = vec![];
+    let mut samples = vec![];
+    decode_image(file, buf, limit, |header, palette, row| {
+        maxval = row_samples(header, palette, row.data, &mut samples);
+        if row.y == 0 {
+            names = match header.colour_type {
+                0 => "Y",
+                2 | 3 => "RGB",
+                4 => "YA",
+                _ => "RGBA",
+            };
+            let empty = ChannelStats {
+                min: u16::MAX,
+                max: 0,
+                sum: 0,
+                histogram: [0; HISTOGRAM_BUCKETS],
+            };
+            stats = vec![empty; names.len()];
+        }
+        for (i, &sample) in samples.iter().enumerate() {
+            let s = &mut stats[i % names.len()];
+            s.min = s.min.min(sample);
+            s.max = s.max.max(sample);
+            s.sum += sample as u64;
+            s.histogram[sample as usize * HISTOGRAM_BUCKETS / (maxval as usize + 1)] += 1;
+        }
+        Ok(())
+    })?;
+
+    let values = maxval as usize + 1;
+    for (name, s) in names.chars().zip(&stats) {
+        let count: u64 = s.histogram.iter().sum();
+        println!(
+            "Channel {}: min {}, max {}, mean {:.1}",
+            name,
+            s.min,
+            s.max,
+            s.sum as f64 / count.max(1) as f64
+        );
+        for (i, &n) in s.histogram.iter().enumerate() {
+            // Smallest sample value in buckets i and i + 1
+            let lo = (i * values).div_ceil(HISTOGRAM_BUCKETS);
+            let next = ((i + 1) * values).div_ceil(HISTOGRAM_BUCKETS);
+            if lo == next {
+                // Low bit depths have fewer values than buckets
+                continue;
+            }
+            let hi = next - 1;
+            let fraction = n as f64 / count.max(1) as f64;
+            println!(
+                "  {:>5}-{:<5} {:<40} {:5.1}%",
+                lo,
+                hi,
+                "#".repeat((fraction * 40.0).round() as usize),
+                fraction * 100.0
+            );
+        }
+    }
+    Ok(())
+}
+
+fn print_offsets(mut file: impl std::io::Read, buf: &mut [u8]) -> anyhow::Result<()> {
     let mut dechunker = Dechunker::new();
-    let mut sd = StreamDecoder::new();
+    println!("{:>10} {:>10}  type  length", "offset", "(hex)");
 
     loop {
         let n = file.read(buf)?;
@@ -90,29 +963,142 @@ fn print_window_size(mut file: impl std::io::Read, buf: &mut [u8]) -> anyhow::Re
             break;
         }
         let mut input = &buf[..n];
-
         while !input.is_empty() {
-            let (consumed, mut dc_event) = dechunker.update(&input).unwrap();
+            let (consumed, event) = dechunker.update(input)?;
+            input = &input[consumed..];
+            if let Some(dechunker::Event::BeginChunk(h)) = event {
+                // BeginChunk comes right after the 8-byte length and type
+                let offset = dechunker.bytes_consumed() - 8;
+                println!("{:>10} {:>#10x}  {}  {}", offset, offset, h.type_, h.len);
+            }
+        }
+    }
+    dechunker.eof()?;
+    Ok(())
+}
 
-            while let Some(e) = dc_event {
-                let (leftover, sd_event) = sd.update(e).unwrap();
-
-                if let Some(e) = sd_event {
-                    match e {
-                        sd::Event::ImageData(data) => {
-                            let size = 1 << ((data[0] as u32 >> 4) + 8);
-                            println!("{}", size);
-                            return Ok(());
-                        }
-                        _ => {}
+/// Decodes `data` with this crate and the `png` crate. Returns whether the pixels are the same.
+fn compare(data: &[u8], buf: &mut [u8], limit: Limit) -> anyhow::Result<bool> {
+    let mut decoder = png::Decoder::new(std::io::Cursor::new(data));
+    decoder.set_transformations(png::Transformations::IDENTITY);
+    let mut reader = decoder.read_info()?;
+    let mut expected = vec![0; reader.output_buffer_size().unwrap_or(0)];
+    let info = reader.next_frame(&mut expected)?;
+    let row_len = info.line_size;
+    let bytes_per_pixel = reader.info().bytes_per_pixel().max(1);
+
+    let mut difference = None;
+    let mut rows = 0;
+    let mut height = info.height;
+    decode_image(data, buf, limit, |header, _, row| {
+        height = header.height;
+        let start = row.y as usize * row_len;
+        let expected_row = expected.get(start..start + row_len).unwrap_or_default();
+        if difference.is_none() && row.data != expected_row {
+            let byte = row
+                .data
+                .iter()
+                .zip(expected_row)
+                .position(|(a, b)| a != b)
+                .unwrap_or(0);
+            difference = Some((row.y, byte));
+        }
+        rows += 1;
+        Ok(())
+    })?;
+
+    if let Some((y, byte)) = difference {
+        let x = byte / bytes_per_pixel;
+        let pixel = x * bytes_per_pixel..(x + 1) * bytes_per_pixel;
+        println!(
+            "First difference at row {}, pixel {} (byte {}): png crate {:?}",
+            y,
+            x,
+            byte,
+            expected
+                .get(y as usize * row_len..)
+                .and_then(|r| r.get(pixel)),
+        );
+        return Ok(false);
+    }
+    if rows != height {
+        println!("Decoded {} rows, png crate {}", rows, height);
+        return Ok(false);
+    }
+    println!("Identical ({} rows)", rows);
+    Ok(true)
+}
+
+fn print_summary(path: &Path, mut file: impl std::io::Read, buf: &mut [u8]) -> anyhow::Result<()> {
+    let mut dechunker = Dechunker::new();
+    let mut ihdr = vec![];
+    let mut in_ihdr = false;
+    let mut chunks = 0;
+    let mut idat_size = 0u64;
+
+    loop {
+        let n = file.read(buf)?;
+        if n == 0 {
+            break;
+        }
+        dechunker.update_all(&buf[..n], |e| {
+            match e {
+                dechunker::Event::BeginChunk(h) => {
+                    chunks += 1;
+                    in_ihdr = h.type_.0 == *b"IHDR";
+                    if h.type_.0 == *b"IDAT" {
+                        idat_size += h.len as u64;
                     }
                 }
-
-                dc_event = leftover;
+                dechunker::Event::Data(data) if in_ihdr => ihdr.extend_from_slice(data),
+                _ => {}
             }
+            Ok::<_, anyhow::Error>(())
+        })?;
+    }
+    dechunker.eof()?;
 
-            input = &input[consumed..];
-        }
+    // Parsed here rather than by the StreamDecoder, which rejects interlaced images
+    let ihdr: [u8; 13] = ihdr[..]
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("missing or invalid IHDR"))?;
+    let header = ImageHeader {
+        width: u32::from_be_bytes(ihdr[0..4].try_into().unwrap()),
+        height: u32::from_be_bytes(ihdr[4..8].try_into().unwrap()),
+        bit_depth: ihdr[8],
+        colour_type: ihdr[9],
+        compression_method: ihdr[10],
+        filter_method: ihdr[11],
+        interlace_method: ihdr[12],
+    };
+    let colour_type = match header.colour_type {
+        0 => "grayscale",
+        2 => "RGB",
+        3 => "indexed",
+        4 => "grayscale+alpha",
+        6 => "RGBA",
+        _ => return Err(incremental_png::Error::InvalidColourType.into()),
+    };
+    if !header.bit_depth_allowed() {
+        return Err(incremental_png::Error::InvalidBitDepth.into());
     }
+    let interlace = match header.interlace_method {
+        0 => "non-interlaced",
+        1 => "interlaced",
+        _ => return Err(incremental_png::Error::InvalidInterlaceMethod.into()),
+    };
+    let raw_size = header.raw_image_size().unwrap_or(0);
+    println!(
+        "{}: {}x{}, {}, {}-bit, {}, {} chunks, IDAT {} bytes, ratio {:.2}",
+        path.display(),
+        header.width,
+        header.height,
+        colour_type,
+        header.bit_depth,
+        interlace,
+        chunks,
+        idat_size,
+        raw_size as f64 / idat_size.max(1) as f64
+    );
     Ok(())
 }
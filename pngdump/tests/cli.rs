@@ -0,0 +1,367 @@
+//! Runs the `pngdump` binary on PngSuite images.
+
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+const PNGSUITE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/pngsuite");
+
+fn pngdump(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_pngdump"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+fn suite(name: &str) -> String {
+    format!("{PNGSUITE}/{name}.png")
+}
+
+/// `png` with a chunk inserted after IHDR.
+fn with_chunk(png: &[u8], type_: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    let after_ihdr = 8 + 8 + 13 + 4;
+    let mut chunk = (data.len() as u32).to_be_bytes().to_vec();
+    chunk.extend_from_slice(type_);
+    chunk.extend_from_slice(data);
+    let crc = incremental_png::crc32::checksum(&chunk[4..]);
+    chunk.extend_from_slice(&crc.to_be_bytes());
+    [&png[..after_ihdr], &chunk, &png[after_ihdr..]].concat()
+}
+
+/// Path in the test's own temporary directory, which starts out empty.
+fn temp_path(test: &str, name: &str) -> PathBuf {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(test);
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir.join(name)
+}
+
+#[test]
+fn fix_crc_in_place() {
+    let original = std::fs::read(suite("basn2c08")).unwrap();
+    let mut corrupt = original.clone();
+    // First byte of the IHDR CRC
+    corrupt[8 + 8 + 13] ^= 1;
+    let path = temp_path("fix_crc_in_place", "image.png");
+    std::fs::write(&path, &corrupt).unwrap();
+    let path = path.to_str().unwrap();
+
+    let output = pngdump(&["--fix-crc", path, path]);
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "1 checksum(s) corrected\n");
+    assert_eq!(std::fs::read(path).unwrap(), original);
+}
+
+#[test]
+fn strip_in_place() {
+    let path = temp_path("strip_in_place", "image.png");
+    std::fs::copy(suite("ct1n0g04"), &path).unwrap();
+    let path = path.to_str().unwrap();
+
+    let output = pngdump(&["--strip", path, path]);
+    assert!(output.status.success());
+    assert!(stdout(&output).ends_with("chunk(s) removed\n"));
+    let output = pngdump(&["--verify", path]);
+    assert!(output.status.success(), "{}", stdout(&output));
+    // No temporary file left behind
+    let dir = std::path::Path::new(path).parent().unwrap();
+    assert_eq!(std::fs::read_dir(dir).unwrap().count(), 1);
+}
+
+#[test]
+fn single_output_with_several_inputs() {
+    let path = temp_path("single_output_with_several_inputs", "out.raw");
+    let path = path.to_str().unwrap();
+    let output = pngdump(&["--to-raw", path, &suite("basn0g08"), &suite("basn2c08")]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--to-raw"));
+    assert!(!std::path::Path::new(path).exists());
+
+    // Modes which only print accept several inputs
+    let output = pngdump(&["--summary", &suite("basn0g08"), &suite("basn2c08")]);
+    assert!(output.status.success());
+    assert_eq!(stdout(&output).lines().count(), 2);
+}
+
+#[test]
+fn mode_required() {
+    let output = pngdump(&[&suite("basn0g08")]);
+    assert_eq!(output.status.code(), Some(2));
+    let output = pngdump(&["--json", "--verify", &suite("basn0g08")]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("cannot be used with"));
+}
+
+#[test]
+fn events() {
+    let output = pngdump(&["--events", &suite("basn0g01")]);
+    assert!(output.status.success());
+    let stdout = stdout(&output);
+    let mut lines = stdout.lines();
+    assert_eq!(
+        lines.next(),
+        Some("c: BeginChunk(ChunkHeader { len: 13, type_: ChunkType(IHDR) })")
+    );
+    assert_eq!(
+        lines.next(),
+        Some("c: Data([0, 0, 0, 32, 0, 0, 0, 32, 1, 0, 0, 0, 0])")
+    );
+
+    let output = pngdump(&["--events", "--hexdump", "4", &suite("basn0g01")]);
+    let stdout = self::stdout(&output);
+    let lines: Vec<_> = stdout.lines().take(4).collect();
+    assert_eq!(
+        lines[1..],
+        [
+            "c: Data(13 bytes)",
+            "   00000000  00 00 00 20                                      |... |",
+            "   ...",
+        ]
+    );
+}
+
+#[test]
+fn print_sizes() {
+    let output = pngdump(&["--print-sizes"]);
+    assert!(output.status.success());
+    let stdout = stdout(&output);
+    assert!(stdout.starts_with("Memory usage:\n  Dechunker: "));
+    assert!(stdout.lines().last().unwrap().starts_with("  Total: "));
+}
+
+#[test]
+fn print_window_size() {
+    let output = pngdump(&["--print-window-size", &suite("basn2c08")]);
+    assert_eq!(stdout(&output), "32768\n");
+}
+
+#[test]
+fn json() {
+    let output = pngdump(&["--json", &suite("basn3p04")]);
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["header"]["width"], 32);
+    assert_eq!(json["header"]["colour_type"], 3);
+    assert_eq!(json["palette_size"], 15);
+    assert_eq!(json["chunks"][0]["type"], "IHDR");
+    assert_eq!(json["error"], serde_json::Value::Null);
+}
+
+#[test]
+fn verify() {
+    let output = pngdump(&["--verify", &suite("basn2c08")]);
+    assert!(output.status.success());
+    assert!(stdout(&output).ends_with("basn2c08.png: OK\n"));
+
+    let output = pngdump(&["--verify", &suite("xcsn0g01")]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stdout(&output).contains("problem(s)"));
+}
+
+#[test]
+fn extract_chunk() {
+    let dir = temp_path("extract_chunk", "chunks");
+    let output = pngdump(&[
+        "--extract-chunk",
+        "gAMA",
+        "--out-dir",
+        dir.to_str().unwrap(),
+        &suite("basn2c08"),
+    ]);
+    assert_eq!(stdout(&output), "Extracted 1 chunk(s)\n");
+    // Gamma 1/1.0
+    assert_eq!(
+        std::fs::read(dir.join("gAMA.0")).unwrap(),
+        100000u32.to_be_bytes()
+    );
+}
+
+#[test]
+fn to_ppm() {
+    let path = temp_path("to_ppm", "image.ppm");
+    let output = pngdump(&["--to-ppm", path.to_str().unwrap(), &suite("basn2c08")]);
+    assert!(output.status.success());
+    let ppm = std::fs::read(&path).unwrap();
+    let header = b"P6\n32 32\n255\n";
+    assert_eq!(&ppm[..header.len()], header);
+    assert_eq!(ppm.len(), header.len() + 32 * 32 * 3);
+}
+
+#[test]
+fn to_raw() {
+    let path = temp_path("to_raw", "image.raw");
+    let path = path.to_str().unwrap();
+    let output = pngdump(&["--to-raw", path, &suite("basn2c08")]);
+    assert!(output.status.success());
+    assert_eq!(std::fs::read(path).unwrap().len(), 32 * 32 * 3);
+
+    let output = pngdump(&["--to-raw", path, "--max-rows", "3", &suite("basn2c08")]);
+    assert!(output.status.success());
+    assert_eq!(std::fs::read(path).unwrap().len(), 3 * 32 * 3);
+
+    // No rows would leave an empty, invalid PPM
+    let output = pngdump(&["--to-ppm", path, "--max-rows", "0", &suite("basn2c08")]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--max-rows"));
+}
+
+#[test]
+fn to_png() {
+    let path = temp_path("to_png", "image.png");
+    let path = path.to_str().unwrap();
+    let output = pngdump(&["--to-png", path, &suite("basn3p04")]);
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "Pixels match\n");
+    let output = pngdump(&["--compare", path]);
+    assert!(output.status.success(), "{}", stdout(&output));
+}
+
+#[test]
+fn bench() {
+    let output = pngdump(&[
+        "--bench",
+        "2",
+        "--input-buffer-size",
+        "64",
+        &suite("basn2c08"),
+    ]);
+    assert!(output.status.success());
+    let stdout = stdout(&output);
+    assert!(stdout.contains("2 iterations, input buffer size 64"));
+    assert!(stdout.contains("Unfilterer"));
+}
+
+#[test]
+fn zero_input_buffer_size() {
+    let output = pngdump(&[
+        "--bench",
+        "1",
+        "--input-buffer-size",
+        "0",
+        &suite("basn2c08"),
+    ]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--input-buffer-size"));
+}
+
+#[test]
+fn text() {
+    let output = pngdump(&["--text", &suite("ct1n0g04")]);
+    assert!(output.status.success());
+    assert!(stdout(&output).starts_with("Title: PngSuite\n"));
+}
+
+#[test]
+fn icc() {
+    let profile = b"not really an ICC profile";
+    let mut iccp = b"test\0\0".to_vec();
+    iccp.extend(miniz_oxide::deflate::compress_to_vec_zlib(profile, 6));
+    let png = with_chunk(&std::fs::read(suite("basn2c08")).unwrap(), b"iCCP", &iccp);
+    let input = temp_path("icc", "image.png");
+    std::fs::write(&input, png).unwrap();
+    let path = input.with_file_name("profile.icc");
+
+    let output = pngdump(&["--icc", path.to_str().unwrap(), input.to_str().unwrap()]);
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "test: 25 bytes\n");
+    assert_eq!(std::fs::read(&path).unwrap(), profile);
+
+    let output = pngdump(&["--icc", path.to_str().unwrap(), &suite("basn2c08")]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(stdout(&output), "No iCCP chunk\n");
+}
+
+#[test]
+fn apng() {
+    let output = pngdump(&["--apng", &suite("basn2c08")]);
+    assert_eq!(stdout(&output), "Not an animated PNG\n");
+
+    let png = std::fs::read(suite("basn2c08")).unwrap();
+    let mut fctl = vec![0; 4];
+    for n in [32u32, 32, 0, 0] {
+        fctl.extend(n.to_be_bytes());
+    }
+    fctl.extend([0, 1, 0, 10, 0, 0]);
+    // acTL ends up before fcTL
+    let png = with_chunk(
+        &with_chunk(&png, b"fcTL", &fctl),
+        b"acTL",
+        &[0, 0, 0, 1, 0, 0, 0, 0],
+    );
+    let path = temp_path("apng", "image.png");
+    std::fs::write(&path, png).unwrap();
+
+    let output = pngdump(&["--apng", path.to_str().unwrap()]);
+    assert!(output.status.success());
+    let stdout = stdout(&output);
+    let lines: Vec<_> = stdout.lines().collect();
+    assert_eq!(lines[0], "Frames: 1, plays: infinite");
+    assert!(lines[1].starts_with("Frame 0 (seq 0): 32x32 at (0, 0), delay 1/10 (100 ms)"));
+    assert!(lines[1].ends_with(", default image"));
+}
+
+#[test]
+fn histogram() {
+    let output = pngdump(&["--histogram", &suite("basn2c08")]);
+    assert!(output.status.success());
+    let stdout = stdout(&output);
+    assert!(stdout.starts_with("Channel R: min 0, max 255, mean 191.2\n"));
+    assert!(stdout.contains("Channel B: "));
+}
+
+#[test]
+fn offsets() {
+    let output = pngdump(&["--offsets", &suite("basn2c08")]);
+    assert_eq!(
+        stdout(&output),
+        "    offset      (hex)  type  length\n\
+         \x20        8        0x8  IHDR  13\n\
+         \x20       33       0x21  gAMA  4\n\
+         \x20       49       0x31  IDAT  72\n\
+         \x20      133       0x85  IEND  0\n"
+    );
+}
+
+#[test]
+fn compare() {
+    let output = pngdump(&["--compare", &suite("basn6a16")]);
+    assert!(output.status.success(), "{}", stdout(&output));
+    assert_eq!(stdout(&output), "Identical (32 rows)\n");
+}
+
+#[test]
+fn summary() {
+    let output = pngdump(&["--summary", &suite("basn2c08")]);
+    assert!(output.status.success());
+    assert!(stdout(&output).ends_with(
+        "basn2c08.png: 32x32, RGB, 8-bit, non-interlaced, 4 chunks, IDAT 72 bytes, ratio 43.11\n"
+    ));
+}
+
+#[test]
+fn summary_invalid_header() {
+    let original = std::fs::read(suite("basn2c08")).unwrap();
+    // Colour type, bit depth and interlace method within IHDR
+    for (offset, value, error) in [
+        (9, 5, "InvalidColourType"),
+        (8, 7, "InvalidBitDepth"),
+        (12, 2, "InvalidInterlaceMethod"),
+    ] {
+        let mut png = original.clone();
+        png[8 + 8 + offset] = value;
+        let crc = incremental_png::crc32::checksum(&png[8 + 4..8 + 8 + 13]);
+        png[8 + 8 + 13..8 + 8 + 13 + 4].copy_from_slice(&crc.to_be_bytes());
+        let path = temp_path("summary_invalid_header", "image.png");
+        std::fs::write(&path, png).unwrap();
+
+        let output = pngdump(&["--summary", path.to_str().unwrap()]);
+        assert!(!output.status.success());
+        assert!(
+            String::from_utf8_lossy(&output.stderr).contains(error),
+            "{error}"
+        );
+    }
+}
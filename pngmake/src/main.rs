@@ -0,0 +1,113 @@
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::path::PathBuf;
+
+use clap::{Parser, ValueEnum};
+use incremental_png::encoder::{filter::FilterStrategy, PngEncoder};
+use incremental_png::stream_decoder::ImageHeader;
+
+/// Largest supported row, in bytes
+const MAX_SCANLINE: usize = 1 << 16;
+
+/// Builds a PNG from raw pixel data, one row at a time.
+#[derive(Parser, Debug)]
+struct Args {
+    /// Raw pixels, rows top to bottom, 16-bit samples big-endian. Reads stdin if omitted.
+    #[arg()]
+    input_file: Option<PathBuf>,
+
+    /// Output file. Writes to stdout if omitted.
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    #[arg(long)]
+    width: u32,
+
+    #[arg(long)]
+    height: u32,
+
+    #[arg(long, value_enum, default_value_t = Format::Rgba8)]
+    format: Format,
+
+    /// Compression level, 0-10
+    #[arg(long, default_value_t = 6, value_parser = clap::value_parser!(u8).range(0..=10))]
+    level: u8,
+
+    /// Don't filter rows
+    #[arg(long)]
+    no_filter: bool,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum Format {
+    Gray8,
+    Gray16,
+    Graya8,
+    Graya16,
+    Rgb8,
+    Rgb16,
+    Rgba8,
+    Rgba16,
+}
+
+impl Format {
+    /// Colour type and bit depth
+    fn png_format(self) -> (u8, u8) {
+        match self {
+            Format::Gray8 => (0, 8),
+            Format::Gray16 => (0, 16),
+            Format::Graya8 => (4, 8),
+            Format::Graya16 => (4, 16),
+            Format::Rgb8 => (2, 8),
+            Format::Rgb16 => (2, 16),
+            Format::Rgba8 => (6, 8),
+            Format::Rgba16 => (6, 16),
+        }
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+
+    let mut input: Box<dyn Read> = match &args.input_file {
+        Some(path) => Box::new(File::open(path)?),
+        None => Box::new(std::io::stdin().lock()),
+    };
+    let output: Box<dyn Write> = match &args.output {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(std::io::stdout().lock()),
+    };
+    let mut output = BufWriter::new(output);
+
+    let (colour_type, bit_depth) = args.format.png_format();
+    let header = ImageHeader {
+        width: args.width,
+        height: args.height,
+        bit_depth,
+        colour_type,
+        compression_method: 0,
+        filter_method: 0,
+        interlace_method: 0,
+    };
+    let strategy = if args.no_filter {
+        FilterStrategy::NoneOnly
+    } else {
+        FilterStrategy::Adaptive
+    };
+    let mut encoder = Box::new(PngEncoder::<MAX_SCANLINE>::with_options(
+        header, strategy, args.level,
+    )?);
+
+    let row_len = header.scanline_len().unwrap() as usize - 1;
+    let mut row = vec![0u8; row_len];
+    let mut sink = |data: &[u8]| -> anyhow::Result<()> { Ok(output.write_all(data)?) };
+
+    for _ in 0..args.height {
+        input.read_exact(&mut row)?;
+        encoder.write_row(&row, &mut sink)?;
+    }
+    encoder.finish(&mut sink)?;
+
+    output.flush()?;
+    Ok(())
+}
@@ -0,0 +1,256 @@
+//! Python bindings, so that asset pipeline scripts can check PNGs with the same decoder that runs
+//! on the device. Data is pushed in pieces of any size and each call returns the events it
+//! produced as tuples.
+
+use incremental_png::crc32::Crc32;
+use incremental_png::dechunker::{self, ChunkType, Dechunker};
+use incremental_png::inflater::Inflater;
+use incremental_png::stream_decoder::{ImageHeader, StreamDecoder};
+use incremental_png::unfilter::{self, Unfilterer};
+use incremental_png::Error;
+use pyo3::create_exception;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict};
+use pyo3::IntoPyObjectExt;
+
+create_exception!(
+    incremental_png,
+    PngError,
+    PyValueError,
+    "Invalid or unsupported PNG data."
+);
+
+fn py_error(e: Error) -> PyErr {
+    PngError::new_err(e.to_string())
+}
+
+/// Longest supported scanline in bytes, including the filter type byte. Enough for 8192 pixels
+/// of 16-bit RGBA.
+const MAX_SCANLINE: usize = 8192 * 8 + 1;
+
+/// Dechunker output, collected before converting to Python objects.
+enum ChunkOutput {
+    Begin(ChunkType, u32),
+    Data(Vec<u8>),
+    End(ChunkType, bool),
+}
+
+/// Splits a PNG file into chunks, verifying their CRCs.
+///
+/// `push()` returns a list of `("begin", type, length)`, `("data", bytes)` and
+/// `("end", type, crc_ok)` tuples, where `type` is the chunk type as a string.
+#[pyclass(module = "incremental_png")]
+struct Chunks {
+    dechunker: Dechunker,
+    type_: Option<ChunkType>,
+    crc: Crc32,
+    crc_ok: bool,
+}
+
+#[pymethods]
+impl Chunks {
+    #[new]
+    fn new() -> Self {
+        Self {
+            dechunker: Dechunker::new().with_crc_events(),
+            type_: None,
+            crc: Crc32::new(),
+            crc_ok: false,
+        }
+    }
+
+    fn push(&mut self, py: Python<'_>, data: &[u8]) -> PyResult<Vec<PyObject>> {
+        let Self {
+            dechunker,
+            type_,
+            crc,
+            crc_ok,
+        } = self;
+        let mut output = Vec::new();
+        dechunker
+            .update_all(data, |e| {
+                match e {
+                    dechunker::Event::BeginChunk(header) => {
+                        *type_ = Some(header.type_);
+                        *crc = Crc32::new();
+                        crc.update(&header.type_.0);
+                        output.push(ChunkOutput::Begin(header.type_, header.len));
+                    }
+                    dechunker::Event::Data(data) => {
+                        crc.update(data);
+                        output.push(ChunkOutput::Data(data.to_vec()));
+                    }
+                    dechunker::Event::Crc(stored) => *crc_ok = crc.finish() == stored,
+                    dechunker::Event::EndChunk => {
+                        let type_ = type_.take().ok_or(Error::UnexpectedEvent)?;
+                        output.push(ChunkOutput::End(type_, *crc_ok));
+                    }
+                    // Not enabled
+                    dechunker::Event::Resync(_) => return Err(Error::UnexpectedEvent),
+                }
+                Ok::<_, Error>(())
+            })
+            .map_err(py_error)?;
+
+        output
+            .into_iter()
+            .map(|o| match o {
+                ChunkOutput::Begin(type_, len) => ("begin", type_.to_string(), len).into_py_any(py),
+                ChunkOutput::Data(data) => ("data", PyBytes::new(py, &data)).into_py_any(py),
+                ChunkOutput::End(type_, crc_ok) => {
+                    ("end", type_.to_string(), crc_ok).into_py_any(py)
+                }
+            })
+            .collect()
+    }
+
+    /// Raises `PngError` if the file ended in the middle of a chunk.
+    fn finish(&self) -> PyResult<()> {
+        self.dechunker.eof().map_err(py_error)
+    }
+
+    /// Number of bytes pushed so far.
+    #[getter]
+    fn bytes_consumed(&self) -> u64 {
+        self.dechunker.bytes_consumed()
+    }
+}
+
+struct Pipeline {
+    dechunker: Dechunker,
+    stream_decoder: StreamDecoder,
+    inflater: Inflater<4096>,
+    unfilterer: Unfilterer<MAX_SCANLINE>,
+}
+
+/// Decoder output, collected before converting to Python objects.
+enum Output {
+    Header(ImageHeader),
+    Row(Vec<u8>),
+    End,
+}
+
+/// Decodes the image, running the whole pipeline.
+///
+/// `push()` returns a list of `("header", dict)`, `("row", bytes)` and `("end",)` tuples. Rows
+/// are unfiltered, but otherwise in the file's format: packed samples for bit depths below 8,
+/// big endian for 16, palette indices for colour type 3.
+#[pyclass(module = "incremental_png")]
+struct Decoder {
+    pipeline: Box<Pipeline>,
+}
+
+#[pymethods]
+impl Decoder {
+    #[new]
+    fn new() -> Self {
+        Self {
+            pipeline: Box::new(Pipeline {
+                dechunker: Dechunker::new(),
+                stream_decoder: StreamDecoder::new(),
+                inflater: Inflater::new(),
+                unfilterer: Unfilterer::new(),
+            }),
+        }
+    }
+
+    fn push(&mut self, py: Python<'_>, data: &[u8]) -> PyResult<Vec<PyObject>> {
+        let Pipeline {
+            dechunker,
+            stream_decoder,
+            inflater,
+            unfilterer,
+        } = &mut *self.pipeline;
+        let mut output = Vec::new();
+        dechunker
+            .update_all(data, |e| {
+                let mut dc_event = Some(e);
+                while let Some(e) = dc_event {
+                    let (leftover, mut sd_event) = stream_decoder.update(e)?;
+                    while let Some(e) = sd_event {
+                        let (leftover, mut i_event) = inflater.update(e)?;
+                        while let Some(e) = i_event {
+                            let (leftover, u_event) = unfilterer.update(e)?;
+                            match u_event {
+                                Some(unfilter::Event::ImageHeader(h)) => {
+                                    output.push(Output::Header(h))
+                                }
+                                Some(unfilter::Event::Row(row)) => {
+                                    output.push(Output::Row(row.to_vec()))
+                                }
+                                Some(unfilter::Event::End) => output.push(Output::End),
+                                Some(
+                                    unfilter::Event::Extension(_)
+                                    | unfilter::Event::ImageInfo(_)
+                                    | unfilter::Event::Warning(_),
+                                )
+                                | None => {}
+                            }
+                            i_event = leftover;
+                        }
+                        sd_event = leftover;
+                    }
+                    dc_event = leftover;
+                }
+                Ok::<_, Error>(())
+            })
+            .map_err(py_error)?;
+
+        output
+            .into_iter()
+            .map(|o| match o {
+                Output::Header(h) => ("header", header_dict(py, &h)?).into_py_any(py),
+                Output::Row(row) => ("row", PyBytes::new(py, &row)).into_py_any(py),
+                Output::End => ("end",).into_py_any(py),
+            })
+            .collect()
+    }
+
+    /// Palette entries as `(r, g, b, a)` tuples, once the PLTE chunk has been decoded.
+    fn palette(&self) -> Vec<(u8, u8, u8, u8)> {
+        let palette = self.pipeline.stream_decoder.palette();
+        (0..palette.len())
+            .map(|i| {
+                let [r, g, b, a] = palette.color_at_rgba(i as u8);
+                (r, g, b, a)
+            })
+            .collect()
+    }
+
+    /// Raises `PngError` unless the whole image was decoded: the file ended in the middle of a
+    /// chunk or before `IEND`, the zlib stream was cut short, or rows are missing.
+    fn finish(&self) -> PyResult<()> {
+        let Pipeline {
+            dechunker,
+            stream_decoder,
+            inflater,
+            unfilterer,
+        } = &*self.pipeline;
+        dechunker.eof().map_err(py_error)?;
+        stream_decoder.eof().map_err(py_error)?;
+        inflater.eof().map_err(py_error)?;
+        unfilterer.eof().map_err(py_error)
+    }
+}
+
+fn header_dict<'py>(py: Python<'py>, h: &ImageHeader) -> PyResult<Bound<'py, PyDict>> {
+    let dict = PyDict::new(py);
+    dict.set_item("width", h.width)?;
+    dict.set_item("height", h.height)?;
+    dict.set_item("bit_depth", h.bit_depth)?;
+    dict.set_item("colour_type", h.colour_type)?;
+    dict.set_item("compression_method", h.compression_method)?;
+    dict.set_item("filter_method", h.filter_method)?;
+    dict.set_item("interlace_method", h.interlace_method)?;
+    Ok(dict)
+}
+
+#[pymodule]
+#[pyo3(name = "incremental_png")]
+fn python_module(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add("PngError", m.py().get_type::<PngError>())?;
+    m.add_class::<Chunks>()?;
+    m.add_class::<Decoder>()?;
+    Ok(())
+}
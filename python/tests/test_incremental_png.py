@@ -0,0 +1,81 @@
+import pathlib
+import struct
+import unittest
+import zlib
+
+import incremental_png
+
+PNGSUITE = pathlib.Path(__file__).parents[2] / "pngdump" / "pngsuite"
+
+
+def decode(data, piece_size):
+    decoder = incremental_png.Decoder()
+    events = []
+    for i in range(0, len(data), piece_size):
+        events += decoder.push(data[i : i + piece_size])
+    decoder.finish()
+    return decoder, events
+
+
+class ChunksTest(unittest.TestCase):
+    def test_chunk_list(self):
+        data = (PNGSUITE / "basn0g01.png").read_bytes()
+        chunks = incremental_png.Chunks()
+        events = chunks.push(data)
+        chunks.finish()
+        begins = [e for e in events if e[0] == "begin"]
+        self.assertEqual(begins[0], ("begin", "IHDR", 13))
+        self.assertEqual(begins[-1], ("begin", "IEND", 0))
+        self.assertTrue(all(e[2] for e in events if e[0] == "end"))
+        self.assertEqual(chunks.bytes_consumed, len(data))
+
+    def test_crc_mismatch(self):
+        data = bytearray((PNGSUITE / "basn0g01.png").read_bytes())
+        data[8 + 8 + 13] ^= 1  # IHDR CRC
+        events = incremental_png.Chunks().push(bytes(data))
+        self.assertEqual(events[2], ("end", "IHDR", False))
+
+    def test_truncated(self):
+        data = (PNGSUITE / "basn0g01.png").read_bytes()
+        chunks = incremental_png.Chunks()
+        chunks.push(data[:20])
+        with self.assertRaises(incremental_png.PngError):
+            chunks.finish()
+
+
+class DecoderTest(unittest.TestCase):
+    def test_rows(self):
+        data = (PNGSUITE / "basn6a08.png").read_bytes()
+        _, events = decode(data, 1)
+        kind, header = events[0]
+        self.assertEqual(kind, "header")
+        self.assertEqual((header["width"], header["height"]), (32, 32))
+        self.assertEqual(header["colour_type"], 6)
+        rows = [e[1] for e in events if e[0] == "row"]
+        self.assertEqual(len(rows), 32)
+        self.assertTrue(all(len(row) == 32 * 4 for row in rows))
+        self.assertEqual(events[-1], ("end",))
+        self.assertEqual(events, decode(data, len(data))[1])
+
+    def test_palette(self):
+        decoder, _ = decode((PNGSUITE / "basn3p08.png").read_bytes(), 1000)
+        self.assertEqual(len(decoder.palette()), 256)
+
+    def test_truncated_image_data(self):
+        data = (PNGSUITE / "basn0g08.png").read_bytes()
+        start = data.index(b"IDAT") - 4
+        (length,) = struct.unpack(">I", data[start : start + 4])
+        idat = data[start + 8 : start + 8 + length // 2]
+        chunk = b"IDAT" + idat
+        chunk = struct.pack(">I", len(idat)) + chunk + struct.pack(">I", zlib.crc32(chunk))
+        data = data[:start] + chunk + data[start + 12 + length :]
+        with self.assertRaises(incremental_png.PngError):
+            decode(data, len(data))
+
+    def test_invalid(self):
+        with self.assertRaises(incremental_png.PngError):
+            incremental_png.Decoder().push(b"GIF89a")
+
+
+if __name__ == "__main__":
+    unittest.main()
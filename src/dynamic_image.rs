@@ -0,0 +1,193 @@
+//! Conversion to the `image` crate's [`DynamicImage`], for comparing what the device renders with
+//! a reference on the host.
+
+extern crate std;
+
+use std::boxed::Box;
+use std::vec::Vec;
+
+use image::{DynamicImage, ImageBuffer};
+
+use crate::decoder::{Decoder, DecoderConfig};
+use crate::pixel_iter::{Pixels, RowTracker};
+use crate::stream_decoder::ImageHeader;
+use crate::unfilter;
+use crate::{Error, Palette};
+
+/// Longest supported scanline in bytes, including the filter type byte. Enough for 8192 pixels
+/// of 16-bit RGBA.
+const MAX_SCANLINE: usize = 8192 * 8 + 1;
+
+/// Decodes a whole PNG file with the streaming pipeline, deinterlacing Adam7 images.
+///
+/// Samples keep their bit depth and channels, except that indexed images become RGBA8 (with
+/// the alpha from tRNS) and greyscale below 8 bits is scaled to `Luma8`.
+pub fn to_dynamic_image(png: &[u8]) -> Result<DynamicImage, Error> {
+    let mut decoder: Box<Decoder<256, 4096, MAX_SCANLINE>> = Box::new(
+        DecoderConfig::new()
+            .verify_crc(true)
+            .adam7_pass_limit(7)
+            .build(),
+    );
+    let mut tracker = RowTracker::new();
+    let mut header = None;
+    let mut pixels = Vec::new();
+
+    decoder.update_all(png, |e, context| {
+        if let unfilter::Event::ImageHeader(h) = e {
+            pixel_size(&h)?;
+            header = Some(h);
+        }
+        if let Some(row) = tracker.pixels(&e) {
+            let h = header.as_ref().ok_or(Error::NoImageHeader)?;
+            write_row(h, context.palette(), row, &mut pixels)?;
+        }
+        Ok::<_, Error>(())
+    })?;
+    decoder.finish()?;
+
+    let header = header.ok_or(Error::NoImageHeader)?;
+    let (width, height) = (header.width, header.height);
+    let image = if header.bit_depth == 16 {
+        let samples = pixels
+            .chunks_exact(2)
+            .map(|s| u16::from_be_bytes([s[0], s[1]]))
+            .collect();
+        match header.colour_type {
+            0 => ImageBuffer::from_raw(width, height, samples).map(DynamicImage::ImageLuma16),
+            2 => ImageBuffer::from_raw(width, height, samples).map(DynamicImage::ImageRgb16),
+            4 => ImageBuffer::from_raw(width, height, samples).map(DynamicImage::ImageLumaA16),
+            _ => ImageBuffer::from_raw(width, height, samples).map(DynamicImage::ImageRgba16),
+        }
+    } else {
+        match header.colour_type {
+            0 => ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageLuma8),
+            2 => ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageRgb8),
+            4 => ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageLumaA8),
+            _ => ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageRgba8),
+        }
+    };
+    image.ok_or(Error::RowCountMismatch)
+}
+
+/// Bytes per pixel in the layout described at [`to_dynamic_image`]. Fails for colour types and
+/// bit depths which aren't allowed.
+fn pixel_size(header: &ImageHeader) -> Result<usize, Error> {
+    let layout = header.pixel_layout().ok_or(Error::InvalidColourType)?;
+    if !header.bit_depth_allowed() {
+        return Err(Error::InvalidBitDepth);
+    }
+    Ok(match layout.colour_type {
+        3 => 4,
+        _ if layout.bit_depth < 8 => 1,
+        _ => layout.bytes_per_pixel as usize,
+    })
+}
+
+/// Writes the pixels of an unfiltered row to their place in `pixels`, in the layout described
+/// at [`to_dynamic_image`].
+///
+/// `pixels` only grows as far as the rows written so far, so a header declaring a huge image
+/// doesn't allocate anything by itself.
+fn write_row(
+    header: &ImageHeader,
+    palette: &Palette,
+    row: Pixels,
+    pixels: &mut Vec<u8>,
+) -> Result<(), Error> {
+    let size = pixel_size(header)?;
+    let max = (1u32 << header.bit_depth.min(8)) - 1;
+    for (x, y, samples) in row {
+        let start = (y as usize)
+            .checked_mul(header.width as usize)
+            .and_then(|n| n.checked_add(x as usize))
+            .and_then(|n| n.checked_mul(size))
+            .ok_or(Error::ArithmeticOverflow)?;
+        let end = start + size;
+        if pixels.len() < end {
+            pixels
+                .try_reserve(end - pixels.len())
+                .map_err(|_| Error::ArithmeticOverflow)?;
+            pixels.resize(end, 0);
+        }
+        let dst = &mut pixels[start..end];
+        match *samples {
+            [index] if header.colour_type == 3 => {
+                dst.copy_from_slice(&palette.color_at_rgba(index))
+            }
+            [sample] if header.bit_depth < 8 => {
+                dst.copy_from_slice(&[(sample as u32 * 255 / max) as u8])
+            }
+            _ => dst.copy_from_slice(samples),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PNGSUITE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/pngdump/pngsuite");
+
+    #[test]
+    fn matches_reference_decoder() {
+        for name in [
+            "basn0g01", "basn0g02", "basn0g04", "basn0g08", "basn0g16", "basn2c08", "basn2c16",
+            "basn3p01", "basn3p04", "basn3p08", "basn4a08", "basn4a16", "basn6a08", "basn6a16",
+            "tbbn3p08", "basi0g01", "basi6a16",
+        ] {
+            let data = std::fs::read(std::format!("{PNGSUITE}/{name}.png")).unwrap();
+            let ours = to_dynamic_image(&data).unwrap();
+            let reference = image::load_from_memory(&data).unwrap();
+            // The reference expands palettes without tRNS to RGB
+            if !name.contains("3p") {
+                assert_eq!(ours.color(), reference.color(), "{name}");
+            }
+            assert_eq!(ours.to_rgba16(), reference.to_rgba16(), "{name}");
+        }
+    }
+
+    #[test]
+    fn truncated() {
+        let data = std::fs::read(std::format!("{PNGSUITE}/basn0g08.png")).unwrap();
+        assert_eq!(
+            to_dynamic_image(&data[..data.len() / 2]).err(),
+            Some(Error::UnfinishedChunk)
+        );
+    }
+
+    #[test]
+    fn huge_height() {
+        // Signature and an IHDR declaring 8192x(2^31-1) RGBA16, and nothing else
+        let mut ihdr = b"IHDR".to_vec();
+        ihdr.extend(8192u32.to_be_bytes());
+        ihdr.extend(0x7fff_ffffu32.to_be_bytes());
+        ihdr.extend([16, 6, 0, 0, 0]);
+        let mut data = b"\x89PNG\r\n\x1a\n".to_vec();
+        data.extend(13u32.to_be_bytes());
+        data.extend(&ihdr);
+        data.extend(crate::crc32::checksum(&ihdr).to_be_bytes());
+        assert_eq!(data.len(), 33);
+        assert!(to_dynamic_image(&data).is_err());
+    }
+
+    #[test]
+    fn invalid_header() {
+        let header = |colour_type, bit_depth| ImageHeader {
+            width: 1,
+            height: 1,
+            bit_depth,
+            colour_type,
+            compression_method: 0,
+            filter_method: 0,
+            interlace_method: 0,
+        };
+        assert_eq!(pixel_size(&header(0, 1)), Ok(1));
+        assert_eq!(pixel_size(&header(6, 16)), Ok(8));
+        assert_eq!(pixel_size(&header(0, 0)), Err(Error::InvalidBitDepth));
+        assert_eq!(pixel_size(&header(0, 9)), Err(Error::InvalidBitDepth));
+        assert_eq!(pixel_size(&header(2, 4)), Err(Error::InvalidBitDepth));
+        assert_eq!(pixel_size(&header(1, 8)), Err(Error::InvalidColourType));
+    }
+}
@@ -0,0 +1,192 @@
+//! Pixels of decoded rows as [`embedded_graphics_core`] colours, to be drawn with
+//! [`DrawTarget::draw_iter`](embedded_graphics_core::draw_target::DrawTarget::draw_iter) in
+//! whatever colour type the display driver uses.
+
+use embedded_graphics_core::geometry::Point;
+use embedded_graphics_core::pixelcolor::{BinaryColor, Gray8, PixelColor, Rgb565, Rgb888};
+use embedded_graphics_core::Pixel;
+
+use crate::pixel_iter::Pixels;
+use crate::stream_decoder::PixelLayout;
+use crate::Palette;
+
+/// Colour types which decoded pixels can be converted to. Implement it for the colour type of
+/// a display driver which isn't covered here.
+pub trait FromRgba8: PixelColor {
+    /// Alpha can be ignored, or used e.g. as a mask.
+    fn from_rgba8(rgba: [u8; 4]) -> Self;
+}
+
+impl FromRgba8 for Rgb888 {
+    fn from_rgba8([r, g, b, _]: [u8; 4]) -> Self {
+        Rgb888::new(r, g, b)
+    }
+}
+
+impl FromRgba8 for Rgb565 {
+    fn from_rgba8([r, g, b, _]: [u8; 4]) -> Self {
+        Rgb565::new(r >> 3, g >> 2, b >> 3)
+    }
+}
+
+impl FromRgba8 for Gray8 {
+    fn from_rgba8(rgba: [u8; 4]) -> Self {
+        Gray8::new(luma(rgba))
+    }
+}
+
+/// Thresholded at half brightness, so that fully transparent pixels are off.
+impl FromRgba8 for BinaryColor {
+    fn from_rgba8(rgba: [u8; 4]) -> Self {
+        if rgba[3] >= 0x80 && luma(rgba) >= 0x80 {
+            BinaryColor::On
+        } else {
+            BinaryColor::Off
+        }
+    }
+}
+
+/// BT.601 luma
+fn luma([r, g, b, _]: [u8; 4]) -> u8 {
+    ((r as u32 * 77 + g as u32 * 150 + b as u32 * 29) >> 8) as u8
+}
+
+/// Converts the samples of a pixel, as given by [`Pixels`], to RGBA8.
+///
+/// 16-bit samples are truncated, greyscale below 8 bits is scaled up, and indexed pixels are
+/// looked up in `palette`, including their alpha from tRNS. Transparency given by tRNS for other
+/// colour types isn't applied.
+pub fn to_rgba8<const N: usize>(
+    layout: &PixelLayout,
+    samples: &[u8],
+    palette: &Palette<N>,
+) -> [u8; 4] {
+    let step = if layout.bit_depth == 16 { 2 } else { 1 };
+    let sample = |i: usize| samples.get(i * step).copied().unwrap_or(0);
+    match layout.colour_type {
+        0 if layout.bit_depth < 8 => {
+            let max = (1u32 << layout.bit_depth) - 1;
+            let v = (sample(0) as u32 * 255 / max) as u8;
+            [v, v, v, 0xff]
+        }
+        0 => [sample(0), sample(0), sample(0), 0xff],
+        2 => [sample(0), sample(1), sample(2), 0xff],
+        3 => palette.color_at_rgba(sample(0)),
+        4 => [sample(0), sample(0), sample(0), sample(1)],
+        _ => [sample(0), sample(1), sample(2), sample(3)],
+    }
+}
+
+/// Iterator over the pixels of a row as [`Pixel`]s of colour `C`.
+#[derive(Clone)]
+pub struct ColorPixels<'a, C, const N: usize = 256> {
+    pixels: Pixels<'a>,
+    palette: &'a Palette<N>,
+    _color: core::marker::PhantomData<C>,
+}
+
+impl<C, const N: usize> core::fmt::Debug for ColorPixels<'_, C, N> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("ColorPixels")
+            .field("pixels", &self.pixels)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<'a, C: FromRgba8, const N: usize> ColorPixels<'a, C, N> {
+    /// `palette` is only used for indexed images, see
+    /// [`Context::palette`](crate::decoder::Context::palette).
+    pub fn new(pixels: Pixels<'a>, palette: &'a Palette<N>) -> Self {
+        Self {
+            pixels,
+            palette,
+            _color: core::marker::PhantomData,
+        }
+    }
+}
+
+impl<C: FromRgba8, const N: usize> Iterator for ColorPixels<'_, C, N> {
+    type Item = Pixel<C>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let layout = self.pixels.layout();
+        let (x, y, samples) = self.pixels.next()?;
+        let color = C::from_rgba8(to_rgba8(&layout, samples, self.palette));
+        Some(Pixel(Point::new(x as i32, y as i32), color))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    extern crate std;
+
+    #[test]
+    fn conversions() {
+        assert_eq!(
+            Rgb565::from_rgba8([0xff, 0x80, 0x08, 0xff]),
+            Rgb565::new(0x1f, 0x20, 0x01)
+        );
+        assert_eq!(Gray8::from_rgba8([0xff, 0xff, 0xff, 0]), Gray8::new(0xff));
+        assert_eq!(BinaryColor::from_rgba8([0xff; 4]), BinaryColor::On);
+        assert_eq!(
+            BinaryColor::from_rgba8([0xff, 0xff, 0xff, 0]),
+            BinaryColor::Off
+        );
+
+        let layout = |colour_type, bit_depth, channels| PixelLayout {
+            colour_type,
+            bit_depth,
+            channels,
+            bytes_per_pixel: 1,
+        };
+        let palette = Palette::<0>::new();
+        assert_eq!(
+            to_rgba8(&layout(0, 2, 1), &[2], &palette),
+            [170, 170, 170, 255]
+        );
+        assert_eq!(
+            to_rgba8(&layout(2, 16, 3), &[1, 2, 3, 4, 5, 6], &palette),
+            [1, 3, 5, 255]
+        );
+        assert_eq!(to_rgba8(&layout(4, 8, 2), &[7, 8], &palette), [7, 7, 7, 8]);
+    }
+
+    #[test]
+    #[cfg(feature = "inflate")]
+    fn decoded_image() {
+        use crate::decoder::{Decoder, DecoderConfig};
+        use crate::pixel_iter::RowTracker;
+        use crate::unfilter;
+        use embedded_graphics_core::pixelcolor::RgbColor;
+        use std::vec::Vec;
+
+        let png = std::fs::read(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/pngdump/pngsuite/basn3p04.png"
+        ))
+        .unwrap();
+        let mut decoder: Decoder<256, 4096, 1024> = DecoderConfig::new().build();
+        let mut tracker = RowTracker::new();
+        let mut drawn: Vec<Pixel<Rgb888>> = Vec::new();
+        decoder
+            .update_all(&png, |e, context| {
+                if let Some(pixels) = tracker.pixels(&e) {
+                    drawn.extend(ColorPixels::new(pixels, context.palette()));
+                }
+                if let unfilter::Event::End = e {
+                    assert_eq!(drawn.len(), 32 * 32);
+                }
+                Ok::<_, crate::Error>(())
+            })
+            .unwrap();
+        decoder.finish().unwrap();
+
+        let reference = image::load_from_memory(&png).unwrap().to_rgb8();
+        for Pixel(point, color) in drawn {
+            let [r, g, b] = reference.get_pixel(point.x as u32, point.y as u32).0;
+            assert_eq!((color.r(), color.g(), color.b()), (r, g, b), "{point:?}");
+        }
+    }
+}
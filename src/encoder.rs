@@ -0,0 +1,1954 @@
+//! Writing PNG files. The stages mirror the decoder ones, running in the opposite direction.
+
+pub use chunker::Chunker;
+#[cfg(feature = "alloc")]
+pub use deflater::Deflater;
+pub use filter::Filterer;
+#[cfg(feature = "alloc")]
+pub use transcode::Transcoder;
+
+#[cfg(feature = "alloc")]
+pub use crate::apng::{BlendOp, DisposeOp, FrameControl};
+#[cfg(feature = "alloc")]
+use crate::{
+    dechunker::{self, ChunkHeader, ChunkType},
+    stream_decoder::ImageHeader,
+    Error,
+};
+
+/// Metadata chunk for [`PngEncoder::write_ancillary`].
+#[cfg(feature = "alloc")]
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum Ancillary<'a> {
+    /// tEXt, Latin-1 keyword (1-79 bytes) and text
+    Text { keyword: &'a [u8], text: &'a [u8] },
+    /// pHYs, pixels per unit. The unit is the metre if `unit_is_metre`, otherwise only the
+    /// aspect ratio is meaningful.
+    PhysicalDimensions { x: u32, y: u32, unit_is_metre: bool },
+    /// gAMA, image gamma times 100000
+    Gamma(u32),
+    /// tIME, last modification time in UTC
+    Time {
+        year: u16,
+        month: u8,
+        day: u8,
+        hour: u8,
+        minute: u8,
+        second: u8,
+    },
+}
+
+#[cfg(feature = "alloc")]
+impl Ancillary<'_> {
+    pub fn chunk_type(&self) -> ChunkType {
+        ChunkType(*match self {
+            Ancillary::Text { .. } => b"tEXt",
+            Ancillary::PhysicalDimensions { .. } => b"pHYs",
+            Ancillary::Gamma(_) => b"gAMA",
+            Ancillary::Time { .. } => b"tIME",
+        })
+    }
+}
+
+/// Writes a complete PNG one row at a time.
+///
+/// Rows are filtered, compressed and split into IDAT chunks as they come in, and the encoded
+/// bytes are passed to a `sink` callback, so only a couple of rows are held in memory.
+/// `MAX_SCANLINE` bounds the row size (see [`Filterer`]), `CHUNK_SIZE` the IDAT chunk size
+/// (see [`Deflater`]).
+///
+/// Animated PNGs are written by calling [`PngEncoder::write_animation_control`] first, then
+/// [`PngEncoder::begin_frame`] before the rows of each frame. If the first frame begins before
+/// any rows, the default image is part of the animation.
+#[cfg(feature = "alloc")]
+pub struct PngEncoder<const MAX_SCANLINE: usize = 1024, const CHUNK_SIZE: usize = 8192> {
+    header: ImageHeader,
+    filterer: Filterer<MAX_SCANLINE>,
+    deflater: Deflater<CHUNK_SIZE>,
+    chunker: Chunker,
+    strategy: filter::FilterStrategy,
+    level: u8,
+    header_written: bool,
+    palette_len: usize,
+    image_data_started: bool,
+    image_data_finished: bool,
+    /// Size of the current APNG frame, or the whole image
+    frame_width: u32,
+    frame_height: u32,
+    /// Rows written in the current frame
+    rows_written: u32,
+    /// Declared in acTL, 0 if not animated
+    num_frames: u32,
+    frames_written: u32,
+    sequence_number: u32,
+    /// Whether image data goes to fdAT instead of IDAT
+    fdat: bool,
+    /// Packed samples for [`PngEncoder::write_row_u16`]
+    row_buf: [u8; MAX_SCANLINE],
+}
+
+#[cfg(feature = "alloc")]
+impl<const MAX_SCANLINE: usize, const CHUNK_SIZE: usize> PngEncoder<MAX_SCANLINE, CHUNK_SIZE> {
+    /// Creates an encoder with adaptive filtering and the default compression level.
+    pub fn new(header: ImageHeader) -> Result<Self, Error> {
+        Self::with_options(
+            header,
+            filter::FilterStrategy::default(),
+            deflater::DEFAULT_LEVEL,
+        )
+    }
+
+    pub fn with_options(
+        header: ImageHeader,
+        strategy: filter::FilterStrategy,
+        level: u8,
+    ) -> Result<Self, Error> {
+        if !(1..=dechunker::MAX_CHUNK_LEN).contains(&header.width)
+            || !(1..=dechunker::MAX_CHUNK_LEN).contains(&header.height)
+        {
+            return Err(Error::InvalidImageDimensions);
+        }
+        if header.channels().is_none() {
+            return Err(Error::InvalidColourType);
+        }
+        if !header.bit_depth_allowed() {
+            return Err(Error::InvalidBitDepth);
+        }
+        if header.interlace_method != 0 {
+            return Err(Error::InterlaceNotSupported);
+        }
+        Ok(Self {
+            header,
+            filterer: Filterer::new(&header, strategy)?,
+            deflater: Deflater::with_level(level),
+            chunker: Chunker::new(),
+            strategy,
+            level,
+            header_written: false,
+            palette_len: 0,
+            image_data_started: false,
+            image_data_finished: false,
+            frame_width: header.width,
+            frame_height: header.height,
+            rows_written: 0,
+            num_frames: 0,
+            frames_written: 0,
+            sequence_number: 0,
+            fdat: false,
+            row_buf: [0; MAX_SCANLINE],
+        })
+    }
+
+    /// Writes the PLTE chunk, and tRNS if `alpha` is not empty. Required for indexed-colour
+    /// images, optional for truecolour ones, and must come before the first row.
+    pub fn write_palette<E: From<Error>>(
+        &mut self,
+        palette: &[[u8; 3]],
+        alpha: &[u8],
+        sink: &mut impl FnMut(&[u8]) -> Result<(), E>,
+    ) -> Result<(), E> {
+        let h = &self.header;
+        if h.colour_type == 0 || h.colour_type == 4 {
+            return Err(Error::PaletteNotAllowed.into());
+        }
+        if self.palette_len != 0 {
+            return Err(Error::DuplicateChunk(ChunkType(*b"PLTE")).into());
+        }
+        if self.image_data_started {
+            return Err(Error::ChunkOutOfOrder(ChunkType(*b"PLTE")).into());
+        }
+        let max_len = if h.colour_type == 3 {
+            1 << h.bit_depth.min(8)
+        } else {
+            256
+        };
+        if palette.is_empty() || palette.len() > max_len {
+            return Err(Error::InvalidPaletteChunkSize.into());
+        }
+        if (h.colour_type != 3 && !alpha.is_empty()) || alpha.len() > palette.len() {
+            return Err(Error::InvalidTransparencyChunkSize.into());
+        }
+
+        self.write_header(sink)?;
+        write_chunk(
+            &mut self.chunker,
+            ChunkType(*b"PLTE"),
+            &[palette.as_flattened()],
+            sink,
+        )?;
+        if !alpha.is_empty() {
+            write_chunk(&mut self.chunker, ChunkType(*b"tRNS"), &[alpha], sink)?;
+        }
+        self.palette_len = palette.len();
+        Ok(())
+    }
+
+    /// Writes a metadata chunk. gAMA must come before the palette, pHYs before the first row.
+    /// tEXt and tIME can also be written after the last row, but not in between rows.
+    pub fn write_ancillary<E: From<Error>>(
+        &mut self,
+        chunk: &Ancillary,
+        sink: &mut impl FnMut(&[u8]) -> Result<(), E>,
+    ) -> Result<(), E> {
+        let type_ = chunk.chunk_type();
+        let out_of_order = match chunk {
+            Ancillary::Gamma(_) => self.palette_len != 0 || self.image_data_started,
+            Ancillary::PhysicalDimensions { .. } => self.image_data_started,
+            Ancillary::Text { .. } | Ancillary::Time { .. } => {
+                self.rows_written != 0 && self.rows_written != self.frame_height
+            }
+        };
+        if out_of_order {
+            return Err(Error::ChunkOutOfOrder(type_).into());
+        }
+
+        self.write_header(sink)?;
+        if self.rows_written != 0 {
+            self.finish_image_data(sink)?;
+        }
+        let mut buf = [0; 9];
+        let parts: [&[u8]; 3] = match *chunk {
+            Ancillary::Text { keyword, text } => {
+                if keyword.is_empty()
+                    || keyword.len() > 79
+                    || keyword.contains(&0)
+                    || text.contains(&0)
+                {
+                    return Err(Error::InvalidTextChunk.into());
+                }
+                [keyword, &[0], text]
+            }
+            Ancillary::PhysicalDimensions {
+                x,
+                y,
+                unit_is_metre,
+            } => {
+                buf[0..4].copy_from_slice(&x.to_be_bytes());
+                buf[4..8].copy_from_slice(&y.to_be_bytes());
+                buf[8] = unit_is_metre as u8;
+                [&buf, &[], &[]]
+            }
+            Ancillary::Gamma(gamma) => {
+                buf[0..4].copy_from_slice(&gamma.to_be_bytes());
+                [&buf[..4], &[], &[]]
+            }
+            Ancillary::Time {
+                year,
+                month,
+                day,
+                hour,
+                minute,
+                second,
+            } => {
+                buf[0..2].copy_from_slice(&year.to_be_bytes());
+                buf[2..7].copy_from_slice(&[month, day, hour, minute, second]);
+                [&buf[..7], &[], &[]]
+            }
+        };
+        write_chunk(&mut self.chunker, type_, &parts, sink)
+    }
+
+    /// Writes the APNG acTL chunk. Must come before the first row. `num_plays` of 0 means
+    /// looping forever.
+    pub fn write_animation_control<E: From<Error>>(
+        &mut self,
+        num_frames: u32,
+        num_plays: u32,
+        sink: &mut impl FnMut(&[u8]) -> Result<(), E>,
+    ) -> Result<(), E> {
+        if self.num_frames != 0 {
+            return Err(Error::DuplicateChunk(ChunkType(*b"acTL")).into());
+        }
+        if self.image_data_started || self.frames_written != 0 {
+            return Err(Error::ChunkOutOfOrder(ChunkType(*b"acTL")).into());
+        }
+        if num_frames == 0 {
+            return Err(Error::FrameCountMismatch.into());
+        }
+        self.write_header(sink)?;
+        write_chunk(
+            &mut self.chunker,
+            ChunkType(*b"acTL"),
+            &[&num_frames.to_be_bytes(), &num_plays.to_be_bytes()],
+            sink,
+        )?;
+        self.num_frames = num_frames;
+        Ok(())
+    }
+
+    /// Starts an APNG frame, writing its fcTL chunk. All rows of the previous frame must have
+    /// been written. Rows of the frame are `frame.width` wide.
+    pub fn begin_frame<E: From<Error>>(
+        &mut self,
+        frame: &FrameControl,
+        sink: &mut impl FnMut(&[u8]) -> Result<(), E>,
+    ) -> Result<(), E> {
+        if self.num_frames == 0 {
+            return Err(Error::ChunkOutOfOrder(ChunkType(*b"fcTL")).into());
+        }
+        if self.frames_written == self.num_frames {
+            return Err(Error::FrameCountMismatch.into());
+        }
+        if (self.image_data_started || self.frames_written != 0)
+            && self.rows_written != self.frame_height
+        {
+            return Err(Error::RowCountMismatch.into());
+        }
+        let fits = |offset: u32, size: u32, max: u32| {
+            size != 0 && offset.checked_add(size).is_some_and(|end| end <= max)
+        };
+        let default_image = !self.image_data_started;
+        if !fits(frame.x_offset, frame.width, self.header.width)
+            || !fits(frame.y_offset, frame.height, self.header.height)
+            || default_image
+                && (frame.width, frame.height, frame.x_offset, frame.y_offset)
+                    != (self.header.width, self.header.height, 0, 0)
+        {
+            return Err(Error::InvalidFrameControl.into());
+        }
+
+        self.write_header(sink)?;
+        if !default_image {
+            self.finish_image_data(sink)?;
+            let header = ImageHeader {
+                width: frame.width,
+                height: frame.height,
+                ..self.header
+            };
+            self.filterer = Filterer::new(&header, self.strategy)?;
+            self.deflater = Deflater::with_level(self.level);
+            self.image_data_finished = false;
+            self.fdat = true;
+        }
+
+        let fctl = frame.to_bytes(self.sequence_number);
+        write_chunk(&mut self.chunker, ChunkType(*b"fcTL"), &[&fctl], sink)?;
+        self.sequence_number += 1;
+
+        self.frames_written += 1;
+        self.frame_width = frame.width;
+        self.frame_height = frame.height;
+        self.rows_written = 0;
+        Ok(())
+    }
+
+    /// Encodes one row of raw pixel data, in the format described by the header.
+    /// The PNG signature and IHDR are written before the first row.
+    pub fn write_row<E: From<Error>>(
+        &mut self,
+        row: &[u8],
+        sink: &mut impl FnMut(&[u8]) -> Result<(), E>,
+    ) -> Result<(), E> {
+        if self.rows_written == self.frame_height {
+            return Err(Error::RowCountMismatch.into());
+        }
+        if self.header.colour_type == 3 {
+            self.check_indices(row)?;
+        }
+        self.write_header(sink)?;
+        let scanline = self.filterer.update(row)?;
+        compress(
+            &mut self.deflater,
+            &mut self.chunker,
+            self.fdat.then_some(&mut self.sequence_number),
+            deflater::Input::Data(scanline),
+            sink,
+        )?;
+        self.image_data_started = true;
+        self.rows_written += 1;
+        Ok(())
+    }
+
+    /// Encodes one row of a 16-bit image, given as native integers, one per sample.
+    /// Samples are packed big-endian as PNG requires.
+    pub fn write_row_u16<E: From<Error>>(
+        &mut self,
+        samples: &[u16],
+        sink: &mut impl FnMut(&[u8]) -> Result<(), E>,
+    ) -> Result<(), E> {
+        if self.header.bit_depth != 16 {
+            return Err(Error::InvalidBitDepth.into());
+        }
+        let len = samples.len() * 2;
+        if len >= MAX_SCANLINE {
+            return Err(Error::InvalidRowLength.into());
+        }
+        if self.rows_written == self.frame_height {
+            return Err(Error::RowCountMismatch.into());
+        }
+        self.write_header(sink)?;
+        for (bytes, sample) in self.row_buf.chunks_exact_mut(2).zip(samples) {
+            bytes.copy_from_slice(&sample.to_be_bytes());
+        }
+        let scanline = self.filterer.update(&self.row_buf[..len])?;
+        compress(
+            &mut self.deflater,
+            &mut self.chunker,
+            self.fdat.then_some(&mut self.sequence_number),
+            deflater::Input::Data(scanline),
+            sink,
+        )?;
+        self.image_data_started = true;
+        self.rows_written += 1;
+        Ok(())
+    }
+
+    /// Flushes the compressed stream and writes IEND, after all rows (of all frames) were
+    /// written.
+    pub fn finish<E: From<Error>>(
+        &mut self,
+        sink: &mut impl FnMut(&[u8]) -> Result<(), E>,
+    ) -> Result<(), E> {
+        if self.rows_written != self.frame_height {
+            return Err(Error::RowCountMismatch.into());
+        }
+        if self.frames_written != self.num_frames {
+            return Err(Error::FrameCountMismatch.into());
+        }
+        self.write_header(sink)?;
+        self.finish_image_data(sink)?;
+        write_chunk(&mut self.chunker, ChunkType(*b"IEND"), &[], sink)?;
+        self.chunker.eof()?;
+        Ok(())
+    }
+
+    fn check_indices(&self, row: &[u8]) -> Result<(), Error> {
+        if self.palette_len == 0 {
+            return Err(Error::MissingPalette);
+        }
+        let bit_depth = self.header.bit_depth as usize;
+        let mask = ((1u16 << bit_depth) - 1) as u8;
+        for i in 0..self.frame_width as usize {
+            let bit = i.checked_mul(bit_depth).ok_or(Error::ArithmeticOverflow)?;
+            let shift = 8 - bit_depth - bit % 8;
+            let byte = row.get(bit / 8).ok_or(Error::InvalidRowLength)?;
+            if ((byte >> shift) & mask) as usize >= self.palette_len {
+                return Err(Error::PaletteIndexOutOfRange);
+            }
+        }
+        Ok(())
+    }
+
+    fn write_header<E: From<Error>>(
+        &mut self,
+        sink: &mut impl FnMut(&[u8]) -> Result<(), E>,
+    ) -> Result<(), E> {
+        if self.header_written {
+            return Ok(());
+        }
+        let h = &self.header;
+        let mut ihdr = [0; 13];
+        ihdr[0..4].copy_from_slice(&h.width.to_be_bytes());
+        ihdr[4..8].copy_from_slice(&h.height.to_be_bytes());
+        ihdr[8..].copy_from_slice(&[
+            h.bit_depth,
+            h.colour_type,
+            h.compression_method,
+            h.filter_method,
+            h.interlace_method,
+        ]);
+        write_chunk(&mut self.chunker, ChunkType(*b"IHDR"), &[&ihdr], sink)?;
+        self.header_written = true;
+        Ok(())
+    }
+
+    fn finish_image_data<E: From<Error>>(
+        &mut self,
+        sink: &mut impl FnMut(&[u8]) -> Result<(), E>,
+    ) -> Result<(), E> {
+        if !self.image_data_finished {
+            compress(
+                &mut self.deflater,
+                &mut self.chunker,
+                self.fdat.then_some(&mut self.sequence_number),
+                deflater::Input::Finish,
+                sink,
+            )?;
+            self.image_data_finished = true;
+        }
+        Ok(())
+    }
+}
+
+/// Compresses `input`, writing the output as IDAT chunks, or fdAT chunks if
+/// `sequence_number` is given.
+#[cfg(feature = "alloc")]
+fn compress<E: From<Error>, const CHUNK_SIZE: usize>(
+    deflater: &mut Deflater<CHUNK_SIZE>,
+    chunker: &mut Chunker,
+    mut sequence_number: Option<&mut u32>,
+    input: deflater::Input,
+    sink: &mut impl FnMut(&[u8]) -> Result<(), E>,
+) -> Result<(), E> {
+    let mut event = Some(input);
+    while let Some(e) = event {
+        let (leftover, output) = deflater.update(e)?;
+        if let Some(deflater::Event::ImageData(data)) = output {
+            match sequence_number.as_deref_mut() {
+                Some(seq) => {
+                    let parts = [&seq.to_be_bytes()[..], data];
+                    write_chunk(chunker, ChunkType(*b"fdAT"), &parts, sink)?;
+                    *seq += 1;
+                }
+                None => write_chunk(chunker, ChunkType(*b"IDAT"), &[data], sink)?,
+            }
+        }
+        event = leftover;
+    }
+    Ok(())
+}
+
+/// Writes a complete chunk with the concatenation of `parts` as data through `chunker`.
+#[cfg(feature = "alloc")]
+fn write_chunk<E: From<Error>>(
+    chunker: &mut Chunker,
+    type_: ChunkType,
+    parts: &[&[u8]],
+    sink: &mut impl FnMut(&[u8]) -> Result<(), E>,
+) -> Result<(), E> {
+    let mut buf = [0; 256];
+    let len = parts.iter().map(|p| p.len() as u32).sum();
+    let events = core::iter::once(dechunker::Event::BeginChunk(ChunkHeader { len, type_ }))
+        .chain(
+            parts
+                .iter()
+                .filter(|p| !p.is_empty())
+                .map(|p| dechunker::Event::Data(p)),
+        )
+        .chain(core::iter::once(dechunker::Event::EndChunk));
+    for e in events {
+        let mut event = Some(e);
+        while let Some(e) = event {
+            let (leftover, n) = chunker.update(e, &mut buf)?;
+            if n > 0 {
+                sink(&buf[..n])?;
+            }
+            event = leftover;
+        }
+    }
+    Ok(())
+}
+
+/// Rewriting existing PNG files, see [`Transcoder`].
+#[cfg(feature = "alloc")]
+pub mod transcode {
+    use super::{compress, deflater, Chunker, Deflater};
+    use crate::dechunker::{self, ChunkType};
+    use crate::inflater::{self, Inflater};
+    use crate::stream_decoder as sd;
+    use crate::Error;
+
+    #[derive(Clone, Copy, Eq, PartialEq, Debug)]
+    enum State {
+        BetweenChunks,
+        /// Passing the chunk through unchanged
+        Copying,
+        Skipping,
+        /// In an IDAT chunk
+        ImageData,
+    }
+
+    /// Decodes a PNG from dechunker events and encodes it again, incrementally.
+    ///
+    /// Image data is decompressed and compressed again, with the configured level and IDAT
+    /// chunk size (see [`Deflater`]). Other chunks are copied, except ancillary chunks selected
+    /// by [`Transcoder::strip`]. All CRCs are recomputed, so this also repairs bad checksums.
+    ///
+    /// `BUFFER_SIZE` is the output buffer of the [`Inflater`].
+    pub struct Transcoder<const CHUNK_SIZE: usize = 8192, const BUFFER_SIZE: usize = 1024> {
+        state: State,
+        inflater: Inflater<BUFFER_SIZE>,
+        deflater: Deflater<CHUNK_SIZE>,
+        chunker: Chunker,
+        level: u8,
+        chunk_size: usize,
+        strip: fn(ChunkType) -> bool,
+        /// Whether IDAT data was compressed and not yet finished
+        image_data_pending: bool,
+    }
+
+    impl<const CHUNK_SIZE: usize, const BUFFER_SIZE: usize> Default
+        for Transcoder<CHUNK_SIZE, BUFFER_SIZE>
+    {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl<const CHUNK_SIZE: usize, const BUFFER_SIZE: usize> Transcoder<CHUNK_SIZE, BUFFER_SIZE> {
+        pub fn new() -> Self {
+            Self {
+                state: State::BetweenChunks,
+                inflater: Inflater::new(),
+                deflater: Deflater::new(),
+                chunker: Chunker::new(),
+                level: deflater::DEFAULT_LEVEL,
+                chunk_size: CHUNK_SIZE,
+                strip: |_| false,
+                image_data_pending: false,
+            }
+        }
+
+        /// Compression level for the image data, see [`Deflater::with_level`].
+        pub fn with_level(mut self, level: u8) -> Self {
+            self.level = level;
+            self.deflater = Deflater::with_level(level).with_chunk_size(self.chunk_size);
+            self
+        }
+
+        /// Size of the written IDAT chunks, see [`Deflater::with_chunk_size`].
+        pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+            self.chunk_size = chunk_size;
+            self.deflater = Deflater::with_level(self.level).with_chunk_size(chunk_size);
+            self
+        }
+
+        /// Drops ancillary chunks for which `strip` returns true. Critical chunks are always
+        /// kept.
+        pub fn strip(mut self, strip: fn(ChunkType) -> bool) -> Self {
+            self.strip = strip;
+            self
+        }
+
+        /// Processes one event, passing the output bytes to `sink`.
+        pub fn update<E: From<Error>>(
+            &mut self,
+            event: dechunker::Event,
+            sink: &mut impl FnMut(&[u8]) -> Result<(), E>,
+        ) -> Result<(), E> {
+            match event {
+                dechunker::Event::BeginChunk(header) => {
+                    if header.type_ == ChunkType(*b"IDAT") {
+                        self.state = State::ImageData;
+                        self.image_data_pending = true;
+                        return Ok(());
+                    }
+                    // The IDAT chunks are consecutive, so the image data ends here
+                    self.finish_image_data(sink)?;
+                    if header.type_.is_ancillary() && (self.strip)(header.type_) {
+                        self.state = State::Skipping;
+                        return Ok(());
+                    }
+                    self.state = State::Copying;
+                    self.write(dechunker::Event::BeginChunk(header), sink)
+                }
+                dechunker::Event::Data(data) => match self.state {
+                    State::Copying => self.write(dechunker::Event::Data(data), sink),
+                    State::ImageData => {
+                        let mut event = Some(sd::Event::ImageData(data));
+                        while let Some(e) = event {
+                            let (leftover, output) = self.inflater.update(e)?;
+                            if let Some(inflater::Event::ImageData(data)) = output {
+                                compress(
+                                    &mut self.deflater,
+                                    &mut self.chunker,
+                                    None,
+                                    deflater::Input::Data(data),
+                                    sink,
+                                )?;
+                            }
+                            event = leftover;
+                        }
+                        Ok(())
+                    }
+                    State::Skipping | State::BetweenChunks => Ok(()),
+                },
+                dechunker::Event::Crc(_) => Ok(()),
+                dechunker::Event::EndChunk => {
+                    let state = core::mem::replace(&mut self.state, State::BetweenChunks);
+                    if state == State::Copying {
+                        self.write(dechunker::Event::EndChunk, sink)?;
+                    }
+                    Ok(())
+                }
+                // Part of a chunk which was copied or recompressed can't be taken back
+                dechunker::Event::Resync(_) => match self.state {
+                    State::Skipping | State::BetweenChunks => {
+                        self.state = State::BetweenChunks;
+                        Ok(())
+                    }
+                    State::Copying | State::ImageData => Err(Error::UnexpectedEvent.into()),
+                },
+            }
+        }
+
+        /// Checks that the output is complete, after all input was processed. Fails with
+        /// [`Error::UnfinishedImageData`] if the deflate stream of the image data didn't end.
+        pub fn finish<E: From<Error>>(
+            &mut self,
+            sink: &mut impl FnMut(&[u8]) -> Result<(), E>,
+        ) -> Result<(), E> {
+            self.finish_image_data(sink)?;
+            self.chunker.eof()?;
+            Ok(())
+        }
+
+        fn finish_image_data<E: From<Error>>(
+            &mut self,
+            sink: &mut impl FnMut(&[u8]) -> Result<(), E>,
+        ) -> Result<(), E> {
+            if self.image_data_pending {
+                // Truncated image data would otherwise be written as a valid stream
+                self.inflater.eof()?;
+                compress(
+                    &mut self.deflater,
+                    &mut self.chunker,
+                    None,
+                    deflater::Input::Finish,
+                    sink,
+                )?;
+                self.image_data_pending = false;
+            }
+            Ok(())
+        }
+
+        fn write<E: From<Error>>(
+            &mut self,
+            event: dechunker::Event,
+            sink: &mut impl FnMut(&[u8]) -> Result<(), E>,
+        ) -> Result<(), E> {
+            let mut buf = [0; 256];
+            let mut event = Some(event);
+            while let Some(e) = event {
+                let (leftover, n) = self.chunker.update(e, &mut buf)?;
+                if n > 0 {
+                    sink(&buf[..n])?;
+                }
+                event = leftover;
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(all(test, feature = "alloc"))]
+mod tests {
+    use super::*;
+    use crate::inflater::{self, Inflater};
+    use crate::stream_decoder::StreamDecoder;
+
+    extern crate std;
+    use std::vec::Vec;
+
+    /// Decodes `png` with the decoder pipeline, returning the header and decompressed data.
+    pub(crate) fn decode(png: &[u8]) -> (ImageHeader, Vec<u8>) {
+        let mut dechunker = dechunker::Dechunker::new();
+        let mut sd = StreamDecoder::new();
+        let mut inflater = Inflater::<1024>::new();
+        let mut header = None;
+        let mut data = Vec::new();
+
+        dechunker
+            .update_all(png, |e| {
+                let mut dc_event = Some(e);
+                while let Some(e) = dc_event {
+                    let (leftover, mut sd_event) = sd.update(e)?;
+                    while let Some(e) = sd_event {
+                        let (leftover, i_event) = inflater.update(e)?;
+                        match i_event {
+                            Some(inflater::Event::ImageHeader(h)) => header = Some(h),
+                            Some(inflater::Event::ImageData(d)) => data.extend_from_slice(d),
+                            _ => {}
+                        }
+                        sd_event = leftover;
+                    }
+                    dc_event = leftover;
+                }
+                Ok::<_, Error>(())
+            })
+            .unwrap();
+        dechunker.eof().unwrap();
+
+        (header.unwrap(), data)
+    }
+
+    fn header(width: u32, height: u32) -> ImageHeader {
+        ImageHeader {
+            width,
+            height,
+            bit_depth: 8,
+            colour_type: 2,
+            compression_method: 0,
+            filter_method: 0,
+            interlace_method: 0,
+        }
+    }
+
+    #[test]
+    fn encode_rows() {
+        let mut encoder = PngEncoder::<64, 32>::with_options(
+            header(4, 3),
+            filter::FilterStrategy::NoneOnly,
+            deflater::DEFAULT_LEVEL,
+        )
+        .unwrap();
+        let mut png = Vec::new();
+        let mut sink = |data: &[u8]| {
+            png.extend_from_slice(data);
+            Ok::<_, Error>(())
+        };
+
+        let rows: [[u8; 12]; 3] = core::array::from_fn(|y| core::array::from_fn(|x| (x * y) as u8));
+        for row in &rows {
+            encoder.write_row(row, &mut sink).unwrap();
+        }
+        encoder.finish(&mut sink).unwrap();
+
+        assert_eq!(&png[..8], crate::dechunker::PNG_SIGNATURE);
+        assert_eq!(&png[png.len() - 8..png.len() - 4], b"IEND");
+
+        let (decoded_header, data) = decode(&png);
+        assert_eq!(decoded_header, header(4, 3));
+        // One filter byte + 12 bytes per row
+        assert_eq!(data.len(), 3 * 13);
+        for (y, row) in rows.iter().enumerate() {
+            assert_eq!(data[y * 13], 0);
+            assert_eq!(&data[y * 13 + 1..(y + 1) * 13], row);
+        }
+    }
+
+    #[test]
+    fn encode_indexed() {
+        let mut h = header(5, 2);
+        h.colour_type = 3;
+        h.bit_depth = 2;
+        let mut encoder = PngEncoder::<64, 32>::new(h).unwrap();
+        let mut png = Vec::new();
+        let mut sink = |data: &[u8]| {
+            png.extend_from_slice(data);
+            Ok::<_, Error>(())
+        };
+
+        assert_eq!(
+            encoder.write_row(&[0, 0], &mut sink),
+            Err(Error::MissingPalette)
+        );
+        assert_eq!(
+            encoder.write_palette(&[[0; 3]; 5], &[], &mut sink),
+            Err(Error::InvalidPaletteChunkSize)
+        );
+        let palette = [[255, 0, 0], [0, 255, 0], [0, 0, 255]];
+        encoder.write_palette(&palette, &[128], &mut sink).unwrap();
+        assert_eq!(
+            encoder.write_palette(&palette, &[], &mut sink),
+            Err(Error::DuplicateChunk(ChunkType(*b"PLTE")))
+        );
+
+        // Index 3 in the last pixel
+        assert_eq!(
+            encoder.write_row(&[0b00011000, 0b11000000], &mut sink),
+            Err(Error::PaletteIndexOutOfRange)
+        );
+        // Padding bits are not checked
+        encoder
+            .write_row(&[0b00011000, 0b10111111], &mut sink)
+            .unwrap();
+        encoder
+            .write_row(&[0b10100101, 0b00000000], &mut sink)
+            .unwrap();
+        encoder.finish(&mut sink).unwrap();
+
+        let plte = png.windows(4).position(|w| w == b"PLTE").unwrap();
+        assert_eq!(&png[plte + 4..plte + 13], palette.as_flattened());
+        let trns = png.windows(4).position(|w| w == b"tRNS").unwrap();
+        assert_eq!(&png[trns - 4..trns], &1u32.to_be_bytes());
+        assert_eq!(png[trns + 4], 128);
+
+        let (decoded_header, data) = decode(&png);
+        assert_eq!(decoded_header, h);
+        assert_eq!(data.len(), 2 * 3);
+    }
+
+    #[test]
+    fn encode_grayscale_16() {
+        let mut h = header(3, 2);
+        h.colour_type = 4;
+        h.bit_depth = 16;
+        let mut encoder = PngEncoder::<64, 32>::with_options(
+            h,
+            filter::FilterStrategy::NoneOnly,
+            deflater::DEFAULT_LEVEL,
+        )
+        .unwrap();
+        let mut png = Vec::new();
+        let mut sink = |data: &[u8]| {
+            png.extend_from_slice(data);
+            Ok::<_, Error>(())
+        };
+
+        assert_eq!(
+            encoder.write_row_u16(&[0; 5], &mut sink),
+            Err(Error::InvalidRowLength)
+        );
+        encoder
+            .write_row_u16(&[0x0102, 0xffff, 0x0304, 0x8000, 0, 0xabcd], &mut sink)
+            .unwrap();
+        encoder.write_row_u16(&[7; 6], &mut sink).unwrap();
+        encoder.finish(&mut sink).unwrap();
+
+        let (decoded_header, data) = decode(&png);
+        assert_eq!(decoded_header, h);
+        assert_eq!(
+            &data[..13],
+            &[0, 1, 2, 0xff, 0xff, 3, 4, 0x80, 0, 0, 0, 0xab, 0xcd]
+        );
+        assert_eq!(&data[13..], &[0, 0, 7, 0, 7, 0, 7, 0, 7, 0, 7, 0, 7]);
+    }
+
+    #[test]
+    fn invalid_bit_depth() {
+        let mut h = header(1, 1);
+        h.bit_depth = 4;
+        assert_eq!(
+            PngEncoder::<64, 32>::new(h).err(),
+            Some(Error::InvalidBitDepth)
+        );
+        h.colour_type = 0;
+        let mut encoder = PngEncoder::<64, 32>::new(h).unwrap();
+        let mut sink = |_: &[u8]| Ok::<_, Error>(());
+        assert_eq!(
+            encoder.write_row_u16(&[0], &mut sink),
+            Err(Error::InvalidBitDepth)
+        );
+        encoder.write_row(&[0x50], &mut sink).unwrap();
+        encoder.finish(&mut sink).unwrap();
+    }
+
+    #[test]
+    fn invalid_dimensions() {
+        for (width, height) in [(0, 1), (1, 0), (1 << 31, 1), (1, 1 << 31)] {
+            assert_eq!(
+                PngEncoder::<64, 32>::new(header(width, height)).err(),
+                Some(Error::InvalidImageDimensions)
+            );
+        }
+    }
+
+    /// Chunk types in `png`, in order
+    fn chunk_types(png: &[u8]) -> Vec<ChunkType> {
+        let mut types = Vec::new();
+        dechunker::Dechunker::new()
+            .update_all(png, |e| {
+                if let dechunker::Event::BeginChunk(h) = e {
+                    types.push(h.type_);
+                }
+                Ok::<_, Error>(())
+            })
+            .unwrap();
+        types
+    }
+
+    #[test]
+    fn ancillary_chunks() {
+        let mut h = header(1, 2);
+        h.colour_type = 3;
+        let mut encoder = PngEncoder::<64, 32>::new(h).unwrap();
+        let mut png = Vec::new();
+        let mut sink = |data: &[u8]| {
+            png.extend_from_slice(data);
+            Ok::<_, Error>(())
+        };
+
+        encoder
+            .write_ancillary(&Ancillary::Gamma(45455), &mut sink)
+            .unwrap();
+        encoder.write_palette(&[[1, 2, 3]], &[], &mut sink).unwrap();
+        assert_eq!(
+            encoder.write_ancillary(&Ancillary::Gamma(45455), &mut sink),
+            Err(Error::ChunkOutOfOrder(ChunkType(*b"gAMA")))
+        );
+        let phys = Ancillary::PhysicalDimensions {
+            x: 3780,
+            y: 3780,
+            unit_is_metre: true,
+        };
+        encoder.write_ancillary(&phys, &mut sink).unwrap();
+        let text = Ancillary::Text {
+            keyword: b"Software",
+            text: b"incremental-png",
+        };
+        encoder.write_ancillary(&text, &mut sink).unwrap();
+        encoder.write_row(&[0], &mut sink).unwrap();
+
+        assert_eq!(
+            encoder.write_ancillary(&text, &mut sink),
+            Err(Error::ChunkOutOfOrder(ChunkType(*b"tEXt")))
+        );
+        assert_eq!(
+            encoder.write_ancillary(&phys, &mut sink),
+            Err(Error::ChunkOutOfOrder(ChunkType(*b"pHYs")))
+        );
+        encoder.write_row(&[0], &mut sink).unwrap();
+        let time = Ancillary::Time {
+            year: 2024,
+            month: 2,
+            day: 29,
+            hour: 12,
+            minute: 30,
+            second: 0,
+        };
+        encoder.write_ancillary(&time, &mut sink).unwrap();
+        encoder.finish(&mut sink).unwrap();
+
+        let types: Vec<_> = chunk_types(&png).iter().map(|t| t.0).collect();
+        assert_eq!(
+            types,
+            [*b"IHDR", *b"gAMA", *b"PLTE", *b"pHYs", *b"tEXt", *b"IDAT", *b"tIME", *b"IEND"]
+        );
+        let text = png.windows(4).position(|w| w == b"tEXt").unwrap();
+        assert_eq!(&png[text + 4..text + 28], b"Software\0incremental-png");
+        let time = png.windows(4).position(|w| w == b"tIME").unwrap();
+        assert_eq!(&png[time + 4..time + 11], &[0x07, 0xe8, 2, 29, 12, 30, 0]);
+        decode(&png);
+    }
+
+    #[test]
+    fn animation() {
+        let mut encoder = PngEncoder::<64, 32>::with_options(
+            header(2, 2),
+            filter::FilterStrategy::NoneOnly,
+            deflater::DEFAULT_LEVEL,
+        )
+        .unwrap();
+        let mut png = Vec::new();
+        let mut sink = |data: &[u8]| {
+            png.extend_from_slice(data);
+            Ok::<_, Error>(())
+        };
+        let mut frame = FrameControl {
+            width: 2,
+            height: 2,
+            x_offset: 0,
+            y_offset: 0,
+            delay_num: 1,
+            delay_den: 10,
+            dispose_op: DisposeOp::None,
+            blend_op: BlendOp::Source,
+        };
+
+        assert_eq!(
+            encoder.begin_frame(&frame, &mut sink),
+            Err(Error::ChunkOutOfOrder(ChunkType(*b"fcTL")))
+        );
+        encoder.write_animation_control(2, 0, &mut sink).unwrap();
+        encoder.begin_frame(&frame, &mut sink).unwrap();
+        encoder.write_row(&[1; 6], &mut sink).unwrap();
+        assert_eq!(
+            encoder.begin_frame(&frame, &mut sink),
+            Err(Error::RowCountMismatch)
+        );
+        encoder.write_row(&[2; 6], &mut sink).unwrap();
+
+        frame.x_offset = 1;
+        assert_eq!(
+            encoder.begin_frame(&frame, &mut sink),
+            Err(Error::InvalidFrameControl)
+        );
+        frame.width = 1;
+        frame.height = 1;
+        frame.y_offset = 1;
+        encoder.begin_frame(&frame, &mut sink).unwrap();
+        assert_eq!(
+            encoder.write_row(&[1; 6], &mut sink),
+            Err(Error::InvalidRowLength)
+        );
+        encoder.write_row(&[3; 3], &mut sink).unwrap();
+        assert_eq!(
+            encoder.begin_frame(&frame, &mut sink),
+            Err(Error::FrameCountMismatch)
+        );
+        encoder.finish(&mut sink).unwrap();
+
+        let types: Vec<_> = chunk_types(&png).iter().map(|t| t.0).collect();
+        assert_eq!(
+            types,
+            [*b"IHDR", *b"acTL", *b"fcTL", *b"IDAT", *b"fcTL", *b"fdAT", *b"IEND"]
+        );
+        let fctl = png.windows(4).rposition(|w| w == b"fcTL").unwrap();
+        // Sequence number, then the frame rectangle
+        assert_eq!(&png[fctl + 4..fctl + 8], &1u32.to_be_bytes());
+        assert_eq!(&png[fctl + 8..fctl + 12], &1u32.to_be_bytes());
+        assert_eq!(&png[fctl + 28..fctl + 30], &[0, 0]);
+        let fdat = png.windows(4).position(|w| w == b"fdAT").unwrap();
+        let fdat_len = u32::from_be_bytes(png[fdat - 4..fdat].try_into().unwrap()) as usize;
+        assert_eq!(&png[fdat + 4..fdat + 8], &2u32.to_be_bytes());
+
+        let mut inflater = Inflater::<1024>::new();
+        let mut frame_data = Vec::new();
+        let mut event = Some(crate::stream_decoder::Event::ImageData(
+            &png[fdat + 8..fdat + 4 + fdat_len],
+        ));
+        while let Some(e) = event {
+            let (leftover, out) = inflater.update(e).unwrap();
+            if let Some(inflater::Event::ImageData(data)) = out {
+                frame_data.extend_from_slice(data);
+            }
+            event = leftover;
+        }
+        assert_eq!(frame_data, [0, 3, 3, 3]);
+
+        let (_, data) = decode(&png);
+        assert_eq!(data, [0, 1, 1, 1, 1, 1, 1, 0, 2, 2, 2, 2, 2, 2]);
+    }
+
+    #[test]
+    fn transcode() {
+        let mut encoder = PngEncoder::<64, 1024>::new(header(8, 8)).unwrap();
+        let mut png = Vec::new();
+        let mut sink = |data: &[u8]| {
+            png.extend_from_slice(data);
+            Ok::<_, Error>(())
+        };
+        encoder
+            .write_ancillary(&Ancillary::Gamma(45455), &mut sink)
+            .unwrap();
+        let text = Ancillary::Text {
+            keyword: b"Comment",
+            text: b"hello",
+        };
+        encoder.write_ancillary(&text, &mut sink).unwrap();
+        for y in 0..8 {
+            let row: [u8; 24] = core::array::from_fn(|x| (x * 37 + y * 101) as u8);
+            encoder.write_row(&row, &mut sink).unwrap();
+        }
+        encoder.finish(&mut sink).unwrap();
+        // Corrupt the gAMA CRC
+        let gama = png.windows(4).position(|w| w == b"gAMA").unwrap();
+        png[gama + 8] ^= 0xff;
+
+        let mut transcoder = Transcoder::<1024>::new()
+            .with_level(9)
+            .with_chunk_size(16)
+            .strip(|t| t == ChunkType(*b"tEXt"));
+        let mut output = Vec::new();
+        let mut sink = |data: &[u8]| {
+            output.extend_from_slice(data);
+            Ok::<_, Error>(())
+        };
+        dechunker::Dechunker::new()
+            .update_all(&png, |e| transcoder.update(e, &mut sink))
+            .unwrap();
+        transcoder.finish(&mut sink).unwrap();
+
+        let types = chunk_types(&output);
+        assert_eq!(types[..2], [ChunkType(*b"IHDR"), ChunkType(*b"gAMA")]);
+        assert!(types[2..types.len() - 1]
+            .iter()
+            .all(|&t| t == ChunkType(*b"IDAT")));
+        assert!(types.len() > 4);
+        assert_eq!(types.last(), Some(&ChunkType(*b"IEND")));
+
+        // All CRCs are valid
+        let mut crc = crate::crc32::Crc32::new();
+        dechunker::Dechunker::new()
+            .with_crc_events()
+            .update_all(&output, |e| {
+                match e {
+                    dechunker::Event::BeginChunk(h) => {
+                        crc = crate::crc32::Crc32::new();
+                        crc.update(&h.type_.0);
+                    }
+                    dechunker::Event::Data(data) => crc.update(data),
+                    dechunker::Event::Crc(c) => assert_eq!(c, crc.clone().finish()),
+                    dechunker::Event::EndChunk | dechunker::Event::Resync(_) => {}
+                }
+                Ok::<_, Error>(())
+            })
+            .unwrap();
+
+        assert_eq!(decode(&output), decode(&png));
+    }
+
+    #[test]
+    fn transcode_truncated_image_data() {
+        let mut encoder = PngEncoder::<64, 1024>::new(header(8, 8)).unwrap();
+        let mut png = Vec::new();
+        let mut sink = |data: &[u8]| {
+            png.extend_from_slice(data);
+            Ok::<_, Error>(())
+        };
+        for y in 0..8 {
+            let row: [u8; 24] = core::array::from_fn(|x| (x * 37 + y * 101) as u8);
+            encoder.write_row(&row, &mut sink).unwrap();
+        }
+        encoder.finish(&mut sink).unwrap();
+        // Keep the first half of the image data, the CRC isn't checked
+        let idat = png.windows(4).position(|w| w == b"IDAT").unwrap() - 4;
+        let len = u32::from_be_bytes(png[idat..idat + 4].try_into().unwrap()) as usize;
+        let iend = png[png.len() - 12..].to_vec();
+        png.truncate(idat + 8 + len / 2);
+        png[idat..idat + 4].copy_from_slice(&(len as u32 / 2).to_be_bytes());
+        png.extend_from_slice(&[0; 4]);
+        png.extend_from_slice(&iend);
+
+        let mut transcoder = Transcoder::<1024, 64>::new();
+        let mut sink = |_: &[u8]| Ok::<_, Error>(());
+        assert_eq!(
+            dechunker::Dechunker::new()
+                .update_all(&png, |e| transcoder.update(e, &mut sink))
+                .and_then(|()| transcoder.finish(&mut sink)),
+            Err(Error::UnfinishedImageData)
+        );
+    }
+
+    #[test]
+    fn invalid_text() {
+        let mut encoder = PngEncoder::<64, 32>::new(header(1, 1)).unwrap();
+        let mut sink = |_: &[u8]| Ok::<_, Error>(());
+        for (keyword, text) in [(&b""[..], &b""[..]), (&[b'a'; 80], b""), (b"a", b"\0")] {
+            assert_eq!(
+                encoder.write_ancillary(&Ancillary::Text { keyword, text }, &mut sink),
+                Err(Error::InvalidTextChunk)
+            );
+        }
+    }
+
+    #[test]
+    fn palette_not_allowed() {
+        let mut h = header(1, 1);
+        h.colour_type = 0;
+        let mut encoder = PngEncoder::<64, 32>::new(h).unwrap();
+        let mut sink = |_: &[u8]| Ok::<_, Error>(());
+        assert_eq!(
+            encoder.write_palette(&[[0; 3]], &[], &mut sink),
+            Err(Error::PaletteNotAllowed)
+        );
+    }
+
+    #[test]
+    fn row_count() {
+        let mut encoder = PngEncoder::<64, 32>::new(header(1, 1)).unwrap();
+        let mut sink = |_: &[u8]| Ok::<_, Error>(());
+
+        assert_eq!(encoder.finish(&mut sink), Err(Error::RowCountMismatch));
+        encoder.write_row(&[1, 2, 3], &mut sink).unwrap();
+        assert_eq!(
+            encoder.write_row(&[1, 2, 3], &mut sink),
+            Err(Error::RowCountMismatch)
+        );
+        encoder.finish(&mut sink).unwrap();
+    }
+}
+
+pub mod chunker {
+    use crate::crc32::Crc32;
+    use crate::dechunker::{self, ChunkHeader, PNG_SIGNATURE};
+    use crate::{ArrayVec, Error};
+
+    /// Serializes chunks, the counterpart of [`Dechunker`](crate::dechunker::Dechunker).
+    ///
+    /// Takes the same events the `Dechunker` produces and writes the PNG signature, chunk length,
+    /// type, data and CRC into caller-provided output buffers. CRCs are computed on the fly;
+    /// incoming [`dechunker::Event::Crc`] events are ignored.
+    pub struct Chunker {
+        state: State,
+        /// Header or CRC bytes not yet written to the output
+        pending: ArrayVec<u8, { PNG_SIGNATURE.len() + CHUNK_HEADER_SIZE }>,
+        pending_pos: usize,
+        signature_written: bool,
+    }
+
+    const CHUNK_HEADER_SIZE: usize = 8;
+
+    #[derive(Clone, PartialEq, Eq, Debug)]
+    enum State {
+        BetweenChunks,
+        InChunk { remaining: u32, crc: Crc32 },
+    }
+
+    impl Default for Chunker {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl Chunker {
+        pub fn new() -> Self {
+            Self {
+                state: State::BetweenChunks,
+                pending: ArrayVec::new(),
+                pending_pos: 0,
+                signature_written: false,
+            }
+        }
+
+        /// Creates a chunker which writes only chunks, e.g. to append to an existing datastream.
+        pub fn without_signature() -> Self {
+            Self {
+                signature_written: true,
+                ..Self::new()
+            }
+        }
+
+        /// Checks that all chunks were finished and all bytes written out.
+        pub fn eof(&self) -> Result<(), Error> {
+            match self.state {
+                State::BetweenChunks if self.pending_pos == self.pending.len() => Ok(()),
+                _ => Err(Error::UnfinishedChunk),
+            }
+        }
+
+        /// Processes `input`, writing as much as fits into `output`.
+        ///
+        /// Returns the leftover event (to be passed to the next call) if `output` was too small,
+        /// and the number of bytes written.
+        pub fn update<'a>(
+            &mut self,
+            input: dechunker::Event<'a>,
+            output: &mut [u8],
+        ) -> Result<(Option<dechunker::Event<'a>>, usize), Error> {
+            let mut written = self.flush_pending(output);
+            if self.pending_pos < self.pending.len() {
+                return Ok((Some(input), written));
+            }
+            let output = &mut output[written..];
+
+            match input {
+                dechunker::Event::BeginChunk(ChunkHeader { len, type_ }) => {
+                    // The previous chunk wasn't ended
+                    if self.state != State::BetweenChunks {
+                        return Err(Error::UnexpectedEvent);
+                    }
+                    self.pending.clear();
+                    self.pending_pos = 0;
+                    if !self.signature_written {
+                        self.pending.extend_from_slice(PNG_SIGNATURE).unwrap();
+                        self.signature_written = true;
+                    }
+                    self.pending.extend_from_slice(&len.to_be_bytes()).unwrap();
+                    self.pending.extend_from_slice(&type_.0).unwrap();
+
+                    let mut crc = Crc32::new();
+                    crc.update(&type_.0);
+                    self.state = State::InChunk {
+                        remaining: len,
+                        crc,
+                    };
+
+                    written += self.flush_pending(output);
+                    Ok((self.pending_leftover(), written))
+                }
+                dechunker::Event::Data(data) => match &mut self.state {
+                    State::InChunk { remaining, crc } => {
+                        if data.len() > *remaining as usize {
+                            return Err(Error::ChunkLengthMismatch);
+                        }
+                        let n = core::cmp::min(data.len(), output.len());
+                        output[..n].copy_from_slice(&data[..n]);
+                        crc.update(&data[..n]);
+                        *remaining -= n as u32;
+                        let leftover = if n < data.len() {
+                            Some(dechunker::Event::Data(&data[n..]))
+                        } else {
+                            None
+                        };
+                        Ok((leftover, written + n))
+                    }
+                    // Used internally to continue flushing
+                    State::BetweenChunks if data.is_empty() => Ok((None, written)),
+                    State::BetweenChunks => Err(Error::UnexpectedEvent),
+                },
+                dechunker::Event::Crc(_) => Ok((None, written)),
+                dechunker::Event::EndChunk => match &self.state {
+                    State::InChunk { remaining: 0, crc } => {
+                        self.pending.clear();
+                        self.pending_pos = 0;
+                        self.pending
+                            .extend_from_slice(&crc.finish().to_be_bytes())
+                            .unwrap();
+                        self.state = State::BetweenChunks;
+
+                        written += self.flush_pending(output);
+                        Ok((self.pending_leftover(), written))
+                    }
+                    State::InChunk { .. } => Err(Error::ChunkLengthMismatch),
+                    State::BetweenChunks => Err(Error::UnexpectedEvent),
+                },
+                // Part of the chunk may have been written already
+                dechunker::Event::Resync(_) => match self.state {
+                    State::BetweenChunks => Ok((None, written)),
+                    State::InChunk { .. } => Err(Error::UnexpectedEvent),
+                },
+            }
+        }
+
+        fn flush_pending(&mut self, output: &mut [u8]) -> usize {
+            let pending = &self.pending[self.pending_pos..];
+            let n = core::cmp::min(pending.len(), output.len());
+            output[..n].copy_from_slice(&pending[..n]);
+            self.pending_pos += n;
+            n
+        }
+
+        /// If there are still bytes to write, asks the caller to call again.
+        fn pending_leftover<'a>(&self) -> Option<dechunker::Event<'a>> {
+            if self.pending_pos < self.pending.len() {
+                Some(dechunker::Event::Data(&[]))
+            } else {
+                None
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::dechunker::{ChunkType, Dechunker};
+        use heapless::Vec;
+
+        fn write_all<const N: usize>(
+            chunker: &mut Chunker,
+            events: &[dechunker::Event],
+            out_buf_size: usize,
+        ) -> Vec<u8, N> {
+            let mut output = Vec::<u8, N>::new();
+            let mut buf = [0u8; 64];
+            for e in events {
+                let mut event = Some(e.clone());
+                while let Some(e) = event {
+                    let (leftover, n) = chunker.update(e, &mut buf[..out_buf_size]).unwrap();
+                    output.extend_from_slice(&buf[..n]).unwrap();
+                    event = leftover;
+                }
+            }
+            chunker.eof().unwrap();
+            output
+        }
+
+        const EVENTS: &[dechunker::Event] = &[
+            dechunker::Event::BeginChunk(ChunkHeader {
+                len: 5,
+                type_: ChunkType(*b"tEXt"),
+            }),
+            dechunker::Event::Data(b"he"),
+            dechunker::Event::Data(b"llo"),
+            dechunker::Event::EndChunk,
+            dechunker::Event::BeginChunk(ChunkHeader {
+                len: 0,
+                type_: ChunkType(*b"IEND"),
+            }),
+            dechunker::Event::EndChunk,
+        ];
+
+        #[test]
+        fn write_chunks() {
+            let output = write_all::<64>(&mut Chunker::new(), EVENTS, 64);
+            let crc = crate::crc32::checksum(b"tEXthello").to_be_bytes();
+
+            let mut expected = Vec::<u8, 64>::new();
+            expected.extend_from_slice(PNG_SIGNATURE).unwrap();
+            expected.extend_from_slice(&[0, 0, 0, 5]).unwrap();
+            expected.extend_from_slice(b"tEXthello").unwrap();
+            expected.extend_from_slice(&crc).unwrap();
+            expected.extend_from_slice(&[0, 0, 0, 0]).unwrap();
+            expected.extend_from_slice(b"IEND").unwrap();
+            expected
+                .extend_from_slice(&[0xae, 0x42, 0x60, 0x82])
+                .unwrap();
+
+            assert_eq!(output, expected);
+        }
+
+        #[test]
+        fn tiny_output_buffer() {
+            let expected = write_all::<64>(&mut Chunker::new(), EVENTS, 64);
+            for size in 1..4 {
+                assert_eq!(write_all::<64>(&mut Chunker::new(), EVENTS, size), expected);
+            }
+        }
+
+        #[test]
+        fn events_out_of_order() {
+            let mut buf = [0u8; 64];
+            let mut c = Chunker::new();
+            assert_eq!(
+                c.update(dechunker::Event::Data(b"x"), &mut buf),
+                Err(Error::UnexpectedEvent)
+            );
+            assert_eq!(
+                c.update(dechunker::Event::EndChunk, &mut buf),
+                Err(Error::UnexpectedEvent)
+            );
+
+            c.update(EVENTS[0].clone(), &mut buf).unwrap();
+            assert_eq!(
+                c.update(EVENTS[0].clone(), &mut buf),
+                Err(Error::UnexpectedEvent)
+            );
+            assert_eq!(
+                c.update(dechunker::Event::Data(b"too long"), &mut buf),
+                Err(Error::ChunkLengthMismatch)
+            );
+        }
+
+        #[test]
+        fn roundtrip_through_dechunker() {
+            let output = write_all::<64>(&mut Chunker::new(), EVENTS, 64);
+
+            let mut d = Dechunker::new().with_crc_events();
+            let mut events = Vec::<dechunker::Event, 16>::new();
+            d.update_all(&output, |e| {
+                events.push(e).unwrap();
+                Ok::<_, Error>(())
+            })
+            .unwrap();
+
+            assert_eq!(
+                events[2],
+                dechunker::Event::Crc(crate::crc32::checksum(b"tEXthello"))
+            );
+            events.retain(|e| !matches!(e, dechunker::Event::Crc(_)));
+            assert_eq!(
+                events,
+                [
+                    dechunker::Event::BeginChunk(ChunkHeader {
+                        len: 5,
+                        type_: ChunkType(*b"tEXt"),
+                    }),
+                    dechunker::Event::Data(b"hello"),
+                    dechunker::Event::EndChunk,
+                    dechunker::Event::BeginChunk(ChunkHeader {
+                        len: 0,
+                        type_: ChunkType(*b"IEND"),
+                    }),
+                    dechunker::Event::EndChunk,
+                ]
+            );
+        }
+
+        #[test]
+        fn length_mismatch() {
+            let mut c = Chunker::new();
+            let mut buf = [0u8; 64];
+            c.update(
+                dechunker::Event::BeginChunk(ChunkHeader {
+                    len: 2,
+                    type_: ChunkType(*b"IDAT"),
+                }),
+                &mut buf,
+            )
+            .unwrap();
+            assert_eq!(
+                c.update(dechunker::Event::Data(b"abc"), &mut buf),
+                Err(Error::ChunkLengthMismatch)
+            );
+            c.update(dechunker::Event::Data(b"a"), &mut buf).unwrap();
+            assert_eq!(
+                c.update(dechunker::Event::EndChunk, &mut buf),
+                Err(Error::ChunkLengthMismatch)
+            );
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+pub mod deflater {
+    use crate::Error;
+    use miniz_oxide::deflate::core::CompressorOxide;
+    use miniz_oxide::{DataFormat, MZError, MZFlush, MZStatus};
+
+    /// Compresses filtered scanlines into a zlib stream, the counterpart of
+    /// [`Inflater`](crate::inflater::Inflater).
+    ///
+    /// Output is collected into payloads of `chunk_size` bytes (at most `BUFFER_SIZE`), each of
+    /// which is meant to be written as one IDAT chunk. Requires the `alloc` feature, since
+    /// miniz_oxide's compressor keeps its tables on the heap.
+    pub struct Deflater<const BUFFER_SIZE: usize = 8192> {
+        compressor: CompressorOxide,
+        output_buf: [u8; BUFFER_SIZE],
+        chunk_size: usize,
+        filled: usize,
+        /// Output buffer was handed out and has to be reset on the next call
+        emitted: bool,
+        finished: bool,
+    }
+
+    #[derive(Eq, PartialEq, Debug)]
+    pub enum Input<'a> {
+        /// Filtered scanline data
+        Data(&'a [u8]),
+        /// No more data, terminate the zlib stream
+        Finish,
+    }
+
+    #[derive(Eq, PartialEq, Debug)]
+    pub enum Event<'a> {
+        /// Payload for one IDAT chunk
+        ImageData(&'a [u8]),
+        End,
+    }
+
+    /// Level used by [`Deflater::new`], on miniz_oxide's 0-10 scale.
+    pub const DEFAULT_LEVEL: u8 = 6;
+
+    impl<const BUFFER_SIZE: usize> Default for Deflater<BUFFER_SIZE> {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl<const BUFFER_SIZE: usize> Deflater<BUFFER_SIZE> {
+        pub fn new() -> Self {
+            Self::with_level(DEFAULT_LEVEL)
+        }
+
+        /// Creates a deflater with the given compression level (0-10).
+        pub fn with_level(level: u8) -> Self {
+            let mut compressor = CompressorOxide::default();
+            compressor.set_format_and_level(DataFormat::Zlib, level);
+            Self {
+                compressor,
+                output_buf: [0; BUFFER_SIZE],
+                chunk_size: BUFFER_SIZE,
+                filled: 0,
+                emitted: false,
+                finished: false,
+            }
+        }
+
+        /// Limits the IDAT payload size to `chunk_size` (clamped to `1..=BUFFER_SIZE`).
+        pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+            self.chunk_size = chunk_size.clamp(1, BUFFER_SIZE);
+            self
+        }
+
+        pub fn update<'this, 'a>(
+            &'this mut self,
+            input: Input<'a>,
+        ) -> Result<(Option<Input<'a>>, Option<Event<'this>>), Error> {
+            if self.emitted {
+                self.filled = 0;
+                self.emitted = false;
+            }
+            if self.finished {
+                return Ok((None, Some(Event::End)));
+            }
+
+            let (data, flush) = match input {
+                Input::Data(data) => (data, MZFlush::None),
+                Input::Finish => (&[][..], MZFlush::Finish),
+            };
+
+            let result = miniz_oxide::deflate::stream::deflate(
+                &mut self.compressor,
+                data,
+                &mut self.output_buf[self.filled..self.chunk_size],
+                flush,
+            );
+
+            match result.status {
+                Ok(MZStatus::StreamEnd) => self.finished = true,
+                Ok(_) => {}
+                // No progress possible, it just wants more input
+                Err(MZError::Buf) => {}
+                // Param if data comes after Finish, Stream shouldn't happen
+                Err(e) => {
+                    debug!("deflater: {:?}", crate::DebugFormat(e));
+                    return Err(Error::CompressionFailed);
+                }
+            }
+
+            self.filled += result.bytes_written;
+
+            let leftover = if result.bytes_consumed < data.len() {
+                Some(Input::Data(&data[result.bytes_consumed..]))
+            } else if flush == MZFlush::Finish {
+                // Either more output to flush, or the End event to emit
+                Some(Input::Finish)
+            } else {
+                None
+            };
+
+            if self.filled == self.chunk_size || (self.finished && self.filled > 0) {
+                self.emitted = true;
+                Ok((
+                    leftover,
+                    Some(Event::ImageData(&self.output_buf[..self.filled])),
+                ))
+            } else if self.finished {
+                Ok((None, Some(Event::End)))
+            } else {
+                Ok((leftover, None))
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::inflater::{self, Inflater};
+        use crate::stream_decoder as sd;
+
+        extern crate std;
+        use std::vec::Vec;
+
+        fn compress<const N: usize>(d: &mut Deflater<N>, inputs: &[&[u8]]) -> Vec<Vec<u8>> {
+            let mut chunks = Vec::new();
+            for &input in inputs.iter().chain([&[][..]].iter()) {
+                let mut event = Some(if input.is_empty() {
+                    Input::Finish
+                } else {
+                    Input::Data(input)
+                });
+                while let Some(e) = event {
+                    let (leftover, output) = d.update(e).unwrap();
+                    match output {
+                        Some(Event::ImageData(data)) => chunks.push(data.to_vec()),
+                        Some(Event::End) => return chunks,
+                        None => {}
+                    }
+                    event = leftover;
+                }
+            }
+            panic!("no End event");
+        }
+
+        #[test]
+        fn data_after_finish() {
+            let mut d = Deflater::<4>::new();
+            d.update(Input::Data(&[7; 64])).unwrap();
+            // Not finished yet, the output buffer is too small
+            let (leftover, _) = d.update(Input::Finish).unwrap();
+            assert_eq!(leftover, Some(Input::Finish));
+            assert_eq!(
+                d.update(Input::Data(b"more")),
+                Err(Error::CompressionFailed)
+            );
+        }
+
+        fn decompress(chunks: &[Vec<u8>]) -> Vec<u8> {
+            let mut inflater = Inflater::<1024>::new();
+            let mut output = Vec::new();
+            for chunk in chunks {
+                let mut event = Some(sd::Event::ImageData(chunk));
+                while let Some(e) = event {
+                    let (leftover, out) = inflater.update(e).unwrap();
+                    if let Some(inflater::Event::ImageData(data)) = out {
+                        output.extend_from_slice(data);
+                    }
+                    event = leftover;
+                }
+            }
+            output
+        }
+
+        #[test]
+        fn roundtrip() {
+            let input: Vec<u8> = (0..10000u32)
+                .map(|i| (i.wrapping_mul(2654435761) >> 24) as u8)
+                .collect();
+            let mut d = Deflater::<1024>::new();
+
+            let chunks = compress(&mut d, &[&input[..3000], &input[3000..]]);
+
+            assert!(chunks.len() > 1);
+            assert!(chunks[..chunks.len() - 1].iter().all(|c| c.len() == 1024));
+            assert_eq!(decompress(&chunks), input);
+        }
+
+        #[test]
+        fn chunk_size_and_level() {
+            let input = [42u8; 5000];
+            let mut d = Deflater::<1024>::with_level(0).with_chunk_size(100);
+
+            let chunks = compress(&mut d, &[&input]);
+
+            assert!(chunks.iter().all(|c| c.len() <= 100));
+            // Level 0 stores the data uncompressed
+            assert!(chunks.iter().map(|c| c.len()).sum::<usize>() > input.len());
+            assert_eq!(decompress(&chunks), input);
+        }
+    }
+}
+
+pub mod filter {
+    use crate::stream_decoder::ImageHeader;
+    use crate::Error;
+
+    /// <https://www.w3.org/TR/png-3/#9Filter-types>
+    #[derive(Clone, Copy, Eq, PartialEq, Debug)]
+    #[repr(u8)]
+    pub enum FilterType {
+        None = 0,
+        Sub = 1,
+        Up = 2,
+        Average = 3,
+        Paeth = 4,
+    }
+
+    const FILTER_TYPES: [FilterType; 5] = [
+        FilterType::None,
+        FilterType::Sub,
+        FilterType::Up,
+        FilterType::Average,
+        FilterType::Paeth,
+    ];
+
+    #[derive(Clone, Copy, Default, Eq, PartialEq, Debug)]
+    pub enum FilterStrategy {
+        /// Don't filter at all. Cheapest, but compresses worse.
+        NoneOnly,
+        /// Pick the filter with the minimum sum of absolute differences for each row.
+        #[default]
+        Adaptive,
+    }
+
+    /// <https://www.w3.org/TR/png-3/#9Filter-type-4-Paeth>
+    pub(crate) fn paeth(a: u8, b: u8, c: u8) -> u8 {
+        let p = a as i16 + b as i16 - c as i16;
+        let pa = (p - a as i16).abs();
+        let pb = (p - b as i16).abs();
+        let pc = (p - c as i16).abs();
+        if pa <= pb && pa <= pc {
+            a
+        } else if pb <= pc {
+            b
+        } else {
+            c
+        }
+    }
+
+    fn filter_byte(filter: FilterType, x: u8, a: u8, b: u8, c: u8) -> u8 {
+        match filter {
+            FilterType::None => x,
+            FilterType::Sub => x.wrapping_sub(a),
+            FilterType::Up => x.wrapping_sub(b),
+            FilterType::Average => x.wrapping_sub(((a as u16 + b as u16) / 2) as u8),
+            FilterType::Paeth => x.wrapping_sub(paeth(a, b, c)),
+        }
+    }
+
+    /// Filters scanlines before compression. Holds the previous row, so scanlines of up to
+    /// `MAX_SCANLINE - 1` bytes are supported.
+    pub struct Filterer<const MAX_SCANLINE: usize = 1024> {
+        strategy: FilterStrategy,
+        /// Filter distance, bytes per complete pixel (at least 1)
+        bpp: usize,
+        scanline_len: usize,
+        /// Previous unfiltered row, starting at index 1
+        prev: [u8; MAX_SCANLINE],
+        output_buf: [u8; MAX_SCANLINE],
+    }
+
+    impl<const MAX_SCANLINE: usize> Filterer<MAX_SCANLINE> {
+        pub fn new(header: &ImageHeader, strategy: FilterStrategy) -> Result<Self, Error> {
+            let bits_per_pixel = header.bits_per_pixel().ok_or(Error::InvalidColourType)?;
+            let scanline_len = header.scanline_len().ok_or(Error::InvalidColourType)?;
+            if scanline_len > MAX_SCANLINE as u64 {
+                return Err(Error::RowTooLarge);
+            }
+            Ok(Self {
+                strategy,
+                bpp: core::cmp::max(1, bits_per_pixel as usize / 8),
+                scanline_len: scanline_len as usize,
+                prev: [0; MAX_SCANLINE],
+                output_buf: [0; MAX_SCANLINE],
+            })
+        }
+
+        /// Filters one row of raw pixel data, returning the scanline (filter type byte followed by
+        /// filtered data) to be compressed.
+        pub fn update<'this>(&'this mut self, row: &[u8]) -> Result<&'this [u8], Error> {
+            if row.len() != self.scanline_len - 1 {
+                return Err(Error::InvalidRowLength);
+            }
+
+            let filter = match self.strategy {
+                FilterStrategy::NoneOnly => FilterType::None,
+                FilterStrategy::Adaptive => self.choose_filter(row),
+            };
+
+            let prev = &self.prev[1..self.scanline_len];
+            let out = &mut self.output_buf[..self.scanline_len];
+            out[0] = filter as u8;
+            for i in 0..row.len() {
+                let (a, c) = if i >= self.bpp {
+                    (row[i - self.bpp], prev[i - self.bpp])
+                } else {
+                    (0, 0)
+                };
+                out[i + 1] = filter_byte(filter, row[i], a, prev[i], c);
+            }
+
+            self.prev[1..self.scanline_len].copy_from_slice(row);
+            Ok(&self.output_buf[..self.scanline_len])
+        }
+
+        /// Minimum sum of absolute differences heuristic, treating filtered bytes as signed.
+        fn choose_filter(&self, row: &[u8]) -> FilterType {
+            let prev = &self.prev[1..self.scanline_len];
+            let mut sums = [0u64; FILTER_TYPES.len()];
+            for i in 0..row.len() {
+                let (a, c) = if i >= self.bpp {
+                    (row[i - self.bpp], prev[i - self.bpp])
+                } else {
+                    (0, 0)
+                };
+                for (sum, &filter) in sums.iter_mut().zip(FILTER_TYPES.iter()) {
+                    let v = filter_byte(filter, row[i], a, prev[i], c);
+                    *sum += (v as i8).unsigned_abs() as u64;
+                }
+            }
+            let best = (0..sums.len()).min_by_key(|&i| sums[i]).unwrap();
+            FILTER_TYPES[best]
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn header(width: u32, colour_type: u8, bit_depth: u8) -> ImageHeader {
+            ImageHeader {
+                width,
+                height: 2,
+                bit_depth,
+                colour_type,
+                compression_method: 0,
+                filter_method: 0,
+                interlace_method: 0,
+            }
+        }
+
+        #[test]
+        fn none_only() {
+            let mut f = Filterer::<16>::new(&header(3, 0, 8), FilterStrategy::NoneOnly).unwrap();
+            assert_eq!(f.update(&[1, 2, 3]).unwrap(), &[0, 1, 2, 3]);
+            assert_eq!(f.update(&[4, 5, 6]).unwrap(), &[0, 4, 5, 6]);
+        }
+
+        #[test]
+        fn adaptive_picks_sub_for_gradient() {
+            let mut f = Filterer::<16>::new(&header(4, 0, 8), FilterStrategy::Adaptive).unwrap();
+            assert_eq!(f.update(&[10, 20, 30, 40]).unwrap(), &[1, 10, 10, 10, 10]);
+        }
+
+        #[test]
+        fn adaptive_picks_up_for_repeated_row() {
+            let mut f = Filterer::<16>::new(&header(2, 2, 8), FilterStrategy::Adaptive).unwrap();
+            f.update(&[9, 200, 3, 71, 18, 140]).unwrap();
+            assert_eq!(
+                f.update(&[9, 200, 3, 71, 18, 140]).unwrap(),
+                &[2, 0, 0, 0, 0, 0, 0]
+            );
+        }
+
+        #[test]
+        fn row_length() {
+            let mut f = Filterer::<16>::new(&header(3, 0, 8), FilterStrategy::Adaptive).unwrap();
+            assert_eq!(f.update(&[1, 2]), Err(Error::InvalidRowLength));
+            assert_eq!(
+                Filterer::<4>::new(&header(4, 0, 8), FilterStrategy::Adaptive).err(),
+                Some(Error::RowTooLarge)
+            );
+        }
+
+        #[test]
+        fn paeth_predictor() {
+            assert_eq!(paeth(1, 2, 3), 1);
+            assert_eq!(paeth(10, 20, 10), 20);
+            assert_eq!(paeth(20, 10, 10), 20);
+            assert_eq!(paeth(50, 60, 100), 50);
+        }
+    }
+}
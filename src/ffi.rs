@@ -0,0 +1,519 @@
+//! C interface, declared in `include/incremental_png.h`. The decoder lives in memory provided
+//! by the caller, and reports the image header and the unfiltered rows through a callback:
+//!
+//! ```c
+//! static _Alignas(16) uint8_t mem[56 * 1024];
+//! IncPng *png = inc_png_new(mem, sizeof mem); /* null if smaller than inc_png_size() */
+//! while ((n = read(fd, buf, sizeof buf)) > 0) {
+//!     if (inc_png_update(png, buf, n, on_event, ctx) != INC_PNG_STATUS_OK)
+//!         break;
+//! }
+//! inc_png_free(png);
+//! ```
+
+use core::ffi::{c_int, c_void};
+
+use crate::dechunker::{ChunkType, Dechunker};
+use crate::inflater::Inflater;
+use crate::stream_decoder::{ImageHeader, StreamDecoder};
+use crate::unfilter::{self, Unfilterer};
+use crate::Error;
+
+/// Longest supported scanline in bytes, including the filter type byte.
+pub const INC_PNG_MAX_SCANLINE: usize = 4096;
+
+/// Opaque decoder handle.
+pub struct IncPng {
+    dechunker: Dechunker,
+    stream_decoder: StreamDecoder,
+    inflater: Inflater<1024>,
+    unfilterer: Unfilterer<INC_PNG_MAX_SCANLINE>,
+}
+
+/// Result of the `inc_png_*` functions. Besides `Ok`, `Aborted` and `InvalidArgument`, these
+/// correspond to the variants of [`Error`].
+#[repr(C)]
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum IncPngStatus {
+    Ok = 0,
+    /// The callback returned non-zero
+    Aborted,
+    /// Null pointer, or memory too small or misaligned
+    InvalidArgument,
+    InvalidPngSignature,
+    UnfinishedChunk,
+    InvalidImageHeaderLength,
+    NoImageHeader,
+    InvalidDeflateStream,
+    ChecksumMismatch,
+    InvalidEndChunkSize,
+    InvalidPaletteChunkSize,
+    InvalidTransparencyChunkSize,
+    PaletteIndexOutOfRange,
+    ChunkTooLarge,
+    DuplicateChunk,
+    PaletteNotAllowed,
+    UnsupportedCompressionMethod,
+    UnsupportedFilterMethod,
+    InterlaceNotSupported,
+    InvalidInterlaceMethod,
+    ChunkLengthMismatch,
+    InvalidColourType,
+    RowTooLarge,
+    InvalidRowLength,
+    RowCountMismatch,
+    MissingPalette,
+    ChunkOutOfOrder,
+    InvalidBitDepth,
+    InvalidTextChunk,
+    InvalidFrameControl,
+    FrameCountMismatch,
+    InvalidAnimationControl,
+    InvalidFilterType,
+    UnexpectedEvent,
+    MissingEndChunk,
+    UnfinishedImageData,
+    DatastreamTooLong,
+    InvalidIco,
+    InvalidExtensionChunk,
+    ArithmeticOverflow,
+    EventTooLarge,
+    FramebufferTooSmall,
+    TruncatedImageData,
+    TrailingCompressedData,
+    InvalidImageDimensions,
+    PaletteTooLarge,
+    CompressionFailed,
+}
+
+impl From<Error> for IncPngStatus {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::InvalidPngSignature => Self::InvalidPngSignature,
+            Error::UnfinishedChunk => Self::UnfinishedChunk,
+            Error::InvalidImageHeaderLength => Self::InvalidImageHeaderLength,
+            Error::NoImageHeader => Self::NoImageHeader,
+            Error::InvalidDeflateStream => Self::InvalidDeflateStream,
+            Error::ChecksumMismatch => Self::ChecksumMismatch,
+            Error::InvalidEndChunkSize => Self::InvalidEndChunkSize,
+            Error::InvalidPaletteChunkSize => Self::InvalidPaletteChunkSize,
+            Error::InvalidTransparencyChunkSize => Self::InvalidTransparencyChunkSize,
+            Error::PaletteIndexOutOfRange => Self::PaletteIndexOutOfRange,
+            Error::ChunkTooLarge => Self::ChunkTooLarge,
+            Error::DuplicateChunk(ChunkType(_)) => Self::DuplicateChunk,
+            Error::PaletteNotAllowed => Self::PaletteNotAllowed,
+            Error::UnsupportedCompressionMethod => Self::UnsupportedCompressionMethod,
+            Error::UnsupportedFilterMethod => Self::UnsupportedFilterMethod,
+            Error::InterlaceNotSupported => Self::InterlaceNotSupported,
+            Error::InvalidInterlaceMethod => Self::InvalidInterlaceMethod,
+            Error::ChunkLengthMismatch => Self::ChunkLengthMismatch,
+            Error::InvalidColourType => Self::InvalidColourType,
+            Error::RowTooLarge => Self::RowTooLarge,
+            Error::InvalidRowLength => Self::InvalidRowLength,
+            Error::RowCountMismatch => Self::RowCountMismatch,
+            Error::MissingPalette => Self::MissingPalette,
+            Error::ChunkOutOfOrder(ChunkType(_)) => Self::ChunkOutOfOrder,
+            Error::InvalidBitDepth => Self::InvalidBitDepth,
+            Error::InvalidTextChunk => Self::InvalidTextChunk,
+            Error::InvalidFrameControl => Self::InvalidFrameControl,
+            Error::FrameCountMismatch => Self::FrameCountMismatch,
+            Error::InvalidAnimationControl => Self::InvalidAnimationControl,
+            Error::InvalidFilterType => Self::InvalidFilterType,
+            Error::UnexpectedEvent => Self::UnexpectedEvent,
+            Error::MissingEndChunk => Self::MissingEndChunk,
+            Error::UnfinishedImageData => Self::UnfinishedImageData,
+            Error::DatastreamTooLong => Self::DatastreamTooLong,
+            Error::InvalidIco => Self::InvalidIco,
+            Error::InvalidExtensionChunk(ChunkType(_)) => Self::InvalidExtensionChunk,
+            Error::ArithmeticOverflow => Self::ArithmeticOverflow,
+            Error::EventTooLarge => Self::EventTooLarge,
+            Error::FramebufferTooSmall => Self::FramebufferTooSmall,
+            Error::TruncatedImageData { .. } => Self::TruncatedImageData,
+            Error::TrailingCompressedData => Self::TrailingCompressedData,
+            Error::InvalidImageDimensions => Self::InvalidImageDimensions,
+            Error::PaletteTooLarge => Self::PaletteTooLarge,
+            Error::CompressionFailed => Self::CompressionFailed,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum IncPngEventKind {
+    /// `header` is valid
+    Header,
+    /// `data` points to `len` bytes of an unfiltered row, without the filter type byte
+    Row,
+    /// IEND reached
+    End,
+}
+
+/// Fields of the IHDR chunk which matter for interpreting the rows.
+#[repr(C)]
+#[derive(Clone, Copy, Default, Eq, PartialEq, Debug)]
+pub struct IncPngHeader {
+    pub width: u32,
+    pub height: u32,
+    pub bit_depth: u8,
+    pub colour_type: u8,
+    pub interlace_method: u8,
+}
+
+impl From<ImageHeader> for IncPngHeader {
+    fn from(header: ImageHeader) -> Self {
+        Self {
+            width: header.width,
+            height: header.height,
+            bit_depth: header.bit_depth,
+            colour_type: header.colour_type,
+            interlace_method: header.interlace_method,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct IncPngEvent {
+    pub kind: IncPngEventKind,
+    pub header: IncPngHeader,
+    pub data: *const u8,
+    pub len: usize,
+}
+
+/// Called with each event. Returning non-zero stops decoding, and the `inc_png_update` call
+/// returns `Aborted`. The event is only valid during the call.
+pub type IncPngCallback =
+    Option<unsafe extern "C" fn(user_data: *mut c_void, event: *const IncPngEvent) -> c_int>;
+
+/// Why the pipeline stopped.
+enum Stop {
+    Error(Error),
+    Aborted,
+}
+
+impl From<Error> for Stop {
+    fn from(e: Error) -> Self {
+        Stop::Error(e)
+    }
+}
+
+impl IncPng {
+    fn new() -> Self {
+        Self {
+            dechunker: Dechunker::new(),
+            stream_decoder: StreamDecoder::new(),
+            inflater: Inflater::new(),
+            unfilterer: Unfilterer::new(),
+        }
+    }
+
+    fn update(
+        &mut self,
+        input: &[u8],
+        mut f: impl FnMut(&IncPngEvent) -> c_int,
+    ) -> Result<(), Stop> {
+        let Self {
+            dechunker,
+            stream_decoder,
+            inflater,
+            unfilterer,
+        } = self;
+        dechunker.update_all(input, |e| {
+            let mut dc_event = Some(e);
+            while let Some(e) = dc_event {
+                let (leftover, mut sd_event) = stream_decoder.update(e)?;
+                while let Some(e) = sd_event {
+                    let (leftover, mut i_event) = inflater.update(e)?;
+                    while let Some(e) = i_event {
+                        let (leftover, u_event) = unfilterer.update(e)?;
+                        let event = match u_event {
+                            Some(unfilter::Event::ImageHeader(header)) => IncPngEvent {
+                                kind: IncPngEventKind::Header,
+                                header: header.into(),
+                                data: core::ptr::null(),
+                                len: 0,
+                            },
+                            Some(unfilter::Event::Row(row)) => IncPngEvent {
+                                kind: IncPngEventKind::Row,
+                                header: IncPngHeader::default(),
+                                data: row.as_ptr(),
+                                len: row.len(),
+                            },
+                            Some(unfilter::Event::End) => IncPngEvent {
+                                kind: IncPngEventKind::End,
+                                header: IncPngHeader::default(),
+                                data: core::ptr::null(),
+                                len: 0,
+                            },
+                            // Not exposed through the C interface yet
+                            #[cfg(feature = "ancillary")]
+                            Some(unfilter::Event::Extension(_)) => {
+                                i_event = leftover;
+                                continue;
+                            }
+                            Some(unfilter::Event::Warning(_) | unfilter::Event::ImageInfo(_)) => {
+                                i_event = leftover;
+                                continue;
+                            }
+                            None => {
+                                i_event = leftover;
+                                continue;
+                            }
+                        };
+                        if f(&event) != 0 {
+                            return Err(Stop::Aborted);
+                        }
+                        i_event = leftover;
+                    }
+                    sd_event = leftover;
+                }
+                dc_event = leftover;
+            }
+            Ok(())
+        })
+    }
+}
+
+fn status(result: Result<(), Stop>) -> IncPngStatus {
+    match result {
+        Ok(()) => IncPngStatus::Ok,
+        Err(Stop::Error(e)) => e.into(),
+        Err(Stop::Aborted) => IncPngStatus::Aborted,
+    }
+}
+
+/// Number of bytes `inc_png_new` needs.
+#[no_mangle]
+pub extern "C" fn inc_png_size() -> usize {
+    core::mem::size_of::<IncPng>()
+}
+
+/// Required alignment of the memory passed to `inc_png_new`.
+#[no_mangle]
+pub extern "C" fn inc_png_align() -> usize {
+    core::mem::align_of::<IncPng>()
+}
+
+/// Creates a decoder in `mem`, which must hold at least `inc_png_size()` bytes aligned to
+/// `inc_png_align()`. Returns null if it doesn't.
+///
+/// # Safety
+///
+/// `mem` must be null or valid for writes of `size` bytes, and not be used for anything else
+/// until `inc_png_free`.
+#[no_mangle]
+pub unsafe extern "C" fn inc_png_new(mem: *mut c_void, size: usize) -> *mut IncPng {
+    let png = mem.cast::<IncPng>();
+    if png.is_null() || size < inc_png_size() || !png.is_aligned() {
+        return core::ptr::null_mut();
+    }
+    png.write(IncPng::new());
+    png
+}
+
+/// Feeds `len` bytes of the file to the decoder, calling `callback` with each event.
+///
+/// After an error (including `Aborted`) the decoder is in an unspecified state and should
+/// only be freed.
+///
+/// # Safety
+///
+/// `png` must come from `inc_png_new`, and `data` must be valid for reads of `len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn inc_png_update(
+    png: *mut IncPng,
+    data: *const u8,
+    len: usize,
+    callback: IncPngCallback,
+    user_data: *mut c_void,
+) -> IncPngStatus {
+    let (Some(png), Some(callback)) = (png.as_mut(), callback) else {
+        return IncPngStatus::InvalidArgument;
+    };
+    if data.is_null() && len != 0 {
+        return IncPngStatus::InvalidArgument;
+    }
+    let input = if len == 0 {
+        &[]
+    } else {
+        core::slice::from_raw_parts(data, len)
+    };
+    status(png.update(input, |event| callback(user_data, event)))
+}
+
+/// Checks that the whole image was decoded, like
+/// [`Decoder::finish`](crate::decoder::Decoder::finish): the file didn't end in the middle of a
+/// chunk or before IEND, the deflate stream ended, and as many rows as the header says were
+/// decoded.
+///
+/// # Safety
+///
+/// `png` must come from `inc_png_new`.
+#[no_mangle]
+pub unsafe extern "C" fn inc_png_eof(png: *const IncPng) -> IncPngStatus {
+    let Some(png) = png.as_ref() else {
+        return IncPngStatus::InvalidArgument;
+    };
+    let result = png
+        .dechunker
+        .eof()
+        .and_then(|()| png.stream_decoder.eof())
+        .and_then(|()| png.inflater.eof())
+        .and_then(|()| png.unfilterer.eof());
+    status(result.map_err(Stop::Error))
+}
+
+/// Destroys the decoder. The memory passed to `inc_png_new` can be reused afterwards.
+///
+/// # Safety
+///
+/// `png` must be null or come from `inc_png_new`, and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn inc_png_free(png: *mut IncPng) {
+    if !png.is_null() {
+        png.drop_in_place();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    extern crate std;
+    use std::vec::Vec;
+
+    fn encode(rows: &[&[u8]]) -> Vec<u8> {
+        let mut raw = Vec::new();
+        for row in rows {
+            raw.push(0);
+            raw.extend_from_slice(row);
+        }
+        let idat = miniz_oxide::deflate::compress_to_vec_zlib(&raw, 5);
+        encode_with_image_data(rows[0].len() as u32, rows.len() as u32, &idat)
+    }
+
+    fn encode_with_image_data(width: u32, height: u32, idat: &[u8]) -> Vec<u8> {
+        let mut png = crate::dechunker::PNG_SIGNATURE.to_vec();
+        let mut chunk = |type_: &[u8; 4], data: &[u8]| {
+            png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            png.extend_from_slice(type_);
+            png.extend_from_slice(data);
+            let crc = crate::crc32::checksum(&[&type_[..], data].concat());
+            png.extend_from_slice(&crc.to_be_bytes());
+        };
+        let ihdr = [
+            &width.to_be_bytes()[..],
+            &height.to_be_bytes(),
+            &[8, 0, 0, 0, 0],
+        ]
+        .concat();
+        chunk(b"IHDR", &ihdr);
+        chunk(b"IDAT", idat);
+        chunk(b"IEND", &[]);
+        png
+    }
+
+    #[derive(Default)]
+    struct Collected {
+        header: Option<IncPngHeader>,
+        rows: Vec<Vec<u8>>,
+        end: bool,
+        abort_after_rows: Option<usize>,
+    }
+
+    unsafe extern "C" fn collect(user_data: *mut c_void, event: *const IncPngEvent) -> c_int {
+        let collected = &mut *user_data.cast::<Collected>();
+        let event = &*event;
+        match event.kind {
+            IncPngEventKind::Header => collected.header = Some(event.header),
+            IncPngEventKind::Row => collected
+                .rows
+                .push(core::slice::from_raw_parts(event.data, event.len).to_vec()),
+            IncPngEventKind::End => collected.end = true,
+        }
+        (Some(collected.rows.len()) == collected.abort_after_rows) as c_int
+    }
+
+    #[repr(align(16))]
+    struct Memory([u8; 64 * 1024]);
+
+    #[test]
+    fn decode_in_pieces() {
+        let data = encode(&[&[1, 2, 3], &[4, 5, 6]]);
+        let mut mem = std::boxed::Box::new(Memory([0; 64 * 1024]));
+        assert!(inc_png_size() <= mem.0.len());
+        let mut collected = Collected::default();
+        unsafe {
+            let png = inc_png_new(mem.0.as_mut_ptr().cast(), mem.0.len());
+            assert!(!png.is_null());
+            for piece in data.chunks(5) {
+                let user_data = (&raw mut collected).cast();
+                let status =
+                    inc_png_update(png, piece.as_ptr(), piece.len(), Some(collect), user_data);
+                assert_eq!(status, IncPngStatus::Ok);
+            }
+            assert_eq!(inc_png_eof(png), IncPngStatus::Ok);
+            inc_png_free(png);
+        }
+        let header = collected.header.unwrap();
+        assert_eq!((header.width, header.height, header.bit_depth), (3, 2, 8));
+        assert_eq!(collected.rows, [[1, 2, 3], [4, 5, 6]]);
+        assert!(collected.end);
+    }
+
+    #[test]
+    fn errors() {
+        let mut mem = std::boxed::Box::new(Memory([0; 64 * 1024]));
+        let mut collected = Collected {
+            abort_after_rows: Some(1),
+            ..Collected::default()
+        };
+        let user_data = (&raw mut collected).cast();
+        unsafe {
+            assert!(inc_png_new(mem.0.as_mut_ptr().cast(), 16).is_null());
+            assert!(inc_png_new(mem.0.as_mut_ptr().add(1).cast(), mem.0.len() - 1).is_null());
+
+            let png = inc_png_new(mem.0.as_mut_ptr().cast(), mem.0.len());
+            assert_eq!(
+                inc_png_update(png, b"GIF89a".as_ptr(), 6, Some(collect), user_data),
+                IncPngStatus::InvalidPngSignature
+            );
+            assert_eq!(
+                inc_png_update(png, core::ptr::null(), 0, None, user_data),
+                IncPngStatus::InvalidArgument
+            );
+            inc_png_free(png);
+
+            let data = encode(&[&[1], &[2]]);
+            let png = inc_png_new(mem.0.as_mut_ptr().cast(), mem.0.len());
+            assert_eq!(
+                inc_png_update(png, data.as_ptr(), data.len(), Some(collect), user_data),
+                IncPngStatus::Aborted
+            );
+            inc_png_free(png);
+        }
+        assert_eq!(collected.rows, [[1]]);
+    }
+
+    #[test]
+    fn eof_checks_image_data() {
+        let mut mem = std::boxed::Box::new(Memory([0; 64 * 1024]));
+        let mut collected = Collected::default();
+        let user_data = (&raw mut collected).cast();
+
+        let raw = [0, 1, 2, 3, 0, 4, 5, 6];
+        let compressed = miniz_oxide::deflate::compress_to_vec_zlib(&raw, 0);
+        // Stored, so the first row comes out before the deflate stream is cut off
+        let data = encode_with_image_data(3, 2, &compressed[..compressed.len() - 6]);
+        unsafe {
+            let png = inc_png_new(mem.0.as_mut_ptr().cast(), mem.0.len());
+            assert_eq!(
+                inc_png_update(png, data.as_ptr(), data.len(), Some(collect), user_data),
+                IncPngStatus::Ok
+            );
+            assert_eq!(inc_png_eof(png), IncPngStatus::UnfinishedImageData);
+            inc_png_free(png);
+        }
+        assert_eq!(collected.rows, [[1, 2, 3]]);
+    }
+}
@@ -0,0 +1,353 @@
+//! ICO (and CUR) containers, whose entries are often PNG datastreams. The [`IcoReader`] parses
+//! the directory incrementally and passes on the bytes of one entry, which can be fed to a
+//! [`Dechunker`](crate::dechunker::Dechunker) as they arrive.
+//!
+//! Entries can also be BMP images, which the Dechunker rejects with
+//! [`Error::InvalidPngSignature`].
+//!
+//! <https://learn.microsoft.com/en-us/previous-versions/ms997538(v=msdn.10)>
+
+use crate::array_vec::ArrayVec;
+use crate::{fill, Error};
+
+const HEADER_SIZE: usize = 6;
+const ENTRY_SIZE: usize = 16;
+
+/// Directory entry.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub struct IconEntry {
+    /// Position in the directory
+    pub index: u16,
+    pub width: u32,
+    pub height: u32,
+    /// Bits per pixel. Often 0 for PNG entries, which store it in their header.
+    pub bit_count: u16,
+    /// Length of the image data in bytes
+    pub size: u32,
+    /// Position of the image data in the file
+    pub offset: u32,
+}
+
+impl IconEntry {
+    fn parse(index: u16, data: &[u8; ENTRY_SIZE]) -> Self {
+        let u32_at =
+            |i: usize| u32::from_le_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]);
+        // 0 stands for 256
+        let dimension = |b: u8| if b == 0 { 256 } else { b as u32 };
+        Self {
+            index,
+            width: dimension(data[0]),
+            height: dimension(data[1]),
+            bit_count: u16::from_le_bytes([data[6], data[7]]),
+            size: u32_at(8),
+            offset: u32_at(12),
+        }
+    }
+}
+
+/// Which entry [`IcoReader`] passes on.
+#[derive(Clone, Copy, Default, Eq, PartialEq, Debug)]
+pub enum Selection {
+    /// Largest in pixels, then in bits per pixel. The first one if tied.
+    #[default]
+    Largest,
+    /// Entry at the given position in the directory
+    Index(u16),
+}
+
+impl Selection {
+    fn prefers(&self, entry: &IconEntry, current: Option<&IconEntry>) -> bool {
+        match (self, current) {
+            (Selection::Index(index), _) => entry.index == *index,
+            (Selection::Largest, None) => true,
+            (Selection::Largest, Some(current)) => {
+                let key = |e: &IconEntry| (e.width as u64 * e.height as u64, e.bit_count);
+                key(entry) > key(current)
+            }
+        }
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+enum State {
+    Header(ArrayVec<u8, HEADER_SIZE>),
+    Entries {
+        index: u16,
+        count: u16,
+        buf: ArrayVec<u8, ENTRY_SIZE>,
+    },
+    /// Skipping to the selected entry
+    BeforeImage,
+    Image {
+        remaining: u32,
+    },
+    /// Ignoring the rest of the file
+    Done,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum Event<'a> {
+    /// Emitted for every directory entry
+    Entry(IconEntry),
+    /// The data of the selected entry follows
+    BeginImage(IconEntry),
+    /// Data of the selected entry
+    Data(&'a [u8]),
+}
+
+/// Splits an ICO file, passing on the data of the [`Selection`].
+#[derive(Debug)]
+pub struct IcoReader {
+    state: State,
+    selection: Selection,
+    selected: Option<IconEntry>,
+    bytes_consumed: u64,
+}
+
+impl Default for IcoReader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl IcoReader {
+    /// Selects the largest entry.
+    pub fn new() -> Self {
+        Self {
+            state: State::Header(ArrayVec::new()),
+            selection: Selection::Largest,
+            selected: None,
+            bytes_consumed: 0,
+        }
+    }
+
+    pub fn with_selection(mut self, selection: Selection) -> Self {
+        self.selection = selection;
+        self
+    }
+
+    /// The selected entry, once the directory has been read.
+    pub fn selected(&self) -> Option<&IconEntry> {
+        match self.state {
+            State::Header(_) | State::Entries { .. } => None,
+            _ => self.selected.as_ref(),
+        }
+    }
+
+    /// Fails with [`Error::UnfinishedChunk`] unless all the data of the selected entry was
+    /// received.
+    pub fn eof(&self) -> Result<(), Error> {
+        match self.state {
+            State::Done => Ok(()),
+            _ => Err(Error::UnfinishedChunk),
+        }
+    }
+
+    pub fn update<'a>(&mut self, input: &'a [u8]) -> Result<(usize, Option<Event<'a>>), Error> {
+        let (n, event) = self.step(input)?;
+        self.bytes_consumed += n as u64;
+        Ok((n, event))
+    }
+
+    /// Consumes all of `input`, calling `f` with each event.
+    pub fn update_all<'a, E: From<Error>>(
+        &mut self,
+        mut input: &'a [u8],
+        mut f: impl FnMut(Event<'a>) -> Result<(), E>,
+    ) -> Result<(), E> {
+        while !input.is_empty() {
+            let (n, event) = self.update(input)?;
+            input = input.get(n..).unwrap_or_default();
+            if let Some(event) = event {
+                f(event)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn step<'a>(&mut self, input: &'a [u8]) -> Result<(usize, Option<Event<'a>>), Error> {
+        match &mut self.state {
+            State::Header(buf) => {
+                let n = fill(buf, input);
+                let Ok(&[r0, r1, t0, t1, c0, c1]) = <&[u8; HEADER_SIZE]>::try_from(&buf[..]) else {
+                    return Ok((n, None));
+                };
+                let count = u16::from_le_bytes([c0, c1]);
+                // Type 1 is an icon, 2 a cursor
+                if [r0, r1] != [0, 0] || !matches!([t0, t1], [1, 0] | [2, 0]) || count == 0 {
+                    return Err(Error::InvalidIco);
+                }
+                self.state = State::Entries {
+                    index: 0,
+                    count,
+                    buf: ArrayVec::new(),
+                };
+                Ok((n, None))
+            }
+            State::Entries { index, count, buf } => {
+                let n = fill(buf, input);
+                let Ok(data) = <&[u8; ENTRY_SIZE]>::try_from(&buf[..]) else {
+                    return Ok((n, None));
+                };
+                let entry = IconEntry::parse(*index, data);
+                if self.selection.prefers(&entry, self.selected.as_ref()) {
+                    self.selected = Some(entry);
+                }
+                buf.clear();
+                *index += 1;
+                if *index == *count {
+                    let directory_end = self.bytes_consumed + n as u64;
+                    match self.selected {
+                        Some(selected)
+                            if selected.size > 0 && selected.offset as u64 >= directory_end =>
+                        {
+                            self.state = State::BeforeImage
+                        }
+                        _ => return Err(Error::InvalidIco),
+                    }
+                }
+                Ok((n, Some(Event::Entry(entry))))
+            }
+            State::BeforeImage => {
+                let selected = self.selected.ok_or(Error::InvalidIco)?;
+                let to_skip = (selected.offset as u64).saturating_sub(self.bytes_consumed);
+                let n = core::cmp::min(input.len() as u64, to_skip) as usize;
+                if n as u64 == to_skip {
+                    self.state = State::Image {
+                        remaining: selected.size,
+                    };
+                    Ok((n, Some(Event::BeginImage(selected))))
+                } else {
+                    Ok((n, None))
+                }
+            }
+            State::Image { remaining } => {
+                let n = core::cmp::min(input.len(), *remaining as usize);
+                *remaining -= n as u32;
+                if *remaining == 0 {
+                    self.state = State::Done;
+                }
+                let data = input.get(..n).unwrap_or_default();
+                Ok((n, (n > 0).then_some(Event::Data(data))))
+            }
+            State::Done => Ok((input.len(), None)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dechunker::{self, ChunkType, Dechunker};
+
+    extern crate std;
+    use std::vec::Vec;
+
+    fn ico(entries: &[(u8, u8, &[u8])]) -> Vec<u8> {
+        let mut file = Vec::from([0, 0, 1, 0, entries.len() as u8, 0]);
+        let mut offset = HEADER_SIZE + ENTRY_SIZE * entries.len();
+        for (width, height, data) in entries {
+            file.extend_from_slice(&[*width, *height, 0, 0, 1, 0, 32, 0]);
+            file.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            file.extend_from_slice(&(offset as u32).to_le_bytes());
+            offset += data.len();
+        }
+        for (_, _, data) in entries {
+            file.extend_from_slice(data);
+        }
+        file
+    }
+
+    /// The selected entry's data, fed in pieces of `piece_len` bytes.
+    fn selected_data(
+        reader: &mut IcoReader,
+        file: &[u8],
+        piece_len: usize,
+    ) -> Result<(Vec<IconEntry>, Vec<u8>), Error> {
+        let (mut entries, mut data) = (Vec::new(), Vec::new());
+        for piece in file.chunks(piece_len) {
+            reader.update_all(piece, |e| {
+                match e {
+                    Event::Entry(entry) => entries.push(entry),
+                    Event::BeginImage(_) => assert!(data.is_empty()),
+                    Event::Data(d) => data.extend_from_slice(d),
+                }
+                Ok::<_, Error>(())
+            })?;
+        }
+        reader.eof()?;
+        Ok((entries, data))
+    }
+
+    #[test]
+    fn selects_largest_entry() {
+        let png = std::fs::read(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/pngdump/pngsuite/basn0g08.png"
+        ))
+        .unwrap();
+        let file = ico(&[(16, 16, b"BM small"), (0, 0, &png), (32, 32, b"BM")]);
+
+        for piece_len in [1, 5, file.len()] {
+            let mut reader = IcoReader::new();
+            let (entries, data) = selected_data(&mut reader, &file, piece_len).unwrap();
+            assert_eq!(entries.len(), 3);
+            assert_eq!((entries[1].width, entries[1].height), (256, 256));
+            assert_eq!(reader.selected(), Some(&entries[1]));
+            assert_eq!(data, png);
+        }
+
+        // The selected data is a PNG datastream
+        let mut reader = IcoReader::new();
+        let mut dechunker = Dechunker::new();
+        let mut types = Vec::new();
+        reader
+            .update_all(&file, |e| {
+                if let Event::Data(data) = e {
+                    dechunker.update_all(data, |e| {
+                        if let dechunker::Event::BeginChunk(h) = e {
+                            types.push(h.type_);
+                        }
+                        Ok::<_, Error>(())
+                    })?;
+                }
+                Ok::<_, Error>(())
+            })
+            .unwrap();
+        dechunker.eof().unwrap();
+        assert_eq!(types.last(), Some(&ChunkType(*b"IEND")));
+    }
+
+    #[test]
+    fn selects_by_index() {
+        let file = ico(&[(16, 16, b"first"), (32, 32, b"second")]);
+        let mut reader = IcoReader::new().with_selection(Selection::Index(0));
+        let (_, data) = selected_data(&mut reader, &file, 3).unwrap();
+        assert_eq!(data, b"first");
+
+        let mut reader = IcoReader::new().with_selection(Selection::Index(2));
+        assert_eq!(selected_data(&mut reader, &file, 3), Err(Error::InvalidIco));
+    }
+
+    #[test]
+    fn invalid() {
+        let mut file = ico(&[(16, 16, b"data")]);
+        // Truncated
+        let mut reader = IcoReader::new();
+        assert_eq!(
+            selected_data(&mut reader, &file[..file.len() - 1], 4),
+            Err(Error::UnfinishedChunk)
+        );
+        // Offset inside the directory
+        file[18] = 4;
+        assert_eq!(
+            selected_data(&mut IcoReader::new(), &file, 4),
+            Err(Error::InvalidIco)
+        );
+        // Not an icon or cursor
+        assert_eq!(
+            selected_data(&mut IcoReader::new(), &[0, 0, 3, 0, 1, 0], 4),
+            Err(Error::InvalidIco)
+        );
+    }
+}
@@ -1,8 +1,68 @@
 #![no_std]
+#![allow(clippy::upper_case_acronyms)]
 
-use heapless::Vec;
+use array_vec::ArrayVec;
+use footprint::MemoryUsage;
+#[cfg(any(feature = "inflate", feature = "pixels"))]
+use uninit_buf::UninitBuf;
+
+/// Trace-level message through the `log` crate and/or `defmt`. Without either feature nothing is
+/// compiled in, but the arguments are still type-checked.
+macro_rules! trace {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "log")]
+        log::trace!($($arg)*);
+        #[cfg(feature = "defmt")]
+        defmt::trace!($($arg)*);
+        #[cfg(not(any(feature = "log", feature = "defmt")))]
+        let _ = format_args!($($arg)*);
+    }};
+}
+
+/// Debug-level message, used for the context of errors. See
+/// [`trace!`].
+macro_rules! debug {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "log")]
+        log::debug!($($arg)*);
+        #[cfg(feature = "defmt")]
+        defmt::debug!($($arg)*);
+        #[cfg(not(any(feature = "log", feature = "defmt")))]
+        let _ = format_args!($($arg)*);
+    }};
+}
+
+/// Warn-level message, for problems which were recovered from. See
+/// [`trace!`].
+macro_rules! warn {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "log")]
+        log::warn!($($arg)*);
+        #[cfg(feature = "defmt")]
+        defmt::warn!($($arg)*);
+        #[cfg(not(any(feature = "log", feature = "defmt")))]
+        let _ = format_args!($($arg)*);
+    }};
+}
+
+/// Logs a value through its `Debug` impl, for types without a `defmt::Format` impl.
+struct DebugFormat<T>(T);
+
+impl<T: core::fmt::Debug> core::fmt::Debug for DebugFormat<T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl<T: core::fmt::Debug> defmt::Format for DebugFormat<T> {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(f, "{}", defmt::Debug2Format(&self.0))
+    }
+}
 
 #[derive(Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum Error {
     InvalidPngSignature,
     UnfinishedChunk,
@@ -12,74 +72,728 @@ pub enum Error {
     ChecksumMismatch,
     InvalidEndChunkSize,
     InvalidPaletteChunkSize,
+    InvalidTransparencyChunkSize,
+    PaletteIndexOutOfRange,
+    ChunkTooLarge,
+    DuplicateChunk(dechunker::ChunkType),
+    /// PLTE chunk in a greyscale image
+    PaletteNotAllowed,
+    UnsupportedCompressionMethod,
+    UnsupportedFilterMethod,
+    /// Adam7 interlacing isn't supported by the pixel stages yet
+    InterlaceNotSupported,
+    InvalidInterlaceMethod,
+    /// Chunk data written to the encoder doesn't match the declared chunk length
+    ChunkLengthMismatch,
+    InvalidColourType,
+    /// Scanline doesn't fit into the buffer of a stage
+    RowTooLarge,
+    /// Row doesn't match the image width, e.g. one passed to the encoder or to
+    /// [`Palette::expand_row`]
+    InvalidRowLength,
+    /// Number of rows written to the encoder or decoded doesn't match the image height
+    RowCountMismatch,
+    /// Indexed-colour image written without a palette
+    MissingPalette,
+    /// Chunk written after a chunk that must follow it
+    ChunkOutOfOrder(dechunker::ChunkType),
+    /// Bit depth not allowed for the colour type
+    InvalidBitDepth,
+    /// tEXt keyword empty, longer than 79 bytes, or text containing a null byte
+    InvalidTextChunk,
+    /// APNG frame outside the image bounds, or first frame not covering the default image
+    InvalidFrameControl,
+    /// Number of APNG frames doesn't match acTL
+    FrameCountMismatch,
+    /// acTL chunk with the wrong length or zero frames
+    InvalidAnimationControl,
+    /// Scanline starts with an unknown filter type
+    InvalidFilterType,
+    /// Event sequence which a [`Dechunker`](dechunker::Dechunker) doesn't produce, e.g. `Data`
+    /// outside of a chunk
+    UnexpectedEvent,
+    /// File ended before the IEND chunk
+    MissingEndChunk,
+    /// Deflate stream of the image data didn't end
+    UnfinishedImageData,
+    /// Chunk extends past the length given to
+    /// [`Dechunker::with_total_len`](dechunker::Dechunker::with_total_len)
+    DatastreamTooLong,
+    /// ICO header or directory is invalid, or the selected entry doesn't exist
+    InvalidIco,
+    /// Extension chunk, see [`extension`], with an invalid length or contents
+    InvalidExtensionChunk(dechunker::ChunkType),
+    /// Size computed from the image dimensions doesn't fit the integer type, e.g. `usize` on
+    /// 32-bit targets
+    ArithmeticOverflow,
+    /// Event data longer than the buffer of an owned event, e.g. a
+    /// [`dechunker::OwnedEvent`]
+    EventTooLarge,
+    /// Deflate stream of the image data ended before the size given by the header, in bytes
+    TruncatedImageData {
+        expected: u64,
+        got: u64,
+    },
+    /// Image doesn't fit the framebuffer of a
+    /// [`FramebufferWriter`](framebuffer::FramebufferWriter)
+    FramebufferTooSmall,
+    /// Data after the end of the deflate stream of the image data
+    TrailingCompressedData,
+    /// Image width or height of zero, or above 2^31-1
+    InvalidImageDimensions,
+    /// PLTE chunk of an indexed-colour image with more entries than the capacity of the
+    /// [`Palette`]
+    PaletteTooLarge,
+    /// The encoder's compressor failed, e.g. because data was passed to the `Deflater` after
+    /// `Input::Finish`
+    CompressionFailed,
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        core::fmt::Debug::fmt(self, f)
+    }
+}
+
+impl core::error::Error for Error {}
+
+/// Problem which decoding carried on after, reported as a `Warning` event, e.g.
+/// [`stream_decoder::Event::Warning`], so that applications can log it.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum Warning {
+    /// Stored CRC of a chunk doesn't match its contents. The chunk was decoded anyway, see
+    /// [`DecoderConfig::crc_warnings`](decoder::DecoderConfig::crc_warnings), or skipped
+    /// as an ancillary chunk with [`Strictness::Lenient`].
+    CrcMismatch {
+        chunk_type: dechunker::ChunkType,
+        stored: u32,
+        computed: u32,
+    },
+    /// Ancillary chunk which isn't registered, neither in the specification nor as an
+    /// extension, skipped. Registered ones which aren't interpreted, e.g. tEXt, are skipped
+    /// silently.
+    UnknownChunk(dechunker::ChunkType),
+    /// Chunk in a place the specification doesn't allow, e.g. PLTE after IDAT, accepted
+    /// anyway
+    ChunkOutOfOrder(dechunker::ChunkType),
+}
+
+impl core::fmt::Display for Warning {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        core::fmt::Debug::fmt(self, f)
+    }
+}
+
+/// The eight bytes every PNG file starts with.
+///
+/// <https://www.w3.org/TR/png-3/#5PNG-file-signature>
+pub const SIGNATURE: [u8; 8] = [0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A];
+
+/// Whether `prefix`, the start of a file, is long enough and starts with the PNG signature.
+pub fn is_png(prefix: &[u8]) -> bool {
+    sniff(prefix) == Sniff::Png
+}
+
+/// Result of [`sniff`].
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum Sniff {
+    /// The file starts with the PNG signature
+    Png,
+    /// The file isn't a PNG
+    NotPng,
+    /// The prefix matches so far, but this many more bytes are needed to tell
+    NeedMore(usize),
+}
+
+/// Checks the start of a file against the PNG signature, e.g. to pick a decoder in a loader for
+/// several formats. Any bytes after the signature are ignored.
+pub fn sniff(prefix: &[u8]) -> Sniff {
+    let n = core::cmp::min(prefix.len(), SIGNATURE.len());
+    match (prefix.get(..n), SIGNATURE.get(..n)) {
+        (Some(a), Some(b)) if a != b => Sniff::NotPng,
+        _ if n < SIGNATURE.len() => Sniff::NeedMore(SIGNATURE.len() - n),
+        _ => Sniff::Png,
+    }
+}
+
+/// Resource limits applied while decoding untrusted input.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub struct Limits {
+    /// Largest accepted chunk length. Lengths above 2^31-1 are always rejected, as required by
+    /// the spec.
+    pub max_chunk_len: u32,
 }
 
-pub struct Palette {
-    data: Vec<u8, { 256 * 3 }>,
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_chunk_len: dechunker::MAX_CHUNK_LEN,
+        }
+    }
+}
+
+/// How to treat spec violations which can be recovered from.
+#[derive(Clone, Copy, Default, Eq, PartialEq, Debug)]
+pub enum Strictness {
+    /// Report them as errors.
+    #[default]
+    Strict,
+    /// Recover, e.g. render out-of-range palette indices as black, or skip ancillary chunks
+    /// whose CRC doesn't match.
+    Lenient,
 }
 
-impl Palette {
+/// Colour palette from the PLTE chunk, holding at most `CAPACITY` entries.
+///
+/// Indexed-colour images with more entries than that fail with [`Error::PaletteTooLarge`]. The
+/// suggested palette of other images is cut short instead, so they can be decoded with
+/// `CAPACITY = 0` to save RAM.
+#[cfg(feature = "palette")]
+#[derive(Clone)]
+pub struct Palette<const CAPACITY: usize = 256> {
+    entries: ArrayVec<[u8; 3], CAPACITY>,
+    /// Per-entry alpha from the tRNS chunk. Entries past its end are opaque.
+    alpha: ArrayVec<u8, CAPACITY>,
+}
+
+#[cfg(feature = "palette")]
+impl<const CAPACITY: usize> Palette<CAPACITY> {
+    fn new() -> Self {
+        Self {
+            entries: ArrayVec::new(),
+            alpha: ArrayVec::new(),
+        }
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.alpha.clear();
+    }
+
+    /// Returns the colour of the given entry combined with its alpha from tRNS, or transparent
+    /// black if the index is out of range.
+    pub fn color_at_rgba(&self, index: u8) -> [u8; 4] {
+        match self.get(index) {
+            Some([r, g, b]) => {
+                let a = self.alpha.get(index as usize).copied().unwrap_or(0xff);
+                [r, g, b, a]
+            }
+            None => [0; 4],
+        }
+    }
+
+    /// Returns the colour of the given entry, or black if the index is out of range.
     pub fn color_at(&self, index: u8) -> [u8; 3] {
-        if (index as usize + 1) * 3 > self.data.len() {
-            [0; 3]
-        } else {
-            self.data[index as usize * 3..(index as usize + 1) * 3]
-                .try_into()
-                .unwrap()
+        self.get(index).unwrap_or([0; 3])
+    }
+
+    /// Returns the colour of the given entry, or `None` if the index is out of range.
+    pub fn get(&self, index: u8) -> Option<[u8; 3]> {
+        self.entries.get(index as usize).copied()
+    }
+
+    /// Expands a row of packed palette indices (`bit_depth` of 1, 2, 4 or 8) into RGB colours,
+    /// one for each element of `out`.
+    ///
+    /// Out-of-range indices are an error in [`Strictness::Strict`] mode, and black otherwise.
+    /// Fails with [`Error::InvalidBitDepth`] for other bit depths, and with
+    /// [`Error::InvalidRowLength`] if `row` is shorter than `out.len()` packed indices.
+    pub fn expand_row(
+        &self,
+        row: &[u8],
+        bit_depth: u8,
+        out: &mut [[u8; 3]],
+        strictness: Strictness,
+    ) -> Result<(), Error> {
+        let mask: u8 = match bit_depth {
+            1 => 0x01,
+            2 => 0x03,
+            4 => 0x0f,
+            8 => 0xff,
+            _ => return Err(Error::InvalidBitDepth),
+        };
+        let bit_depth = bit_depth as usize;
+        for (i, pixel) in out.iter_mut().enumerate() {
+            let bit = i.checked_mul(bit_depth).ok_or(Error::ArithmeticOverflow)?;
+            // Can't underflow, `bit % 8` is a multiple of `bit_depth`
+            let shift = 8 - bit_depth - bit % 8;
+            let byte = row.get(bit / 8).ok_or(Error::InvalidRowLength)?;
+            let index = (byte >> shift) & mask;
+            *pixel = match (self.get(index), strictness) {
+                (Some(colour), _) => colour,
+                (None, Strictness::Strict) => return Err(Error::PaletteIndexOutOfRange),
+                (None, Strictness::Lenient) => [0; 3],
+            };
+        }
+        Ok(())
+    }
+
+    /// Number of stored entries.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = [u8; 3]> + '_ {
+        self.entries.iter().copied()
+    }
+
+    /// Entries as consecutive RGB triplets.
+    pub fn as_rgb_slice(&self) -> &[u8] {
+        self.entries.as_flattened()
+    }
+}
+
+#[cfg(feature = "image")]
+mod dynamic_image;
+#[cfg(feature = "embedded-graphics")]
+pub mod embedded_graphics;
+pub mod encoder;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "ico")]
+pub mod ico;
+#[cfg(all(feature = "alloc", feature = "pixels"))]
+pub mod random_access;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "image")]
+pub use dynamic_image::to_dynamic_image;
+
+mod array_vec {
+    //! Fixed-capacity vector for the small buffers of the decoder stages: a plain array and a
+    //! length.
+
+    /// Vector of at most `N` elements, stored inline. Unused slots hold `T::default()`.
+    #[derive(Clone, Copy)]
+    pub(crate) struct ArrayVec<T, const N: usize> {
+        buf: [T; N],
+        len: usize,
+    }
+
+    impl<T: Copy + Default, const N: usize> ArrayVec<T, N> {
+        pub(crate) fn new() -> Self {
+            Self {
+                buf: [T::default(); N],
+                len: 0,
+            }
+        }
+
+        pub(crate) fn capacity(&self) -> usize {
+            N
+        }
+
+        pub(crate) fn is_full(&self) -> bool {
+            self.len == N
+        }
+
+        pub(crate) fn clear(&mut self) {
+            self.len = 0;
+        }
+
+        /// Appends `value`, or returns it if the vector is full.
+        pub(crate) fn push(&mut self, value: T) -> Result<(), T> {
+            match self.buf.get_mut(self.len) {
+                Some(slot) => {
+                    *slot = value;
+                    self.len += 1;
+                    Ok(())
+                }
+                None => Err(value),
+            }
+        }
+
+        /// Appends all of `values`, or nothing if they don't fit.
+        pub(crate) fn extend_from_slice(&mut self, values: &[T]) -> Result<(), ()> {
+            let end = self.len + values.len();
+            let dst = self.buf.get_mut(self.len..end).ok_or(())?;
+            dst.copy_from_slice(values);
+            self.len = end;
+            Ok(())
+        }
+    }
+
+    impl<T, const N: usize> core::ops::Deref for ArrayVec<T, N> {
+        type Target = [T];
+
+        fn deref(&self) -> &[T] {
+            self.buf.get(..self.len).unwrap_or_default()
+        }
+    }
+
+    impl<T, const N: usize> core::ops::DerefMut for ArrayVec<T, N> {
+        fn deref_mut(&mut self) -> &mut [T] {
+            self.buf.get_mut(..self.len).unwrap_or_default()
+        }
+    }
+
+    impl<T: PartialEq, const N: usize> PartialEq for ArrayVec<T, N> {
+        fn eq(&self, other: &Self) -> bool {
+            **self == **other
+        }
+    }
+
+    impl<T: Eq, const N: usize> Eq for ArrayVec<T, N> {}
+
+    impl<T: core::fmt::Debug, const N: usize> core::fmt::Debug for ArrayVec<T, N> {
+        fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+            (**self).fmt(f)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn push_and_extend() {
+            let mut v = ArrayVec::<u8, 4>::new();
+            assert!(v.is_empty());
+            v.push(1).unwrap();
+            v.extend_from_slice(&[2, 3]).unwrap();
+            assert_eq!(*v, [1, 2, 3]);
+            assert_eq!(v.extend_from_slice(&[4, 5]), Err(()));
+            assert_eq!(*v, [1, 2, 3]);
+            v.push(4).unwrap();
+            assert!(v.is_full());
+            assert_eq!(v.push(5), Err(5));
+
+            // Stale elements don't matter for equality
+            let mut w = ArrayVec::<u8, 4>::new();
+            w.extend_from_slice(&[9, 9]).unwrap();
+            w.clear();
+            v.clear();
+            assert_eq!(v, w);
+        }
+    }
+}
+
+#[cfg(any(feature = "inflate", feature = "pixels"))]
+mod uninit_buf {
+    //! Byte buffer which is zeroed lazily, only as far as it's used, so that creating a stage
+    //! with a large buffer doesn't cost a `memset` of the whole buffer.
+
+    use core::mem::MaybeUninit;
+
+    #[derive(Clone)]
+    pub(crate) struct UninitBuf<const N: usize> {
+        buf: [MaybeUninit<u8>; N],
+        /// Length of the prefix of `buf` which has been written
+        init: usize,
+    }
+
+    impl<const N: usize> UninitBuf<N> {
+        pub(crate) const fn new() -> Self {
+            Self {
+                buf: [MaybeUninit::uninit(); N],
+                init: 0,
+            }
+        }
+
+        /// The first `len` bytes (at most `N`), zeroing the part which wasn't initialized yet.
+        pub(crate) fn initialized_mut(&mut self, len: usize) -> &mut [u8] {
+            let len = core::cmp::min(len, N);
+            if let Some(uninit) = self.buf.get_mut(self.init..len) {
+                for b in uninit {
+                    b.write(0);
+                }
+                self.init = len;
+            }
+            let init = self.buf.get_mut(..self.init).unwrap_or_default();
+            // SAFETY: the first `self.init` bytes have been written, and `MaybeUninit<u8>` has
+            // the same layout as `u8`
+            let init = unsafe { &mut *(init as *mut [MaybeUninit<u8>] as *mut [u8]) };
+            init.get_mut(..len).unwrap_or_default()
+        }
+
+        /// Number of bytes used so far, the most ever passed to `initialized_mut`.
+        pub(crate) fn initialized_len(&self) -> usize {
+            self.init
+        }
+
+        /// The first `len` bytes, or fewer if they weren't initialized yet.
+        pub(crate) fn initialized(&self, len: usize) -> &[u8] {
+            let len = core::cmp::min(len, self.init);
+            let init = self.buf.get(..len).unwrap_or_default();
+            // SAFETY: as above
+            unsafe { &*(init as *const [MaybeUninit<u8>] as *const [u8]) }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn zeroes_lazily() {
+            let mut b = UninitBuf::<8>::new();
+            assert_eq!(b.initialized(4), []);
+            b.initialized_mut(3).copy_from_slice(&[1, 2, 3]);
+            assert_eq!(b.initialized(8), [1, 2, 3]);
+            assert_eq!(b.initialized_mut(5), [1, 2, 3, 0, 0]);
+            assert_eq!(b.initialized_mut(2), [1, 2]);
+            assert_eq!(b.initialized_mut(100).len(), 8);
+            assert_eq!(b.initialized(100), [1, 2, 3, 0, 0, 0, 0, 0]);
         }
     }
 }
 
+/// Appends as much of `input` as fits into `buf`. Returns the number of bytes appended.
+fn fill<const N: usize>(buf: &mut ArrayVec<u8, N>, input: &[u8]) -> usize {
+    let input = input.get(..N - buf.len()).unwrap_or(input);
+    // Can't fail, `input` fits
+    let _ = buf.extend_from_slice(input);
+    input.len()
+}
+
+/// Copies the data of an event for an `OwnedEvent`, e.g. [`dechunker::OwnedEvent`].
+#[cfg(feature = "heapless")]
+fn owned<const N: usize>(data: &[u8]) -> Result<heapless::Vec<u8, N>, Error> {
+    heapless::Vec::from_slice(data).map_err(|_| Error::EventTooLarge)
+}
+
 pub mod dechunker {
     use super::*;
 
+    #[derive(Clone, Debug)]
     pub struct Dechunker {
         state: State,
+        bytes_consumed: u64,
+        crc_events: bool,
+        limits: Limits,
+        /// Whether the datastream starts with the PNG signature
+        signature: bool,
+        /// Length of the datastream, if known
+        total_len: Option<u64>,
+        /// Whether invalid chunk headers start [`State::Resync`] rather than failing
+        resync: bool,
     }
 
+    /// <https://www.w3.org/TR/png-3/#5Chunk-layout>
+    pub const MAX_CHUNK_LEN: u32 = (1 << 31) - 1;
+
     const CHUNK_HEADER_SIZE: usize = 8;
     const CRC_SIZE: usize = 4;
 
     #[derive(Clone, PartialEq, Eq, Debug)]
     enum State {
-        PngSignature { pos: usize },
-        ChunkHeader(Vec<u8, CHUNK_HEADER_SIZE>),
-        InChunk { remaining: usize },
-        CRC(Vec<u8, CRC_SIZE>),
+        PngSignature {
+            pos: usize,
+        },
+        ChunkHeader(ArrayVec<u8, CHUNK_HEADER_SIZE>),
+        InChunk {
+            type_: ChunkType,
+            remaining: usize,
+        },
+        CRC {
+            type_: ChunkType,
+            buf: ArrayVec<u8, CRC_SIZE>,
+            /// Whether `Event::Crc` was already emitted for this chunk
+            reported: bool,
+        },
+        /// Scanning for a plausible chunk header after corruption
+        Resync {
+            /// Candidate header, a byte at a time
+            window: ArrayVec<u8, CHUNK_HEADER_SIZE>,
+            skipped: u64,
+        },
     }
 
-    #[derive(Eq, PartialEq, Debug)]
+    #[derive(Clone, Eq, PartialEq, Debug)]
+    #[cfg_attr(feature = "defmt", derive(defmt::Format))]
     pub struct ChunkHeader {
         pub len: u32,
         pub type_: ChunkType,
     }
 
-    pub type ChunkType = [u8; 4];
+    /// <https://www.w3.org/TR/png-3/#5Chunk-naming-conventions>
+    #[derive(Clone, Copy, Eq, PartialEq, Hash)]
+    pub struct ChunkType(pub [u8; 4]);
+
+    impl ChunkType {
+        const PROPERTY_BIT: u8 = 0x20;
+
+        pub fn is_critical(&self) -> bool {
+            !self.is_ancillary()
+        }
+
+        pub fn is_ancillary(&self) -> bool {
+            self.0[0] & Self::PROPERTY_BIT != 0
+        }
+
+        pub fn is_private(&self) -> bool {
+            self.0[1] & Self::PROPERTY_BIT != 0
+        }
+
+        pub fn is_safe_to_copy(&self) -> bool {
+            self.0[3] & Self::PROPERTY_BIT != 0
+        }
+
+        /// Whether all four bytes are ASCII letters, as the specification requires.
+        pub fn is_valid(&self) -> bool {
+            self.0.iter().all(u8::is_ascii_alphabetic)
+        }
+    }
+
+    #[cfg(feature = "defmt")]
+    impl defmt::Format for ChunkType {
+        fn format(&self, f: defmt::Formatter) {
+            defmt::write!(f, "{=[u8]:a}", self.0)
+        }
+    }
+
+    impl core::fmt::Display for ChunkType {
+        fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+            for &c in &self.0 {
+                if c.is_ascii_graphic() {
+                    write!(f, "{}", c as char)?;
+                } else {
+                    write!(f, "\\x{:02x}", c)?;
+                }
+            }
+            Ok(())
+        }
+    }
+
+    impl core::fmt::Debug for ChunkType {
+        fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+            write!(f, "ChunkType({})", self)
+        }
+    }
 
-    #[derive(Eq, PartialEq, Debug)]
+    #[derive(Clone, Eq, PartialEq, Debug)]
     pub enum Event<'a> {
         BeginChunk(ChunkHeader),
         Data(&'a [u8]),
+        /// CRC stored in the chunk, emitted right before `EndChunk` if enabled with
+        /// [`Dechunker::with_crc_events`]. It isn't verified.
+        Crc(u32),
+        EndChunk,
+        /// A plausible chunk header was found after corruption, see
+        /// [`Dechunker::with_resync`], with the number of bytes skipped. Its `BeginChunk`
+        /// follows. The chunk being read when the corruption was found, if any, gets no
+        /// `EndChunk`.
+        Resync(u64),
+    }
+
+    /// [`Event`] with the data copied into a buffer of `N` bytes, so that it can be queued,
+    /// e.g. from an interrupt handler to the task running the later stages. Convert with
+    /// `OwnedEvent::try_from(event)` and back with `Event::from(&owned)`.
+    #[cfg(feature = "heapless")]
+    #[derive(Clone, Eq, PartialEq, Debug)]
+    pub enum OwnedEvent<const N: usize> {
+        BeginChunk(ChunkHeader),
+        Data(heapless::Vec<u8, N>),
+        Crc(u32),
         EndChunk,
+        Resync(u64),
+    }
+
+    #[cfg(feature = "heapless")]
+    impl<const N: usize> TryFrom<Event<'_>> for OwnedEvent<N> {
+        type Error = Error;
+
+        /// Fails with [`Error::EventTooLarge`] if the data is longer than `N` bytes.
+        fn try_from(event: Event<'_>) -> Result<Self, Error> {
+            Ok(match event {
+                Event::BeginChunk(header) => OwnedEvent::BeginChunk(header),
+                Event::Data(data) => OwnedEvent::Data(owned(data)?),
+                Event::Crc(crc) => OwnedEvent::Crc(crc),
+                Event::EndChunk => OwnedEvent::EndChunk,
+                Event::Resync(skipped) => OwnedEvent::Resync(skipped),
+            })
+        }
+    }
+
+    #[cfg(feature = "heapless")]
+    impl<'a, const N: usize> From<&'a OwnedEvent<N>> for Event<'a> {
+        fn from(event: &'a OwnedEvent<N>) -> Self {
+            match event {
+                OwnedEvent::BeginChunk(header) => Event::BeginChunk(header.clone()),
+                OwnedEvent::Data(data) => Event::Data(data),
+                OwnedEvent::Crc(crc) => Event::Crc(*crc),
+                OwnedEvent::EndChunk => Event::EndChunk,
+                OwnedEvent::Resync(skipped) => Event::Resync(*skipped),
+            }
+        }
     }
 
-    /// <https://www.w3.org/TR/png-3/#5PNG-file-signature>
-    const PNG_SIGNATURE: &[u8; 8] = &[0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A];
+    pub(crate) const PNG_SIGNATURE: &[u8; 8] = &crate::SIGNATURE;
+
+    impl Default for Dechunker {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
 
     impl Dechunker {
+        /// Size of a `Dechunker` in bytes. It doesn't use any other memory.
+        pub const MEMORY_FOOTPRINT: usize = core::mem::size_of::<Self>();
+
         pub fn new() -> Self {
             Self {
                 state: State::PngSignature { pos: 0 },
+                bytes_consumed: 0,
+                crc_events: false,
+                limits: Limits::default(),
+                signature: true,
+                total_len: None,
+                resync: false,
             }
         }
 
-        #[cfg(test)]
-        fn new_without_png_signature() -> Self {
+        /// For datastreams embedded in a container which doesn't store the PNG signature, or
+        /// has checked it already: the input starts with the first chunk.
+        pub fn new_without_png_signature() -> Self {
             Self {
-                state: State::ChunkHeader(Vec::new()),
+                state: State::ChunkHeader(ArrayVec::new()),
+                signature: false,
+                ..Self::new()
             }
         }
 
+        /// Rejects chunks longer than `limits.max_chunk_len` with [`Error::ChunkTooLarge`].
+        pub fn with_limits(mut self, limits: Limits) -> Self {
+            self.limits = limits;
+            self
+        }
+
+        /// Rejects chunks extending past the first `total_len` bytes of input (including the
+        /// signature, if any) with [`Error::DatastreamTooLong`], for datastreams of known length
+        /// embedded in a container.
+        pub fn with_total_len(mut self, total_len: u64) -> Self {
+            self.total_len = Some(total_len);
+            self
+        }
+
+        /// Makes the dechunker emit [`Event::Crc`] for every chunk.
+        pub fn with_crc_events(mut self) -> Self {
+            self.crc_events = true;
+            self
+        }
+
+        /// Recovers from corruption, e.g. on lossy transports: a chunk header with a type which
+        /// isn't four letters, or a length rejected by the limits, makes the dechunker scan
+        /// forward for the next plausible header instead of failing. Scanning ends with
+        /// [`Event::Resync`].
+        pub fn with_resync(mut self) -> Self {
+            self.resync = true;
+            self
+        }
+
+        /// Abandons the current chunk and scans for the next plausible chunk header, as
+        /// [`with_resync`](Self::with_resync) does for invalid headers. For corruption found
+        /// by the later stages, e.g. a CRC mismatch.
+        pub fn resync(&mut self) {
+            self.state = State::Resync {
+                window: ArrayVec::new(),
+                skipped: 0,
+            };
+        }
+
         pub fn eof(&self) -> Result<(), Error> {
             match &self.state {
                 State::ChunkHeader(header) if header.is_empty() => Ok(()),
@@ -87,41 +801,157 @@ pub mod dechunker {
             }
         }
 
+        /// Abandons the current datastream, so that the next `update` starts a new one
+        /// (beginning with the PNG signature).
+        pub fn abort(&mut self) {
+            self.state = if self.signature {
+                State::PngSignature { pos: 0 }
+            } else {
+                State::ChunkHeader(ArrayVec::new())
+            };
+            self.bytes_consumed = 0;
+        }
+
+        /// Total number of input bytes consumed so far.
+        pub fn bytes_consumed(&self) -> u64 {
+            self.bytes_consumed
+        }
+
+        /// The dechunker has no buffers beyond its fixed state.
+        pub fn memory_usage(&self) -> MemoryUsage {
+            MemoryUsage {
+                static_bytes: Self::MEMORY_FOOTPRINT,
+                peak_dynamic: 0,
+            }
+        }
+
+        /// What the dechunker is reading, for debugging.
+        pub fn state_name(&self) -> &'static str {
+            match self.state {
+                State::PngSignature { .. } => "PNG signature",
+                State::ChunkHeader(_) => "chunk header",
+                State::InChunk { .. } => "chunk data",
+                State::CRC { .. } => "CRC",
+                State::Resync { .. } => "resync",
+            }
+        }
+
+        /// Type of the chunk being read, from the end of its header until its CRC is complete.
+        pub fn current_chunk_type(&self) -> Option<ChunkType> {
+            match self.state {
+                State::InChunk { type_, .. } | State::CRC { type_, .. } => Some(type_),
+                _ => None,
+            }
+        }
+
+        /// Bytes of chunk data still expected, while reading chunk data.
+        pub fn remaining_in_chunk(&self) -> Option<usize> {
+            match self.state {
+                State::InChunk { remaining, .. } => Some(remaining),
+                _ => None,
+            }
+        }
+
         pub fn update<'a>(&mut self, input: &'a [u8]) -> Result<(usize, Option<Event<'a>>), Error> {
+            let (n, event) = self.step(input).inspect_err(|e| {
+                debug!("dechunker: {} at offset {}", e, self.bytes_consumed);
+            })?;
+            self.bytes_consumed += n as u64;
+            match &event {
+                Some(Event::BeginChunk(h)) => trace!(
+                    "dechunker: {} chunk of {} bytes, data at offset {}",
+                    h.type_,
+                    h.len,
+                    self.bytes_consumed
+                ),
+                Some(Event::EndChunk) => {
+                    trace!("dechunker: end of chunk at offset {}", self.bytes_consumed)
+                }
+                Some(Event::Resync(skipped)) => warn!(
+                    "dechunker: skipped {} bytes of corrupt data before offset {}",
+                    skipped, self.bytes_consumed
+                ),
+                _ => {}
+            }
+            Ok((n, event))
+        }
+
+        /// Consumes all of `input`, calling `f` with each event, rather than returning after
+        /// every event like [`update`](Self::update).
+        pub fn update_all<'a, E: From<Error>>(
+            &mut self,
+            mut input: &'a [u8],
+            mut f: impl FnMut(Event<'a>) -> Result<(), E>,
+        ) -> Result<(), E> {
+            while !input.is_empty() {
+                let (n, event) = self.update(input)?;
+                input = input.get(n..).unwrap_or_default();
+                if let Some(event) = event {
+                    f(event)?;
+                }
+            }
+            Ok(())
+        }
+
+        fn step<'a>(&mut self, input: &'a [u8]) -> Result<(usize, Option<Event<'a>>), Error> {
             match &mut self.state {
                 State::PngSignature { pos } => {
                     let n = core::cmp::min(input.len(), PNG_SIGNATURE.len() - *pos);
-                    if &input[..n] != &PNG_SIGNATURE[*pos..*pos + n] {
+                    if PNG_SIGNATURE.get(*pos..*pos + n) != Some(&input[..n]) {
                         return Err(Error::InvalidPngSignature);
                     }
                     *pos += n;
                     if *pos == PNG_SIGNATURE.len() {
-                        self.state = State::ChunkHeader(Vec::new());
+                        self.state = State::ChunkHeader(ArrayVec::new());
                     }
                     Ok((n, None))
                 }
                 State::ChunkHeader(buf) => {
-                    let n = core::cmp::min(input.len(), buf.capacity() - buf.len());
-                    buf.extend_from_slice(&input[..n]).unwrap();
-                    if buf.is_full() {
-                        let header = ChunkHeader {
-                            len: u32::from_be_bytes(buf[0..4].try_into().unwrap()),
-                            type_: buf[4..8].try_into().unwrap(),
-                        };
-                        self.state = State::InChunk {
-                            remaining: header.len as usize,
-                        };
-                        Ok((n, Some(Event::BeginChunk(header))))
-                    } else {
-                        Ok((n, None))
+                    let (n, [l0, l1, l2, l3, t0, t1, t2, t3]) = match input.first_chunk() {
+                        // The whole header is in the input, no need to buffer it
+                        Some(&header) if buf.is_empty() => (CHUNK_HEADER_SIZE, header),
+                        _ => {
+                            let n = fill(buf, input);
+                            let Ok(&header) = <&[u8; CHUNK_HEADER_SIZE]>::try_from(&buf[..]) else {
+                                return Ok((n, None));
+                            };
+                            (n, header)
+                        }
+                    };
+                    let header = ChunkHeader {
+                        len: u32::from_be_bytes([l0, l1, l2, l3]),
+                        type_: ChunkType([t0, t1, t2, t3]),
+                    };
+                    let data_start = self.bytes_consumed + n as u64;
+                    let checked = self.check_header(&header, data_start);
+                    if self.resync && (checked.is_err() || !header.type_.is_valid()) {
+                        warn!(
+                            "dechunker: invalid header {:?} at offset {}, resynchronizing",
+                            header, self.bytes_consumed
+                        );
+                        let mut window = ArrayVec::new();
+                        let _ = window.extend_from_slice(&[l0, l1, l2, l3, t0, t1, t2, t3]);
+                        self.state = State::Resync { window, skipped: 0 };
+                        return Ok((n, None));
                     }
+                    checked?;
+                    self.state = State::InChunk {
+                        type_: header.type_,
+                        remaining: header.len as usize,
+                    };
+                    Ok((n, Some(Event::BeginChunk(header))))
                 }
-                State::InChunk { remaining } => {
-                    let n = core::cmp::min(input.len(), *remaining);
+                State::InChunk { type_, remaining } => {
+                    let (type_, n) = (*type_, core::cmp::min(input.len(), *remaining));
                     self.state = if *remaining == n {
-                        State::CRC(Vec::new())
+                        State::CRC {
+                            type_,
+                            buf: ArrayVec::new(),
+                            reported: false,
+                        }
                     } else {
                         State::InChunk {
+                            type_,
                             remaining: *remaining - n,
                         }
                     };
@@ -134,24 +964,106 @@ pub mod dechunker {
                         },
                     ))
                 }
-                State::CRC(buf) => {
-                    let n = core::cmp::min(input.len(), buf.capacity() - buf.len());
-                    buf.extend_from_slice(&input[..n]).unwrap();
+                State::CRC { buf, reported, .. } => {
+                    if let (true, Some(&crc)) = (buf.is_empty(), input.first_chunk::<CRC_SIZE>()) {
+                        // The whole CRC is in the input. If it's reported, that's done without
+                        // consuming anything, so that it's still there to produce EndChunk.
+                        if self.crc_events && !*reported {
+                            *reported = true;
+                            return Ok((0, Some(Event::Crc(u32::from_be_bytes(crc)))));
+                        }
+                        self.state = State::ChunkHeader(ArrayVec::new());
+                        return Ok((CRC_SIZE, Some(Event::EndChunk)));
+                    }
+                    let needed = buf.capacity() - buf.len();
+                    let crc_bytes = input.get(..needed).and_then(<[u8]>::split_last);
+                    if let (true, false, Some((last, rest))) =
+                        (self.crc_events, *reported, crc_bytes)
+                    {
+                        // Emit the CRC one byte early, so that the final byte is still there
+                        // to produce EndChunk on the next call.
+                        let crc = (buf.iter().chain(rest).chain([last]))
+                            .fold(0, |crc, &b| (crc << 8) | b as u32);
+                        let n = fill(buf, rest);
+                        *reported = true;
+                        return Ok((n, Some(Event::Crc(crc))));
+                    }
+                    let n = fill(buf, input);
                     if buf.is_full() {
                         // Ignoring CRC for now
-                        self.state = State::ChunkHeader(Vec::new());
+                        self.state = State::ChunkHeader(ArrayVec::new());
                         Ok((n, Some(Event::EndChunk)))
                     } else {
                         Ok((n, None))
                     }
                 }
+                State::Resync { window, skipped } => {
+                    let (mut window, skipped) = (*window, *skipped);
+                    let n = fill(&mut window, input);
+                    let Ok(&[l0, l1, l2, l3, t0, t1, t2, t3]) =
+                        <&[u8; CHUNK_HEADER_SIZE]>::try_from(&window[..])
+                    else {
+                        return Ok((n, None));
+                    };
+                    let header = ChunkHeader {
+                        len: u32::from_be_bytes([l0, l1, l2, l3]),
+                        type_: ChunkType([t0, t1, t2, t3]),
+                    };
+                    let data_start = self.bytes_consumed + n as u64;
+                    if header.type_.is_valid() && self.check_header(&header, data_start).is_ok() {
+                        // The header is parsed again, producing BeginChunk without consuming
+                        // anything
+                        self.state = State::ChunkHeader(window);
+                        return Ok((n, Some(Event::Resync(skipped))));
+                    }
+                    let mut next = ArrayVec::new();
+                    let _ = next.extend_from_slice(window.get(1..).unwrap_or_default());
+                    self.state = State::Resync {
+                        window: next,
+                        skipped: skipped + 1,
+                    };
+                    Ok((n, None))
+                }
+            }
+        }
+
+        /// Fails if the header is rejected by the limits. `data_start` is the offset of the
+        /// chunk data.
+        fn check_header(&self, header: &ChunkHeader, data_start: u64) -> Result<(), Error> {
+            if header.len > MAX_CHUNK_LEN || header.len > self.limits.max_chunk_len {
+                return Err(Error::ChunkTooLarge);
             }
+            let chunk_end = data_start + header.len as u64 + CRC_SIZE as u64;
+            if self
+                .total_len
+                .is_some_and(|total_len| chunk_end > total_len)
+            {
+                return Err(Error::DatastreamTooLong);
+            }
+            Ok(())
         }
     }
 
     #[cfg(test)]
     mod tests {
         use super::*;
+        use heapless::Vec;
+
+        #[test]
+        fn sniff_signature() {
+            use crate::{is_png, sniff, Sniff};
+
+            assert_eq!(sniff(&[]), Sniff::NeedMore(8));
+            assert_eq!(sniff(&PNG_SIGNATURE[..3]), Sniff::NeedMore(5));
+            assert_eq!(sniff(b"GIF89a"), Sniff::NotPng);
+            assert_eq!(sniff(&[0x89, b'P', b'N', b'X']), Sniff::NotPng);
+            assert_eq!(
+                sniff(&[PNG_SIGNATURE.as_slice(), b"\0\0\0\x0dIHDR"].concat()),
+                Sniff::Png
+            );
+            assert!(is_png(PNG_SIGNATURE));
+            assert!(!is_png(&PNG_SIGNATURE[..7]));
+        }
 
         #[test]
         fn png_signature_and_chunk_header() {
@@ -171,7 +1083,7 @@ pub mod dechunker {
                 event,
                 Some(Event::BeginChunk(ChunkHeader {
                     len: 13,
-                    type_: *b"IHDR"
+                    type_: ChunkType(*b"IHDR")
                 }))
             );
             data = &data[n..];
@@ -201,7 +1113,7 @@ pub mod dechunker {
                 event,
                 Some(Event::BeginChunk(ChunkHeader {
                     len: 13,
-                    type_: *b"IHDR"
+                    type_: ChunkType(*b"IHDR")
                 }))
             );
             data = &data[n..];
@@ -232,7 +1144,7 @@ pub mod dechunker {
                 event,
                 Some(Event::BeginChunk(ChunkHeader {
                     len: 5,
-                    type_: *b"IDAT"
+                    type_: ChunkType(*b"IDAT")
                 }))
             );
             data = &data[n..];
@@ -263,7 +1175,7 @@ pub mod dechunker {
                 event,
                 Some(Event::BeginChunk(ChunkHeader {
                     len: 0,
-                    type_: *b"IEND"
+                    type_: ChunkType(*b"IEND")
                 }))
             );
             data = &data[n..];
@@ -282,10 +1194,321 @@ pub mod dechunker {
         }
 
         #[test]
-        fn partial_chunk_header() {
-            let mut d = Dechunker::new_without_png_signature();
-            let mut data: &[u8] = &[
-                0, 0, 0, 5, // len
+        fn update_all() {
+            let mut d = Dechunker::new();
+            let data: &[u8] = &[
+                0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A, // signature
+                0, 0, 0, 2, // len
+                b'I', b'D', b'A', b'T', // type
+                b'h', b'i', // data
+                0, 0, 0, 0, // crc (ignored)
+                0, 0, 0, 0, // len
+                b'I', b'E', b'N', b'D', // type
+                0, 0, 0, 0, // crc (ignored)
+            ];
+
+            let mut events = Vec::<Event, 8>::new();
+            d.update_all(data, |e| {
+                events.push(e).unwrap();
+                Ok::<_, Error>(())
+            })
+            .unwrap();
+
+            assert_eq!(
+                events,
+                [
+                    Event::BeginChunk(ChunkHeader {
+                        len: 2,
+                        type_: ChunkType(*b"IDAT")
+                    }),
+                    Event::Data(b"hi"),
+                    Event::EndChunk,
+                    Event::BeginChunk(ChunkHeader {
+                        len: 0,
+                        type_: ChunkType(*b"IEND")
+                    }),
+                    Event::EndChunk,
+                ]
+            );
+            d.eof().unwrap();
+        }
+
+        #[test]
+        fn resync() {
+            extern crate std;
+            use std::vec::Vec;
+
+            let data: &[u8] = &[
+                0, 0, 0, 2, // len
+                b'I', b'D', b'A', b'T', // type
+                b'h', b'i', // data
+                0, 0, 0, 0, // crc (ignored)
+                0xff, 0xff, 0xff, 0xff, b'x', b'y', // garbage
+                0, 0, 0, 0, // len
+                b'I', b'E', b'N', b'D', // type
+                0, 0, 0, 0, // crc (ignored)
+            ];
+            let end_header = Event::BeginChunk(ChunkHeader {
+                len: 0,
+                type_: ChunkType(*b"IEND"),
+            });
+
+            assert_eq!(
+                Dechunker::new_without_png_signature().update_all(data, |_| Ok::<_, Error>(())),
+                Err(Error::ChunkTooLarge)
+            );
+
+            for piece_len in [1, 5, data.len()] {
+                let mut d = Dechunker::new_without_png_signature().with_resync();
+                let mut events = Vec::new();
+                for piece in data.chunks(piece_len) {
+                    d.update_all(piece, |e| {
+                        if !matches!(e, Event::Data(_)) {
+                            events.push(e);
+                        }
+                        Ok::<_, Error>(())
+                    })
+                    .unwrap();
+                }
+                d.eof().unwrap();
+                assert_eq!(
+                    events[1..],
+                    [
+                        Event::EndChunk,
+                        Event::Resync(6),
+                        end_header.clone(),
+                        Event::EndChunk
+                    ]
+                );
+            }
+
+            // Abandoning a chunk in the middle
+            let mut d = Dechunker::new_without_png_signature();
+            let (n, _) = d.update(data).unwrap();
+            d.resync();
+            let mut events = Vec::new();
+            d.update_all(&data[n + 1..], |e| {
+                events.push(e);
+                Ok::<_, Error>(())
+            })
+            .unwrap();
+            assert_eq!(events, [Event::Resync(11), end_header, Event::EndChunk]);
+        }
+
+        #[cfg(feature = "log")]
+        #[test]
+        fn logs_chunk_boundaries_and_errors() {
+            extern crate std;
+            use std::string::{String, ToString};
+            use std::vec::Vec;
+
+            std::thread_local! {
+                static MESSAGES: core::cell::RefCell<Vec<String>> = const {
+                    core::cell::RefCell::new(Vec::new())
+                };
+            }
+
+            /// Collects the messages logged by the current thread
+            struct Logger;
+
+            impl log::Log for Logger {
+                fn enabled(&self, _: &log::Metadata) -> bool {
+                    true
+                }
+
+                fn log(&self, record: &log::Record) {
+                    let message = record.args().to_string();
+                    MESSAGES.with(|m| m.borrow_mut().push(message));
+                }
+
+                fn flush(&self) {}
+            }
+
+            let _ = log::set_logger(&Logger);
+            log::set_max_level(log::LevelFilter::Trace);
+
+            let mut d = Dechunker::new();
+            let data: &[u8] = &[
+                0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A, // signature
+                0, 0, 0, 2, // len
+                b'I', b'D', b'A', b'T', // type
+                b'h', b'i', // data
+                0, 0, 0, 0, // crc (ignored)
+                0xff, 0xff, 0xff, 0xff, // len
+                b'I', b'E', b'N', b'D', // type
+            ];
+            assert_eq!(
+                d.update_all(data, |_| Ok::<_, Error>(())),
+                Err(Error::ChunkTooLarge)
+            );
+            assert_eq!(
+                MESSAGES.with(|m| m.take()),
+                [
+                    "dechunker: IDAT chunk of 2 bytes, data at offset 16",
+                    "dechunker: end of chunk at offset 22",
+                    "dechunker: ChunkTooLarge at offset 22",
+                ]
+            );
+        }
+
+        #[test]
+        fn state_introspection() {
+            let mut d = Dechunker::new_without_png_signature();
+            assert_eq!(d.state_name(), "chunk header");
+            assert_eq!(d.current_chunk_type(), None);
+
+            d.update(&[0, 0, 0, 5, b'I', b'D', b'A', b'T']).unwrap();
+            d.update(b"ab").unwrap();
+            assert_eq!(d.state_name(), "chunk data");
+            assert_eq!(d.current_chunk_type(), Some(ChunkType(*b"IDAT")));
+            assert_eq!(d.remaining_in_chunk(), Some(3));
+
+            d.update(b"cde").unwrap();
+            assert_eq!(d.state_name(), "CRC");
+            assert_eq!(d.current_chunk_type(), Some(ChunkType(*b"IDAT")));
+            assert_eq!(d.remaining_in_chunk(), None);
+        }
+
+        #[test]
+        fn chunk_too_large() {
+            let mut d = Dechunker::new_without_png_signature();
+            assert_eq!(
+                d.update(&[0xff, 0xff, 0xff, 0xff, b'I', b'D', b'A', b'T']),
+                Err(Error::ChunkTooLarge)
+            );
+
+            let mut d =
+                Dechunker::new_without_png_signature().with_limits(Limits { max_chunk_len: 4 });
+            assert_eq!(
+                d.update(&[0, 0, 0, 5, b'I', b'D', b'A', b'T']),
+                Err(Error::ChunkTooLarge)
+            );
+        }
+
+        #[test]
+        fn total_len() {
+            let data: &[u8] = &[
+                0, 0, 0, 1, // len
+                b'I', b'D', b'A', b'T', // type
+                b'h', // data
+                0, 0, 0, 0, // crc (ignored)
+                0, 0, 0, 0, // next chunk
+                b'I', b'E', b'N', b'D', // type
+            ];
+
+            let mut d = Dechunker::new_without_png_signature().with_total_len(13);
+            let mut events = Vec::<_, 4>::new();
+            d.update_all(&data[..13], |e| {
+                events.push(e).unwrap();
+                Ok::<_, Error>(())
+            })
+            .unwrap();
+            assert_eq!(events.last(), Some(&Event::EndChunk));
+            assert_eq!(d.update(&data[13..]), Err(Error::DatastreamTooLong));
+
+            let mut d = Dechunker::new_without_png_signature().with_total_len(12);
+            assert_eq!(d.update(data), Err(Error::DatastreamTooLong));
+
+            // Counted from the signature
+            let mut d = Dechunker::new().with_total_len(20);
+            d.update(PNG_SIGNATURE).unwrap();
+            assert_eq!(d.update(data), Err(Error::DatastreamTooLong));
+        }
+
+        #[test]
+        fn crc_events() {
+            let mut d = Dechunker::new_without_png_signature().with_crc_events();
+            let mut data: &[u8] = &[
+                0, 0, 0, 1, // len
+                b'I', b'D', b'A', b'T', // type
+                b'h', // data
+                1, 2, 3, 4, // crc
+            ];
+
+            let (n, _) = d.update(data).unwrap();
+            data = &data[n..];
+            let (n, _) = d.update(data).unwrap();
+            data = &data[n..];
+
+            let (n, event) = d.update(&data[..2]).unwrap();
+            assert_eq!(event, None);
+            data = &data[n..];
+
+            let (n, event) = d.update(data).unwrap();
+            assert_eq!(event, Some(Event::Crc(0x01020304)));
+            data = &data[n..];
+
+            let (n, event) = d.update(data).unwrap();
+            assert_eq!(event, Some(Event::EndChunk));
+            data = &data[n..];
+
+            assert_eq!(data, b"");
+            d.eof().unwrap();
+        }
+
+        #[test]
+        #[cfg(feature = "heapless")]
+        fn owned_events() {
+            extern crate std;
+
+            let data = include_bytes!("../pngdump/pngsuite/basn0g01.png");
+            let (mut owned, mut direct) = (std::vec::Vec::new(), std::vec::Vec::new());
+            let (mut d1, mut d2) = (Dechunker::new(), Dechunker::new());
+            for piece in data.chunks(16) {
+                d1.update_all(piece, |e| {
+                    owned.push(OwnedEvent::<16>::try_from(e)?);
+                    Ok::<_, Error>(())
+                })
+                .unwrap();
+                d2.update_all(piece, |e| {
+                    direct.push(e);
+                    Ok::<_, Error>(())
+                })
+                .unwrap();
+            }
+            assert!(owned.iter().map(Event::from).eq(direct));
+
+            assert_eq!(
+                OwnedEvent::<4>::try_from(Event::Data(&[0; 5])),
+                Err(Error::EventTooLarge)
+            );
+        }
+
+        #[test]
+        fn whole_header_and_crc_in_input() {
+            let mut d = Dechunker::new_without_png_signature().with_crc_events();
+            let data: &[u8] = &[
+                0, 0, 0, 0, // len
+                b'I', b'E', b'N', b'D', // type
+                1, 2, 3, 4, // crc
+                0, 0, // next chunk
+            ];
+
+            assert_eq!(
+                d.update(data).unwrap(),
+                (
+                    8,
+                    Some(Event::BeginChunk(ChunkHeader {
+                        len: 0,
+                        type_: ChunkType(*b"IEND")
+                    }))
+                )
+            );
+            // Empty chunk data
+            assert_eq!(d.update(&data[8..]).unwrap(), (0, None));
+            assert_eq!(
+                d.update(&data[8..]).unwrap(),
+                (0, Some(Event::Crc(0x01020304)))
+            );
+            assert_eq!(d.update(&data[8..]).unwrap(), (4, Some(Event::EndChunk)));
+            assert_eq!(d.update(&data[12..]).unwrap(), (2, None));
+        }
+
+        #[test]
+        fn partial_chunk_header() {
+            let mut d = Dechunker::new_without_png_signature();
+            let mut data: &[u8] = &[
+                0, 0, 0, 5, // len
                 b'I', b'D', b'A', b'T', // type
             ];
 
@@ -298,7 +1521,7 @@ pub mod dechunker {
                 event,
                 Some(Event::BeginChunk(ChunkHeader {
                     len: 5,
-                    type_: *b"IDAT"
+                    type_: ChunkType(*b"IDAT")
                 }))
             );
             data = &data[n..];
@@ -321,7 +1544,7 @@ pub mod dechunker {
                 event,
                 Some(Event::BeginChunk(ChunkHeader {
                     len: 5,
-                    type_: *b"IDAT"
+                    type_: ChunkType(*b"IDAT")
                 }))
             );
             data = &data[n..];
@@ -342,6 +1565,66 @@ pub mod dechunker {
             d.eof().unwrap();
         }
 
+        #[test]
+        fn abort_in_chunk() {
+            let mut d = Dechunker::new();
+            let data: &[u8] = &[
+                0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A, // signature
+                0, 0, 0, 5, // len
+                b'I', b'D', b'A', b'T', // type
+                b'h', b'e', // data
+            ];
+
+            let mut input = data;
+            while !input.is_empty() {
+                let (n, _) = d.update(input).unwrap();
+                input = &input[n..];
+            }
+            assert_eq!(d.eof(), Err(Error::UnfinishedChunk));
+
+            d.abort();
+            assert_eq!(d.bytes_consumed(), 0);
+            assert_eq!(d.update(&data[..8]).unwrap(), (8, None));
+            assert_eq!(
+                d.update(&data[8..]).unwrap(),
+                (
+                    8,
+                    Some(Event::BeginChunk(ChunkHeader {
+                        len: 5,
+                        type_: ChunkType(*b"IDAT")
+                    }))
+                )
+            );
+        }
+
+        #[test]
+        fn chunk_type_properties() {
+            let ihdr = ChunkType(*b"IHDR");
+            assert!(ihdr.is_critical());
+            assert!(!ihdr.is_ancillary());
+            assert!(!ihdr.is_private());
+            assert!(!ihdr.is_safe_to_copy());
+
+            let private = ChunkType(*b"prVt");
+            assert!(!private.is_critical());
+            assert!(private.is_ancillary());
+            assert!(private.is_private());
+            assert!(private.is_safe_to_copy());
+        }
+
+        #[test]
+        fn chunk_type_display() {
+            use core::fmt::Write;
+
+            let mut s = heapless::String::<16>::new();
+            write!(s, "{}", ChunkType(*b"tEXt")).unwrap();
+            assert_eq!(s, "tEXt");
+
+            s.clear();
+            write!(s, "{}", ChunkType([b'a', 0, b' ', b'b'])).unwrap();
+            assert_eq!(s, "a\\x00\\x20b");
+        }
+
         #[test]
         #[ignore = "test not implemented"]
         fn test_unfinished_chunk() {
@@ -353,19 +1636,63 @@ pub mod dechunker {
 pub mod stream_decoder {
     use crate::dechunker::ChunkHeader;
     use crate::dechunker::ChunkType;
+    #[cfg(feature = "ancillary")]
+    use crate::extension::{
+        CalibrationParser, Extension, ImageOffset, PhysicalScale, StereoMode, OFFS, PCAL, SCAL,
+        STER,
+    };
 
     use super::*;
 
-    pub struct StreamDecoder {
+    /// Interprets chunks. Stores up to `PALETTE_SIZE` palette entries, see [`Palette`].
+    #[derive(Clone)]
+    pub struct StreamDecoder<const PALETTE_SIZE: usize = 256> {
         state: State,
-        palette: Palette,
+        #[cfg(feature = "palette")]
+        palette: Palette<PALETTE_SIZE>,
+        header: Option<ImageHeader>,
+        #[cfg(feature = "palette")]
+        seen_palette: bool,
+        seen_end: bool,
+        /// Whether interlaced images are passed on rather than rejected
+        interlaced: bool,
+        /// Whether a CgBI chunk came before IHDR
+        cgbi: bool,
+        seen_image_data: bool,
+        /// Whether `Event::Warning` is emitted
+        warnings: bool,
+        /// Ancillary chunks before IDAT, for `ImageInfo`
+        #[cfg(feature = "ancillary")]
+        ancillary: AncillaryInfo,
     }
 
-    #[derive(Clone, PartialEq, Eq, Debug)]
+    #[derive(Clone, PartialEq, Debug)]
     enum State {
         BeforeChunk,
-        IHDR(Vec<u8, { ImageHeader::SIZE }>),
-        PLTE,
+        IHDR(ArrayVec<u8, { ImageHeader::SIZE }>),
+        #[cfg(feature = "palette")]
+        PLTE {
+            /// Bytes of an incomplete palette entry
+            partial: ArrayVec<u8, 3>,
+            /// Bytes still expected according to the chunk length
+            remaining: u32,
+        },
+        /// Palette alpha values
+        #[cfg(all(feature = "palette", feature = "ancillary"))]
+        TRNS,
+        /// Transparent colour of a greyscale or truecolour image
+        #[cfg(feature = "ancillary")]
+        ColourKey(ArrayVec<u8, 6>),
+        #[cfg(feature = "ancillary")]
+        GAMA(ArrayVec<u8, 4>),
+        #[cfg(feature = "ancillary")]
+        OFFS(ArrayVec<u8, { ImageOffset::SIZE }>),
+        #[cfg(feature = "ancillary")]
+        SCAL(ArrayVec<u8, { PhysicalScale::MAX_SIZE }>),
+        #[cfg(feature = "ancillary")]
+        PCAL(CalibrationParser),
+        #[cfg(feature = "ancillary")]
+        STER(ArrayVec<u8, { StereoMode::SIZE }>),
         IDAT,
         IgnoredChunk,
         IEND,
@@ -378,7 +1705,7 @@ pub mod stream_decoder {
     }
 
     /// <https://www.w3.org/TR/png-3/#11IHDR>
-    #[derive(Eq, PartialEq, Debug)]
+    #[derive(Clone, Copy, Eq, PartialEq, Debug)]
     pub struct ImageHeader {
         pub width: u32,
         pub height: u32,
@@ -391,56 +1718,508 @@ pub mod stream_decoder {
 
     impl ImageHeader {
         const SIZE: usize = 13;
+
+        /// Number of samples per pixel, or `None` for an invalid colour type.
+        pub fn channels(&self) -> Option<u8> {
+            match self.colour_type {
+                0 | 3 => Some(1),
+                4 => Some(2),
+                2 => Some(3),
+                6 => Some(4),
+                _ => None,
+            }
+        }
+
+        /// Whether the bit depth is allowed for the colour type.
+        ///
+        /// <https://www.w3.org/TR/png-3/#table111>
+        pub fn bit_depth_allowed(&self) -> bool {
+            match self.colour_type {
+                0 => matches!(self.bit_depth, 1 | 2 | 4 | 8 | 16),
+                3 => matches!(self.bit_depth, 1 | 2 | 4 | 8),
+                2 | 4 | 6 => matches!(self.bit_depth, 8 | 16),
+                _ => false,
+            }
+        }
+
+        pub fn bits_per_pixel(&self) -> Option<u8> {
+            self.channels()?.checked_mul(self.bit_depth)
+        }
+
+        /// Layout of the pixels in unfiltered rows, or `None` for an invalid colour type.
+        pub fn pixel_layout(&self) -> Option<PixelLayout> {
+            let bits = self.bits_per_pixel()?;
+            Some(PixelLayout {
+                colour_type: self.colour_type,
+                bit_depth: self.bit_depth,
+                channels: self.channels()?,
+                bytes_per_pixel: core::cmp::max(1, bits / 8),
+            })
+        }
+
+        /// Size of a scanline of the given width in the decompressed stream, including the
+        /// filter type byte.
+        pub(crate) fn scanline_len_for(&self, width: u32) -> Option<u64> {
+            let bits = width as u64 * self.bits_per_pixel()? as u64;
+            Some(1 + bits.div_ceil(8))
+        }
+
+        /// Size of a single scanline in the decompressed stream, including the filter type byte.
+        /// Only meaningful for non-interlaced images.
+        pub fn scanline_len(&self) -> Option<u64> {
+            self.scanline_len_for(self.width)
+        }
+
+        /// Total number of bytes the decompressed image data should contain, or `None` for an
+        /// invalid colour type or if it doesn't fit in a `u64`.
+        ///
+        /// <https://www.w3.org/TR/png-3/#8Interlace>
+        pub fn raw_image_size(&self) -> Option<u64> {
+            if self.interlace_method == 0 {
+                return self.scanline_len()?.checked_mul(self.height as u64);
+            }
+            let mut size: u64 = 0;
+            for pass in 1..=7 {
+                let (width, height) = self.adam7_pass_size(pass)?;
+                if width > 0 && height > 0 {
+                    let pass_size = self.scanline_len_for(width)?.checked_mul(height as u64)?;
+                    size = size.checked_add(pass_size)?;
+                }
+            }
+            Some(size)
+        }
+
+        /// Width and height of the reduced image of Adam7 pass `pass` (1 to 7), which can be
+        /// empty. Pass 1 has every 8th pixel of every 8th row.
+        pub fn adam7_pass_size(&self, pass: u8) -> Option<(u32, u32)> {
+            let &(x0, y0, dx, dy) = ADAM7_PASSES.get((pass as usize).checked_sub(1)?)?;
+            // Checked, as the optimizer can't tell that the steps aren't zero
+            let width = (self.width as u64 + dx - 1 - x0).checked_div(dx)?;
+            let height = (self.height as u64 + dy - 1 - y0).checked_div(dy)?;
+            Some((width as u32, height as u32))
+        }
+    }
+
+    /// How samples are stored in unfiltered rows, everything needed to interpret them without
+    /// the rest of the [`ImageHeader`].
+    ///
+    /// <https://www.w3.org/TR/png-3/#7Scanline>
+    #[derive(Clone, Copy, Eq, PartialEq, Debug)]
+    pub struct PixelLayout {
+        pub colour_type: u8,
+        /// Bits per sample. Below 8, several pixels are packed into a byte, leftmost pixel in
+        /// the high-order bits. 16-bit samples are big endian.
+        pub bit_depth: u8,
+        pub channels: u8,
+        /// Bytes per complete pixel, 1 when several pixels share a byte. Also the distance
+        /// used by the scanline filters.
+        pub bytes_per_pixel: u8,
+    }
+
+    impl PixelLayout {
+        pub fn bits_per_pixel(&self) -> u8 {
+            self.channels * self.bit_depth
+        }
+
+        /// Bytes in a row of `width` pixels, or `None` if that doesn't fit in a `usize`.
+        pub fn row_len(&self, width: u32) -> Option<usize> {
+            let bits = width as u64 * self.bits_per_pixel() as u64;
+            usize::try_from(bits.div_ceil(8)).ok()
+        }
+    }
+
+    /// Adam7 passes as `(x_offset, y_offset, x_step, y_step)`.
+    pub(crate) const ADAM7_PASSES: [(u64, u64, u64, u64); 7] = [
+        (0, 0, 8, 8),
+        (4, 0, 8, 8),
+        (0, 4, 4, 8),
+        (2, 0, 4, 4),
+        (0, 2, 2, 4),
+        (1, 0, 2, 2),
+        (0, 1, 1, 2),
+    ];
+
+    const IHDR: ChunkType = ChunkType(*b"IHDR");
+    #[cfg(feature = "palette")]
+    const PLTE: ChunkType = ChunkType(*b"PLTE");
+    const IDAT: ChunkType = ChunkType(*b"IDAT");
+    const IEND: ChunkType = ChunkType(*b"IEND");
+    /// Apple's extension, see [`StreamDecoder::is_cgbi`]
+    const CGBI: ChunkType = ChunkType(*b"CgBI");
+
+    /// Ancillary chunks of the specification (including APNG) and the registered extensions,
+    /// whether interpreted or not. Others are reported as [`Warning::UnknownChunk`].
+    const REGISTERED_ANCILLARY: [ChunkType; 27] = [
+        ChunkType(*b"cHRM"),
+        ChunkType(*b"gAMA"),
+        ChunkType(*b"iCCP"),
+        ChunkType(*b"sBIT"),
+        ChunkType(*b"sRGB"),
+        ChunkType(*b"cICP"),
+        ChunkType(*b"mDCV"),
+        ChunkType(*b"cLLI"),
+        ChunkType(*b"bKGD"),
+        ChunkType(*b"hIST"),
+        ChunkType(*b"tRNS"),
+        ChunkType(*b"eXIf"),
+        ChunkType(*b"pHYs"),
+        ChunkType(*b"sPLT"),
+        ChunkType(*b"tIME"),
+        ChunkType(*b"iTXt"),
+        ChunkType(*b"tEXt"),
+        ChunkType(*b"zTXt"),
+        ChunkType(*b"acTL"),
+        ChunkType(*b"fcTL"),
+        ChunkType(*b"fdAT"),
+        ChunkType(*b"oFFs"),
+        ChunkType(*b"pCAL"),
+        ChunkType(*b"sCAL"),
+        ChunkType(*b"sTER"),
+        ChunkType(*b"gIFg"),
+        ChunkType(*b"gIFx"),
+    ];
+    #[cfg(feature = "ancillary")]
+    const TRNS: ChunkType = ChunkType(*b"tRNS");
+    #[cfg(feature = "ancillary")]
+    const GAMA: ChunkType = ChunkType(*b"gAMA");
+
+    /// What's known about the image once the chunks before the image data were decoded, for
+    /// setting up buffers and colour conversion. Emitted as [`Event::ImageInfo`] at the first
+    /// IDAT chunk, see also [`StreamDecoder::image_info`].
+    #[derive(Clone, Copy, PartialEq, Debug)]
+    pub struct ImageInfo {
+        pub header: ImageHeader,
+        /// Entries of the palette, which is available from [`StreamDecoder::palette`] (up to
+        /// its capacity)
+        #[cfg(feature = "palette")]
+        pub palette_len: usize,
+        /// Palette entries with an alpha value from tRNS
+        #[cfg(all(feature = "palette", feature = "ancillary"))]
+        pub palette_alpha_len: usize,
+        #[cfg(feature = "ancillary")]
+        pub ancillary: AncillaryInfo,
+    }
+
+    /// Ancillary chunks before the image data, see [`ImageInfo`].
+    #[cfg(feature = "ancillary")]
+    #[derive(Clone, Copy, Default, PartialEq, Debug)]
+    pub struct AncillaryInfo {
+        /// gAMA, the image gamma times 100000
+        pub gamma: Option<u32>,
+        /// tRNS of a greyscale or truecolour image
+        pub colour_key: Option<ColourKey>,
+        pub offset: Option<ImageOffset>,
+        pub scale: Option<PhysicalScale>,
+        pub stereo: Option<StereoMode>,
     }
 
-    const IHDR: ChunkType = *b"IHDR";
-    const PLTE: ChunkType = *b"PLTE";
-    const IDAT: ChunkType = *b"IDAT";
-    const IEND: ChunkType = *b"IEND";
+    /// Pixels of this colour are fully transparent, the others opaque. Samples are at the
+    /// image's bit depth.
+    #[cfg(feature = "ancillary")]
+    #[derive(Clone, Copy, Eq, PartialEq, Debug)]
+    pub enum ColourKey {
+        Grey(u16),
+        Rgb(u16, u16, u16),
+    }
 
-    #[derive(Eq, PartialEq, Debug)]
+    #[derive(PartialEq, Debug)]
     pub enum Event<'a> {
         ImageHeader(ImageHeader),
+        /// At the first IDAT chunk
+        ImageInfo(ImageInfo),
         ImageData(&'a [u8]),
+        /// Extension chunk, after its data was received
+        #[cfg(feature = "ancillary")]
+        Extension(Extension),
+        /// See [`StreamDecoder::with_warnings`]
+        Warning(Warning),
+        End,
+    }
+
+    /// [`Event`] with the data copied, see [`dechunker::OwnedEvent`].
+    #[cfg(feature = "heapless")]
+    #[derive(Clone, PartialEq, Debug)]
+    pub enum OwnedEvent<const N: usize> {
+        ImageHeader(ImageHeader),
+        ImageInfo(ImageInfo),
+        ImageData(heapless::Vec<u8, N>),
+        #[cfg(feature = "ancillary")]
+        Extension(Extension),
+        Warning(Warning),
         End,
     }
 
+    #[cfg(feature = "heapless")]
+    impl<const N: usize> TryFrom<Event<'_>> for OwnedEvent<N> {
+        type Error = Error;
+
+        /// Fails with [`Error::EventTooLarge`] if the data is longer than `N` bytes.
+        fn try_from(event: Event<'_>) -> Result<Self, Error> {
+            Ok(match event {
+                Event::ImageHeader(header) => OwnedEvent::ImageHeader(header),
+                Event::ImageInfo(info) => OwnedEvent::ImageInfo(info),
+                Event::ImageData(data) => OwnedEvent::ImageData(owned(data)?),
+                #[cfg(feature = "ancillary")]
+                Event::Extension(e) => OwnedEvent::Extension(e),
+                Event::Warning(w) => OwnedEvent::Warning(w),
+                Event::End => OwnedEvent::End,
+            })
+        }
+    }
+
+    #[cfg(feature = "heapless")]
+    impl<'a, const N: usize> From<&'a OwnedEvent<N>> for Event<'a> {
+        fn from(event: &'a OwnedEvent<N>) -> Self {
+            match event {
+                OwnedEvent::ImageHeader(header) => Event::ImageHeader(*header),
+                OwnedEvent::ImageInfo(info) => Event::ImageInfo(*info),
+                OwnedEvent::ImageData(data) => Event::ImageData(data),
+                #[cfg(feature = "ancillary")]
+                OwnedEvent::Extension(e) => Event::Extension(*e),
+                OwnedEvent::Warning(w) => Event::Warning(*w),
+                OwnedEvent::End => Event::End,
+            }
+        }
+    }
+
+    impl<const PALETTE_SIZE: usize> Default for StreamDecoder<PALETTE_SIZE> {
+        fn default() -> Self {
+            Self::with_palette_capacity()
+        }
+    }
+
     impl StreamDecoder {
         pub fn new() -> Self {
+            Self::with_palette_capacity()
+        }
+    }
+
+    /// Shows the state and header, and only the size of the palette.
+    impl<const PALETTE_SIZE: usize> core::fmt::Debug for StreamDecoder<PALETTE_SIZE> {
+        fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+            let mut s = f.debug_struct("StreamDecoder");
+            s.field("state", &self.state).field("header", &self.header);
+            #[cfg(feature = "palette")]
+            s.field("palette_len", &self.palette.len())
+                .field("seen_palette", &self.seen_palette);
+            s.finish()
+        }
+    }
+
+    impl<const PALETTE_SIZE: usize> StreamDecoder<PALETTE_SIZE> {
+        /// Size of a `StreamDecoder` in bytes, including the palette.
+        pub const MEMORY_FOOTPRINT: usize = core::mem::size_of::<Self>();
+
+        /// Creates a decoder storing at most `PALETTE_SIZE` palette entries, e.g.
+        /// `StreamDecoder::<0>::with_palette_capacity()` for truecolour-only assets.
+        pub fn with_palette_capacity() -> Self {
             Self {
                 state: State::initial(),
-                palette: Palette {
-                    data: Default::default(),
-                },
+                #[cfg(feature = "palette")]
+                palette: Palette::new(),
+                header: None,
+                #[cfg(feature = "palette")]
+                seen_palette: false,
+                seen_end: false,
+                interlaced: false,
+                cgbi: false,
+                seen_image_data: false,
+                warnings: false,
+                #[cfg(feature = "ancillary")]
+                ancillary: AncillaryInfo::default(),
             }
         }
 
-        pub fn palette(&self) -> &Palette {
+        /// Passes on interlaced images instead of failing with
+        /// [`Error::InterlaceNotSupported`], for later stages which handle them, e.g. an
+        /// [`Unfilterer`](crate::unfilter::Unfilterer) with a pass limit.
+        pub fn with_interlaced(mut self) -> Self {
+            self.interlaced = true;
+            self
+        }
+
+        /// Emits [`Event::Warning`] for unknown ancillary chunks and chunks out of order, which
+        /// are otherwise skipped or accepted silently.
+        pub fn with_warnings(mut self) -> Self {
+            self.warnings = true;
+            self
+        }
+
+        #[cfg(feature = "palette")]
+        pub fn palette(&self) -> &Palette<PALETTE_SIZE> {
             &self.palette
         }
 
+        /// Drops the chunk being interpreted, e.g. an ancillary chunk whose CRC didn't match:
+        /// the rest of its events are ignored and nothing from it is passed on. Palette alpha
+        /// from a partial tRNS is cleared.
+        ///
+        /// Data already passed on, i.e. from IDAT, can't be taken back, so this is only useful
+        /// for ancillary chunks.
+        pub fn discard_chunk(&mut self) {
+            #[cfg(all(feature = "palette", feature = "ancillary"))]
+            if let State::TRNS = self.state {
+                self.palette.alpha.clear();
+            }
+            if !matches!(self.state, State::BeforeChunk) {
+                self.state = State::IgnoredChunk;
+            }
+        }
+
+        /// Abandons the current image, discarding any partially received chunk and the palette.
+        pub fn abort(&mut self) {
+            self.state = State::initial();
+            #[cfg(feature = "palette")]
+            {
+                self.palette.clear();
+                self.seen_palette = false;
+            }
+            self.header = None;
+            self.seen_end = false;
+            self.cgbi = false;
+            self.seen_image_data = false;
+            #[cfg(feature = "ancillary")]
+            {
+                self.ancillary = AncillaryInfo::default();
+            }
+        }
+
+        /// Fails with [`Error::MissingEndChunk`] if IEND wasn't decoded.
         pub fn eof(&self) -> Result<(), Error> {
-            // TODO: we should check if we got IEND
-            Ok(())
+            if self.seen_end {
+                Ok(())
+            } else {
+                Err(Error::MissingEndChunk)
+            }
+        }
+
+        /// The image header, once IHDR was decoded.
+        pub fn header(&self) -> Option<&ImageHeader> {
+            self.header.as_ref()
+        }
+
+        /// What's known about the image so far, once IHDR was decoded. Complete at the first
+        /// IDAT chunk, when it's also emitted as [`Event::ImageInfo`].
+        pub fn image_info(&self) -> Option<ImageInfo> {
+            Some(ImageInfo {
+                header: self.header?,
+                #[cfg(feature = "palette")]
+                palette_len: self.palette.len(),
+                #[cfg(all(feature = "palette", feature = "ancillary"))]
+                palette_alpha_len: self.palette.alpha.len(),
+                #[cfg(feature = "ancillary")]
+                ancillary: self.ancillary,
+            })
+        }
+
+        /// Whether the file is Apple's CgBI variant, as produced for iOS apps, i.e. it starts
+        /// with a CgBI chunk. Its image data is a deflate stream without the zlib wrapper, and
+        /// its pixels are BGR(A) with premultiplied alpha, see
+        /// [`Inflater::set_raw_deflate`](crate::inflater::Inflater::set_raw_deflate) and
+        /// [`Unfilterer::set_bgr_order`](crate::unfilter::Unfilterer::set_bgr_order).
+        pub fn is_cgbi(&self) -> bool {
+            self.cgbi
+        }
+
+        /// The dynamic part is the palette, with its alpha values. It only grows until
+        /// [`abort`](Self::abort), so the current size is the peak.
+        pub fn memory_usage(&self) -> MemoryUsage {
+            #[cfg(feature = "palette")]
+            let peak_dynamic = self.palette.len() * 3 + self.palette.alpha.len();
+            #[cfg(not(feature = "palette"))]
+            let peak_dynamic = 0;
+            MemoryUsage {
+                static_bytes: Self::MEMORY_FOOTPRINT,
+                peak_dynamic,
+            }
+        }
+
+        /// The chunk being interpreted, for debugging.
+        pub fn state_name(&self) -> &'static str {
+            match self.state {
+                State::BeforeChunk => "before chunk",
+                State::IHDR(_) => "IHDR",
+                #[cfg(feature = "palette")]
+                State::PLTE { .. } => "PLTE",
+                #[cfg(all(feature = "palette", feature = "ancillary"))]
+                State::TRNS => "tRNS",
+                #[cfg(feature = "ancillary")]
+                State::ColourKey(_) => "tRNS",
+                #[cfg(feature = "ancillary")]
+                State::GAMA(_) => "gAMA",
+                #[cfg(feature = "ancillary")]
+                State::OFFS(_) => "oFFs",
+                #[cfg(feature = "ancillary")]
+                State::SCAL(_) => "sCAL",
+                #[cfg(feature = "ancillary")]
+                State::PCAL(_) => "pCAL",
+                #[cfg(feature = "ancillary")]
+                State::STER(_) => "sTER",
+                State::IDAT => "IDAT",
+                State::IgnoredChunk => "ignored chunk",
+                State::IEND => "IEND",
+            }
         }
 
         pub fn update<'a>(
             &mut self,
             input: dechunker::Event<'a>,
         ) -> Result<(Option<dechunker::Event<'a>>, Option<Event<'a>>), Error> {
+            let before = core::mem::discriminant(&self.state);
+            let result = self.step(input);
+            match &result {
+                Err(e) => debug!(
+                    "stream decoder: {} in state {:?}",
+                    e,
+                    DebugFormat(&self.state)
+                ),
+                Ok(_) if core::mem::discriminant(&self.state) != before => {
+                    trace!("stream decoder: state {:?}", DebugFormat(&self.state))
+                }
+                Ok(_) => {}
+            }
+            result
+        }
+
+        /// A [`Warning::ChunkOutOfOrder`] event for `type_`, if enabled.
+        #[cfg(feature = "palette")]
+        fn warning_if<'a>(&self, out_of_order: bool, type_: ChunkType) -> Option<Event<'a>> {
+            (self.warnings && out_of_order)
+                .then_some(Event::Warning(Warning::ChunkOutOfOrder(type_)))
+        }
+
+        fn step<'a>(
+            &mut self,
+            input: dechunker::Event<'a>,
+        ) -> Result<(Option<dechunker::Event<'a>>, Option<Event<'a>>), Error> {
+            match input {
+                dechunker::Event::Crc(_) => return Ok((None, None)),
+                // The chunk being interpreted, if any, was cut short
+                dechunker::Event::Resync(_) => {
+                    self.discard_chunk();
+                    self.state = State::initial();
+                    return Ok((None, None));
+                }
+                _ => {}
+            }
             match &mut self.state {
                 State::BeforeChunk => match input {
                     dechunker::Event::BeginChunk(ChunkHeader { len, type_: IHDR }) => {
+                        if self.header.is_some() {
+                            return Err(Error::DuplicateChunk(IHDR));
+                        }
                         if len as usize != ImageHeader::SIZE {
                             return Err(Error::InvalidImageHeaderLength);
                         }
-                        self.state = State::IHDR(Vec::new());
+                        self.state = State::IHDR(ArrayVec::new());
                         Ok((None, None))
                     }
                     dechunker::Event::BeginChunk(ChunkHeader { type_: IDAT, .. }) => {
                         // TODO: check if we got header already?
                         self.state = State::IDAT;
-                        Ok((None, None))
+                        let first = !core::mem::replace(&mut self.seen_image_data, true);
+                        let info = self.image_info().filter(|_| first);
+                        Ok((None, info.map(Event::ImageInfo)))
                     }
                     dechunker::Event::BeginChunk(ChunkHeader { type_: IEND, len }) => {
                         if len != 0 {
@@ -449,62 +2228,312 @@ pub mod stream_decoder {
                         self.state = State::IEND;
                         Ok((None, None))
                     }
+                    #[cfg(feature = "palette")]
                     dechunker::Event::BeginChunk(ChunkHeader { type_: PLTE, len }) => {
+                        if self.seen_palette {
+                            return Err(Error::DuplicateChunk(PLTE));
+                        }
+                        if let Some(ImageHeader {
+                            colour_type: 0 | 4, ..
+                        }) = self.header
+                        {
+                            return Err(Error::PaletteNotAllowed);
+                        }
                         if len % 3 != 0 || len > 256 * 3 {
                             return Err(Error::InvalidPaletteChunkSize);
                         }
-                        self.state = State::PLTE;
-                        Ok((None, None))
+                        // Only a suggestion for other colour types, so it may be cut short
+                        if matches!(self.header, Some(ImageHeader { colour_type: 3, .. }))
+                            && len as usize > PALETTE_SIZE * 3
+                        {
+                            debug!(
+                                "stream decoder: {} palette entries, capacity {}",
+                                len / 3,
+                                PALETTE_SIZE
+                            );
+                            return Err(Error::PaletteTooLarge);
+                        }
+                        self.seen_palette = true;
+                        self.state = State::PLTE {
+                            partial: ArrayVec::new(),
+                            remaining: len,
+                        };
+                        Ok((None, self.warning_if(self.seen_image_data, PLTE)))
+                    }
+                    #[cfg(all(feature = "palette", feature = "ancillary"))]
+                    dechunker::Event::BeginChunk(ChunkHeader { type_: TRNS, len })
+                        if matches!(self.header, Some(ImageHeader { colour_type: 3, .. })) =>
+                    {
+                        if len > 256 {
+                            return Err(Error::InvalidTransparencyChunkSize);
+                        }
+                        self.state = State::TRNS;
+                        let out_of_order = !self.seen_palette || self.seen_image_data;
+                        Ok((None, self.warning_if(out_of_order, TRNS)))
+                    }
+                    #[cfg(feature = "ancillary")]
+                    dechunker::Event::BeginChunk(ChunkHeader { type_: TRNS, len })
+                        if matches!(
+                            (self.header, len),
+                            (Some(ImageHeader { colour_type: 0, .. }), 2)
+                                | (Some(ImageHeader { colour_type: 2, .. }), 6)
+                        ) =>
+                    {
+                        self.state = State::ColourKey(ArrayVec::new());
+                        Ok((None, None))
+                    }
+                    #[cfg(feature = "ancillary")]
+                    dechunker::Event::BeginChunk(ChunkHeader {
+                        type_: GAMA,
+                        len: 4,
+                    }) => {
+                        self.state = State::GAMA(ArrayVec::new());
+                        Ok((None, None))
+                    }
+                    #[cfg(feature = "ancillary")]
+                    dechunker::Event::BeginChunk(ChunkHeader { type_: OFFS, len }) => {
+                        if len as usize != ImageOffset::SIZE {
+                            return Err(Error::InvalidExtensionChunk(OFFS));
+                        }
+                        self.state = State::OFFS(ArrayVec::new());
+                        Ok((None, None))
+                    }
+                    #[cfg(feature = "ancillary")]
+                    dechunker::Event::BeginChunk(ChunkHeader { type_: SCAL, len })
+                        if len as usize <= PhysicalScale::MAX_SIZE =>
+                    {
+                        self.state = State::SCAL(ArrayVec::new());
+                        Ok((None, None))
+                    }
+                    #[cfg(feature = "ancillary")]
+                    dechunker::Event::BeginChunk(ChunkHeader { type_: PCAL, .. }) => {
+                        self.state = State::PCAL(CalibrationParser::new());
+                        Ok((None, None))
+                    }
+                    #[cfg(feature = "ancillary")]
+                    dechunker::Event::BeginChunk(ChunkHeader { type_: STER, len }) => {
+                        if len as usize != StereoMode::SIZE {
+                            return Err(Error::InvalidExtensionChunk(STER));
+                        }
+                        self.state = State::STER(ArrayVec::new());
+                        Ok((None, None))
                     }
-                    dechunker::Event::BeginChunk(ChunkHeader { .. }) => {
+                    dechunker::Event::BeginChunk(ChunkHeader { type_: CGBI, .. })
+                        if self.header.is_none() =>
+                    {
+                        self.cgbi = true;
                         self.state = State::IgnoredChunk;
                         Ok((None, None))
                     }
-                    _ => panic!("Illegal event in BeforeChunk state"),
+                    dechunker::Event::BeginChunk(ChunkHeader { type_, .. }) => {
+                        self.state = State::IgnoredChunk;
+                        let unknown =
+                            type_.is_ancillary() && !REGISTERED_ANCILLARY.contains(&type_);
+                        let warning = Event::Warning(Warning::UnknownChunk(type_));
+                        Ok((None, (self.warnings && unknown).then_some(warning)))
+                    }
+                    _ => Err(Error::UnexpectedEvent),
                 },
 
                 State::IHDR(buf) => match input {
                     dechunker::Event::Data(input) => {
                         if buf.extend_from_slice(input).is_err() {
-                            panic!("Too much data in IHDR chunk");
+                            return Err(Error::InvalidImageHeaderLength);
                         }
                         Ok((None, None))
                     }
 
                     dechunker::Event::EndChunk => {
-                        assert!(
-                            buf.is_full(),
-                            "Got IHDR EndChunk, but buffer is not filled!"
-                        );
+                        let Ok(&[w0, w1, w2, w3, h0, h1, h2, h3, b, c, cm, fm, im]) =
+                            <&[u8; ImageHeader::SIZE]>::try_from(&buf[..])
+                        else {
+                            return Err(Error::InvalidImageHeaderLength);
+                        };
                         let header = ImageHeader {
-                            width: u32::from_be_bytes(buf[0..4].try_into().unwrap()),
-                            height: u32::from_be_bytes(buf[4..8].try_into().unwrap()),
-                            bit_depth: buf[8],
-                            colour_type: buf[9],
-                            compression_method: buf[10],
-                            filter_method: buf[11],
-                            interlace_method: buf[12],
+                            width: u32::from_be_bytes([w0, w1, w2, w3]),
+                            height: u32::from_be_bytes([h0, h1, h2, h3]),
+                            bit_depth: b,
+                            colour_type: c,
+                            compression_method: cm,
+                            filter_method: fm,
+                            interlace_method: im,
                         };
+                        if !(1..=dechunker::MAX_CHUNK_LEN).contains(&header.width)
+                            || !(1..=dechunker::MAX_CHUNK_LEN).contains(&header.height)
+                        {
+                            return Err(Error::InvalidImageDimensions);
+                        }
+                        if !matches!(header.colour_type, 0 | 2 | 3 | 4 | 6) {
+                            return Err(Error::InvalidColourType);
+                        }
+                        if !header.bit_depth_allowed() {
+                            return Err(Error::InvalidBitDepth);
+                        }
+                        if header.compression_method != 0 {
+                            return Err(Error::UnsupportedCompressionMethod);
+                        }
+                        if header.filter_method != 0 {
+                            return Err(Error::UnsupportedFilterMethod);
+                        }
+                        match header.interlace_method {
+                            0 => {}
+                            1 if self.interlaced => {}
+                            1 => return Err(Error::InterlaceNotSupported),
+                            _ => return Err(Error::InvalidInterlaceMethod),
+                        }
                         self.state = State::BeforeChunk;
+                        self.header = Some(header);
                         Ok((None, Some(Event::ImageHeader(header))))
                     }
-                    dechunker::Event::BeginChunk(_) => {
-                        panic!("Illegal BeginChunk inside of existing chunk")
+                    _ => Err(Error::UnexpectedEvent),
+                },
+
+                #[cfg(feature = "palette")]
+                State::PLTE { partial, remaining } => match input {
+                    dechunker::Event::Data(mut input) => {
+                        if input.len() > *remaining as usize {
+                            return Err(Error::InvalidPaletteChunkSize);
+                        }
+                        *remaining -= input.len() as u32;
+                        while !input.is_empty() {
+                            let n = fill(partial, input);
+                            input = input.get(n..).unwrap_or_default();
+                            if let Ok(&entry) = <&[u8; 3]>::try_from(&partial[..]) {
+                                // Entries which don't fit are dropped, see `Palette`
+                                let _ = self.palette.entries.push(entry);
+                                partial.clear();
+                            }
+                        }
+                        Ok((None, None))
+                    }
+                    dechunker::Event::EndChunk => {
+                        if *remaining != 0 || !partial.is_empty() {
+                            return Err(Error::InvalidPaletteChunkSize);
+                        }
+                        self.state = State::initial();
+                        Ok((None, None))
                     }
+                    _ => Err(Error::UnexpectedEvent),
                 },
 
-                State::PLTE => match input {
+                #[cfg(all(feature = "palette", feature = "ancillary"))]
+                State::TRNS => match input {
                     dechunker::Event::Data(input) => {
-                        if self.palette.data.extend_from_slice(input).is_err() {
-                            panic!("Too much data in PLTE chunk");
-                        }
+                        // Values for entries which don't fit are dropped
+                        fill(&mut self.palette.alpha, input);
+                        Ok((None, None))
+                    }
+                    dechunker::Event::EndChunk => {
+                        self.state = State::initial();
+                        Ok((None, None))
+                    }
+                    _ => Err(Error::UnexpectedEvent),
+                },
+
+                #[cfg(feature = "ancillary")]
+                State::ColourKey(buf) => match input {
+                    dechunker::Event::Data(input) => {
+                        // The length was checked already
+                        fill(buf, input);
+                        Ok((None, None))
+                    }
+                    dechunker::Event::EndChunk => {
+                        self.ancillary.colour_key = match buf[..] {
+                            [g0, g1] => Some(ColourKey::Grey(u16::from_be_bytes([g0, g1]))),
+                            [r0, r1, g0, g1, b0, b1] => Some(ColourKey::Rgb(
+                                u16::from_be_bytes([r0, r1]),
+                                u16::from_be_bytes([g0, g1]),
+                                u16::from_be_bytes([b0, b1]),
+                            )),
+                            _ => return Err(Error::InvalidTransparencyChunkSize),
+                        };
+                        self.state = State::initial();
+                        Ok((None, None))
+                    }
+                    _ => Err(Error::UnexpectedEvent),
+                },
+
+                #[cfg(feature = "ancillary")]
+                State::GAMA(buf) => match input {
+                    dechunker::Event::Data(input) => {
+                        // The length was checked already
+                        fill(buf, input);
+                        Ok((None, None))
+                    }
+                    dechunker::Event::EndChunk => {
+                        let Ok(&gamma) = <&[u8; 4]>::try_from(&buf[..]) else {
+                            return Err(Error::UnexpectedEvent);
+                        };
+                        self.ancillary.gamma = Some(u32::from_be_bytes(gamma));
+                        self.state = State::initial();
+                        Ok((None, None))
+                    }
+                    _ => Err(Error::UnexpectedEvent),
+                },
+
+                #[cfg(feature = "ancillary")]
+                State::OFFS(buf) => match input {
+                    dechunker::Event::Data(input) => {
+                        // The length was checked already
+                        fill(buf, input);
+                        Ok((None, None))
+                    }
+                    dechunker::Event::EndChunk => {
+                        let offset = ImageOffset::parse(buf)?;
+                        self.ancillary.offset = Some(offset);
+                        self.state = State::initial();
+                        Ok((None, Some(Event::Extension(Extension::Offset(offset)))))
+                    }
+                    _ => Err(Error::UnexpectedEvent),
+                },
+
+                #[cfg(feature = "ancillary")]
+                State::SCAL(buf) => match input {
+                    dechunker::Event::Data(input) => {
+                        // The length was checked already
+                        fill(buf, input);
+                        Ok((None, None))
+                    }
+                    dechunker::Event::EndChunk => {
+                        let scale = PhysicalScale::parse(buf)?;
+                        self.ancillary.scale = Some(scale);
+                        self.state = State::initial();
+                        Ok((None, Some(Event::Extension(Extension::Scale(scale)))))
+                    }
+                    _ => Err(Error::UnexpectedEvent),
+                },
+
+                #[cfg(feature = "ancillary")]
+                State::PCAL(parser) => match input {
+                    dechunker::Event::Data(input) => {
+                        parser.update(input)?;
                         Ok((None, None))
                     }
                     dechunker::Event::EndChunk => {
+                        let calibration = parser.finish()?;
                         self.state = State::initial();
+                        Ok((
+                            None,
+                            Some(Event::Extension(Extension::Calibration(calibration))),
+                        ))
+                    }
+                    _ => Err(Error::UnexpectedEvent),
+                },
+
+                #[cfg(feature = "ancillary")]
+                State::STER(buf) => match input {
+                    dechunker::Event::Data(input) => {
+                        // The length was checked already
+                        fill(buf, input);
                         Ok((None, None))
                     }
-                    _ => panic!("Illegal event inside PLTE chunk"),
+                    dechunker::Event::EndChunk => {
+                        let mode = StereoMode::parse(buf)?;
+                        self.ancillary.stereo = Some(mode);
+                        self.state = State::initial();
+                        Ok((None, Some(Event::Extension(Extension::Stereo(mode)))))
+                    }
+                    _ => Err(Error::UnexpectedEvent),
                 },
 
                 State::IDAT => match input {
@@ -513,7 +2542,7 @@ pub mod stream_decoder {
                         self.state = State::initial();
                         Ok((None, None))
                     }
-                    _ => panic!("Illegal event inside IDAT chunk"),
+                    _ => Err(Error::UnexpectedEvent),
                 },
 
                 State::IgnoredChunk => match input {
@@ -522,16 +2551,17 @@ pub mod stream_decoder {
                         self.state = State::initial();
                         Ok((None, None))
                     }
-                    _ => panic!("Illegal event inside ignored chunk"),
+                    _ => Err(Error::UnexpectedEvent),
                 },
 
                 State::IEND => match input {
-                    dechunker::Event::Data(_) => panic!("Data in IEND chunk"),
+                    dechunker::Event::Data(_) => Err(Error::InvalidEndChunkSize),
                     dechunker::Event::EndChunk => {
                         self.state = State::initial();
+                        self.seen_end = true;
                         Ok((None, Some(Event::End)))
                     }
-                    _ => panic!("Illegal event inside IEND chunk"),
+                    _ => Err(Error::UnexpectedEvent),
                 },
             }
         }
@@ -548,7 +2578,7 @@ pub mod stream_decoder {
             assert_eq!(
                 d.update(dechunker::Event::BeginChunk(ChunkHeader {
                     len: 13,
-                    type_: *b"IHDR"
+                    type_: ChunkType(*b"IHDR")
                 }))
                 .unwrap(),
                 (None, None)
@@ -558,7 +2588,7 @@ pub mod stream_decoder {
                 d.update(dechunker::Event::Data(&[
                     0, 0, 0, 1, // width
                     0, 0, 0, 2, // height
-                    3, 4, 5, 6, 7
+                    16, 4, 0, 0, 0
                 ]))
                 .unwrap(),
                 (None, None)
@@ -571,17 +2601,16 @@ pub mod stream_decoder {
                     Some(Event::ImageHeader(ImageHeader {
                         width: 1,
                         height: 2,
-                        bit_depth: 3,
+                        bit_depth: 16,
                         colour_type: 4,
-                        compression_method: 5,
-                        filter_method: 6,
-                        interlace_method: 7,
+                        compression_method: 0,
+                        filter_method: 0,
+                        interlace_method: 0,
                     }))
                 )
             );
 
-            // Hmmm, should we assert that? Which layer checks if we had IEND?
-            d.eof().unwrap();
+            assert_eq!(d.eof(), Err(Error::MissingEndChunk));
         }
 
         #[test]
@@ -591,7 +2620,7 @@ pub mod stream_decoder {
             assert_eq!(
                 d.update(dechunker::Event::BeginChunk(ChunkHeader {
                     len: 13,
-                    type_: *b"IHDR"
+                    type_: ChunkType(*b"IHDR")
                 }))
                 .unwrap(),
                 (None, None)
@@ -601,7 +2630,7 @@ pub mod stream_decoder {
                 d.update(dechunker::Event::Data(&[
                     0, 0, 0, 1, // width
                     0, 0, 0, 2, // height
-                    3, 4, 5, 6, 7
+                    16, 4, 0, 0, 0
                 ]))
                 .unwrap(),
                 (None, None)
@@ -614,11 +2643,11 @@ pub mod stream_decoder {
                     Some(Event::ImageHeader(ImageHeader {
                         width: 1,
                         height: 2,
-                        bit_depth: 3,
+                        bit_depth: 16,
                         colour_type: 4,
-                        compression_method: 5,
-                        filter_method: 6,
-                        interlace_method: 7,
+                        compression_method: 0,
+                        filter_method: 0,
+                        interlace_method: 0,
                     }))
                 )
             );
@@ -626,10 +2655,10 @@ pub mod stream_decoder {
             assert_eq!(
                 d.update(dechunker::Event::BeginChunk(ChunkHeader {
                     len: 0,
-                    type_: *b"IDAT"
+                    type_: ChunkType(*b"IDAT")
                 }))
                 .unwrap(),
-                (None, None)
+                (None, Some(Event::ImageInfo(d.image_info().unwrap())))
             );
 
             assert_eq!(
@@ -647,7 +2676,7 @@ pub mod stream_decoder {
             assert_eq!(
                 d.update(dechunker::Event::BeginChunk(ChunkHeader {
                     len: 13,
-                    type_: *b"IHDR"
+                    type_: ChunkType(*b"IHDR")
                 }))
                 .unwrap(),
                 (None, None)
@@ -665,7 +2694,7 @@ pub mod stream_decoder {
             assert_eq!(
                 d.update(dechunker::Event::Data(&[
                     2, // height
-                    3, 4, 5, 6, 7
+                    16, 4, 0, 0, 0
                 ]))
                 .unwrap(),
                 (None, None)
@@ -678,17 +2707,16 @@ pub mod stream_decoder {
                     Some(Event::ImageHeader(ImageHeader {
                         width: 1,
                         height: 2,
-                        bit_depth: 3,
+                        bit_depth: 16,
                         colour_type: 4,
-                        compression_method: 5,
-                        filter_method: 6,
-                        interlace_method: 7,
+                        compression_method: 0,
+                        filter_method: 0,
+                        interlace_method: 0,
                     }))
                 )
             );
 
-            // Hmmm, should we assert that? Which layer checks if we had IEND?
-            d.eof().unwrap();
+            assert_eq!(d.eof(), Err(Error::MissingEndChunk));
         }
 
         #[test]
@@ -698,7 +2726,7 @@ pub mod stream_decoder {
             assert_eq!(
                 d.update(dechunker::Event::BeginChunk(ChunkHeader {
                     len: 5,
-                    type_: *b"IDAT"
+                    type_: ChunkType(*b"IDAT")
                 }))
                 .unwrap(),
                 (None, None)
@@ -711,8 +2739,7 @@ pub mod stream_decoder {
 
             assert_eq!(d.update(dechunker::Event::EndChunk).unwrap(), (None, None,));
 
-            // Hmmm, should we assert that? Which layer checks if we had IEND?
-            d.eof().unwrap();
+            assert_eq!(d.eof(), Err(Error::MissingEndChunk));
         }
 
         #[test]
@@ -722,7 +2749,7 @@ pub mod stream_decoder {
             assert_eq!(
                 d.update(dechunker::Event::BeginChunk(ChunkHeader {
                     len: 5,
-                    type_: *b"tEXt"
+                    type_: ChunkType(*b"tEXt")
                 }))
                 .unwrap(),
                 (None, None)
@@ -735,8 +2762,37 @@ pub mod stream_decoder {
 
             assert_eq!(d.update(dechunker::Event::EndChunk).unwrap(), (None, None,));
 
-            // Hmmm, should we assert that? Which layer checks if we had IEND?
-            d.eof().unwrap();
+            assert_eq!(d.eof(), Err(Error::MissingEndChunk));
+        }
+
+        #[test]
+        #[cfg(feature = "palette")]
+        fn warnings() {
+            let begin = |type_: &[u8; 4], len| {
+                dechunker::Event::BeginChunk(ChunkHeader {
+                    len,
+                    type_: ChunkType(*type_),
+                })
+            };
+            let mut d = StreamDecoder::new().with_warnings();
+            for (event, expected) in [
+                (begin(b"tEXt", 0), None),
+                (
+                    begin(b"loLz", 0),
+                    Some(Warning::UnknownChunk(ChunkType(*b"loLz"))),
+                ),
+                // Unknown critical chunks aren't reported
+                (begin(b"LOLZ", 0), None),
+                (begin(b"IDAT", 0), None),
+                (begin(b"PLTE", 0), Some(Warning::ChunkOutOfOrder(PLTE))),
+            ] {
+                assert_eq!(d.update(event), Ok((None, expected.map(Event::Warning))));
+                d.update(dechunker::Event::EndChunk).unwrap();
+            }
+
+            // Not reported by default
+            let mut d = StreamDecoder::new();
+            assert_eq!(d.update(begin(b"loLz", 0)), Ok((None, None)));
         }
 
         #[test]
@@ -746,7 +2802,7 @@ pub mod stream_decoder {
             assert_eq!(
                 d.update(dechunker::Event::BeginChunk(ChunkHeader {
                     len: 0,
-                    type_: *b"IEND"
+                    type_: ChunkType(*b"IEND")
                 }))
                 .unwrap(),
                 (None, None)
@@ -757,179 +2813,4703 @@ pub mod stream_decoder {
                 (None, Some(Event::End))
             );
 
-            // Hmmm, should we assert that? Which layer checks if we had IEND?
             d.eof().unwrap();
         }
 
         #[test]
-        fn invalid_iend() {
+        fn unexpected_events() {
+            let mut d = StreamDecoder::new();
+            assert_eq!(
+                d.update(dechunker::Event::Data(b"hello")),
+                Err(Error::UnexpectedEvent)
+            );
+
+            let mut d = StreamDecoder::new();
+            d.update(dechunker::Event::BeginChunk(ChunkHeader {
+                len: 13,
+                type_: ChunkType(*b"IHDR"),
+            }))
+            .unwrap();
+            assert_eq!(
+                d.update(dechunker::Event::Data(&[0; 14])),
+                Err(Error::InvalidImageHeaderLength)
+            );
+
+            let mut d = StreamDecoder::new();
+            d.update(dechunker::Event::BeginChunk(ChunkHeader {
+                len: 13,
+                type_: ChunkType(*b"IHDR"),
+            }))
+            .unwrap();
+            d.update(dechunker::Event::Data(&[0; 12])).unwrap();
+            assert_eq!(
+                d.update(dechunker::Event::EndChunk),
+                Err(Error::InvalidImageHeaderLength)
+            );
+        }
+
+        #[cfg(feature = "palette")]
+        #[test]
+        fn decode_plte() {
             let mut d = StreamDecoder::new();
 
             assert_eq!(
                 d.update(dechunker::Event::BeginChunk(ChunkHeader {
-                    len: 42,
-                    type_: *b"IEND"
-                })),
-                Err(Error::InvalidEndChunkSize)
+                    len: 6,
+                    type_: ChunkType(*b"PLTE")
+                }))
+                .unwrap(),
+                (None, None)
+            );
+            assert_eq!(
+                d.update(dechunker::Event::Data(&[1, 2, 3, 4])).unwrap(),
+                (None, None)
             );
+            assert_eq!(
+                d.update(dechunker::Event::Data(&[5, 6])).unwrap(),
+                (None, None)
+            );
+            assert_eq!(d.update(dechunker::Event::EndChunk).unwrap(), (None, None));
+
+            let palette = d.palette();
+            assert_eq!(palette.len(), 2);
+            assert_eq!(palette.as_rgb_slice(), &[1, 2, 3, 4, 5, 6]);
+            assert_eq!(palette.get(1), Some([4, 5, 6]));
+            assert_eq!(palette.get(2), None);
+            assert_eq!(palette.color_at(2), [0, 0, 0]);
+            assert!(palette.iter().eq([[1, 2, 3], [4, 5, 6]]));
         }
-    }
-}
 
-pub mod inflater {
-    use super::stream_decoder as sd;
-    use super::*;
-    use crate::stream_decoder::ImageHeader;
-    use miniz_oxide::inflate::stream::InflateState;
+        #[cfg(feature = "palette")]
+        #[test]
+        fn palette_capacity() {
+            let mut d = StreamDecoder::<1>::with_palette_capacity();
 
-    pub struct Inflater<const BUFFER_SIZE: usize = 1024> {
-        decompressor: InflateState,
-        output_buf: [u8; BUFFER_SIZE],
-    }
+            d.update(dechunker::Event::BeginChunk(ChunkHeader {
+                len: 6,
+                type_: ChunkType(*b"PLTE"),
+            }))
+            .unwrap();
+            d.update(dechunker::Event::Data(&[1, 2, 3, 4, 5, 6]))
+                .unwrap();
+            d.update(dechunker::Event::EndChunk).unwrap();
 
-    #[derive(Eq, PartialEq, Debug)]
-    pub enum Event<'a> {
-        /// Passthrough
-        ImageHeader(ImageHeader),
-        ImageData(&'a [u8]),
-        /// Passthrough
-        End,
-    }
+            assert_eq!(d.palette().len(), 1);
+            assert_eq!(d.palette().get(0), Some([1, 2, 3]));
+            assert_eq!(d.palette().get(1), None);
+        }
 
-    impl<const BUFFER_SIZE: usize> Inflater<BUFFER_SIZE> {
-        pub fn new() -> Self {
-            Self {
-                decompressor: InflateState::new(miniz_oxide::DataFormat::Zlib),
-                output_buf: [0; BUFFER_SIZE],
+        #[cfg(feature = "palette")]
+        #[test]
+        fn palette_too_large() {
+            for (colour_type, result) in [(2, Ok((None, None))), (3, Err(Error::PaletteTooLarge))] {
+                let mut d = StreamDecoder::<1>::with_palette_capacity();
+                d.update(dechunker::Event::BeginChunk(ChunkHeader {
+                    len: 13,
+                    type_: ChunkType(*b"IHDR"),
+                }))
+                .unwrap();
+                d.update(dechunker::Event::Data(&[
+                    0,
+                    0,
+                    0,
+                    1,
+                    0,
+                    0,
+                    0,
+                    1,
+                    8,
+                    colour_type,
+                    0,
+                    0,
+                    0,
+                ]))
+                .unwrap();
+                d.update(dechunker::Event::EndChunk).unwrap();
+                assert_eq!(
+                    d.update(dechunker::Event::BeginChunk(ChunkHeader {
+                        len: 6,
+                        type_: ChunkType(*b"PLTE"),
+                    })),
+                    result
+                );
             }
         }
-        pub fn update<'this, 'a>(
-            &'this mut self,
-            input: sd::Event<'a>,
-        ) -> Result<(Option<sd::Event<'a>>, Option<Event<'this>>), Error> {
-            match input {
-                sd::Event::ImageHeader(header) => Ok((None, Some(Event::ImageHeader(header)))),
-                sd::Event::ImageData(input) => {
-                    let result = miniz_oxide::inflate::stream::inflate(
-                        &mut self.decompressor,
-                        input,
-                        &mut self.output_buf,
-                        miniz_oxide::MZFlush::None,
-                    );
-
-                    match result.status {
-                        Ok(_) => {}
-                        Err(e) => match e {
-                            miniz_oxide::MZError::ErrNo => panic!("shouldn't happen"),
-                            miniz_oxide::MZError::Stream => {
-                                return Err(Error::InvalidDeflateStream)
-                            }
-                            miniz_oxide::MZError::Data => return Err(Error::InvalidDeflateStream),
-                            miniz_oxide::MZError::Mem => panic!("shouldn't happen"),
-                            miniz_oxide::MZError::Buf => {
-                                if !input.is_empty() {
-                                    panic!("buffer error, input len={}", input.len())
-                                }
-                                // Otherwise okay, it just wants more input
-                            }
-                            miniz_oxide::MZError::Version => panic!("shouldn't happen"),
-                            miniz_oxide::MZError::Param => panic!("shouldn't happen"),
-                        },
-                    }
 
-                    let leftover_input = if result.bytes_consumed < input.len() {
-                        Some(sd::Event::ImageData(&input[result.bytes_consumed..]))
-                    } else if result.bytes_written == self.output_buf.len() {
-                        // If we filled the output buffer, we might possibly need more calls
-                        Some(sd::Event::ImageData(&[]))
-                    } else {
-                        None
-                    };
+        #[cfg(all(feature = "palette", feature = "ancillary"))]
+        #[test]
+        fn decode_palette_transparency() {
+            let mut d = StreamDecoder::new();
 
-                    Ok((
-                        leftover_input,
-                        Some(Event::ImageData(&self.output_buf[..result.bytes_written])),
-                    ))
-                }
-                sd::Event::End => Ok((None, Some(Event::End))),
+            let events = [
+                dechunker::Event::BeginChunk(ChunkHeader {
+                    len: 13,
+                    type_: ChunkType(*b"IHDR"),
+                }),
+                dechunker::Event::Data(&[
+                    0, 0, 0, 1, // width
+                    0, 0, 0, 1, // height
+                    8, 3, 0, 0, 0,
+                ]),
+                dechunker::Event::EndChunk,
+                dechunker::Event::BeginChunk(ChunkHeader {
+                    len: 6,
+                    type_: ChunkType(*b"PLTE"),
+                }),
+                dechunker::Event::Data(&[1, 2, 3, 4, 5, 6]),
+                dechunker::Event::EndChunk,
+                dechunker::Event::BeginChunk(ChunkHeader {
+                    len: 1,
+                    type_: ChunkType(*b"tRNS"),
+                }),
+                dechunker::Event::Data(&[7]),
+                dechunker::Event::EndChunk,
+            ];
+            for e in events {
+                d.update(e).unwrap();
             }
-        }
-    }
 
-    #[cfg(test)]
-    mod tests {
-        use super::sd;
-        use super::*;
-        use crate::inflater::Inflater;
+            assert_eq!(d.palette().color_at_rgba(0), [1, 2, 3, 7]);
+            assert_eq!(d.palette().color_at_rgba(1), [4, 5, 6, 255]);
+            assert_eq!(d.palette().color_at_rgba(2), [0, 0, 0, 0]);
+        }
 
+        #[cfg(feature = "palette")]
         #[test]
-        fn decode_simple_compressed_stream() {
-            let mut d = Inflater::<1024>::new();
+        fn expand_palette_row() {
+            let mut d = StreamDecoder::new();
 
-            let compressed = miniz_oxide::deflate::compress_to_vec_zlib(b"hello", 5);
+            d.update(dechunker::Event::BeginChunk(ChunkHeader {
+                len: 6,
+                type_: ChunkType(*b"PLTE"),
+            }))
+            .unwrap();
+            d.update(dechunker::Event::Data(&[1, 2, 3, 4, 5, 6]))
+                .unwrap();
+            d.update(dechunker::Event::EndChunk).unwrap();
+
+            let mut out = [[0; 3]; 3];
+            d.palette()
+                .expand_row(&[0b01_00_01_00], 2, &mut out, Strictness::Strict)
+                .unwrap();
+            assert_eq!(out, [[4, 5, 6], [1, 2, 3], [4, 5, 6]]);
 
             assert_eq!(
-                d.update(sd::Event::ImageData(&compressed)).unwrap(),
-                (None, Some(Event::ImageData(b"hello")))
+                d.palette()
+                    .expand_row(&[0b0010_0000], 4, &mut out[..1], Strictness::Strict),
+                Err(Error::PaletteIndexOutOfRange)
             );
+            d.palette()
+                .expand_row(&[0b0010_0000], 4, &mut out[..2], Strictness::Lenient)
+                .unwrap();
+            assert_eq!(out[..2], [[0, 0, 0], [1, 2, 3]]);
 
-            // TODO: check if we are at the end?
+            assert_eq!(
+                d.palette()
+                    .expand_row(&[0; 8], 16, &mut out, Strictness::Lenient),
+                Err(Error::InvalidBitDepth)
+            );
+            assert_eq!(
+                d.palette()
+                    .expand_row(&[0], 4, &mut out, Strictness::Lenient),
+                Err(Error::InvalidRowLength)
+            );
         }
 
         #[test]
-        fn very_incremental() {
-            let mut d = Inflater::<1024>::new();
+        #[cfg(feature = "palette")]
+        fn duplicate_chunks() {
+            let mut d = StreamDecoder::new();
 
-            const INPUT: &[u8] = b"hello world";
-            let compressed = miniz_oxide::deflate::compress_to_vec_zlib(INPUT, 5);
+            d.update(dechunker::Event::BeginChunk(ChunkHeader {
+                len: 13,
+                type_: ChunkType(*b"IHDR"),
+            }))
+            .unwrap();
+            d.update(dechunker::Event::Data(&[
+                0, 0, 0, 1, 0, 0, 0, 1, 8, 2, 0, 0, 0,
+            ]))
+            .unwrap();
+            d.update(dechunker::Event::EndChunk).unwrap();
+            assert_eq!(
+                d.update(dechunker::Event::BeginChunk(ChunkHeader {
+                    len: 13,
+                    type_: ChunkType(*b"IHDR"),
+                })),
+                Err(Error::DuplicateChunk(ChunkType(*b"IHDR")))
+            );
 
-            let mut output = Vec::<u8, { INPUT.len() }>::new();
+            d.update(dechunker::Event::BeginChunk(ChunkHeader {
+                len: 0,
+                type_: ChunkType(*b"PLTE"),
+            }))
+            .unwrap();
+            d.update(dechunker::Event::EndChunk).unwrap();
+            assert_eq!(
+                d.update(dechunker::Event::BeginChunk(ChunkHeader {
+                    len: 0,
+                    type_: ChunkType(*b"PLTE"),
+                })),
+                Err(Error::DuplicateChunk(ChunkType(*b"PLTE")))
+            );
+        }
 
-            let mut current_input = compressed.as_slice();
-            while !current_input.is_empty() {
-                let n = core::cmp::min(2, current_input.len());
-                let mut event = Some(sd::Event::ImageData(&current_input[..n]));
-                current_input = &current_input[n..];
-                while let Some(e) = event {
-                    let (leftover, output_event) = d.update(e).unwrap();
-                    match output_event {
-                        Some(Event::ImageData(data)) => output.extend_from_slice(data).unwrap(),
-                        None => {}
-                        _ => panic!("expected only ImageData output"),
-                    }
-                    event = leftover;
-                }
-            }
+        #[test]
+        #[cfg(feature = "palette")]
+        fn palette_in_greyscale_image() {
+            let mut d = StreamDecoder::new();
 
-            assert_eq!(&INPUT, &output);
+            d.update(dechunker::Event::BeginChunk(ChunkHeader {
+                len: 13,
+                type_: ChunkType(*b"IHDR"),
+            }))
+            .unwrap();
+            d.update(dechunker::Event::Data(&[
+                0, 0, 0, 1, 0, 0, 0, 1, 8, 4, 0, 0, 0,
+            ]))
+            .unwrap();
+            d.update(dechunker::Event::EndChunk).unwrap();
+
+            assert_eq!(
+                d.update(dechunker::Event::BeginChunk(ChunkHeader {
+                    len: 3,
+                    type_: ChunkType(*b"PLTE"),
+                })),
+                Err(Error::PaletteNotAllowed)
+            );
         }
 
         #[test]
-        fn decode_inflated_output() {
-            const N: usize = 65536;
+        #[cfg(feature = "palette")]
+        fn truncated_plte() {
+            let mut d = StreamDecoder::new();
 
+            d.update(dechunker::Event::BeginChunk(ChunkHeader {
+                len: 6,
+                type_: ChunkType(*b"PLTE"),
+            }))
+            .unwrap();
+            d.update(dechunker::Event::Data(&[1, 2, 3, 4])).unwrap();
+            assert_eq!(
+                d.update(dechunker::Event::EndChunk),
+                Err(Error::InvalidPaletteChunkSize)
+            );
+        }
+
+        #[test]
+        fn unsupported_methods() {
+            for (data, error) in [
+                (
+                    [0, 0, 0, 1, 0, 0, 0, 1, 8, 2, 1, 0, 0],
+                    Error::UnsupportedCompressionMethod,
+                ),
+                (
+                    [0, 0, 0, 1, 0, 0, 0, 1, 8, 2, 0, 1, 0],
+                    Error::UnsupportedFilterMethod,
+                ),
+                (
+                    [0, 0, 0, 1, 0, 0, 0, 1, 8, 2, 0, 0, 1],
+                    Error::InterlaceNotSupported,
+                ),
+                (
+                    [0, 0, 0, 1, 0, 0, 0, 1, 8, 2, 0, 0, 2],
+                    Error::InvalidInterlaceMethod,
+                ),
+                (
+                    [0, 0, 0, 0, 0, 0, 0, 1, 8, 2, 0, 0, 0],
+                    Error::InvalidImageDimensions,
+                ),
+                (
+                    [0, 0, 0, 1, 0x80, 0, 0, 0, 8, 2, 0, 0, 0],
+                    Error::InvalidImageDimensions,
+                ),
+                (
+                    [0, 0, 0, 1, 0, 0, 0, 1, 8, 5, 0, 0, 0],
+                    Error::InvalidColourType,
+                ),
+                (
+                    [0, 0, 0, 1, 0, 0, 0, 1, 16, 3, 0, 0, 0],
+                    Error::InvalidBitDepth,
+                ),
+                (
+                    [0, 0, 0, 1, 0, 0, 0, 1, 3, 0, 0, 0, 0],
+                    Error::InvalidBitDepth,
+                ),
+            ] {
+                let mut d = StreamDecoder::new();
+                d.update(dechunker::Event::BeginChunk(ChunkHeader {
+                    len: 13,
+                    type_: ChunkType(*b"IHDR"),
+                }))
+                .unwrap();
+                d.update(dechunker::Event::Data(&data)).unwrap();
+                assert_eq!(d.update(dechunker::Event::EndChunk), Err(error));
+            }
+        }
+
+        #[test]
+        fn raw_image_size() {
+            let mut header = ImageHeader {
+                width: 5,
+                height: 3,
+                bit_depth: 2,
+                colour_type: 0,
+                compression_method: 0,
+                filter_method: 0,
+                interlace_method: 0,
+            };
+            assert_eq!(header.scanline_len(), Some(3));
+            assert_eq!(header.raw_image_size(), Some(9));
+
+            header.colour_type = 6;
+            header.bit_depth = 8;
+            assert_eq!(header.scanline_len(), Some(21));
+
+            // Pass 3 is empty in a 5x3 image
+            header.interlace_method = 1;
+            assert_eq!(header.raw_image_size(), Some(5 + 5 + 5 + 13 + 2 * 9 + 21));
+            assert_eq!(header.adam7_pass_size(1), Some((1, 1)));
+            assert_eq!(header.adam7_pass_size(3), Some((2, 0)));
+            assert_eq!(header.adam7_pass_size(7), Some((5, 1)));
+            assert_eq!(header.adam7_pass_size(0), None);
+            assert_eq!(header.adam7_pass_size(8), None);
+
+            // About 2^67 bytes
+            let huge = ImageHeader {
+                width: u32::MAX,
+                height: u32::MAX,
+                bit_depth: 16,
+                ..header
+            };
+            assert_eq!(huge.raw_image_size(), None);
+            assert_eq!(
+                ImageHeader {
+                    interlace_method: 0,
+                    ..huge
+                }
+                .raw_image_size(),
+                None
+            );
+
+            header.colour_type = 5;
+            assert_eq!(header.raw_image_size(), None);
+        }
+
+        #[test]
+        fn pixel_layout() {
+            let mut header = ImageHeader {
+                width: 5,
+                height: 3,
+                bit_depth: 2,
+                colour_type: 0,
+                compression_method: 0,
+                filter_method: 0,
+                interlace_method: 0,
+            };
+            let layout = header.pixel_layout().unwrap();
+            assert_eq!(
+                layout,
+                PixelLayout {
+                    colour_type: 0,
+                    bit_depth: 2,
+                    channels: 1,
+                    bytes_per_pixel: 1,
+                }
+            );
+            assert_eq!(layout.row_len(header.width), Some(2));
+
+            header.colour_type = 6;
+            header.bit_depth = 16;
+            let layout = header.pixel_layout().unwrap();
+            assert_eq!((layout.channels, layout.bytes_per_pixel), (4, 8));
+            assert_eq!(layout.row_len(header.width), Some(40));
+
+            header.colour_type = 5;
+            assert_eq!(header.pixel_layout(), None);
+        }
+
+        #[test]
+        #[cfg(feature = "ancillary")]
+        fn decode_offs() {
+            use crate::extension::OffsetUnit;
+
+            let mut d = StreamDecoder::new();
+            d.update(dechunker::Event::BeginChunk(ChunkHeader {
+                len: 9,
+                type_: ChunkType(*b"oFFs"),
+            }))
+            .unwrap();
+            d.update(dechunker::Event::Data(&[0, 0, 0, 7, 0xff]))
+                .unwrap();
+            d.update(dechunker::Event::Data(&[0xff, 0xff, 0xf0, 0]))
+                .unwrap();
+            assert_eq!(
+                d.update(dechunker::Event::EndChunk).unwrap(),
+                (
+                    None,
+                    Some(Event::Extension(Extension::Offset(ImageOffset {
+                        x: 7,
+                        y: -16,
+                        unit: OffsetUnit::Pixel
+                    })))
+                )
+            );
+
+            assert_eq!(
+                d.update(dechunker::Event::BeginChunk(ChunkHeader {
+                    len: 8,
+                    type_: ChunkType(*b"oFFs"),
+                })),
+                Err(Error::InvalidExtensionChunk(ChunkType(*b"oFFs")))
+            );
+        }
+
+        #[test]
+        #[cfg(feature = "ancillary")]
+        fn decode_scal() {
+            use crate::extension::{PhysicalScale, ScaleUnit};
+
+            let mut d = StreamDecoder::new();
+            let data = b"\x021.5E-6\x000.25";
+            d.update(dechunker::Event::BeginChunk(ChunkHeader {
+                len: data.len() as u32,
+                type_: ChunkType(*b"sCAL"),
+            }))
+            .unwrap();
+            d.update(dechunker::Event::Data(&data[..4])).unwrap();
+            d.update(dechunker::Event::Data(&data[4..])).unwrap();
+            assert_eq!(
+                d.update(dechunker::Event::EndChunk).unwrap(),
+                (
+                    None,
+                    Some(Event::Extension(Extension::Scale(PhysicalScale {
+                        unit: ScaleUnit::Radian,
+                        width: 1.5e-6,
+                        height: 0.25
+                    })))
+                )
+            );
+
+            // Too long to buffer, ignored
+            d.update(dechunker::Event::BeginChunk(ChunkHeader {
+                len: PhysicalScale::MAX_SIZE as u32 + 1,
+                type_: ChunkType(*b"sCAL"),
+            }))
+            .unwrap();
+            d.update(dechunker::Event::Data(&[b'1'; PhysicalScale::MAX_SIZE + 1]))
+                .unwrap();
+            assert_eq!(d.update(dechunker::Event::EndChunk).unwrap(), (None, None));
+        }
+
+        #[test]
+        #[cfg(feature = "ancillary")]
+        fn decode_ster() {
+            use crate::extension::StereoMode;
+
+            let mut d = StreamDecoder::new();
+            d.update(dechunker::Event::BeginChunk(ChunkHeader {
+                len: 1,
+                type_: ChunkType(*b"sTER"),
+            }))
+            .unwrap();
+            d.update(dechunker::Event::Data(&[1])).unwrap();
+            assert_eq!(
+                d.update(dechunker::Event::EndChunk).unwrap(),
+                (
+                    None,
+                    Some(Event::Extension(Extension::Stereo(
+                        StereoMode::DivergingFuse
+                    )))
+                )
+            );
+
+            d.update(dechunker::Event::BeginChunk(ChunkHeader {
+                len: 1,
+                type_: ChunkType(*b"sTER"),
+            }))
+            .unwrap();
+            d.update(dechunker::Event::Data(&[2])).unwrap();
+            assert_eq!(
+                d.update(dechunker::Event::EndChunk),
+                Err(Error::InvalidExtensionChunk(ChunkType(*b"sTER")))
+            );
+        }
+
+        #[test]
+        #[cfg(feature = "ancillary")]
+        fn decode_pcal() {
+            use crate::extension::{Calibration, EquationType};
+
+            let data = b"thermal\0\0\0\0\0\0\0\xff\xff\x00\x02Celsius\0-40\0.0025";
+            // In pieces of any size
+            for piece_len in [1, 7, data.len()] {
+                let mut d = StreamDecoder::new();
+                d.update(dechunker::Event::BeginChunk(ChunkHeader {
+                    len: data.len() as u32,
+                    type_: ChunkType(*b"pCAL"),
+                }))
+                .unwrap();
+                for piece in data.chunks(piece_len) {
+                    assert_eq!(
+                        d.update(dechunker::Event::Data(piece)).unwrap(),
+                        (None, None)
+                    );
+                }
+                let (_, event) = d.update(dechunker::Event::EndChunk).unwrap();
+                let Some(Event::Extension(Extension::Calibration(calibration))) = event else {
+                    panic!("{event:?}");
+                };
+                assert_eq!(
+                    calibration,
+                    Calibration {
+                        x0: 0,
+                        x1: 65535,
+                        equation: EquationType::Linear,
+                        params: [-40.0, 0.0025, 0.0, 0.0]
+                    }
+                );
+                assert_eq!(calibration.params(), [-40.0, 0.0025]);
+            }
+
+            for invalid in [
+                // Missing parameter
+                &b"t\0\0\0\0\0\0\0\0\x01\x00\x02\0-40"[..],
+                // Wrong parameter count for the equation
+                b"t\0\0\0\0\0\0\0\0\x01\x01\x02\x001\x002",
+                // Empty name
+                b"\0\0\0\0\0\0\0\0\x01\x00\x02\x001\x002",
+                // Not a number
+                b"t\0\0\0\0\0\0\0\0\x01\x00\x02\0one\x002",
+            ] {
+                let mut d = StreamDecoder::new();
+                d.update(dechunker::Event::BeginChunk(ChunkHeader {
+                    len: invalid.len() as u32,
+                    type_: ChunkType(*b"pCAL"),
+                }))
+                .unwrap();
+                let result = d
+                    .update(dechunker::Event::Data(invalid))
+                    .and_then(|_| d.update(dechunker::Event::EndChunk));
+                assert_eq!(
+                    result,
+                    Err(Error::InvalidExtensionChunk(ChunkType(*b"pCAL"))),
+                    "{invalid:?}"
+                );
+            }
+        }
+
+        #[test]
+        fn invalid_iend() {
+            let mut d = StreamDecoder::new();
+
+            assert_eq!(
+                d.update(dechunker::Event::BeginChunk(ChunkHeader {
+                    len: 42,
+                    type_: ChunkType(*b"IEND")
+                })),
+                Err(Error::InvalidEndChunkSize)
+            );
+        }
+    }
+}
+
+pub mod inflater {
+    //! Decompression of the image data. Without the `inflate` feature, only the event type is
+    //! left, for feeding the later stages from a different decompressor.
+
+    #[cfg(feature = "inflate")]
+    use super::stream_decoder as sd;
+    #[cfg(feature = "inflate")]
+    use super::*;
+    #[cfg(feature = "inflate")]
+    use crate::dechunker::Dechunker;
+    #[cfg(feature = "ancillary")]
+    use crate::extension::Extension;
+    use crate::stream_decoder::{ImageHeader, ImageInfo};
+    use crate::Warning;
+    #[cfg(feature = "heapless")]
+    use crate::{owned, Error};
+    #[cfg(feature = "inflate")]
+    use miniz_oxide::inflate::stream::{FullReset, InflateState, MinReset};
+
+    /// Snapshot of decoding progress, see [`Inflater::progress`].
+    #[derive(Clone, Copy, Eq, PartialEq, Debug)]
+    pub struct Progress {
+        /// Total bytes of the PNG file consumed.
+        pub input_bytes: u64,
+        /// Compressed image data consumed.
+        pub image_data_in: u64,
+        /// Decompressed image data produced.
+        pub image_data_out: u64,
+        /// Expected size of the decompressed image data, once the header is known.
+        pub expected_image_data: Option<u64>,
+        /// Complete scanlines decompressed so far (non-interlaced images only).
+        pub rows_completed: Option<u64>,
+    }
+
+    impl Progress {
+        /// Rough completion estimate in percent, based on the amount of decompressed data.
+        pub fn percent(&self) -> Option<u8> {
+            match self.expected_image_data {
+                Some(0) => Some(100),
+                Some(expected) => {
+                    let out = self.image_data_out.min(expected) as u128;
+                    Some((out * 100 / expected as u128) as u8)
+                }
+                None => None,
+            }
+        }
+    }
+
+    #[cfg(feature = "inflate")]
+    #[derive(Clone)]
+    pub struct Inflater<const BUFFER_SIZE: usize = 1024> {
+        decompressor: InflateState,
+        output_buf: UninitBuf<BUFFER_SIZE>,
+        header: Option<ImageHeader>,
+        total_in: u64,
+        total_out: u64,
+        /// Largest `ImageData` event produced by `update`
+        max_event_size: usize,
+    }
+
+    #[derive(PartialEq, Debug)]
+    pub enum Event<'a> {
+        /// Passthrough
+        ImageHeader(ImageHeader),
+        /// Passthrough
+        ImageInfo(ImageInfo),
+        ImageData(&'a [u8]),
+        /// Passthrough
+        #[cfg(feature = "ancillary")]
+        Extension(Extension),
+        /// Passthrough
+        Warning(Warning),
+        /// Passthrough
+        End,
+    }
+
+    /// [`Event`] with the data copied, see [`dechunker::OwnedEvent`](crate::dechunker::OwnedEvent).
+    #[cfg(feature = "heapless")]
+    #[derive(Clone, PartialEq, Debug)]
+    pub enum OwnedEvent<const N: usize> {
+        ImageHeader(ImageHeader),
+        ImageInfo(ImageInfo),
+        ImageData(heapless::Vec<u8, N>),
+        #[cfg(feature = "ancillary")]
+        Extension(Extension),
+        Warning(Warning),
+        End,
+    }
+
+    #[cfg(feature = "heapless")]
+    impl<const N: usize> TryFrom<Event<'_>> for OwnedEvent<N> {
+        type Error = Error;
+
+        /// Fails with [`Error::EventTooLarge`] if the data is longer than `N` bytes.
+        fn try_from(event: Event<'_>) -> Result<Self, Error> {
+            Ok(match event {
+                Event::ImageHeader(header) => OwnedEvent::ImageHeader(header),
+                Event::ImageInfo(info) => OwnedEvent::ImageInfo(info),
+                Event::ImageData(data) => OwnedEvent::ImageData(owned(data)?),
+                #[cfg(feature = "ancillary")]
+                Event::Extension(e) => OwnedEvent::Extension(e),
+                Event::Warning(w) => OwnedEvent::Warning(w),
+                Event::End => OwnedEvent::End,
+            })
+        }
+    }
+
+    #[cfg(feature = "heapless")]
+    impl<'a, const N: usize> From<&'a OwnedEvent<N>> for Event<'a> {
+        fn from(event: &'a OwnedEvent<N>) -> Self {
+            match event {
+                OwnedEvent::ImageHeader(header) => Event::ImageHeader(*header),
+                OwnedEvent::ImageInfo(info) => Event::ImageInfo(*info),
+                OwnedEvent::ImageData(data) => Event::ImageData(data),
+                #[cfg(feature = "ancillary")]
+                OwnedEvent::Extension(e) => Event::Extension(*e),
+                OwnedEvent::Warning(w) => Event::Warning(*w),
+                OwnedEvent::End => Event::End,
+            }
+        }
+    }
+
+    #[cfg(feature = "inflate")]
+    impl<const BUFFER_SIZE: usize> Default for Inflater<BUFFER_SIZE> {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    /// Shows the counters and the decompressor status, but not the buffers.
+    #[cfg(feature = "inflate")]
+    impl<const BUFFER_SIZE: usize> core::fmt::Debug for Inflater<BUFFER_SIZE> {
+        fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+            f.debug_struct("Inflater")
+                .field("state", &self.state_name())
+                .field("header", &self.header)
+                .field("total_in", &self.total_in)
+                .field("total_out", &self.total_out)
+                .finish()
+        }
+    }
+
+    #[cfg(feature = "inflate")]
+    impl<const BUFFER_SIZE: usize> Inflater<BUFFER_SIZE> {
+        /// Size of an `Inflater` in bytes, including the output buffer and the decompressor
+        /// state (which has the 32 KiB deflate window).
+        pub const MEMORY_FOOTPRINT: usize = core::mem::size_of::<Self>();
+
+        /// The output buffer is zeroed lazily, as far as it's used. miniz_oxide's state is
+        /// initialized right away though.
+        pub fn new() -> Self {
+            Self {
+                decompressor: InflateState::new(miniz_oxide::DataFormat::Zlib),
+                output_buf: UninitBuf::new(),
+                header: None,
+                total_in: 0,
+                total_out: 0,
+                max_event_size: BUFFER_SIZE,
+            }
+        }
+
+        /// Limits `ImageData` events produced by [`update`](Self::update) to `max_event_size`
+        /// bytes (clamped to `1..=BUFFER_SIZE`), e.g. to match a downstream DMA buffer which is
+        /// smaller than the output buffer.
+        pub fn with_max_event_size(mut self, max_event_size: usize) -> Self {
+            self.max_event_size = max_event_size.clamp(1, BUFFER_SIZE);
+            self
+        }
+
+        /// Expects a raw deflate stream, without the zlib header and Adler-32 checksum, as in
+        /// CgBI files (see [`StreamDecoder::is_cgbi`](crate::stream_decoder::StreamDecoder::is_cgbi)).
+        /// Resets the decompressor, so it has to be set before the image data.
+        pub fn set_raw_deflate(&mut self, raw: bool) {
+            let format = if raw {
+                miniz_oxide::DataFormat::Raw
+            } else {
+                miniz_oxide::DataFormat::Zlib
+            };
+            self.decompressor.reset_as(FullReset(format));
+        }
+
+        /// Abandons the current deflate stream and resets the counters.
+        ///
+        /// The decompressor state is reset in place, without reinitializing its buffers.
+        pub fn abort(&mut self) {
+            self.decompressor.reset_as(MinReset);
+            self.header = None;
+            self.total_in = 0;
+            self.total_out = 0;
+        }
+
+        /// The dynamic part is the output buffer, as far as it was used. miniz_oxide's state is
+        /// counted as static.
+        pub fn memory_usage(&self) -> MemoryUsage {
+            MemoryUsage {
+                static_bytes: Self::MEMORY_FOOTPRINT,
+                peak_dynamic: self.output_buf.initialized_len(),
+            }
+        }
+
+        /// Status of the deflate stream, for debugging.
+        pub fn state_name(&self) -> &'static str {
+            use miniz_oxide::inflate::TINFLStatus;
+
+            match (self.header, self.decompressor.last_status()) {
+                (None, _) => "waiting for header",
+                (_, TINFLStatus::NeedsMoreInput) => "needs input",
+                (_, TINFLStatus::HasMoreOutput) => "has more output",
+                (_, TINFLStatus::Done) => "done",
+                _ => "failed",
+            }
+        }
+
+        /// Fails with [`Error::UnfinishedImageData`] if the deflate stream didn't end.
+        pub fn eof(&self) -> Result<(), Error> {
+            match self.decompressor.last_status() {
+                miniz_oxide::inflate::TINFLStatus::Done => Ok(()),
+                _ => Err(Error::UnfinishedImageData),
+            }
+        }
+
+        /// Compressed image data consumed so far in the current stream, in bytes.
+        pub fn total_in(&self) -> u64 {
+            self.total_in
+        }
+
+        /// Decompressed bytes produced so far in the current stream, filter type bytes included.
+        /// Less than [`ImageHeader::raw_image_size`] at the end of the stream means the image
+        /// data was truncated.
+        pub fn total_out(&self) -> u64 {
+            self.total_out
+        }
+
+        /// Decode progress so far, combined with the input position of `dechunker`.
+        pub fn progress(&self, dechunker: &Dechunker) -> Progress {
+            let expected_image_data = self.header.as_ref().and_then(|h| h.raw_image_size());
+            let rows_completed = self
+                .header
+                .as_ref()
+                .filter(|h| h.interlace_method == 0)
+                .and_then(|h| h.scanline_len())
+                .map(|len| self.total_out / len);
+            Progress {
+                input_bytes: dechunker.bytes_consumed(),
+                image_data_in: self.total_in,
+                image_data_out: self.total_out,
+                expected_image_data,
+                rows_completed,
+            }
+        }
+        pub fn update<'this, 'a>(
+            &'this mut self,
+            input: sd::Event<'a>,
+        ) -> Result<(Option<sd::Event<'a>>, Option<Event<'this>>), Error> {
+            self.update_bounded(input, self.max_event_size)
+        }
+
+        /// Like [`update`](Self::update), but produces at most `max_output_bytes` of
+        /// decompressed data (clamped to `1..=BUFFER_SIZE`) in a single call, instead of the
+        /// limit set with [`with_max_event_size`](Self::with_max_event_size).
+        ///
+        /// Each call runs the decompressor exactly once, so this puts a hard upper bound on the
+        /// work done per call. Input which wasn't consumed is returned as the leftover event,
+        /// as usual.
+        pub fn update_bounded<'this, 'a>(
+            &'this mut self,
+            input: sd::Event<'a>,
+            max_output_bytes: usize,
+        ) -> Result<(Option<sd::Event<'a>>, Option<Event<'this>>), Error> {
+            let limit = max_output_bytes.clamp(1, BUFFER_SIZE);
+            match input {
+                sd::Event::ImageHeader(header) => {
+                    self.header = Some(header);
+                    Ok((None, Some(Event::ImageHeader(header))))
+                }
+                sd::Event::ImageInfo(info) => Ok((None, Some(Event::ImageInfo(info)))),
+                sd::Event::ImageData(input) => {
+                    let Self {
+                        decompressor,
+                        output_buf,
+                        header,
+                        total_in,
+                        total_out,
+                        ..
+                    } = self;
+                    let (consumed, written) = inflate(
+                        decompressor,
+                        (total_in, total_out),
+                        header.as_ref().and_then(ImageHeader::raw_image_size),
+                        input,
+                        output_buf.initialized_mut(limit),
+                    )?;
+
+                    let leftover_input = if let Some(rest @ [_, ..]) = input.get(consumed..) {
+                        Some(sd::Event::ImageData(rest))
+                    } else if written == limit {
+                        // If we filled the output buffer, we might possibly need more calls
+                        Some(sd::Event::ImageData(&[]))
+                    } else {
+                        None
+                    };
+
+                    Ok((
+                        leftover_input,
+                        Some(Event::ImageData(self.output_buf.initialized(written))),
+                    ))
+                }
+                #[cfg(feature = "ancillary")]
+                sd::Event::Extension(e) => Ok((None, Some(Event::Extension(e)))),
+                sd::Event::Warning(w) => Ok((None, Some(Event::Warning(w)))),
+                sd::Event::End => Ok((None, Some(Event::End))),
+            }
+        }
+    }
+
+    #[cfg(feature = "inflate")]
+    impl<const BUFFER_SIZE: usize> Inflater<BUFFER_SIZE> {
+        /// Decompresses image data straight into `out`, bypassing the output buffer, so that
+        /// each byte is written once. `out` is typically the rest of the current row,
+        /// see [`Unfilterer::row_space`](crate::unfilter::Unfilterer::row_space).
+        ///
+        /// Returns the input which wasn't consumed and the number of bytes written. If `out`
+        /// was filled, there may be more output even if all of the input was consumed.
+        pub fn inflate_into<'a>(
+            &mut self,
+            input: &'a [u8],
+            out: &mut [u8],
+        ) -> Result<(&'a [u8], usize), Error> {
+            let (consumed, written) = inflate(
+                &mut self.decompressor,
+                (&mut self.total_in, &mut self.total_out),
+                self.header.as_ref().and_then(ImageHeader::raw_image_size),
+                input,
+                out,
+            )?;
+            Ok((input.get(consumed..).unwrap_or_default(), written))
+        }
+    }
+
+    /// Runs the decompressor once, updating the counters. Returns the number of bytes consumed
+    /// and written.
+    ///
+    /// Fails with [`Error::TruncatedImageData`] if the stream ends before `expected_out` bytes,
+    /// the raw image size, if known, and with [`Error::TrailingCompressedData`] if `input`
+    /// goes on after the end of the stream, which the decompressor would never consume.
+    #[cfg(feature = "inflate")]
+    fn inflate(
+        decompressor: &mut InflateState,
+        (total_in, total_out): (&mut u64, &mut u64),
+        expected_out: Option<u64>,
+        input: &[u8],
+        out: &mut [u8],
+    ) -> Result<(usize, usize), Error> {
+        let result = miniz_oxide::inflate::stream::inflate(
+            decompressor,
+            input,
+            out,
+            miniz_oxide::MZFlush::None,
+        );
+
+        match result.status {
+            Ok(_) => {}
+            // Okay, it just wants more input
+            Err(miniz_oxide::MZError::Buf) if input.is_empty() => {}
+            // Stream and Data are corrupt input. The others shouldn't happen, but
+            // aren't worth a panic either.
+            Err(e) => {
+                debug!(
+                    "inflater: {:?} after {} bytes in, {} out",
+                    DebugFormat(e),
+                    total_in,
+                    total_out
+                );
+                return Err(Error::InvalidDeflateStream);
+            }
+        }
+
+        *total_in += result.bytes_consumed as u64;
+        *total_out += result.bytes_written as u64;
+        if result.status == Ok(miniz_oxide::MZStatus::StreamEnd) {
+            trace!(
+                "inflater: end of stream, {} bytes in, {} out",
+                total_in,
+                total_out
+            );
+            if let Some(expected) = expected_out.filter(|&expected| *total_out < expected) {
+                debug!("inflater: expected {} bytes of image data", expected);
+                return Err(Error::TruncatedImageData {
+                    expected,
+                    got: *total_out,
+                });
+            }
+            if result.bytes_consumed < input.len() {
+                debug!(
+                    "inflater: {} bytes after the end of the stream",
+                    input.len() - result.bytes_consumed
+                );
+                return Err(Error::TrailingCompressedData);
+            }
+        }
+        Ok((result.bytes_consumed, result.bytes_written))
+    }
+
+    #[cfg(all(test, feature = "inflate"))]
+    mod tests {
+        use super::sd;
+        use super::*;
+        use crate::inflater::Inflater;
+        use heapless::Vec;
+
+        #[test]
+        fn decode_simple_compressed_stream() {
+            let mut d = Inflater::<1024>::new();
+
+            let compressed = miniz_oxide::deflate::compress_to_vec_zlib(b"hello", 5);
+
+            assert_eq!(
+                d.update(sd::Event::ImageData(&compressed)).unwrap(),
+                (None, Some(Event::ImageData(b"hello")))
+            );
+
+            // TODO: check if we are at the end?
+        }
+
+        #[test]
+        fn state_introspection() {
+            extern crate std;
+            use std::format;
+
+            let mut d = Inflater::<1024>::new();
+            assert_eq!(d.state_name(), "waiting for header");
+            d.update(sd::Event::ImageHeader(ImageHeader {
+                width: 1,
+                height: 1,
+                bit_depth: 8,
+                colour_type: 0,
+                compression_method: 0,
+                filter_method: 0,
+                interlace_method: 0,
+            }))
+            .unwrap();
+            let compressed = miniz_oxide::deflate::compress_to_vec_zlib(b"hello", 5);
+            d.update(sd::Event::ImageData(&compressed[..4])).unwrap();
+            assert_eq!(d.state_name(), "needs input");
+            d.update(sd::Event::ImageData(&compressed[4..])).unwrap();
+            assert_eq!(d.state_name(), "done");
+            assert!(format!("{d:?}").contains("total_out: 5"));
+        }
+
+        #[test]
+        fn very_incremental() {
             let mut d = Inflater::<1024>::new();
 
-            let input = [b'A'; N];
+            const INPUT: &[u8] = b"hello world";
+            let compressed = miniz_oxide::deflate::compress_to_vec_zlib(INPUT, 5);
+
+            let mut output = Vec::<u8, { INPUT.len() }>::new();
+
+            let mut current_input = compressed.as_slice();
+            while !current_input.is_empty() {
+                let n = core::cmp::min(2, current_input.len());
+                let mut event = Some(sd::Event::ImageData(&current_input[..n]));
+                current_input = &current_input[n..];
+                while let Some(e) = event {
+                    let (leftover, output_event) = d.update(e).unwrap();
+                    match output_event {
+                        Some(Event::ImageData(data)) => output.extend_from_slice(data).unwrap(),
+                        None => {}
+                        _ => panic!("expected only ImageData output"),
+                    }
+                    event = leftover;
+                }
+            }
+
+            assert_eq!(&INPUT, &output);
+        }
+
+        #[test]
+        fn decode_inflated_output() {
+            const N: usize = 65536;
+
+            let mut d = Inflater::<1024>::new();
+
+            let input = [b'A'; N];
+            let compressed = miniz_oxide::deflate::compress_to_vec_zlib(&input, 5);
+
+            let mut output = Vec::<u8, N>::new();
+
+            let mut event = Some(sd::Event::ImageData(&compressed));
+            while let Some(e) = event {
+                let (leftover, output_event) = d.update(e).unwrap();
+                match output_event {
+                    Some(Event::ImageData(data)) => output.extend_from_slice(data).unwrap(),
+                    None => {}
+                    _ => panic!("expected only ImageData output"),
+                }
+                event = leftover;
+            }
+
+            assert_eq!(input.len(), output.len());
+            for c in output {
+                assert_eq!(c, b'A');
+            }
+        }
+
+        #[test]
+        fn progress() {
+            let mut d = Inflater::<1024>::new();
+            let dechunker = Dechunker::new();
+
+            assert_eq!(d.progress(&dechunker).percent(), None);
+
+            let header = ImageHeader {
+                width: 3,
+                height: 4,
+                bit_depth: 8,
+                colour_type: 0,
+                compression_method: 0,
+                filter_method: 0,
+                interlace_method: 0,
+            };
+            d.update(sd::Event::ImageHeader(header)).unwrap();
+
+            // Stored, so that half of the data comes out of the zlib header, the block header
+            // and 8 bytes
+            let compressed = miniz_oxide::deflate::compress_to_vec_zlib(&[0; 16], 0);
+            d.update(sd::Event::ImageData(&compressed[..2 + 5 + 8]))
+                .unwrap();
+
+            let progress = d.progress(&dechunker);
+            assert_eq!(progress.image_data_in, 2 + 5 + 8);
+            assert_eq!(progress.image_data_out, 8);
+            assert_eq!(progress.expected_image_data, Some(16));
+            assert_eq!(progress.rows_completed, Some(2));
+            assert_eq!(progress.percent(), Some(50));
+        }
+
+        #[test]
+        fn truncated_image_data() {
+            let mut d = Inflater::<1024>::new();
+            let header = ImageHeader {
+                width: 3,
+                height: 4,
+                bit_depth: 8,
+                colour_type: 0,
+                compression_method: 0,
+                filter_method: 0,
+                interlace_method: 0,
+            };
+            d.update(sd::Event::ImageHeader(header)).unwrap();
+
+            let compressed = miniz_oxide::deflate::compress_to_vec_zlib(&[0; 12], 5);
+            assert_eq!(
+                d.update(sd::Event::ImageData(&compressed)),
+                Err(Error::TruncatedImageData {
+                    expected: 16,
+                    got: 12
+                })
+            );
+        }
+
+        #[test]
+        fn trailing_compressed_data() {
+            let compressed = miniz_oxide::deflate::compress_to_vec_zlib(b"hello", 5);
+            let with_trailing = [&compressed[..], &[1, 2, 3, 4]].concat();
+            let mut d = Inflater::<1024>::new();
+            assert_eq!(
+                d.update(sd::Event::ImageData(&with_trailing)),
+                Err(Error::TrailingCompressedData)
+            );
+
+            // In a later IDAT chunk
+            let mut d = Inflater::<1024>::new();
+            d.update(sd::Event::ImageData(&compressed)).unwrap();
+            assert_eq!(
+                d.update(sd::Event::ImageData(&[0])),
+                Err(Error::TrailingCompressedData)
+            );
+            let mut out = [0; 4];
+            assert_eq!(
+                d.inflate_into(&[0], &mut out),
+                Err(Error::TrailingCompressedData)
+            );
+        }
+
+        #[test]
+        fn inflate_into() {
+            let mut d = Inflater::<1>::new();
+
+            let compressed = miniz_oxide::deflate::compress_to_vec_zlib(b"hello world", 5);
+            let mut out = [0; 16];
+            let (rest, n) = d.inflate_into(&compressed, &mut out[..5]).unwrap();
+            assert_eq!(&out[..n], b"hello");
+            let (rest, n) = d.inflate_into(rest, &mut out[5..]).unwrap();
+            assert_eq!((rest, n), (&[][..], 6));
+            assert_eq!(&out[..11], b"hello world");
+            assert_eq!((d.total_in(), d.total_out()), (compressed.len() as u64, 11));
+        }
+
+        #[test]
+        fn eof() {
+            let mut d = Inflater::<1024>::new();
+            assert_eq!(d.eof(), Err(Error::UnfinishedImageData));
+
+            let compressed = miniz_oxide::deflate::compress_to_vec_zlib(b"hello", 5);
+            d.update(sd::Event::ImageData(&compressed[..4])).unwrap();
+            assert_eq!(d.eof(), Err(Error::UnfinishedImageData));
+            d.update(sd::Event::ImageData(&compressed[4..])).unwrap();
+            d.eof().unwrap();
+        }
+
+        #[test]
+        fn max_event_size() {
+            let mut d = Inflater::<1024>::new().with_max_event_size(512);
+
+            let compressed = miniz_oxide::deflate::compress_to_vec_zlib(&[b'A'; 1200], 5);
+            let mut sizes = Vec::<usize, 8>::new();
+            let mut event = Some(sd::Event::ImageData(&compressed));
+            while let Some(e) = event {
+                let (leftover, output_event) = d.update(e).unwrap();
+                if let Some(Event::ImageData(data @ [_, ..])) = output_event {
+                    sizes.push(data.len()).unwrap();
+                }
+                event = leftover;
+            }
+            assert_eq!(sizes, [512, 512, 176]);
+        }
+
+        #[test]
+        fn abort_mid_stream() {
+            let mut d = Inflater::<1024>::new();
+
+            let compressed = miniz_oxide::deflate::compress_to_vec_zlib(b"hello", 5);
+            d.update(sd::Event::ImageData(&compressed[..4])).unwrap();
+
+            d.abort();
+
+            assert_eq!(
+                d.update(sd::Event::ImageData(&compressed)).unwrap(),
+                (None, Some(Event::ImageData(b"hello")))
+            );
+        }
+
+        #[test]
+        fn bounded_output() {
+            const N: usize = 1000;
+
+            let mut d = Inflater::<1024>::new();
+
+            let input = [b'A'; N];
+            let compressed = miniz_oxide::deflate::compress_to_vec_zlib(&input, 5);
+
+            let mut output = Vec::<u8, N>::new();
+
+            let mut event = Some(sd::Event::ImageData(&compressed));
+            while let Some(e) = event {
+                let (leftover, output_event) = d.update_bounded(e, 16).unwrap();
+                match output_event {
+                    Some(Event::ImageData(data)) => {
+                        assert!(data.len() <= 16);
+                        output.extend_from_slice(data).unwrap()
+                    }
+                    None => {}
+                    _ => panic!("expected only ImageData output"),
+                }
+                event = leftover;
+            }
+
+            assert_eq!(&input[..], &output[..]);
+        }
+    }
+}
+
+#[cfg(feature = "pixels")]
+pub mod double_buffer {
+    use super::unfilter;
+    use super::*;
+    #[cfg(feature = "ancillary")]
+    use crate::extension::Extension;
+    use crate::stream_decoder::{ImageHeader, ImageInfo};
+
+    /// Packs the rows produced by the [`Unfilterer`](unfilter::Unfilterer) into two caller-owned
+    /// buffers alternately, so one can be filled while the other is still being transferred
+    /// (ping-pong DMA).
+    ///
+    /// The buffers are passed to every [`update`](Self::update) call rather than owned, so the
+    /// caller keeps full control over them. [`current`](Self::current) tells which of the two
+    /// buffers has to be passed next. Rows aren't split across buffers, a buffer which can't hold
+    /// a single row fails with [`Error::RowTooLarge`].
+    #[derive(Debug)]
+    pub struct DoubleBuffer {
+        current: usize,
+        filled: usize,
+        rows: usize,
+    }
+
+    #[derive(PartialEq, Debug)]
+    pub enum Event {
+        /// Passthrough
+        ImageHeader(ImageHeader),
+        /// Passthrough
+        ImageInfo(ImageInfo),
+        /// The buffer with the given index holds `rows` complete rows in its first `len` bytes
+        /// and can be handed off. Subsequent rows go to the other buffer.
+        BufferReady {
+            index: usize,
+            len: usize,
+            rows: usize,
+        },
+        /// Passthrough
+        #[cfg(feature = "ancillary")]
+        Extension(Extension),
+        /// Passthrough
+        Warning(Warning),
+        /// Passthrough
+        End,
+    }
+
+    impl Default for DoubleBuffer {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl DoubleBuffer {
+        /// Size of a `DoubleBuffer` in bytes, not counting the buffers, which are passed in.
+        pub const MEMORY_FOOTPRINT: usize = core::mem::size_of::<Self>();
+
+        pub fn new() -> Self {
+            Self {
+                current: 0,
+                filled: 0,
+                rows: 0,
+            }
+        }
+
+        /// Index (0 or 1) of the buffer which should be passed to the next `update` call.
+        pub fn current(&self) -> usize {
+            self.current
+        }
+
+        /// Number of bytes already written to the current buffer.
+        pub fn filled(&self) -> usize {
+            self.filled
+        }
+
+        /// Without the buffers, which are passed in.
+        pub fn memory_usage(&self) -> MemoryUsage {
+            MemoryUsage {
+                static_bytes: Self::MEMORY_FOOTPRINT,
+                peak_dynamic: 0,
+            }
+        }
+
+        /// Discards the partially filled buffer and starts over with buffer 0.
+        pub fn abort(&mut self) {
+            self.current = 0;
+            self.filled = 0;
+            self.rows = 0;
+        }
+
+        pub fn update<'a>(
+            &mut self,
+            input: unfilter::Event<'a>,
+            buf: &mut [u8],
+        ) -> Result<(Option<unfilter::Event<'a>>, Option<Event>), Error> {
+            match input {
+                unfilter::Event::ImageHeader(header) => {
+                    Ok((None, Some(Event::ImageHeader(header))))
+                }
+                unfilter::Event::ImageInfo(info) => Ok((None, Some(Event::ImageInfo(info)))),
+                unfilter::Event::Row(row) => {
+                    if row.is_empty() || row.len() > buf.len() {
+                        debug!(
+                            "row of {} bytes doesn't fit a buffer of {}",
+                            row.len(),
+                            buf.len()
+                        );
+                        return Err(Error::RowTooLarge);
+                    }
+                    let end = self.filled + row.len();
+                    let Some(dst) = buf.get_mut(self.filled..end) else {
+                        // Hand off the full buffer, the row goes to the other one
+                        return Ok((Some(unfilter::Event::Row(row)), Some(self.flip())));
+                    };
+                    dst.copy_from_slice(row);
+                    self.filled = end;
+                    self.rows += 1;
+
+                    if buf.len() - self.filled < row.len() {
+                        Ok((None, Some(self.flip())))
+                    } else {
+                        Ok((None, None))
+                    }
+                }
+                #[cfg(feature = "ancillary")]
+                unfilter::Event::Extension(e) => Ok((None, Some(Event::Extension(e)))),
+                unfilter::Event::Warning(w) => Ok((None, Some(Event::Warning(w)))),
+                unfilter::Event::End => {
+                    if self.rows > 0 {
+                        // Flush the partially filled buffer first
+                        Ok((Some(unfilter::Event::End), Some(self.flip())))
+                    } else {
+                        Ok((None, Some(Event::End)))
+                    }
+                }
+            }
+        }
+
+        fn flip(&mut self) -> Event {
+            let event = Event::BufferReady {
+                index: self.current,
+                len: self.filled,
+                rows: self.rows,
+            };
+            self.current = 1 - self.current;
+            self.filled = 0;
+            self.rows = 0;
+            event
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn alternates_buffers() {
+            let mut d = DoubleBuffer::new();
+            let mut bufs = [[0u8; 5]; 2];
+
+            assert_eq!(
+                d.update(unfilter::Event::Row(b"ab"), &mut bufs[d.current()])
+                    .unwrap(),
+                (None, None)
+            );
+            // The buffer has no room for another row
+            assert_eq!(
+                d.update(unfilter::Event::Row(b"cd"), &mut bufs[d.current()])
+                    .unwrap(),
+                (
+                    None,
+                    Some(Event::BufferReady {
+                        index: 0,
+                        len: 4,
+                        rows: 2
+                    })
+                )
+            );
+            assert_eq!(&bufs[0][..4], b"abcd");
+            assert_eq!(d.current(), 1);
+
+            assert_eq!(
+                d.update(unfilter::Event::Row(b"ef"), &mut bufs[d.current()])
+                    .unwrap(),
+                (None, None)
+            );
+            assert_eq!(d.filled(), 2);
+            assert_eq!(
+                d.update(unfilter::Event::Row(b"gh"), &mut bufs[d.current()])
+                    .unwrap(),
+                (
+                    None,
+                    Some(Event::BufferReady {
+                        index: 1,
+                        len: 4,
+                        rows: 2
+                    })
+                )
+            );
+            assert_eq!(&bufs[1][..4], b"efgh");
+            assert_eq!(d.current(), 0);
+        }
+
+        #[test]
+        fn flushes_partial_buffer_at_end() {
+            let mut d = DoubleBuffer::new();
+            let mut bufs = [[0u8; 4]; 2];
+
+            assert_eq!(
+                d.update(unfilter::Event::Row(b"a"), &mut bufs[d.current()])
+                    .unwrap(),
+                (None, None)
+            );
+
+            assert_eq!(
+                d.update(unfilter::Event::End, &mut bufs[d.current()])
+                    .unwrap(),
+                (
+                    Some(unfilter::Event::End),
+                    Some(Event::BufferReady {
+                        index: 0,
+                        len: 1,
+                        rows: 1
+                    })
+                )
+            );
+            assert_eq!(&bufs[0][..1], b"a");
+
+            assert_eq!(
+                d.update(unfilter::Event::End, &mut bufs[d.current()])
+                    .unwrap(),
+                (None, Some(Event::End))
+            );
+        }
+
+        #[test]
+        fn buffer_too_small() {
+            let mut d = DoubleBuffer::new();
+            assert_eq!(
+                d.update(unfilter::Event::Row(b"abc"), &mut [0; 2]),
+                Err(Error::RowTooLarge)
+            );
+            assert_eq!(
+                d.update(unfilter::Event::Row(b"a"), &mut []),
+                Err(Error::RowTooLarge)
+            );
+            assert_eq!(d.filled(), 0);
+        }
+    }
+}
+
+#[cfg(feature = "pixels")]
+pub mod unfilter {
+    use super::inflater;
+    use super::*;
+    use crate::encoder::filter::paeth;
+    #[cfg(feature = "ancillary")]
+    use crate::extension::Extension;
+    use crate::stream_decoder::{ImageHeader, ImageInfo, PixelLayout};
+
+    /// Reverses scanline filtering, producing rows of raw pixel data. Holds two scanlines, so
+    /// scanlines of up to `MAX_SCANLINE` bytes (including the filter type byte) are supported.
+    ///
+    /// Interlaced images are only supported up to a pass limit, see
+    /// [`with_adam7_pass_limit`](Unfilterer::with_adam7_pass_limit).
+    #[derive(Clone)]
+    pub struct Unfilterer<const MAX_SCANLINE: usize = 1024> {
+        rows: RowBuffer<MAX_SCANLINE>,
+        /// Bytes of the current scanline received so far
+        filled: usize,
+        /// Filter distance, bytes per complete pixel (at least 1)
+        bpp: usize,
+        layout: Option<PixelLayout>,
+        /// Rows produced so far, and expected according to the header
+        rows_done: u32,
+        height: u32,
+        /// Only every `row_step`th row is passed on
+        row_step: u32,
+        /// Header of the image, for the sizes of the Adam7 passes
+        header: Option<ImageHeader>,
+        /// Last Adam7 pass decoded, 0 if interlaced images aren't accepted
+        max_pass: u8,
+        /// Current Adam7 pass, 0 for non-interlaced images
+        pass: u8,
+        /// The passes up to `max_pass` are done, the rest of the image data is dropped
+        skipping: bool,
+        /// Red and blue are swapped in 8-bit RGB(A) pixels
+        bgr: bool,
+    }
+
+    #[derive(PartialEq, Debug)]
+    pub enum Event<'a> {
+        /// Passthrough
+        ImageHeader(ImageHeader),
+        /// Passthrough
+        ImageInfo(ImageInfo),
+        /// Unfiltered row, without the filter type byte
+        Row(&'a [u8]),
+        /// Passthrough
+        #[cfg(feature = "ancillary")]
+        Extension(Extension),
+        /// Passthrough, or reported by the [`Decoder`](crate::decoder::Decoder)
+        Warning(Warning),
+        /// Passthrough
+        End,
+    }
+
+    /// [`Event`] with the row copied, see [`dechunker::OwnedEvent`](crate::dechunker::OwnedEvent).
+    /// E.g. for handing rows to a render task.
+    #[cfg(feature = "heapless")]
+    #[derive(Clone, PartialEq, Debug)]
+    pub enum OwnedEvent<const N: usize> {
+        ImageHeader(ImageHeader),
+        ImageInfo(ImageInfo),
+        Row(heapless::Vec<u8, N>),
+        #[cfg(feature = "ancillary")]
+        Extension(Extension),
+        Warning(Warning),
+        End,
+    }
+
+    #[cfg(feature = "heapless")]
+    impl<const N: usize> TryFrom<Event<'_>> for OwnedEvent<N> {
+        type Error = Error;
+
+        /// Fails with [`Error::EventTooLarge`] if the row is longer than `N` bytes.
+        fn try_from(event: Event<'_>) -> Result<Self, Error> {
+            Ok(match event {
+                Event::ImageHeader(header) => OwnedEvent::ImageHeader(header),
+                Event::ImageInfo(info) => OwnedEvent::ImageInfo(info),
+                Event::Row(row) => OwnedEvent::Row(owned(row)?),
+                #[cfg(feature = "ancillary")]
+                Event::Extension(e) => OwnedEvent::Extension(e),
+                Event::Warning(w) => OwnedEvent::Warning(w),
+                Event::End => OwnedEvent::End,
+            })
+        }
+    }
+
+    #[cfg(feature = "heapless")]
+    impl<'a, const N: usize> From<&'a OwnedEvent<N>> for Event<'a> {
+        fn from(event: &'a OwnedEvent<N>) -> Self {
+            match event {
+                OwnedEvent::ImageHeader(header) => Event::ImageHeader(*header),
+                OwnedEvent::ImageInfo(info) => Event::ImageInfo(*info),
+                OwnedEvent::Row(row) => Event::Row(row),
+                #[cfg(feature = "ancillary")]
+                OwnedEvent::Extension(e) => Event::Extension(*e),
+                OwnedEvent::Warning(w) => Event::Warning(*w),
+                OwnedEvent::End => Event::End,
+            }
+        }
+    }
+
+    impl<const MAX_SCANLINE: usize> Default for Unfilterer<MAX_SCANLINE> {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    /// Shows the position in the scanline, but not the rows.
+    impl<const MAX_SCANLINE: usize> core::fmt::Debug for Unfilterer<MAX_SCANLINE> {
+        fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+            f.debug_struct("Unfilterer")
+                .field("state", &self.state_name())
+                .field("filled", &self.filled)
+                .field("scanline_len", &self.rows.scanline_len())
+                .field("layout", &self.layout)
+                .finish()
+        }
+    }
+
+    impl<const MAX_SCANLINE: usize> Unfilterer<MAX_SCANLINE> {
+        /// Size of an `Unfilterer` in bytes, including both row buffers.
+        pub const MEMORY_FOOTPRINT: usize = core::mem::size_of::<Self>();
+
+        /// The row buffers are zeroed lazily, up to the scanline length of the image.
+        pub fn new() -> Self {
+            Self {
+                rows: RowBuffer::new(),
+                filled: 0,
+                bpp: 1,
+                layout: None,
+                rows_done: 0,
+                height: 0,
+                row_step: 1,
+                header: None,
+                max_pass: 0,
+                pass: 0,
+                skipping: false,
+                bgr: false,
+            }
+        }
+
+        /// Accepts interlaced images, passing on the rows of Adam7 passes 1 to `last_pass` in
+        /// turn, each pass a reduced image of the size given by
+        /// [`ImageHeader::adam7_pass_size`]. The image data after them is dropped.
+        ///
+        /// Pass 1 is a thumbnail of 1/64 of the pixels, which is available after the first
+        /// 1/64 or so of the image data: once [`eof`](Self::eof) succeeds the rest of the file
+        /// can be skipped.
+        pub fn with_adam7_pass_limit(mut self, last_pass: u8) -> Self {
+            self.max_pass = last_pass.clamp(1, 7);
+            self
+        }
+
+        /// The Adam7 pass the rows currently belong to, `None` for non-interlaced images.
+        pub fn current_pass(&self) -> Option<u8> {
+            (self.pass > 0).then_some(self.pass)
+        }
+
+        /// Passes on only rows 0, `step`, `2 * step`, ..., e.g. for quick thumbnails. Every row is
+        /// still unfiltered, as the next one depends on it, but the skipped ones don't produce an
+        /// [`Event::Row`], so whatever the caller does with rows is skipped too.
+        pub fn with_row_step(mut self, step: u32) -> Self {
+            self.row_step = core::cmp::max(step, 1);
+            self
+        }
+
+        /// Swaps the red and blue samples of 8-bit RGB and RGBA images, whose pixels are stored
+        /// as BGR(A), as in CgBI files (see
+        /// [`StreamDecoder::is_cgbi`](crate::stream_decoder::StreamDecoder::is_cgbi)). Alpha
+        /// stays premultiplied.
+        pub fn set_bgr_order(&mut self, bgr: bool) {
+            self.bgr = bgr;
+        }
+
+        /// Layout of the rows, once the image header has passed through.
+        pub fn pixel_layout(&self) -> Option<PixelLayout> {
+            self.layout
+        }
+
+        /// Fails with [`Error::RowCountMismatch`] unless exactly as many rows as the header says
+        /// were produced (or, for interlaced images, the rows of all the passes up to the limit).
+        pub fn eof(&self) -> Result<(), Error> {
+            if self.rows.scanline_len() == 0 {
+                return Err(Error::NoImageHeader);
+            }
+            if self.skipping {
+                return Ok(());
+            }
+            if self.rows_done != self.height
+                || (0 < self.filled && self.filled < self.rows.scanline_len())
+                || (self.pass > 0 && self.next_pass().is_some())
+            {
+                return Err(Error::RowCountMismatch);
+            }
+            Ok(())
+        }
+
+        /// The dynamic part is the two row buffers, as far as the longest scanline so far used
+        /// them.
+        pub fn memory_usage(&self) -> MemoryUsage {
+            MemoryUsage {
+                static_bytes: Self::MEMORY_FOOTPRINT,
+                peak_dynamic: self.rows.memory_usage().peak_dynamic,
+            }
+        }
+
+        /// Position in the scanline, for debugging.
+        pub fn state_name(&self) -> &'static str {
+            match self.filled {
+                _ if self.rows.scanline_len() == 0 => "waiting for header",
+                0 => "between rows",
+                n if n == self.rows.scanline_len() => "row complete",
+                _ => "in row",
+            }
+        }
+
+        pub fn update<'this, 'a>(
+            &'this mut self,
+            input: inflater::Event<'a>,
+        ) -> Result<(Option<inflater::Event<'a>>, Option<Event<'this>>), Error> {
+            match input {
+                inflater::Event::ImageHeader(header) => {
+                    match header.interlace_method {
+                        0 => {}
+                        1 if self.max_pass > 0 => {}
+                        _ => return Err(Error::InterlaceNotSupported),
+                    }
+                    let layout = header.pixel_layout().ok_or(Error::InvalidColourType)?;
+                    self.bpp = layout.bytes_per_pixel as usize;
+                    self.layout = Some(layout);
+                    self.header = Some(header);
+                    self.skipping = false;
+                    if header.interlace_method == 0 {
+                        self.pass = 0;
+                        self.start_rows(header.width, header.height)?;
+                    } else {
+                        self.pass = 1;
+                        let (width, height) = header.adam7_pass_size(1).unwrap_or_default();
+                        self.start_rows(width, height)?;
+                    }
+                    trace!(
+                        "unfilterer: {} byte scanlines, {} bytes per pixel",
+                        self.rows.scanline_len(),
+                        self.bpp
+                    );
+                    Ok((None, Some(Event::ImageHeader(header))))
+                }
+                inflater::Event::ImageInfo(info) => Ok((None, Some(Event::ImageInfo(info)))),
+                inflater::Event::ImageData(input) => {
+                    let dst = self.row_space()?;
+                    let n = core::cmp::min(input.len(), dst.len());
+                    for (dst, src) in dst.iter_mut().zip(input) {
+                        *dst = *src;
+                    }
+
+                    let leftover = match input.get(n..) {
+                        Some(rest @ [_, ..]) => Some(inflater::Event::ImageData(rest)),
+                        _ => None,
+                    };
+                    Ok((leftover, self.commit(n)?))
+                }
+                #[cfg(feature = "ancillary")]
+                inflater::Event::Extension(e) => Ok((None, Some(Event::Extension(e)))),
+                inflater::Event::Warning(w) => Ok((None, Some(Event::Warning(w)))),
+                inflater::Event::End => Ok((None, Some(Event::End))),
+            }
+        }
+
+        /// The part of the current scanline which hasn't been received yet, for writing image
+        /// data into directly, e.g. with [`Inflater::inflate_into`](crate::inflater::Inflater::inflate_into).
+        /// Follow up with [`commit`](Self::commit).
+        pub fn row_space(&mut self) -> Result<&mut [u8], Error> {
+            let len = self.rows.scanline_len();
+            if self.filled == len && self.pass > 0 && self.rows_done == self.height {
+                match self.next_pass() {
+                    Some((pass, width, height)) => {
+                        self.pass = pass;
+                        self.start_rows(width, height)?;
+                    }
+                    None => {
+                        // The data of the remaining passes lands in the current row
+                        self.skipping = true;
+                        self.filled = 0;
+                    }
+                }
+            }
+            let len = self.rows.scanline_len();
+            if self.filled == len {
+                // The previous call returned a row, it becomes the previous one now
+                self.rows.swap();
+                self.filled = 0;
+            }
+            if len == 0 {
+                return Err(Error::NoImageHeader);
+            }
+            let filled = self.filled;
+            let (row, _) = self.rows.rows_mut();
+            Ok(row.get_mut(filled..).unwrap_or_default())
+        }
+
+        /// Marks the first `n` bytes of [`row_space`](Self::row_space) as received, returning
+        /// the unfiltered row once it's complete (unless skipped, see
+        /// [`with_row_step`](Self::with_row_step)).
+        pub fn commit(&mut self, n: usize) -> Result<Option<Event<'_>>, Error> {
+            if self.skipping {
+                return Ok(None);
+            }
+            let len = self.rows.scanline_len();
+            self.filled = core::cmp::min(self.filled + n, len);
+            if self.filled < len {
+                return Ok(None);
+            }
+            self.unfilter()?;
+            let index = self.rows_done;
+            self.rows_done += 1;
+            if !index.is_multiple_of(self.row_step) {
+                return Ok(None);
+            }
+            Ok(Some(Event::Row(
+                self.rows.current().get(1..).unwrap_or_default(),
+            )))
+        }
+
+        /// Starts an image (or Adam7 pass) of the given size. The row before the first one is
+        /// all zeros.
+        fn start_rows(&mut self, width: u32, height: u32) -> Result<(), Error> {
+            let header = self.header.as_ref().ok_or(Error::NoImageHeader)?;
+            let scanline_len = header
+                .scanline_len_for(width)
+                .ok_or(Error::InvalidColourType)?;
+            self.rows
+                .start(usize::try_from(scanline_len).map_err(|_| Error::RowTooLarge)?)?;
+            self.rows_done = 0;
+            self.height = height;
+            self.filled = 0;
+            Ok(())
+        }
+
+        /// The next non-empty Adam7 pass up to the limit, with its size.
+        fn next_pass(&self) -> Option<(u8, u32, u32)> {
+            let header = self.header.as_ref()?;
+            (self.pass + 1..=self.max_pass).find_map(|pass| {
+                let (width, height) = header.adam7_pass_size(pass)?;
+                (width > 0 && height > 0).then_some((pass, width, height))
+            })
+        }
+
+        fn unfilter(&mut self) -> Result<(), Error> {
+            let (len, bpp) = (self.rows.scanline_len(), self.bpp);
+            let (row, prev) = self.rows.rows_mut();
+            let (Some((&mut filter_type, row)), Some(prev)) = (
+                row.get_mut(..len).and_then(<[u8]>::split_first_mut),
+                prev.get(1..len),
+            ) else {
+                return Ok(());
+            };
+            // Swapped before unfiltering, which treats each sample position separately, so that
+            // the previous row is in the same order
+            let rgb = matches!(
+                self.layout,
+                Some(PixelLayout {
+                    colour_type: 2 | 6,
+                    bit_depth: 8,
+                    ..
+                })
+            );
+            if self.bgr && rgb && bpp >= 3 {
+                for pixel in row.chunks_exact_mut(bpp) {
+                    if let [b, _, r, ..] = pixel {
+                        core::mem::swap(b, r);
+                    }
+                }
+            }
+            #[cfg(all(feature = "simd", target_arch = "x86_64"))]
+            if simd::unfilter(filter_type, row, prev, bpp) {
+                return Ok(());
+            }
+            unfilter_row(filter_type, row, prev, bpp)
+        }
+    }
+
+    /// The current and previous scanline, as needed for unfiltering: each row is unfiltered
+    /// using the previous one, then becomes the previous one itself. Holds scanlines of up to
+    /// `MAX_SCANLINE` bytes, including the filter type byte.
+    ///
+    /// For assets of known size, the capacity can be checked at compile time:
+    ///
+    /// ```
+    /// use incremental_png::unfilter::RowBuffer;
+    ///
+    /// // 32 pixels of 8-bit RGB
+    /// const _: () = RowBuffer::<97>::assert_fits(32, 24);
+    /// ```
+    #[derive(Clone)]
+    pub struct RowBuffer<const MAX_SCANLINE: usize = 1024> {
+        /// Zeroed lazily, as far as scanlines have used them
+        rows: [UninitBuf<MAX_SCANLINE>; 2],
+        current: usize,
+        scanline_len: usize,
+    }
+
+    impl<const MAX_SCANLINE: usize> Default for RowBuffer<MAX_SCANLINE> {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl<const MAX_SCANLINE: usize> core::fmt::Debug for RowBuffer<MAX_SCANLINE> {
+        fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+            f.debug_struct("RowBuffer")
+                .field("current", &self.current)
+                .field("scanline_len", &self.scanline_len)
+                .finish()
+        }
+    }
+
+    impl<const MAX_SCANLINE: usize> RowBuffer<MAX_SCANLINE> {
+        pub const MEMORY_FOOTPRINT: usize = core::mem::size_of::<Self>();
+
+        pub const fn new() -> Self {
+            Self {
+                rows: [UninitBuf::new(), UninitBuf::new()],
+                current: 0,
+                scanline_len: 0,
+            }
+        }
+
+        /// Bytes in a scanline of `width` pixels of `bits_per_pixel` bits, including the filter
+        /// type byte.
+        pub const fn scanline_len_for(width: u32, bits_per_pixel: u8) -> u64 {
+            1 + (width as u64 * bits_per_pixel as u64).div_ceil(8)
+        }
+
+        /// Whether scanlines of `width` pixels of `bits_per_pixel` bits fit.
+        pub const fn fits(width: u32, bits_per_pixel: u8) -> bool {
+            Self::scanline_len_for(width, bits_per_pixel) <= MAX_SCANLINE as u64
+        }
+
+        /// Fails to compile when used in a constant and scanlines of `width` pixels of
+        /// `bits_per_pixel` bits don't fit.
+        pub const fn assert_fits(width: u32, bits_per_pixel: u8) {
+            assert!(
+                Self::fits(width, bits_per_pixel),
+                "scanline exceeds the row buffer"
+            );
+        }
+
+        /// Starts an image (or Adam7 pass) with scanlines of `len` bytes. The row before the
+        /// first one is all zeros. Fails with [`Error::RowTooLarge`] if they don't fit.
+        pub fn start(&mut self, len: usize) -> Result<(), Error> {
+            if len > MAX_SCANLINE {
+                return Err(Error::RowTooLarge);
+            }
+            self.scanline_len = len;
+            let (_, prev) = self.rows_mut();
+            prev.fill(0);
+            Ok(())
+        }
+
+        /// Length of the scanlines, 0 before [`start`](Self::start).
+        pub fn scanline_len(&self) -> usize {
+            self.scanline_len
+        }
+
+        /// Current and previous row, `scanline_len` bytes each.
+        pub fn rows_mut(&mut self) -> (&mut [u8], &mut [u8]) {
+            let len = self.scanline_len;
+            let [a, b] = &mut self.rows;
+            let (a, b) = (a.initialized_mut(len), b.initialized_mut(len));
+            if self.current == 0 {
+                (a, b)
+            } else {
+                (b, a)
+            }
+        }
+
+        /// The current row.
+        pub fn current(&self) -> &[u8] {
+            let [a, b] = &self.rows;
+            let row = if self.current == 0 { a } else { b };
+            row.initialized(self.scanline_len)
+        }
+
+        /// Makes the current row the previous one. The old previous row is overwritten by the
+        /// next one.
+        pub fn swap(&mut self) {
+            self.current = 1 - self.current;
+        }
+
+        /// The dynamic part is the two rows, as far as the longest scanline so far used them.
+        pub fn memory_usage(&self) -> MemoryUsage {
+            MemoryUsage {
+                static_bytes: Self::MEMORY_FOOTPRINT,
+                peak_dynamic: self.rows.iter().map(UninitBuf::initialized_len).sum(),
+            }
+        }
+    }
+
+    /// Reverses the filter of `row`, given the previous row of the same length.
+    fn unfilter_row(filter_type: u8, row: &mut [u8], prev: &[u8], bpp: usize) -> Result<(), Error> {
+        // Same length, but spelled out so that the bounds checks below can be optimized out
+        let len = core::cmp::min(row.len(), prev.len());
+        let (row, prev) = (&mut row[..len], &prev[..len]);
+        match filter_type {
+            0 => {}
+            1 => {
+                for i in 0..row.len() {
+                    let a = if i >= bpp { row[i - bpp] } else { 0 };
+                    row[i] = row[i].wrapping_add(a);
+                }
+            }
+            2 => {
+                for i in 0..row.len() {
+                    row[i] = row[i].wrapping_add(prev[i]);
+                }
+            }
+            3 => {
+                for i in 0..row.len() {
+                    let a = if i >= bpp { row[i - bpp] } else { 0 };
+                    row[i] = row[i].wrapping_add(((a as u16 + prev[i] as u16) / 2) as u8);
+                }
+            }
+            4 => {
+                for i in 0..row.len() {
+                    let (a, c) = if i >= bpp {
+                        (row[i - bpp], prev[i - bpp])
+                    } else {
+                        (0, 0)
+                    };
+                    row[i] = row[i].wrapping_add(paeth(a, prev[i], c));
+                }
+            }
+            _ => {
+                debug!("unfilterer: invalid filter type {}", filter_type);
+                return Err(Error::InvalidFilterType);
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(all(feature = "simd", target_arch = "x86_64"))]
+    mod simd {
+        //! SSE2 versions of the Sub, Average and Paeth filters for 3 and 4 bytes per pixel, i.e.
+        //! 8-bit RGB and RGBA. These filters depend on the previous pixel, so the gain comes
+        //! from handling all bytes of a pixel at once. Up is vectorized by the compiler anyway.
+        //!
+        //! SSE2 is part of x86_64, so there's no runtime detection.
+
+        use core::arch::x86_64::*;
+
+        /// Unfilters `row` if the filter type and `bpp` are handled here, returning `false`
+        /// otherwise.
+        pub(super) fn unfilter(filter_type: u8, row: &mut [u8], prev: &[u8], bpp: usize) -> bool {
+            if !matches!(bpp, 3 | 4) {
+                return false;
+            }
+            // SAFETY: SSE2 is always available on x86_64
+            unsafe {
+                match filter_type {
+                    1 => sub(row, bpp),
+                    3 => avg(row, prev, bpp),
+                    4 => paeth(row, prev, bpp),
+                    _ => return false,
+                }
+            }
+            true
+        }
+
+        #[target_feature(enable = "sse2")]
+        fn load(pixel: &[u8]) -> __m128i {
+            let mut bytes = [0; 4];
+            for (dst, src) in bytes.iter_mut().zip(pixel) {
+                *dst = *src;
+            }
+            _mm_cvtsi32_si128(i32::from_le_bytes(bytes))
+        }
+
+        #[target_feature(enable = "sse2")]
+        fn store(pixel: &mut [u8], v: __m128i) {
+            for (dst, src) in pixel.iter_mut().zip(_mm_cvtsi128_si32(v).to_le_bytes()) {
+                *dst = src;
+            }
+        }
+
+        #[target_feature(enable = "sse2")]
+        fn sub(row: &mut [u8], bpp: usize) {
+            let mut a = _mm_setzero_si128();
+            for x in row.chunks_exact_mut(bpp) {
+                a = _mm_add_epi8(a, load(x));
+                store(x, a);
+            }
+        }
+
+        #[target_feature(enable = "sse2")]
+        fn avg(row: &mut [u8], prev: &[u8], bpp: usize) {
+            let ones = _mm_set1_epi8(1);
+            let mut a = _mm_setzero_si128();
+            for (x, b) in row.chunks_exact_mut(bpp).zip(prev.chunks_exact(bpp)) {
+                let b = load(b);
+                // `_mm_avg_epu8` rounds up, the filter rounds down
+                let avg =
+                    _mm_sub_epi8(_mm_avg_epu8(a, b), _mm_and_si128(_mm_xor_si128(a, b), ones));
+                a = _mm_add_epi8(load(x), avg);
+                store(x, a);
+            }
+        }
+
+        #[target_feature(enable = "sse2")]
+        fn paeth(row: &mut [u8], prev: &[u8], bpp: usize) {
+            let zero = _mm_setzero_si128();
+            let abs = |v| _mm_max_epi16(v, _mm_sub_epi16(zero, v));
+            let select = |mask, then, otherwise| {
+                _mm_or_si128(_mm_and_si128(mask, then), _mm_andnot_si128(mask, otherwise))
+            };
+            // Samples widened to 16 bits, as the predictor needs signed differences
+            let (mut a, mut c) = (zero, zero);
+            for (x, b) in row.chunks_exact_mut(bpp).zip(prev.chunks_exact(bpp)) {
+                let b = _mm_unpacklo_epi8(load(b), zero);
+                // |p - a|, |p - b| and |p - c| where p = a + b - c
+                let pa = _mm_sub_epi16(b, c);
+                let pb = _mm_sub_epi16(a, c);
+                let pc = abs(_mm_add_epi16(pa, pb));
+                let (pa, pb) = (abs(pa), abs(pb));
+                let smallest = _mm_min_epi16(pc, _mm_min_epi16(pa, pb));
+                // Ties go to a, then b
+                let predictor = select(
+                    _mm_cmpeq_epi16(smallest, pa),
+                    a,
+                    select(_mm_cmpeq_epi16(smallest, pb), b, c),
+                );
+                let result = _mm_add_epi8(load(x), _mm_packus_epi16(predictor, predictor));
+                store(x, result);
+                a = _mm_unpacklo_epi8(result, zero);
+                c = b;
+            }
+        }
+
+        #[cfg(test)]
+        mod tests {
+            extern crate std;
+            use std::vec::Vec;
+
+            #[test]
+            fn matches_scalar() {
+                // Pseudo-random rows covering all byte values
+                let bytes = |seed: u32| -> Vec<u8> {
+                    (0..240u32)
+                        .map(|i| (i.wrapping_mul(2654435761).wrapping_add(seed) >> 13) as u8)
+                        .collect()
+                };
+                let prev = bytes(1);
+                for bpp in [3, 4] {
+                    for filter_type in [1, 3, 4] {
+                        let mut expected = bytes(2);
+                        super::super::unfilter_row(filter_type, &mut expected, &prev, bpp).unwrap();
+                        let mut row = bytes(2);
+                        assert!(super::unfilter(filter_type, &mut row, &prev, bpp));
+                        assert_eq!(row, expected, "filter {filter_type}, bpp {bpp}");
+                    }
+                    assert!(!super::unfilter(2, &mut bytes(2), &prev, bpp));
+                }
+                assert!(!super::unfilter(1, &mut bytes(2), &prev, 2));
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        extern crate std;
+        use std::vec::Vec;
+
+        fn header(width: u32, colour_type: u8) -> ImageHeader {
+            ImageHeader {
+                width,
+                height: 3,
+                bit_depth: 8,
+                colour_type,
+                compression_method: 0,
+                filter_method: 0,
+                interlace_method: 0,
+            }
+        }
+
+        fn unfilter(header: ImageHeader, chunks: &[&[u8]]) -> Result<Vec<Vec<u8>>, Error> {
+            unfilter_with(&mut Unfilterer::new(), header, chunks)
+        }
+
+        fn unfilter_with(
+            u: &mut Unfilterer<16>,
+            header: ImageHeader,
+            chunks: &[&[u8]],
+        ) -> Result<Vec<Vec<u8>>, Error> {
+            let mut rows = Vec::new();
+            u.update(inflater::Event::ImageHeader(header))?;
+            for &chunk in chunks {
+                let mut event = Some(inflater::Event::ImageData(chunk));
+                while let Some(e) = event {
+                    let (leftover, output) = u.update(e)?;
+                    if let Some(Event::Row(row)) = output {
+                        rows.push(row.to_vec());
+                    }
+                    event = leftover;
+                }
+            }
+            Ok(rows)
+        }
+
+        #[test]
+        fn filter_types() {
+            let rows = unfilter(
+                header(2, 2),
+                &[
+                    &[1, 10, 20, 30, 1, 2, 3],
+                    &[2, 1, 1, 1, 1, 1, 1, 3, 0, 0, 0, 2, 2, 2],
+                    &[4, 1, 1, 1, 0, 0, 0],
+                ],
+            )
+            .unwrap();
+            assert_eq!(
+                rows,
+                [
+                    [10, 20, 30, 11, 22, 33],
+                    [11, 21, 31, 12, 23, 34],
+                    [5, 10, 15, 10, 18, 26],
+                    [6, 11, 16, 10, 18, 26],
+                ]
+            );
+        }
+
+        #[test]
+        fn row_step() {
+            // The last row depends on the skipped one
+            let data = [0, 1, 2, 3, 2, 1, 1, 1, 2, 1, 0, 0];
+            let mut u = Unfilterer::new().with_row_step(2);
+            let rows = unfilter_with(&mut u, header(3, 0), &[&data]).unwrap();
+            assert_eq!(rows, [[1, 2, 3], [3, 3, 4]]);
+            u.eof().unwrap();
+        }
+
+        #[test]
+        fn adam7_pass_limit() {
+            // 2x2 pixels: only passes 1, 6 and 7 aren't empty
+            let header = ImageHeader {
+                width: 2,
+                height: 2,
+                interlace_method: 1,
+                ..header(2, 0)
+            };
+            let data = [0, 10, 0, 20, 2, 30, 40];
+            assert_eq!(
+                unfilter(header, &[&data]),
+                Err(Error::InterlaceNotSupported)
+            );
+
+            let mut u = Unfilterer::new().with_adam7_pass_limit(7);
+            let rows = unfilter_with(&mut u, header, &[&data]).unwrap();
+            assert_eq!(rows, [&[10][..], &[20], &[30, 40]]);
+            assert_eq!(u.current_pass(), Some(7));
+            u.eof().unwrap();
+
+            let mut u = Unfilterer::new().with_adam7_pass_limit(1);
+            let rows = unfilter_with(&mut u, header, &[&data[..2]]).unwrap();
+            assert_eq!(rows, [[10]]);
+            u.eof().unwrap();
+            // The other passes are dropped
+            let rows = unfilter_with(&mut u, header, &[&data[..2], &data[2..]]).unwrap();
+            assert_eq!(rows, [[10]]);
+            u.eof().unwrap();
+
+            let mut u = Unfilterer::new().with_adam7_pass_limit(6);
+            unfilter_with(&mut u, header, &[&data[..3]]).unwrap();
+            assert_eq!(u.current_pass(), Some(6));
+            assert_eq!(u.eof(), Err(Error::RowCountMismatch));
+        }
+
+        #[test]
+        fn split_input() {
+            let data = [0, 1, 2, 3, 2, 1, 1, 1, 1, 3, 0, 0];
+            let whole = unfilter(header(3, 0), &[&data]).unwrap();
+            let split = unfilter(header(3, 0), &[&data[..1], &data[1..6], &data[6..]]).unwrap();
+            assert_eq!(whole, [[1, 2, 3], [2, 3, 4], [3, 3, 3]]);
+            assert_eq!(split, whole);
+        }
+
+        #[test]
+        fn pixel_layout() {
+            let mut u = Unfilterer::<16>::new();
+            assert_eq!(u.pixel_layout(), None);
+            u.update(inflater::Event::ImageHeader(header(2, 2)))
+                .unwrap();
+            assert_eq!(u.pixel_layout(), header(2, 2).pixel_layout());
+        }
+
+        #[test]
+        fn eof() {
+            let mut u = Unfilterer::<16>::new();
+            assert_eq!(u.eof(), Err(Error::NoImageHeader));
+            u.update(inflater::Event::ImageHeader(header(1, 0)))
+                .unwrap();
+            for row in [&[0, 1][..], &[0, 2], &[0]] {
+                assert_eq!(u.eof(), Err(Error::RowCountMismatch));
+                u.update(inflater::Event::ImageData(row)).unwrap();
+            }
+            assert_eq!(u.eof(), Err(Error::RowCountMismatch));
+            u.update(inflater::Event::ImageData(&[3])).unwrap();
+            u.eof().unwrap();
+        }
+
+        #[test]
+        fn row_buffer() {
+            const _: () = RowBuffer::<5>::assert_fits(2, 16);
+            assert!(!RowBuffer::<5>::fits(3, 16));
+            assert!(RowBuffer::<2>::fits(8, 1));
+
+            let mut rows = RowBuffer::<4>::new();
+            assert_eq!(rows.start(5), Err(Error::RowTooLarge));
+            rows.start(3).unwrap();
+            assert_eq!(rows.current(), [0, 0, 0]);
+            rows.rows_mut().0.copy_from_slice(&[1, 2, 3]);
+            rows.swap();
+            assert_eq!(rows.rows_mut(), (&mut [0, 0, 0][..], &mut [1, 2, 3][..]));
+
+            // A new image starts from a zero row again
+            rows.start(2).unwrap();
+            assert_eq!(rows.rows_mut().1, [0, 0]);
+            assert_eq!(rows.memory_usage().peak_dynamic, 6);
+        }
+
+        #[test]
+        fn invalid_filter_type() {
+            assert_eq!(
+                unfilter(header(1, 0), &[&[5, 0]]),
+                Err(Error::InvalidFilterType)
+            );
+        }
+    }
+}
+
+#[cfg(feature = "pixels")]
+pub mod pixel_iter {
+    //! Pixels of decoded rows with their coordinates, for sparse operations such as hit-testing
+    //! an icon or sampling a few points.
+
+    use crate::stream_decoder::{ImageHeader, PixelLayout, ADAM7_PASSES};
+    use crate::unfilter;
+
+    /// Values of the samples below 8 bits, which can't be borrowed from the row.
+    static SAMPLE_VALUES: [u8; 16] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15];
+
+    /// Iterator over the pixels of a row as `(x, y, samples)`.
+    ///
+    /// `samples` are the bytes of the pixel as stored in the row, e.g. 6 bytes for 16-bit RGB
+    /// or a palette index. Samples below 8 bits are unpacked to a byte, but not scaled.
+    #[derive(Clone, Debug)]
+    pub struct Pixels<'a> {
+        row: &'a [u8],
+        layout: PixelLayout,
+        /// Pixels in the row
+        width: u32,
+        /// Next pixel in the row
+        index: u32,
+        y: u32,
+        /// Position in the image of the first pixel, and of the following ones relative to it
+        x_offset: u32,
+        x_step: u32,
+    }
+
+    impl<'a> Pixels<'a> {
+        /// Pixels of row `y` of a non-interlaced image `width` pixels wide.
+        pub fn new(row: &'a [u8], layout: PixelLayout, width: u32, y: u32) -> Self {
+            Self {
+                row,
+                layout,
+                width,
+                index: 0,
+                y,
+                x_offset: 0,
+                x_step: 1,
+            }
+        }
+
+        pub fn layout(&self) -> PixelLayout {
+            self.layout
+        }
+
+        fn samples(&self, index: u32) -> Option<&'a [u8]> {
+            let bit_depth = self.layout.bit_depth as usize;
+            if bit_depth >= 8 {
+                let len = self.layout.bytes_per_pixel as usize;
+                let start = (index as usize).checked_mul(len)?;
+                return self.row.get(start..start.checked_add(len)?);
+            }
+            let bit = (index as usize).checked_mul(bit_depth)?;
+            let byte = *self.row.get(bit / 8)?;
+            let shift = 8usize.checked_sub(bit_depth + bit % 8)?;
+            let sample = ((byte >> shift) & ((1 << bit_depth) - 1)) as usize;
+            SAMPLE_VALUES.get(sample..sample + 1)
+        }
+    }
+
+    impl<'a> Iterator for Pixels<'a> {
+        type Item = (u32, u32, &'a [u8]);
+
+        fn next(&mut self) -> Option<Self::Item> {
+            if self.index >= self.width {
+                return None;
+            }
+            let samples = self.samples(self.index)?;
+            let x = self.x_offset + self.index * self.x_step;
+            self.index += 1;
+            Some((x, self.y, samples))
+        }
+    }
+
+    /// Follows the events of an [`Unfilterer`](unfilter::Unfilterer), working out where each
+    /// row goes in the image, also for the passes of interlaced images.
+    #[derive(Clone, Debug)]
+    pub struct RowTracker {
+        header: Option<ImageHeader>,
+        layout: Option<PixelLayout>,
+        /// Current Adam7 pass, 0 for non-interlaced images
+        pass: u8,
+        /// Index of the next row in the pass (or image)
+        row: u32,
+        row_step: u32,
+    }
+
+    impl Default for RowTracker {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl RowTracker {
+        pub fn new() -> Self {
+            Self {
+                header: None,
+                layout: None,
+                pass: 0,
+                row: 0,
+                row_step: 1,
+            }
+        }
+
+        /// For an Unfilterer which passes on only every `step`th row, see
+        /// [`Unfilterer::with_row_step`](unfilter::Unfilterer::with_row_step).
+        pub fn with_row_step(mut self, step: u32) -> Self {
+            self.row_step = core::cmp::max(step, 1);
+            self
+        }
+
+        /// The pixels of an [`Event::Row`](unfilter::Event::Row), `None` for other events.
+        /// Every event of the Unfilterer has to be passed in, in order.
+        pub fn pixels<'a>(&mut self, event: &unfilter::Event<'a>) -> Option<Pixels<'a>> {
+            match event {
+                unfilter::Event::ImageHeader(header) => {
+                    self.header = Some(*header);
+                    self.layout = header.pixel_layout();
+                    self.pass = (header.interlace_method != 0) as u8;
+                    self.row = 0;
+                    None
+                }
+                unfilter::Event::Row(row) => {
+                    let (header, layout) = (self.header?, self.layout?);
+                    if self.pass == 0 {
+                        let y = self.row;
+                        self.row = self.row.saturating_add(self.row_step);
+                        return Some(Pixels::new(row, layout, header.width, y));
+                    }
+                    // Empty passes don't produce rows
+                    while self.pass <= 7 {
+                        let (width, height) = header.adam7_pass_size(self.pass)?;
+                        if width > 0 && self.row < height {
+                            break;
+                        }
+                        self.pass += 1;
+                        self.row = 0;
+                    }
+                    let &(x0, y0, dx, dy) = ADAM7_PASSES.get(self.pass as usize - 1)?;
+                    let (width, _) = header.adam7_pass_size(self.pass)?;
+                    let y = y0 as u32 + self.row * dy as u32;
+                    self.row = self.row.saturating_add(self.row_step);
+                    Some(Pixels {
+                        x_offset: x0 as u32,
+                        x_step: dx as u32,
+                        ..Pixels::new(row, layout, width, y)
+                    })
+                }
+                _ => None,
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        extern crate std;
+        use std::vec::Vec;
+
+        fn header(width: u32, height: u32, bit_depth: u8, interlace_method: u8) -> ImageHeader {
+            ImageHeader {
+                width,
+                height,
+                bit_depth,
+                colour_type: 0,
+                compression_method: 0,
+                filter_method: 0,
+                interlace_method,
+            }
+        }
+
+        fn coordinates(
+            tracker: &mut RowTracker,
+            events: &[unfilter::Event],
+        ) -> Vec<(u32, u32, Vec<u8>)> {
+            events
+                .iter()
+                .filter_map(|e| tracker.pixels(e))
+                .flatten()
+                .map(|(x, y, samples)| (x, y, samples.to_vec()))
+                .collect()
+        }
+
+        #[test]
+        fn sub_byte_samples() {
+            let mut tracker = RowTracker::new();
+            let events = [
+                unfilter::Event::ImageHeader(header(3, 2, 4, 0)),
+                unfilter::Event::Row(&[0x12, 0x30]),
+                unfilter::Event::Row(&[0x45, 0x60]),
+                unfilter::Event::End,
+            ];
+            assert_eq!(
+                coordinates(&mut tracker, &events),
+                [
+                    (0, 0, [1].into()),
+                    (1, 0, [2].into()),
+                    (2, 0, [3].into()),
+                    (0, 1, [4].into()),
+                    (1, 1, [5].into()),
+                    (2, 1, [6].into()),
+                ]
+            );
+
+            let mut tracker = RowTracker::new().with_row_step(2);
+            let events = [
+                unfilter::Event::ImageHeader(header(1, 4, 16, 0)),
+                unfilter::Event::Row(&[1, 2]),
+                unfilter::Event::Row(&[3, 4]),
+            ];
+            assert_eq!(
+                coordinates(&mut tracker, &events),
+                [(0, 0, [1, 2].into()), (0, 2, [3, 4].into())]
+            );
+        }
+
+        #[test]
+        fn interlaced() {
+            // Passes 1 and 2 have a pixel each, pass 3 two
+            let mut tracker = RowTracker::new();
+            let events = [
+                unfilter::Event::ImageHeader(header(8, 8, 8, 1)),
+                unfilter::Event::Row(&[10]),
+                unfilter::Event::Row(&[20]),
+                unfilter::Event::Row(&[30, 31]),
+            ];
+            assert_eq!(
+                coordinates(&mut tracker, &events),
+                [
+                    (0, 0, [10].into()),
+                    (4, 0, [20].into()),
+                    (0, 4, [30].into()),
+                    (4, 4, [31].into()),
+                ]
+            );
+
+            // Pass 2 is empty for images up to 4 pixels wide
+            let mut tracker = RowTracker::new();
+            let events = [
+                unfilter::Event::ImageHeader(header(3, 5, 8, 1)),
+                unfilter::Event::Row(&[10]),
+                unfilter::Event::Row(&[30]),
+            ];
+            assert_eq!(
+                coordinates(&mut tracker, &events),
+                [(0, 0, [10].into()), (0, 4, [30].into())]
+            );
+        }
+    }
+}
+
+#[cfg(feature = "pixels")]
+pub mod framebuffer {
+    //! Decoding straight into a framebuffer, which may be uninitialized.
+
+    use super::*;
+    use crate::unfilter;
+    use core::mem::MaybeUninit;
+
+    /// Copies the rows of an [`Unfilterer`](unfilter::Unfilterer) into a caller-provided
+    /// framebuffer, `stride` bytes apart, in the row format of the image.
+    ///
+    /// Framebuffers are often uninitialized statics, so the buffer is taken as
+    /// `MaybeUninit<u8>` and only the region written is handed back as initialized, without a
+    /// pass zeroing the whole buffer first. The padding between rows is zeroed.
+    ///
+    /// Only non-interlaced images are supported, and every row has to be passed on, i.e. no
+    /// row step.
+    pub struct FramebufferWriter<'fb> {
+        buf: &'fb mut [MaybeUninit<u8>],
+        stride: usize,
+        /// Rows written so far, and expected according to the header
+        rows: usize,
+        height: usize,
+    }
+
+    impl core::fmt::Debug for FramebufferWriter<'_> {
+        fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+            f.debug_struct("FramebufferWriter")
+                .field("len", &self.buf.len())
+                .field("stride", &self.stride)
+                .field("rows", &self.rows)
+                .field("height", &self.height)
+                .finish()
+        }
+    }
+
+    impl<'fb> FramebufferWriter<'fb> {
+        pub fn new(buf: &'fb mut [MaybeUninit<u8>], stride: usize) -> Self {
+            Self {
+                buf,
+                stride,
+                rows: 0,
+                height: 0,
+            }
+        }
+
+        /// For a framebuffer which is initialized already.
+        pub fn new_initialized(buf: &'fb mut [u8], stride: usize) -> Self {
+            // SAFETY: `MaybeUninit<u8>` has the same layout as `u8`, and only initialized
+            // bytes are ever written to the buffer
+            let buf = unsafe { &mut *(buf as *mut [u8] as *mut [MaybeUninit<u8>]) };
+            Self::new(buf, stride)
+        }
+
+        /// Takes every event of the Unfilterer, failing with [`Error::FramebufferTooSmall`]
+        /// at the header if the image doesn't fit.
+        pub fn update(&mut self, event: &unfilter::Event) -> Result<(), Error> {
+            match event {
+                unfilter::Event::ImageHeader(header) => {
+                    if header.interlace_method != 0 {
+                        return Err(Error::InterlaceNotSupported);
+                    }
+                    let layout = header.pixel_layout().ok_or(Error::InvalidColourType)?;
+                    let row_len = layout
+                        .row_len(header.width)
+                        .ok_or(Error::ArithmeticOverflow)?;
+                    let height =
+                        usize::try_from(header.height).map_err(|_| Error::ArithmeticOverflow)?;
+                    let fits = self
+                        .stride
+                        .checked_mul(height)
+                        .is_some_and(|len| len <= self.buf.len());
+                    if row_len > self.stride || !fits {
+                        return Err(Error::FramebufferTooSmall);
+                    }
+                    self.rows = 0;
+                    self.height = height;
+                    Ok(())
+                }
+                unfilter::Event::Row(row) => {
+                    if self.rows == self.height {
+                        return Err(Error::RowCountMismatch);
+                    }
+                    let start = self.rows * self.stride;
+                    let dst = self
+                        .buf
+                        .get_mut(start..start + self.stride)
+                        .ok_or(Error::FramebufferTooSmall)?;
+                    let (pixels, padding) = dst.split_at_mut(core::cmp::min(row.len(), dst.len()));
+                    for (dst, src) in pixels.iter_mut().zip(row.iter()) {
+                        dst.write(*src);
+                    }
+                    for b in padding {
+                        b.write(0);
+                    }
+                    self.rows += 1;
+                    Ok(())
+                }
+                _ => Ok(()),
+            }
+        }
+
+        /// Rows written so far.
+        pub fn rows(&self) -> usize {
+            self.rows
+        }
+
+        /// The rows written so far, `stride` bytes each.
+        pub fn initialized(&mut self) -> &mut [u8] {
+            let len = self.rows * self.stride;
+            let init = self.buf.get_mut(..len).unwrap_or_default();
+            // SAFETY: the first `rows` strides have been written
+            unsafe { &mut *(init as *mut [MaybeUninit<u8>] as *mut [u8]) }
+        }
+
+        /// The decoded image, once all its rows were written, failing with
+        /// [`Error::RowCountMismatch`] otherwise.
+        pub fn finish(self) -> Result<&'fb mut [u8], Error> {
+            if self.height == 0 || self.rows != self.height {
+                return Err(Error::RowCountMismatch);
+            }
+            let len = self.rows * self.stride;
+            let init = self.buf.get_mut(..len).unwrap_or_default();
+            // SAFETY: as in `initialized`
+            Ok(unsafe { &mut *(init as *mut [MaybeUninit<u8>] as *mut [u8]) })
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::stream_decoder::ImageHeader;
+
+        fn header(width: u32, height: u32) -> ImageHeader {
+            ImageHeader {
+                width,
+                height,
+                bit_depth: 8,
+                colour_type: 2,
+                compression_method: 0,
+                filter_method: 0,
+                interlace_method: 0,
+            }
+        }
+
+        #[test]
+        fn writes_rows_at_stride() {
+            let mut buf = [MaybeUninit::uninit(); 16];
+            let mut writer = FramebufferWriter::new(&mut buf, 8);
+            writer
+                .update(&unfilter::Event::ImageHeader(header(2, 2)))
+                .unwrap();
+            writer
+                .update(&unfilter::Event::Row(&[1, 2, 3, 4, 5, 6]))
+                .unwrap();
+            assert_eq!(writer.initialized(), [1, 2, 3, 4, 5, 6, 0, 0]);
+            writer
+                .update(&unfilter::Event::Row(&[7, 8, 9, 10, 11, 12]))
+                .unwrap();
+            assert_eq!(
+                writer.update(&unfilter::Event::Row(&[0; 6])),
+                Err(Error::RowCountMismatch)
+            );
+            assert_eq!(
+                writer.finish().unwrap(),
+                [1, 2, 3, 4, 5, 6, 0, 0, 7, 8, 9, 10, 11, 12, 0, 0]
+            );
+        }
+
+        #[test]
+        fn too_small() {
+            let mut buf = [0; 16];
+            let mut writer = FramebufferWriter::new_initialized(&mut buf, 5);
+            assert_eq!(
+                writer.update(&unfilter::Event::ImageHeader(header(2, 2))),
+                Err(Error::FramebufferTooSmall)
+            );
+            let mut writer = FramebufferWriter::new_initialized(&mut buf, 6);
+            assert_eq!(
+                writer.update(&unfilter::Event::ImageHeader(header(2, 3))),
+                Err(Error::FramebufferTooSmall)
+            );
+            assert_eq!(writer.finish(), Err(Error::RowCountMismatch));
+        }
+    }
+}
+
+#[cfg(feature = "pixels")]
+pub mod checksum {
+    //! Checksum of the decoded pixels, so that firmware can verify assets against golden
+    //! values without storing reference images.
+
+    use super::*;
+    use crate::crc32::Crc32;
+    #[cfg(feature = "ancillary")]
+    use crate::extension::Extension;
+    use crate::stream_decoder::{ImageHeader, ImageInfo};
+    use crate::unfilter;
+    use core::hash::Hasher;
+
+    /// Passes the events of the [`Unfilterer`](unfilter::Unfilterer) through, hashing the
+    /// rows, and emits the checksum of the image right before `End`.
+    ///
+    /// Rows are hashed as the Unfilterer produces them, i.e. in the file's format and without
+    /// the filter type byte. Any [`Hasher`] with a `Default` can be used, e.g.
+    /// `twox_hash::XxHash64` for speed on the host. The default is [`Crc32`], whose checksum is
+    /// in the low 32 bits.
+    #[derive(Debug)]
+    pub struct PixelChecksum<H = Crc32> {
+        hasher: H,
+        /// Whether `Event::Checksum` was already emitted for this image
+        reported: bool,
+    }
+
+    #[derive(PartialEq, Debug)]
+    pub enum Event<'a> {
+        /// Passthrough
+        ImageHeader(ImageHeader),
+        /// Passthrough
+        ImageInfo(ImageInfo),
+        /// Passthrough
+        Row(&'a [u8]),
+        /// Passthrough
+        #[cfg(feature = "ancillary")]
+        Extension(Extension),
+        /// Passthrough
+        Warning(Warning),
+        /// Checksum of all the rows of the image
+        Checksum(u64),
+        /// Passthrough
+        End,
+    }
+
+    impl<H: Hasher + Default> Default for PixelChecksum<H> {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl<H: Hasher + Default> PixelChecksum<H> {
+        pub fn new() -> Self {
+            Self {
+                hasher: H::default(),
+                reported: false,
+            }
+        }
+
+        /// Checksum of the rows of the current image so far.
+        pub fn checksum(&self) -> u64 {
+            self.hasher.finish()
+        }
+
+        pub fn update<'a>(
+            &mut self,
+            input: unfilter::Event<'a>,
+        ) -> Result<(Option<unfilter::Event<'a>>, Option<Event<'a>>), Error> {
+            match input {
+                unfilter::Event::ImageHeader(header) => {
+                    *self = Self::new();
+                    Ok((None, Some(Event::ImageHeader(header))))
+                }
+                unfilter::Event::ImageInfo(info) => Ok((None, Some(Event::ImageInfo(info)))),
+                unfilter::Event::Row(row) => {
+                    self.hasher.write(row);
+                    Ok((None, Some(Event::Row(row))))
+                }
+                #[cfg(feature = "ancillary")]
+                unfilter::Event::Extension(e) => Ok((None, Some(Event::Extension(e)))),
+                unfilter::Event::Warning(w) => Ok((None, Some(Event::Warning(w)))),
+                unfilter::Event::End if !self.reported => {
+                    self.reported = true;
+                    let checksum = Event::Checksum(self.checksum());
+                    Ok((Some(unfilter::Event::End), Some(checksum)))
+                }
+                unfilter::Event::End => Ok((None, Some(Event::End))),
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn checksum_before_end() {
+            let header = ImageHeader {
+                width: 2,
+                height: 2,
+                bit_depth: 8,
+                colour_type: 0,
+                compression_method: 0,
+                filter_method: 0,
+                interlace_method: 0,
+            };
+            let mut c = PixelChecksum::<Crc32>::new();
+            assert_eq!(
+                c.update(unfilter::Event::ImageHeader(header)),
+                Ok((None, Some(Event::ImageHeader(header))))
+            );
+            for row in [b"ab", b"cd"] {
+                assert_eq!(
+                    c.update(unfilter::Event::Row(row)),
+                    Ok((None, Some(Event::Row(row))))
+                );
+            }
+            let expected = crc32::checksum(b"abcd") as u64;
+            assert_eq!(
+                c.update(unfilter::Event::End),
+                Ok((Some(unfilter::Event::End), Some(Event::Checksum(expected))))
+            );
+            assert_eq!(c.update(unfilter::Event::End), Ok((None, Some(Event::End))));
+
+            // Starts over with the next image
+            c.update(unfilter::Event::ImageHeader(header)).unwrap();
+            assert_eq!(c.checksum(), crc32::checksum(b"") as u64);
+        }
+    }
+}
+
+#[cfg(all(feature = "inflate", feature = "pixels"))]
+pub mod decoder {
+    //! The whole pipeline, Dechunker to Unfilterer, set up from a [`DecoderConfig`]:
+    //!
+    //! ```
+    //! use incremental_png::decoder::{Decoder, DecoderConfig};
+    //! use incremental_png::{unfilter, Error, Limits};
+    //!
+    //! let mut decoder: Decoder = DecoderConfig::new()
+    //!     .limits(Limits { max_chunk_len: 64 * 1024 })
+    //!     .verify_crc(true)
+    //!     .build();
+    //! # let input = include_bytes!("../pngdump/pngsuite/basn0g01.png");
+    //! decoder.update_all(input, |e, _| {
+    //!     if let unfilter::Event::Row(row) = e {
+    //!         // ...
+    //!     }
+    //!     Ok::<_, Error>(())
+    //! })?;
+    //! decoder.finish()?;
+    //! # Ok::<_, Error>(())
+    //! ```
+
+    use super::*;
+    use crate::crc32::Crc32;
+    use crate::dechunker::{self, ChunkType, Dechunker};
+    use crate::inflater::{Inflater, Progress};
+    use crate::pixel_iter::RowTracker;
+    use crate::stream_decoder::{self, ImageHeader, StreamDecoder};
+    use crate::unfilter::{self, Unfilterer};
+
+    /// Options for all the stages of a [`Decoder`].
+    #[derive(Clone, Copy, Eq, PartialEq, Debug)]
+    pub struct DecoderConfig {
+        limits: Limits,
+        verify_crc: bool,
+        strictness: Strictness,
+        row_step: u32,
+        adam7_pass_limit: Option<u8>,
+        cgbi: bool,
+        crc_warnings: bool,
+        warnings: bool,
+    }
+
+    impl Default for DecoderConfig {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl DecoderConfig {
+        /// Default limits, no CRC verification, [`Strictness::Strict`].
+        pub fn new() -> Self {
+            Self {
+                limits: Limits::default(),
+                verify_crc: false,
+                strictness: Strictness::Strict,
+                row_step: 1,
+                adam7_pass_limit: None,
+                cgbi: false,
+                crc_warnings: false,
+                warnings: false,
+            }
+        }
+
+        pub fn limits(mut self, limits: Limits) -> Self {
+            self.limits = limits;
+            self
+        }
+
+        /// Checks the CRC of every chunk, failing with [`Error::ChecksumMismatch`]. With
+        /// [`Strictness::Lenient`], ancillary chunks which don't match are skipped instead.
+        pub fn verify_crc(mut self, verify_crc: bool) -> Self {
+            self.verify_crc = verify_crc;
+            self
+        }
+
+        /// Checks the CRC of every chunk like [`verify_crc`](Self::verify_crc), but reports
+        /// mismatches as [`Warning::CrcMismatch`] and decodes the chunk anyway, as plenty of
+        /// files in the wild have bad CRCs on text chunks.
+        pub fn crc_warnings(mut self, crc_warnings: bool) -> Self {
+            self.crc_warnings = crc_warnings;
+            self
+        }
+
+        /// Passes on [`unfilter::Event::Warning`] for recoverable problems: unknown ancillary
+        /// chunks and chunks out of order (see [`StreamDecoder::with_warnings`]), and ancillary
+        /// chunks skipped for a bad CRC with [`Strictness::Lenient`].
+        pub fn warnings(mut self, warnings: bool) -> Self {
+            self.warnings = warnings;
+            self
+        }
+
+        fn checks_crc(&self) -> bool {
+            self.verify_crc || self.crc_warnings
+        }
+
+        /// Whether ancillary chunks with a bad CRC are skipped, see [`verify_crc`](Self::verify_crc).
+        /// Also passed on to [`Palette::expand_row`] by [`Context::expand_row`].
+        pub fn strictness(mut self, strictness: Strictness) -> Self {
+            self.strictness = strictness;
+            self
+        }
+
+        /// Passes on only every `step`th row, see [`Unfilterer::with_row_step`].
+        pub fn row_step(mut self, step: u32) -> Self {
+            self.row_step = step;
+            self
+        }
+
+        /// Decodes interlaced images up to the given Adam7 pass, see
+        /// [`Unfilterer::with_adam7_pass_limit`]. Without it they fail with
+        /// [`Error::InterlaceNotSupported`].
+        pub fn adam7_pass_limit(mut self, last_pass: u8) -> Self {
+            self.adam7_pass_limit = Some(last_pass);
+            self
+        }
+
+        /// Decodes Apple's CgBI variant when the file starts with a CgBI chunk, see
+        /// [`StreamDecoder::is_cgbi`]. Without it such files fail with
+        /// [`Error::InvalidDeflateStream`].
+        pub fn cgbi(mut self, cgbi: bool) -> Self {
+            self.cgbi = cgbi;
+            self
+        }
+
+        /// A `Dechunker` with these options, for composing the stages by hand.
+        pub fn dechunker(&self) -> Dechunker {
+            let dechunker = Dechunker::new().with_limits(self.limits);
+            if self.checks_crc() {
+                dechunker.with_crc_events()
+            } else {
+                dechunker
+            }
+        }
+
+        /// An `Unfilterer` with these options.
+        pub fn unfilterer<const MAX_SCANLINE: usize>(&self) -> Unfilterer<MAX_SCANLINE> {
+            let unfilterer = Unfilterer::new().with_row_step(self.row_step);
+            match self.adam7_pass_limit {
+                Some(last_pass) => unfilterer.with_adam7_pass_limit(last_pass),
+                None => unfilterer,
+            }
+        }
+
+        /// A `RowTracker` for the rows of the Unfilterer, giving the coordinates of their
+        /// pixels.
+        pub fn row_tracker(&self) -> RowTracker {
+            RowTracker::new().with_row_step(self.row_step)
+        }
+
+        pub fn build<
+            const PALETTE_SIZE: usize,
+            const BUFFER_SIZE: usize,
+            const MAX_SCANLINE: usize,
+        >(
+            &self,
+        ) -> Decoder<PALETTE_SIZE, BUFFER_SIZE, MAX_SCANLINE> {
+            let mut stream_decoder = StreamDecoder::with_palette_capacity();
+            if self.warnings {
+                stream_decoder = stream_decoder.with_warnings();
+            }
+            Decoder {
+                dechunker: self.dechunker(),
+                stream_decoder: match self.adam7_pass_limit {
+                    Some(_) => stream_decoder.with_interlaced(),
+                    None => stream_decoder,
+                },
+                inflater: Inflater::new(),
+                unfilterer: self.unfilterer(),
+                crc: Crc32::new(),
+                chunk_type: None,
+                skipped_chunks: 0,
+                config: *self,
+            }
+        }
+    }
+
+    /// What the callback of [`Decoder::update_all`] can see of the decoder.
+    pub struct Context<'a, const PALETTE_SIZE: usize> {
+        stream_decoder: &'a StreamDecoder<PALETTE_SIZE>,
+        config: &'a DecoderConfig,
+    }
+
+    impl<const PALETTE_SIZE: usize> Context<'_, PALETTE_SIZE> {
+        pub fn header(&self) -> Option<&ImageHeader> {
+            self.stream_decoder.header()
+        }
+
+        pub fn strictness(&self) -> Strictness {
+            self.config.strictness
+        }
+
+        #[cfg(feature = "palette")]
+        pub fn palette(&self) -> &Palette<PALETTE_SIZE> {
+            self.stream_decoder.palette()
+        }
+
+        /// Expands a row of palette indices to RGB with the configured strictness, see
+        /// [`Palette::expand_row`].
+        #[cfg(feature = "palette")]
+        pub fn expand_row(
+            &self,
+            row: &[u8],
+            bit_depth: u8,
+            out: &mut [[u8; 3]],
+        ) -> Result<(), Error> {
+            self.palette()
+                .expand_row(row, bit_depth, out, self.strictness())
+        }
+    }
+
+    /// Dechunker, StreamDecoder, Inflater and Unfilterer, driven together. The parameters are
+    /// those of the stages, see [`footprint::decoder`](crate::footprint::decoder).
+    ///
+    /// Image data is decompressed straight into the Unfilterer's rows, so the Inflater's output
+    /// buffer isn't used and `BUFFER_SIZE` can be as small as 1.
+    #[derive(Clone, Debug)]
+    pub struct Decoder<
+        const PALETTE_SIZE: usize = 256,
+        const BUFFER_SIZE: usize = 1024,
+        const MAX_SCANLINE: usize = 1024,
+    > {
+        dechunker: Dechunker,
+        stream_decoder: StreamDecoder<PALETTE_SIZE>,
+        inflater: Inflater<BUFFER_SIZE>,
+        unfilterer: Unfilterer<MAX_SCANLINE>,
+        /// CRC of the current chunk so far, if verifying
+        crc: Crc32,
+        /// Type of the current chunk, if verifying
+        chunk_type: Option<ChunkType>,
+        skipped_chunks: u32,
+        config: DecoderConfig,
+    }
+
+    impl<const PALETTE_SIZE: usize, const BUFFER_SIZE: usize, const MAX_SCANLINE: usize>
+        Decoder<PALETTE_SIZE, BUFFER_SIZE, MAX_SCANLINE>
+    {
+        /// Size of a `Decoder` in bytes, including all the stages.
+        pub const MEMORY_FOOTPRINT: usize = core::mem::size_of::<Self>();
+
+        /// Consumes all of `input`, calling `f` with the output of the Unfilterer.
+        pub fn update_all<E: From<Error>>(
+            &mut self,
+            input: &[u8],
+            mut f: impl FnMut(unfilter::Event, &Context<PALETTE_SIZE>) -> Result<(), E>,
+        ) -> Result<(), E> {
+            let Self {
+                dechunker,
+                stream_decoder,
+                inflater,
+                unfilterer,
+                crc,
+                chunk_type,
+                skipped_chunks,
+                config,
+            } = self;
+            dechunker.update_all(input, |e| {
+                match &e {
+                    dechunker::Event::BeginChunk(h) if config.checks_crc() => {
+                        *crc = Crc32::new();
+                        crc.update(&h.type_.0);
+                        *chunk_type = Some(h.type_);
+                    }
+                    dechunker::Event::Data(data) if config.checks_crc() => crc.update(data),
+                    dechunker::Event::Crc(stored) if *stored != crc.finish() => {
+                        let Some(type_) = *chunk_type else {
+                            return Err(Error::ChecksumMismatch.into());
+                        };
+                        let lenient =
+                            type_.is_ancillary() && config.strictness == Strictness::Lenient;
+                        if config.crc_warnings {
+                            warn!("decoder: bad CRC on {} chunk", type_);
+                        } else if lenient {
+                            warn!("decoder: skipping {} chunk with a bad CRC", type_);
+                            stream_decoder.discard_chunk();
+                            *skipped_chunks += 1;
+                        } else {
+                            return Err(Error::ChecksumMismatch.into());
+                        }
+                        if config.crc_warnings || config.warnings {
+                            let warning = Warning::CrcMismatch {
+                                chunk_type: type_,
+                                stored: *stored,
+                                computed: crc.finish(),
+                            };
+                            let context = Context {
+                                stream_decoder,
+                                config,
+                            };
+                            f(unfilter::Event::Warning(warning), &context)?;
+                        }
+                    }
+                    _ => {}
+                }
+                let mut dc_event = Some(e);
+                while let Some(e) = dc_event {
+                    let (leftover, sd_event) = stream_decoder.update(e)?;
+                    if config.cgbi
+                        && matches!(sd_event, Some(stream_decoder::Event::ImageHeader(_)))
+                    {
+                        inflater.set_raw_deflate(stream_decoder.is_cgbi());
+                        unfilterer.set_bgr_order(stream_decoder.is_cgbi());
+                    }
+                    let context = Context {
+                        stream_decoder,
+                        config,
+                    };
+                    match sd_event {
+                        // Decompressed straight into the Unfilterer's row
+                        Some(stream_decoder::Event::ImageData(mut data)) => loop {
+                            let space = unfilterer.row_space()?;
+                            let space_len = space.len();
+                            let (rest, written) = inflater.inflate_into(data, space)?;
+                            if let Some(e) = unfilterer.commit(written)? {
+                                f(e, &context)?;
+                            }
+                            let stalled = written == 0 && rest.len() == data.len();
+                            data = rest;
+                            if (data.is_empty() && written < space_len) || stalled {
+                                break;
+                            }
+                        },
+                        Some(e) => {
+                            let (_, mut i_event) = inflater.update(e)?;
+                            while let Some(e) = i_event {
+                                let (leftover, u_event) = unfilterer.update(e)?;
+                                if let Some(e) = u_event {
+                                    f(e, &context)?;
+                                }
+                                i_event = leftover;
+                            }
+                        }
+                        None => {}
+                    }
+                    dc_event = leftover;
+                }
+                Ok(())
+            })
+        }
+
+        /// Fails if the file ended in the middle of a chunk or before IEND.
+        pub fn eof(&self) -> Result<(), Error> {
+            self.dechunker.eof()?;
+            self.stream_decoder.eof()
+        }
+
+        /// Checks that the whole image was decoded, returning the first problem found:
+        ///
+        /// - [`Error::UnfinishedChunk`] if the file ended in the middle of a chunk,
+        /// - [`Error::MissingEndChunk`] if it ended before IEND,
+        /// - [`Error::UnfinishedImageData`] if the deflate stream didn't end,
+        /// - [`Error::RowCountMismatch`] if fewer or more rows than the header says were decoded.
+        pub fn finish(&self) -> Result<(), Error> {
+            self.eof()?;
+            self.inflater.eof()?;
+            self.unfilterer.eof()
+        }
+
+        #[cfg(feature = "palette")]
+        pub fn palette(&self) -> &Palette<PALETTE_SIZE> {
+            self.stream_decoder.palette()
+        }
+
+        pub fn progress(&self) -> Progress {
+            self.inflater.progress(&self.dechunker)
+        }
+
+        /// Number of ancillary chunks skipped because of a bad CRC, see
+        /// [`DecoderConfig::strictness`].
+        pub fn skipped_chunks(&self) -> u32 {
+            self.skipped_chunks
+        }
+
+        /// Memory of all the stages, see their `memory_usage`.
+        pub fn memory_usage(&self) -> MemoryUsage {
+            MemoryUsage {
+                static_bytes: Self::MEMORY_FOOTPRINT,
+                peak_dynamic: self.dechunker.memory_usage().peak_dynamic
+                    + self.stream_decoder.memory_usage().peak_dynamic
+                    + self.inflater.memory_usage().peak_dynamic
+                    + self.unfilterer.memory_usage().peak_dynamic,
+            }
+        }
+
+        pub fn config(&self) -> &DecoderConfig {
+            &self.config
+        }
+
+        pub fn dechunker(&self) -> &Dechunker {
+            &self.dechunker
+        }
+
+        pub fn stream_decoder(&self) -> &StreamDecoder<PALETTE_SIZE> {
+            &self.stream_decoder
+        }
+
+        pub fn inflater(&self) -> &Inflater<BUFFER_SIZE> {
+            &self.inflater
+        }
+
+        pub fn unfilterer(&self) -> &Unfilterer<MAX_SCANLINE> {
+            &self.unfilterer
+        }
+    }
+}
+
+/// Animated PNG chunks.
+///
+/// <https://wiki.mozilla.org/APNG_Specification>
+#[cfg(feature = "ancillary")]
+pub mod apng {
+    use super::*;
+
+    /// <https://wiki.mozilla.org/APNG_Specification#.60acTL.60:_The_Animation_Control_Chunk>
+    #[derive(Clone, Copy, Eq, PartialEq, Debug)]
+    pub struct AnimationControl {
+        pub num_frames: u32,
+        /// 0 means looping forever
+        pub num_plays: u32,
+    }
+
+    impl AnimationControl {
+        pub fn parse(data: &[u8]) -> Result<Self, Error> {
+            let data: &[u8; 8] = data
+                .try_into()
+                .map_err(|_| Error::InvalidAnimationControl)?;
+            let num_frames = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+            if num_frames == 0 {
+                return Err(Error::InvalidAnimationControl);
+            }
+            Ok(Self {
+                num_frames,
+                num_plays: u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+            })
+        }
+    }
+
+    /// <https://wiki.mozilla.org/APNG_Specification#.60fcTL.60:_The_Frame_Control_Chunk>
+    #[derive(Clone, Copy, Default, Eq, PartialEq, Debug)]
+    #[repr(u8)]
+    pub enum DisposeOp {
+        #[default]
+        None = 0,
+        Background = 1,
+        Previous = 2,
+    }
+
+    #[derive(Clone, Copy, Default, Eq, PartialEq, Debug)]
+    #[repr(u8)]
+    pub enum BlendOp {
+        #[default]
+        Source = 0,
+        Over = 1,
+    }
+
+    /// Contents of an fcTL chunk, without the sequence number.
+    #[derive(Clone, Copy, Eq, PartialEq, Debug)]
+    pub struct FrameControl {
+        pub width: u32,
+        pub height: u32,
+        pub x_offset: u32,
+        pub y_offset: u32,
+        /// Frame delay in seconds is `delay_num / delay_den`
+        pub delay_num: u16,
+        pub delay_den: u16,
+        pub dispose_op: DisposeOp,
+        pub blend_op: BlendOp,
+    }
+
+    impl FrameControl {
+        pub const SIZE: usize = 26;
+
+        /// Parses fcTL chunk data, returning the sequence number and the frame.
+        pub fn parse(data: &[u8]) -> Result<(u32, Self), Error> {
+            let data: &[u8; Self::SIZE] =
+                data.try_into().map_err(|_| Error::InvalidFrameControl)?;
+            let u32_at =
+                |i: usize| u32::from_be_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]);
+            let u16_at = |i: usize| u16::from_be_bytes([data[i], data[i + 1]]);
+            let dispose_op = match data[24] {
+                0 => DisposeOp::None,
+                1 => DisposeOp::Background,
+                2 => DisposeOp::Previous,
+                _ => return Err(Error::InvalidFrameControl),
+            };
+            let blend_op = match data[25] {
+                0 => BlendOp::Source,
+                1 => BlendOp::Over,
+                _ => return Err(Error::InvalidFrameControl),
+            };
+            let frame = Self {
+                width: u32_at(4),
+                height: u32_at(8),
+                x_offset: u32_at(12),
+                y_offset: u32_at(16),
+                delay_num: u16_at(20),
+                delay_den: u16_at(22),
+                dispose_op,
+                blend_op,
+            };
+            Ok((u32_at(0), frame))
+        }
+
+        /// fcTL chunk data with the given sequence number.
+        pub fn to_bytes(&self, sequence_number: u32) -> [u8; Self::SIZE] {
+            let mut data = [0; Self::SIZE];
+            data[0..4].copy_from_slice(&sequence_number.to_be_bytes());
+            data[4..8].copy_from_slice(&self.width.to_be_bytes());
+            data[8..12].copy_from_slice(&self.height.to_be_bytes());
+            data[12..16].copy_from_slice(&self.x_offset.to_be_bytes());
+            data[16..20].copy_from_slice(&self.y_offset.to_be_bytes());
+            data[20..22].copy_from_slice(&self.delay_num.to_be_bytes());
+            data[22..24].copy_from_slice(&self.delay_den.to_be_bytes());
+            data[24] = self.dispose_op as u8;
+            data[25] = self.blend_op as u8;
+            data
+        }
+
+        /// Frame delay in milliseconds. A denominator of 0 means 1/100 s units.
+        pub fn delay_ms(&self) -> u32 {
+            let den = if self.delay_den == 0 {
+                100
+            } else {
+                self.delay_den as u32
+            };
+            self.delay_num as u32 * 1000 / den
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn frame_control_roundtrip() {
+            let frame = FrameControl {
+                width: 10,
+                height: 20,
+                x_offset: 3,
+                y_offset: 4,
+                delay_num: 1,
+                delay_den: 0,
+                dispose_op: DisposeOp::Previous,
+                blend_op: BlendOp::Over,
+            };
+            let data = frame.to_bytes(7);
+            assert_eq!(FrameControl::parse(&data), Ok((7, frame)));
+            assert_eq!(frame.delay_ms(), 10);
+            assert_eq!(
+                FrameControl::parse(&data[..25]),
+                Err(Error::InvalidFrameControl)
+            );
+            let mut data = data;
+            data[24] = 3;
+            assert_eq!(FrameControl::parse(&data), Err(Error::InvalidFrameControl));
+        }
+
+        #[test]
+        fn animation_control() {
+            assert_eq!(
+                AnimationControl::parse(&[0, 0, 0, 2, 0, 0, 0, 0]),
+                Ok(AnimationControl {
+                    num_frames: 2,
+                    num_plays: 0
+                })
+            );
+            assert_eq!(
+                AnimationControl::parse(&[0; 8]),
+                Err(Error::InvalidAnimationControl)
+            );
+        }
+    }
+}
+
+/// Extension chunks, registered in addition to the ones in the PNG specification. They are
+/// parsed by the [`StreamDecoder`](stream_decoder::StreamDecoder) and passed down the pipeline
+/// as `Extension` events.
+///
+/// <http://www.libpng.org/pub/png/spec/register/pngext-1.5.0.pdf>
+#[cfg(feature = "ancillary")]
+pub mod extension {
+    use super::*;
+    use crate::dechunker::ChunkType;
+
+    pub(crate) const OFFS: ChunkType = ChunkType(*b"oFFs");
+    pub(crate) const SCAL: ChunkType = ChunkType(*b"sCAL");
+    pub(crate) const PCAL: ChunkType = ChunkType(*b"pCAL");
+    pub(crate) const STER: ChunkType = ChunkType(*b"sTER");
+
+    /// Contents of an extension chunk.
+    #[derive(Clone, Copy, PartialEq, Debug)]
+    pub enum Extension {
+        /// oFFs
+        Offset(ImageOffset),
+        /// sCAL
+        Scale(PhysicalScale),
+        /// pCAL
+        Calibration(Calibration),
+        /// sTER
+        Stereo(StereoMode),
+    }
+
+    #[derive(Clone, Copy, Eq, PartialEq, Debug)]
+    pub enum OffsetUnit {
+        Pixel,
+        Micrometre,
+    }
+
+    /// oFFs, position of the image on a page or screen.
+    #[derive(Clone, Copy, Eq, PartialEq, Debug)]
+    pub struct ImageOffset {
+        pub x: i32,
+        pub y: i32,
+        pub unit: OffsetUnit,
+    }
+
+    impl ImageOffset {
+        pub const SIZE: usize = 9;
+
+        pub fn parse(data: &[u8]) -> Result<Self, Error> {
+            let Ok(&[x0, x1, x2, x3, y0, y1, y2, y3, unit]) = <&[u8; Self::SIZE]>::try_from(data)
+            else {
+                return Err(Error::InvalidExtensionChunk(OFFS));
+            };
+            let unit = match unit {
+                0 => OffsetUnit::Pixel,
+                1 => OffsetUnit::Micrometre,
+                _ => return Err(Error::InvalidExtensionChunk(OFFS)),
+            };
+            Ok(Self {
+                x: i32::from_be_bytes([x0, x1, x2, x3]),
+                y: i32::from_be_bytes([y0, y1, y2, y3]),
+                unit,
+            })
+        }
+    }
+
+    #[derive(Clone, Copy, Eq, PartialEq, Debug)]
+    pub enum ScaleUnit {
+        Metre,
+        Radian,
+    }
+
+    /// sCAL, physical size of a pixel of the image subject, e.g. for maps.
+    #[derive(Clone, Copy, PartialEq, Debug)]
+    pub struct PhysicalScale {
+        pub unit: ScaleUnit,
+        /// Width of a pixel
+        pub width: f64,
+        /// Height of a pixel
+        pub height: f64,
+    }
+
+    impl PhysicalScale {
+        /// Longest sCAL chunk decoded by the [`StreamDecoder`](stream_decoder::StreamDecoder).
+        /// The sizes are decimal numbers, so this is plenty in practice; longer chunks are
+        /// ignored.
+        pub const MAX_SIZE: usize = 64;
+
+        pub fn parse(data: &[u8]) -> Result<Self, Error> {
+            let invalid = Error::InvalidExtensionChunk(SCAL);
+            let (&unit, sizes) = data
+                .split_first()
+                .ok_or(Error::InvalidExtensionChunk(SCAL))?;
+            let unit = match unit {
+                1 => ScaleUnit::Metre,
+                2 => ScaleUnit::Radian,
+                _ => return Err(invalid),
+            };
+            let mut sizes = sizes.split(|&b| b == 0).map(parse_float);
+            let (Some(Some(width)), Some(Some(height)), None) =
+                (sizes.next(), sizes.next(), sizes.next())
+            else {
+                return Err(invalid);
+            };
+            if width <= 0.0 || height <= 0.0 {
+                return Err(invalid);
+            }
+            Ok(Self {
+                unit,
+                width,
+                height,
+            })
+        }
+    }
+
+    /// sTER, the image is a stereo pair: the images for both eyes side by side, separated by
+    /// padding columns as described in the spec.
+    #[derive(Clone, Copy, Eq, PartialEq, Debug)]
+    pub enum StereoMode {
+        /// The right eye image is on the left
+        CrossFuse,
+        /// The left eye image is on the left
+        DivergingFuse,
+    }
+
+    impl StereoMode {
+        pub const SIZE: usize = 1;
+
+        pub fn parse(data: &[u8]) -> Result<Self, Error> {
+            match data {
+                [0] => Ok(StereoMode::CrossFuse),
+                [1] => Ok(StereoMode::DivergingFuse),
+                _ => Err(Error::InvalidExtensionChunk(STER)),
+            }
+        }
+    }
+
+    /// How [`Calibration`] maps a sample to a physical value. The stored sample `x` is first
+    /// mapped to the original sample `t = x0 + x * (x1 - x0) / x_max`, where `x_max` is the
+    /// largest sample value of the bit depth; `p` are the parameters.
+    #[derive(Clone, Copy, Eq, PartialEq, Debug)]
+    pub enum EquationType {
+        /// `p0 + p1 * t / (x1 - x0)`
+        Linear,
+        /// `p0 + p1 * e^(p2 * t / (x1 - x0))`
+        Exponential,
+        /// `p0 + p1 * p3^(p2 * t / (x1 - x0))`
+        ArbitraryBaseExponential,
+        /// `p0 + p1 * sinh(p2 * (t - p3) / (x1 - x0))`
+        Hyperbolic,
+    }
+
+    impl EquationType {
+        /// Number of parameters of the equation.
+        pub fn param_count(&self) -> usize {
+            match self {
+                EquationType::Linear => 2,
+                EquationType::Exponential => 3,
+                EquationType::ArbitraryBaseExponential | EquationType::Hyperbolic => 4,
+            }
+        }
+    }
+
+    /// pCAL, mapping of sample values to physical values, e.g. temperatures in a thermal image.
+    ///
+    /// The calibration name and the unit are text of arbitrary length and aren't kept.
+    #[derive(Clone, Copy, PartialEq, Debug)]
+    pub struct Calibration {
+        pub x0: i32,
+        pub x1: i32,
+        pub equation: EquationType,
+        /// Unused parameters are 0, see [`Calibration::params`].
+        pub params: [f64; 4],
+    }
+
+    impl Calibration {
+        /// The parameters of the equation.
+        pub fn params(&self) -> &[f64] {
+            self.params
+                .get(..self.equation.param_count())
+                .unwrap_or_default()
+        }
+    }
+
+    #[derive(Clone, PartialEq, Debug)]
+    enum CalibrationField {
+        /// Calibration name, with the number of bytes so far
+        Name(u8),
+        /// x0, x1, equation type and parameter count
+        Fixed,
+        Unit,
+        /// Parameter with the given index
+        Param(usize),
+    }
+
+    /// Parses a pCAL chunk as it arrives, keeping only one parameter in memory.
+    #[derive(Clone, PartialEq, Debug)]
+    pub(crate) struct CalibrationParser {
+        field: CalibrationField,
+        buf: ArrayVec<u8, { CalibrationParser::MAX_PARAM_LEN }>,
+        calibration: Calibration,
+    }
+
+    impl CalibrationParser {
+        /// Longest parameter text; the spec doesn't limit it, but 32 characters are far more
+        /// than needed for an f64.
+        const MAX_PARAM_LEN: usize = 32;
+
+        pub(crate) fn new() -> Self {
+            Self {
+                field: CalibrationField::Name(0),
+                buf: ArrayVec::new(),
+                calibration: Calibration {
+                    x0: 0,
+                    x1: 0,
+                    equation: EquationType::Linear,
+                    params: [0.0; 4],
+                },
+            }
+        }
+
+        pub(crate) fn update(&mut self, data: &[u8]) -> Result<(), Error> {
+            const INVALID: Error = Error::InvalidExtensionChunk(PCAL);
+            for &b in data {
+                match &mut self.field {
+                    // Keywords are 1-79 bytes
+                    CalibrationField::Name(0) if b == 0 => return Err(INVALID),
+                    CalibrationField::Name(_) if b == 0 => self.field = CalibrationField::Fixed,
+                    CalibrationField::Name(79) => return Err(INVALID),
+                    CalibrationField::Name(len) => *len += 1,
+                    CalibrationField::Fixed => {
+                        self.buf.push(b).map_err(|_| INVALID)?;
+                        let Ok(&[a0, a1, a2, a3, b0, b1, b2, b3, equation, count]) =
+                            <&[u8; 10]>::try_from(&self.buf[..])
+                        else {
+                            continue;
+                        };
+                        let equation = match equation {
+                            0 => EquationType::Linear,
+                            1 => EquationType::Exponential,
+                            2 => EquationType::ArbitraryBaseExponential,
+                            3 => EquationType::Hyperbolic,
+                            _ => return Err(INVALID),
+                        };
+                        let (x0, x1) = (
+                            i32::from_be_bytes([a0, a1, a2, a3]),
+                            i32::from_be_bytes([b0, b1, b2, b3]),
+                        );
+                        if count as usize != equation.param_count() || x0 == x1 {
+                            return Err(INVALID);
+                        }
+                        self.calibration.x0 = x0;
+                        self.calibration.x1 = x1;
+                        self.calibration.equation = equation;
+                        self.buf.clear();
+                        self.field = CalibrationField::Unit;
+                    }
+                    CalibrationField::Unit if b == 0 => self.field = CalibrationField::Param(0),
+                    CalibrationField::Unit => {}
+                    CalibrationField::Param(index) if b == 0 => {
+                        let index = *index;
+                        self.finish_param(index)?;
+                        self.field = CalibrationField::Param(index + 1);
+                    }
+                    CalibrationField::Param(_) => self.buf.push(b).map_err(|_| INVALID)?,
+                }
+            }
+            Ok(())
+        }
+
+        fn finish_param(&mut self, index: usize) -> Result<(), Error> {
+            let param = self
+                .calibration
+                .params
+                .get_mut(..self.calibration.equation.param_count())
+                .and_then(|params| params.get_mut(index))
+                .ok_or(Error::InvalidExtensionChunk(PCAL))?;
+            *param = parse_float(&self.buf).ok_or(Error::InvalidExtensionChunk(PCAL))?;
+            self.buf.clear();
+            Ok(())
+        }
+
+        /// Called at the end of the chunk.
+        pub(crate) fn finish(&mut self) -> Result<Calibration, Error> {
+            // The last parameter isn't terminated
+            match self.field {
+                CalibrationField::Param(index)
+                    if index + 1 == self.calibration.equation.param_count() =>
+                {
+                    self.finish_param(index)?;
+                    Ok(self.calibration)
+                }
+                _ => Err(Error::InvalidExtensionChunk(PCAL)),
+            }
+        }
+    }
+
+    /// Parses an ASCII floating-point number in the format of the extension chunks, e.g.
+    /// `1.5E-3`. Rejects infinity and NaN.
+    ///
+    /// `str::parse` has panic paths which the optimizer doesn't remove, so this is a simpler
+    /// parser. It is exact for up to 19 significant digits and exponents up to 22, which covers
+    /// the values seen in practice, and within a few ULPs beyond that.
+    fn parse_float(text: &[u8]) -> Option<f64> {
+        const POWERS_OF_10: [f64; 23] = [
+            1e0, 1e1, 1e2, 1e3, 1e4, 1e5, 1e6, 1e7, 1e8, 1e9, 1e10, 1e11, 1e12, 1e13, 1e14, 1e15,
+            1e16, 1e17, 1e18, 1e19, 1e20, 1e21, 1e22,
+        ];
+        fn split_sign(text: &[u8]) -> (bool, &[u8]) {
+            match text {
+                [b'-', rest @ ..] => (true, rest),
+                [b'+', rest @ ..] => (false, rest),
+                _ => (false, text),
+            }
+        }
+
+        let mut parts = text.split(|&b| b == b'e' || b == b'E');
+        let (Some(significand), exponent, None) = (parts.next(), parts.next(), parts.next()) else {
+            return None;
+        };
+
+        let (negative, significand) = split_sign(significand);
+        let (mut mantissa, mut exponent_adjust, mut digits, mut seen_point) =
+            (0u64, 0i32, 0, false);
+        for &b in significand {
+            match b {
+                b'.' if !seen_point => seen_point = true,
+                b'0'..=b'9' => {
+                    digits += 1;
+                    if mantissa < 1_000_000_000_000_000_000 {
+                        mantissa = mantissa * 10 + (b - b'0') as u64;
+                        exponent_adjust = exponent_adjust.saturating_sub(seen_point as i32);
+                    } else {
+                        // Too many significant digits, drop the rest
+                        exponent_adjust = exponent_adjust.saturating_add(!seen_point as i32);
+                    }
+                }
+                _ => return None,
+            }
+        }
+        if digits == 0 {
+            return None;
+        }
+
+        let mut exponent = match exponent {
+            Some(text) => {
+                let (negative, text) = split_sign(text);
+                if text.is_empty() || !text.iter().all(u8::is_ascii_digit) {
+                    return None;
+                }
+                // Beyond this the result is 0 or infinite anyway
+                let value = text
+                    .iter()
+                    .fold(0i32, |v, &b| (v * 10 + (b - b'0') as i32).min(1000));
+                if negative {
+                    -value
+                } else {
+                    value
+                }
+            }
+            None => 0,
+        }
+        .saturating_add(exponent_adjust);
+
+        let mut value = mantissa as f64;
+        while exponent > 22 {
+            value *= 1e22;
+            exponent -= 22;
+        }
+        while exponent < -22 {
+            value /= 1e22;
+            exponent += 22;
+        }
+        let power = POWERS_OF_10.get(exponent.unsigned_abs() as usize)?;
+        if exponent < 0 {
+            value /= power;
+        } else {
+            value *= power;
+        }
+        if negative {
+            value = -value;
+        }
+        value.is_finite().then_some(value)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn physical_scale() {
+            assert_eq!(
+                PhysicalScale::parse(b"\x011.5\x002e-3"),
+                Ok(PhysicalScale {
+                    unit: ScaleUnit::Metre,
+                    width: 1.5,
+                    height: 0.002
+                })
+            );
+            for invalid in [
+                &b"\x031\x001"[..],
+                b"\x011",
+                b"\x011\x001\x001",
+                b"\x01-1\x001",
+                b"\x01inf\x001",
+                b"\x01\x001",
+                b"",
+            ] {
+                assert_eq!(
+                    PhysicalScale::parse(invalid),
+                    Err(Error::InvalidExtensionChunk(SCAL)),
+                    "{invalid:?}"
+                );
+            }
+        }
+
+        #[test]
+        fn float() {
+            for (text, value) in [
+                (&b"1"[..], 1.0),
+                (b"+1.", 1.0),
+                (b".5", 0.5),
+                (b"-0.125", -0.125),
+                (b"1.5E-3", 0.0015),
+                (b"25e+2", 2500.0),
+                (b"0.000000000000000000000000001", 1e-27),
+                (b"12345678901234567890123", 1.2345678901234568e22),
+            ] {
+                assert_eq!(parse_float(text), Some(value), "{text:?}");
+            }
+            for invalid in [
+                &b""[..],
+                b".",
+                b"1e",
+                b"1e5e5",
+                b"1..5",
+                b"--1",
+                b"1e999",
+                b"inf",
+            ] {
+                assert_eq!(parse_float(invalid), None, "{invalid:?}");
+            }
+        }
+
+        #[test]
+        fn image_offset() {
+            assert_eq!(
+                ImageOffset::parse(&[0, 0, 1, 0, 0xff, 0xff, 0xff, 0xfe, 1]),
+                Ok(ImageOffset {
+                    x: 256,
+                    y: -2,
+                    unit: OffsetUnit::Micrometre
+                })
+            );
+            assert_eq!(
+                ImageOffset::parse(&[0; 8]),
+                Err(Error::InvalidExtensionChunk(OFFS))
+            );
+            assert_eq!(
+                ImageOffset::parse(&[0, 0, 0, 0, 0, 0, 0, 0, 2]),
+                Err(Error::InvalidExtensionChunk(OFFS))
+            );
+        }
+    }
+}
+
+pub mod crc32 {
+    //! Streaming CRC-32 as used for PNG chunks.
+    //!
+    //! <https://www.w3.org/TR/png-3/#5CRC-algorithm>
+    //!
+    //! The implementation is selected with cargo features, trading flash for speed:
+    //! `crc32-slice-by-8` (8 KiB of tables), `crc32-table` (1 KiB, the default), or neither
+    //! for a table-free bitwise implementation.
+
+    const POLYNOMIAL: u32 = 0xedb8_8320;
+
+    const fn update_bitwise(mut c: u32, byte: u8) -> u32 {
+        c ^= byte as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 {
+                POLYNOMIAL ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        c
+    }
+
+    #[cfg(feature = "crc32-slice-by-8")]
+    const NUM_TABLES: usize = 8;
+    #[cfg(all(feature = "crc32-table", not(feature = "crc32-slice-by-8")))]
+    const NUM_TABLES: usize = 1;
+
+    #[cfg(any(feature = "crc32-table", feature = "crc32-slice-by-8"))]
+    static TABLES: [[u32; 256]; NUM_TABLES] = make_tables();
+
+    /// `TABLES[0]` is the classic byte-at-a-time table, `TABLES[k]` advances a byte through
+    /// `k` more zero bytes.
+    #[cfg(any(feature = "crc32-table", feature = "crc32-slice-by-8"))]
+    const fn make_tables() -> [[u32; 256]; NUM_TABLES] {
+        let mut tables = [[0; 256]; NUM_TABLES];
+        let mut n = 0;
+        while n < 256 {
+            tables[0][n] = update_bitwise(n as u32, 0);
+            n += 1;
+        }
+        let mut k = 1;
+        while k < NUM_TABLES {
+            let mut n = 0;
+            while n < 256 {
+                let prev = tables[k - 1][n];
+                tables[k][n] = (prev >> 8) ^ tables[0][(prev & 0xff) as usize];
+                n += 1;
+            }
+            k += 1;
+        }
+        tables
+    }
+
+    #[cfg(feature = "crc32-slice-by-8")]
+    fn update(mut crc: u32, data: &[u8]) -> u32 {
+        let mut chunks = data.chunks_exact(8);
+        for chunk in &mut chunks {
+            let lo = crc ^ u32::from_le_bytes(chunk[0..4].try_into().unwrap());
+            let hi = u32::from_le_bytes(chunk[4..8].try_into().unwrap());
+            crc = TABLES[7][(lo & 0xff) as usize]
+                ^ TABLES[6][((lo >> 8) & 0xff) as usize]
+                ^ TABLES[5][((lo >> 16) & 0xff) as usize]
+                ^ TABLES[4][(lo >> 24) as usize]
+                ^ TABLES[3][(hi & 0xff) as usize]
+                ^ TABLES[2][((hi >> 8) & 0xff) as usize]
+                ^ TABLES[1][((hi >> 16) & 0xff) as usize]
+                ^ TABLES[0][(hi >> 24) as usize];
+        }
+        for &b in chunks.remainder() {
+            crc = TABLES[0][((crc ^ b as u32) & 0xff) as usize] ^ (crc >> 8);
+        }
+        crc
+    }
+
+    #[cfg(all(feature = "crc32-table", not(feature = "crc32-slice-by-8")))]
+    fn update(mut crc: u32, data: &[u8]) -> u32 {
+        for &b in data {
+            crc = TABLES[0][((crc ^ b as u32) & 0xff) as usize] ^ (crc >> 8);
+        }
+        crc
+    }
+
+    #[cfg(not(any(feature = "crc32-table", feature = "crc32-slice-by-8")))]
+    fn update(mut crc: u32, data: &[u8]) -> u32 {
+        for &b in data {
+            crc = update_bitwise(crc, b);
+        }
+        crc
+    }
+
+    #[derive(Clone, Copy, Eq, PartialEq, Debug)]
+    pub struct Crc32 {
+        state: u32,
+    }
+
+    impl Default for Crc32 {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl Crc32 {
+        pub const fn new() -> Self {
+            Self { state: 0xffff_ffff }
+        }
+
+        pub fn update(&mut self, data: &[u8]) {
+            self.state = update(self.state, data);
+        }
+
+        /// CRC of all data passed to `update` so far. Doesn't reset the state.
+        pub fn finish(&self) -> u32 {
+            self.state ^ 0xffff_ffff
+        }
+    }
+
+    /// For use where any hasher will do, e.g. in
+    /// [`PixelChecksum`](crate::checksum::PixelChecksum). The CRC is in the low 32 bits.
+    impl core::hash::Hasher for Crc32 {
+        fn finish(&self) -> u64 {
+            Crc32::finish(self) as u64
+        }
+
+        fn write(&mut self, bytes: &[u8]) {
+            self.update(bytes);
+        }
+    }
+
+    /// CRC of a complete buffer.
+    pub fn checksum(data: &[u8]) -> u32 {
+        let mut crc = Crc32::new();
+        crc.update(data);
+        crc.finish()
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn iend_crc() {
+            assert_eq!(checksum(b"IEND"), 0xae42_6082);
+        }
+
+        #[test]
+        fn incremental() {
+            let mut crc = Crc32::new();
+            crc.update(b"123");
+            crc.update(b"");
+            crc.update(b"456789");
+            assert_eq!(crc.finish(), 0xcbf4_3926);
+            assert_eq!(crc.finish(), checksum(b"123456789"));
+        }
+
+        #[test]
+        fn matches_bitwise() {
+            let data: [u8; 1000] = core::array::from_fn(|i| (i * 7 + i / 13) as u8);
+            for len in [0, 1, 7, 8, 9, 100, 1000] {
+                let expected = data[..len]
+                    .iter()
+                    .fold(0xffff_ffff, |c, &b| update_bitwise(c, b))
+                    ^ 0xffff_ffff;
+                assert_eq!(checksum(&data[..len]), expected);
+            }
+        }
+    }
+}
+
+pub mod adler32 {
+    //! Streaming Adler-32, the checksum at the end of zlib streams.
+    //!
+    //! <https://www.rfc-editor.org/rfc/rfc1950#section-8>
+
+    const MOD_ADLER: u32 = 65521;
+
+    /// Largest number of bytes which can be summed before `b` may overflow a `u32`.
+    const NMAX: usize = 5552;
+
+    #[derive(Clone, Copy, Eq, PartialEq, Debug)]
+    pub struct Adler32 {
+        a: u32,
+        b: u32,
+    }
+
+    impl Default for Adler32 {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
+
+    impl Adler32 {
+        pub const fn new() -> Self {
+            Self { a: 1, b: 0 }
+        }
+
+        pub fn update(&mut self, data: &[u8]) {
+            for block in data.chunks(NMAX) {
+                for &byte in block {
+                    self.a += byte as u32;
+                    self.b += self.a;
+                }
+                self.a %= MOD_ADLER;
+                self.b %= MOD_ADLER;
+            }
+        }
+
+        /// Checksum of all data passed to `update` so far. Doesn't reset the state.
+        pub fn finish(&self) -> u32 {
+            (self.b << 16) | self.a
+        }
+    }
+
+    /// Checksum of a complete buffer.
+    pub fn checksum(data: &[u8]) -> u32 {
+        let mut adler = Adler32::new();
+        adler.update(data);
+        adler.finish()
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn wikipedia() {
+            assert_eq!(checksum(b"Wikipedia"), 0x11e6_0398);
+        }
+
+        #[test]
+        fn matches_zlib_trailer() {
+            let input = [0xffu8; 20000];
             let compressed = miniz_oxide::deflate::compress_to_vec_zlib(&input, 5);
 
-            let mut output = Vec::<u8, N>::new();
+            let mut adler = Adler32::new();
+            adler.update(&input[..7]);
+            adler.update(&input[7..]);
+            assert_eq!(
+                adler.finish().to_be_bytes(),
+                compressed[compressed.len() - 4..]
+            );
+        }
+    }
+}
 
-            let mut event = Some(sd::Event::ImageData(&compressed));
-            while let Some(e) = event {
-                let (leftover, output_event) = d.update(e).unwrap();
-                match output_event {
-                    Some(Event::ImageData(data)) => output.extend_from_slice(data).unwrap(),
-                    None => {}
-                    _ => panic!("expected only ImageData output"),
+pub mod stats {
+    //! Counters describing a decoded file, so that firmware can log what its assets look like
+    //! and size its buffers from field data. [`DecodeStats`] is fed the events passing between
+    //! the stages, it doesn't affect decoding.
+
+    use super::*;
+    use crate::dechunker::{self, ChunkType};
+    use crate::inflater;
+    use crate::stream_decoder as sd;
+
+    #[derive(Clone, Eq, PartialEq, Debug)]
+    pub struct DecodeStats<const CHUNK_TYPES: usize = 16> {
+        /// Number of chunks of each type, in order of first appearance
+        chunk_counts: ArrayVec<([u8; 4], u32), CHUNK_TYPES>,
+        /// Chunks of types which didn't fit into `chunk_counts`.
+        pub other_chunks: u32,
+        /// Compressed image data, i.e. the payload of IDAT chunks.
+        pub image_data_bytes: u64,
+        /// Decompressed image data.
+        pub decompressed_bytes: u64,
+        /// Largest data payload of any observed event.
+        pub max_event_size: usize,
+    }
+
+    impl<const CHUNK_TYPES: usize> Default for DecodeStats<CHUNK_TYPES> {
+        fn default() -> Self {
+            Self::with_chunk_type_capacity()
+        }
+    }
+
+    impl DecodeStats {
+        pub fn new() -> Self {
+            Self::with_chunk_type_capacity()
+        }
+    }
+
+    impl<const CHUNK_TYPES: usize> DecodeStats<CHUNK_TYPES> {
+        /// Creates counters for at most `CHUNK_TYPES` distinct chunk types. Chunks of further
+        /// types are only counted in [`other_chunks`](Self::other_chunks).
+        pub fn with_chunk_type_capacity() -> Self {
+            Self {
+                chunk_counts: ArrayVec::new(),
+                other_chunks: 0,
+                image_data_bytes: 0,
+                decompressed_bytes: 0,
+                max_event_size: 0,
+            }
+        }
+
+        /// Counts a chunk on `BeginChunk`.
+        pub fn dechunker_event(&mut self, event: &dechunker::Event) {
+            match event {
+                dechunker::Event::BeginChunk(header) => {
+                    let type_ = header.type_.0;
+                    if let Some((_, count)) =
+                        self.chunk_counts.iter_mut().find(|(t, _)| *t == type_)
+                    {
+                        *count += 1;
+                    } else if self.chunk_counts.push((type_, 1)).is_err() {
+                        self.other_chunks += 1;
+                    }
                 }
-                event = leftover;
+                dechunker::Event::Data(data) => self.observe_size(data.len()),
+                dechunker::Event::Crc(_)
+                | dechunker::Event::EndChunk
+                | dechunker::Event::Resync(_) => {}
             }
+        }
 
-            assert_eq!(input.len(), output.len());
-            for c in output {
-                assert_eq!(c, b'A');
+        /// Counts compressed image data.
+        pub fn stream_decoder_event(&mut self, event: &sd::Event) {
+            if let sd::Event::ImageData(data) = event {
+                self.image_data_bytes += data.len() as u64;
+                self.observe_size(data.len());
+            }
+        }
+
+        /// Counts decompressed image data.
+        pub fn inflater_event(&mut self, event: &inflater::Event) {
+            if let inflater::Event::ImageData(data) = event {
+                self.decompressed_bytes += data.len() as u64;
+                self.observe_size(data.len());
+            }
+        }
+
+        fn observe_size(&mut self, size: usize) {
+            self.max_event_size = core::cmp::max(self.max_event_size, size);
+        }
+
+        /// Number of chunks of each type, in order of first appearance.
+        pub fn chunk_counts(&self) -> impl Iterator<Item = (ChunkType, u32)> + '_ {
+            self.chunk_counts
+                .iter()
+                .map(|&(type_, count)| (ChunkType(type_), count))
+        }
+
+        /// Number of chunks of the given type, or `None` if its count didn't fit.
+        pub fn chunk_count(&self, type_: ChunkType) -> Option<u32> {
+            match self.chunk_counts.iter().find(|(t, _)| *t == type_.0) {
+                Some(&(_, count)) => Some(count),
+                None if self.chunk_counts.is_full() => None,
+                None => Some(0),
+            }
+        }
+
+        pub fn total_chunks(&self) -> u32 {
+            self.chunk_counts
+                .iter()
+                .map(|(_, count)| count)
+                .sum::<u32>()
+                + self.other_chunks
+        }
+
+        /// Decompressed size divided by compressed size, once there was image data.
+        pub fn compression_ratio(&self) -> Option<f32> {
+            if self.image_data_bytes == 0 {
+                return None;
+            }
+            Some(self.decompressed_bytes as f32 / self.image_data_bytes as f32)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::dechunker::ChunkHeader;
+
+        fn begin(type_: &[u8; 4], len: u32) -> dechunker::Event<'static> {
+            dechunker::Event::BeginChunk(ChunkHeader {
+                len,
+                type_: ChunkType(*type_),
+            })
+        }
+
+        #[test]
+        fn counts() {
+            let mut stats = DecodeStats::<2>::with_chunk_type_capacity();
+            for e in [
+                begin(b"IHDR", 13),
+                begin(b"IDAT", 3),
+                dechunker::Event::Data(b"abc"),
+                dechunker::Event::EndChunk,
+                begin(b"IDAT", 1),
+                begin(b"IEND", 0),
+            ] {
+                stats.dechunker_event(&e);
             }
+            stats.stream_decoder_event(&sd::Event::ImageData(b"abc"));
+            stats.stream_decoder_event(&sd::Event::ImageData(b"d"));
+            stats.inflater_event(&inflater::Event::ImageData(&[0; 10]));
+
+            assert!(stats
+                .chunk_counts()
+                .eq([(ChunkType(*b"IHDR"), 1), (ChunkType(*b"IDAT"), 2)]));
+            assert_eq!(stats.other_chunks, 1);
+            assert_eq!(stats.total_chunks(), 4);
+            assert_eq!(stats.chunk_count(ChunkType(*b"IDAT")), Some(2));
+            assert_eq!(stats.chunk_count(ChunkType(*b"IEND")), None);
+            assert_eq!(stats.image_data_bytes, 4);
+            assert_eq!(stats.decompressed_bytes, 10);
+            assert_eq!(stats.compression_ratio(), Some(2.5));
+            assert_eq!(stats.max_event_size, 10);
+
+            assert_eq!(DecodeStats::new().compression_ratio(), None);
+            assert_eq!(DecodeStats::new().chunk_count(ChunkType(*b"IEND")), Some(0));
+        }
+    }
+}
+
+pub mod footprint {
+    //! Memory needed by the decoder, known at compile time. Useful for sizing static
+    //! allocations:
+    //!
+    //! ```
+    //! # #[cfg(all(feature = "inflate", feature = "pixels"))]
+    //! use incremental_png::footprint::{assert_fits_in, decoder};
+    //!
+    //! # #[cfg(all(feature = "inflate", feature = "pixels"))]
+    //! const _: () = assert_fits_in::<{ decoder::<256, 1024, 1024>() }, { 64 * 1024 }>();
+    //! ```
+    //!
+    //! How much of it is actually used by an image is reported at runtime as a [`MemoryUsage`].
+
+    #[cfg(all(feature = "inflate", feature = "pixels"))]
+    use crate::{
+        dechunker::Dechunker, inflater::Inflater, stream_decoder::StreamDecoder,
+        unfilter::Unfilterer,
+    };
+
+    /// Memory used by the whole pipeline, Dechunker to Unfilterer, with the given
+    /// `StreamDecoder`, `Inflater` and `Unfilterer` parameters.
+    #[cfg(all(feature = "inflate", feature = "pixels"))]
+    pub const fn decoder<
+        const PALETTE_SIZE: usize,
+        const BUFFER_SIZE: usize,
+        const MAX_SCANLINE: usize,
+    >() -> usize {
+        Dechunker::MEMORY_FOOTPRINT
+            + StreamDecoder::<PALETTE_SIZE>::MEMORY_FOOTPRINT
+            + Inflater::<BUFFER_SIZE>::MEMORY_FOOTPRINT
+            + Unfilterer::<MAX_SCANLINE>::MEMORY_FOOTPRINT
+    }
+
+    /// Memory of a stage at runtime, returned by e.g.
+    /// [`Decoder::memory_usage`](crate::decoder::Decoder::memory_usage) for diagnostics.
+    #[derive(Clone, Copy, Default, Eq, PartialEq, Debug)]
+    pub struct MemoryUsage {
+        /// Size of the stage, its `MEMORY_FOOTPRINT`, including buffers whether used or not
+        pub static_bytes: usize,
+        /// Most of the buffers used so far. Part of `static_bytes`, the rest is headroom.
+        pub peak_dynamic: usize,
+    }
+
+    /// Fails to compile when used in a constant and `SIZE` is larger than `LIMIT`.
+    pub const fn assert_fits_in<const SIZE: usize, const LIMIT: usize>() {
+        assert!(SIZE <= LIMIT, "memory footprint exceeds the limit");
+    }
+
+    #[cfg(all(test, feature = "inflate", feature = "pixels", feature = "palette"))]
+    mod tests {
+        use super::*;
+
+        const _: () = assert_fits_in::<{ Dechunker::MEMORY_FOOTPRINT }, 64>();
+
+        #[test]
+        fn decoder_is_sum_of_stages() {
+            assert_eq!(
+                decoder::<16, 512, 256>(),
+                core::mem::size_of::<Dechunker>()
+                    + core::mem::size_of::<StreamDecoder<16>>()
+                    + core::mem::size_of::<Inflater<512>>()
+                    + core::mem::size_of::<Unfilterer<256>>()
+            );
+            assert!(decoder::<256, 1024, 1024>() > decoder::<0, 1024, 1024>());
+        }
+
+        #[test]
+        fn memory_usage() {
+            use crate::decoder::{Decoder, DecoderConfig};
+
+            let mut decoder: Decoder = DecoderConfig::new().build();
+            assert_eq!(decoder.memory_usage().peak_dynamic, 0);
+            assert_eq!(
+                decoder.memory_usage().static_bytes,
+                Decoder::<256, 1024, 1024>::MEMORY_FOOTPRINT
+            );
+
+            // 32 pixels of 4 bits, with 15 palette entries
+            let input = include_bytes!("../pngdump/pngsuite/basn3p04.png");
+            decoder
+                .update_all(input, |_, _| Ok::<_, crate::Error>(()))
+                .unwrap();
+            // The Inflater's buffer is bypassed, the rows include the filter type byte
+            assert_eq!(decoder.stream_decoder().memory_usage().peak_dynamic, 15 * 3);
+            assert_eq!(decoder.unfilterer().memory_usage().peak_dynamic, 2 * 17);
+            assert_eq!(decoder.memory_usage().peak_dynamic, 15 * 3 + 2 * 17);
+        }
+    }
+}
+
+/// Compile-time checks that the stages and their events are `Send` and `Sync`, so that a
+/// decoder can be moved to another thread or executor between pieces of input.
+mod thread_safety {
+    use crate::*;
+
+    const fn assert_send_sync<T: Send + Sync>() {}
+
+    const _: () = {
+        assert_send_sync::<dechunker::Dechunker>();
+        assert_send_sync::<dechunker::Event>();
+        assert_send_sync::<stream_decoder::StreamDecoder>();
+        assert_send_sync::<stream_decoder::Event>();
+        assert_send_sync::<inflater::Event>();
+        assert_send_sync::<crc32::Crc32>();
+        assert_send_sync::<adler32::Adler32>();
+        assert_send_sync::<stats::DecodeStats>();
+        assert_send_sync::<Error>();
+    };
+
+    #[cfg(feature = "palette")]
+    const _: () = assert_send_sync::<Palette>();
+
+    #[cfg(feature = "inflate")]
+    const _: () = assert_send_sync::<inflater::Inflater>();
+
+    #[cfg(feature = "pixels")]
+    const _: () = {
+        assert_send_sync::<unfilter::Unfilterer>();
+        assert_send_sync::<unfilter::Event>();
+        assert_send_sync::<unfilter::RowBuffer>();
+        assert_send_sync::<double_buffer::DoubleBuffer>();
+        assert_send_sync::<double_buffer::Event>();
+        assert_send_sync::<checksum::PixelChecksum>();
+        assert_send_sync::<checksum::Event>();
+        assert_send_sync::<pixel_iter::RowTracker>();
+        assert_send_sync::<pixel_iter::Pixels>();
+        assert_send_sync::<framebuffer::FramebufferWriter>();
+    };
+
+    #[cfg(feature = "embedded-graphics")]
+    const _: () = assert_send_sync::<
+        embedded_graphics::ColorPixels<embedded_graphics_core::pixelcolor::Rgb565>,
+    >();
+
+    #[cfg(all(feature = "inflate", feature = "pixels"))]
+    const _: () = {
+        assert_send_sync::<decoder::Decoder>();
+        assert_send_sync::<decoder::Context<256>>();
+    };
+
+    #[cfg(feature = "heapless")]
+    const _: () = {
+        assert_send_sync::<dechunker::OwnedEvent<64>>();
+        assert_send_sync::<stream_decoder::OwnedEvent<64>>();
+        assert_send_sync::<inflater::OwnedEvent<64>>();
+    };
+
+    #[cfg(feature = "ico")]
+    const _: () = {
+        assert_send_sync::<ico::IcoReader>();
+        assert_send_sync::<ico::Event>();
+    };
+
+    #[cfg(feature = "alloc")]
+    const _: () = {
+        assert_send_sync::<encoder::PngEncoder>();
+        assert_send_sync::<encoder::Transcoder>();
+        assert_send_sync::<encoder::Deflater>();
+    };
+
+    #[cfg(all(feature = "alloc", feature = "pixels"))]
+    const _: () = assert_send_sync::<random_access::RandomAccessDecoder>();
+}
+
+#[cfg(all(feature = "inflate", feature = "pixels"))]
+pub mod testing {
+    //! Helpers for checking the central promise of this crate: splitting the input differently
+    //! doesn't change the output. Useful for testing new stages too.
+
+    use crate::crc32::Crc32;
+    use crate::dechunker::{self, Dechunker};
+    use crate::inflater::{self, Inflater};
+    use crate::stream_decoder::{self as sd, StreamDecoder};
+    use crate::unfilter::{self, Unfilterer};
+    use crate::Error;
+
+    /// Splits `data` at the given offsets. Offsets are sorted first; out-of-range and
+    /// repeated ones are ignored.
+    pub fn split<const N: usize>(
+        data: &[u8],
+        mut points: [usize; N],
+    ) -> impl Iterator<Item = &[u8]> {
+        points.sort_unstable();
+        let mut start = 0;
+        points
+            .into_iter()
+            .chain(core::iter::once(data.len()))
+            .filter_map(move |point| {
+                if point <= start || point > data.len() {
+                    return None;
+                }
+                let piece = &data[start..point];
+                start = point;
+                Some(piece)
+            })
+    }
+
+    /// Runs `run` on `data` in one piece and split at `points`, and panics if the results
+    /// differ.
+    pub fn assert_split_invariant<'a, T: PartialEq + core::fmt::Debug, const N: usize>(
+        data: &'a [u8],
+        points: [usize; N],
+        mut run: impl FnMut(&mut dyn Iterator<Item = &'a [u8]>) -> T,
+    ) {
+        let whole = run(&mut core::iter::once(data));
+        let pieces = run(&mut split(data, points));
+        assert_eq!(whole, pieces, "output differs when split at {:?}", points);
+    }
+
+    /// CRC-32 of everything each decoder stage produced, event boundaries aside.
+    #[derive(Clone, Copy, Eq, PartialEq, Debug)]
+    pub struct StageDigests {
+        /// Chunk headers, data and ends
+        pub dechunker: u32,
+        /// Image header and compressed image data
+        pub stream_decoder: u32,
+        /// Decompressed image data
+        pub inflater: u32,
+        /// Unfiltered rows
+        pub unfilterer: u32,
+    }
+
+    /// Decodes an image fed in the given pieces, with rows of up to 1023 bytes, and digests
+    /// the output of every stage. Compare the results for different splits with
+    /// [`assert_split_invariant`].
+    pub fn decode_digests(pieces: &mut dyn Iterator<Item = &[u8]>) -> Result<StageDigests, Error> {
+        let mut dechunker = Dechunker::new();
+        let mut sd = StreamDecoder::new();
+        let mut inflater = Inflater::<1024>::new();
+        let mut unfilterer = Unfilterer::<1024>::new();
+        let mut digests = [Crc32::new(), Crc32::new(), Crc32::new(), Crc32::new()];
+
+        for piece in pieces {
+            dechunker.update_all(piece, |e| {
+                match &e {
+                    dechunker::Event::BeginChunk(h) => {
+                        digests[0].update(&h.len.to_be_bytes());
+                        digests[0].update(&h.type_.0);
+                    }
+                    dechunker::Event::Data(data) => digests[0].update(data),
+                    dechunker::Event::Crc(crc) => digests[0].update(&crc.to_be_bytes()),
+                    dechunker::Event::EndChunk => digests[0].update(b"end"),
+                    dechunker::Event::Resync(_) => digests[0].update(b"resync"),
+                }
+                // Leftover events were digested already, when they were first produced
+                let mut dc_event = Some(e);
+                while let Some(e) = dc_event {
+                    let (leftover, mut sd_event) = sd.update(e)?;
+                    match &sd_event {
+                        Some(sd::Event::ImageHeader(h)) => {
+                            digests[1].update(&h.width.to_be_bytes());
+                            digests[1].update(&h.height.to_be_bytes());
+                            digests[1].update(&[h.bit_depth, h.colour_type]);
+                        }
+                        Some(sd::Event::ImageInfo(_)) => digests[1].update(b"info"),
+                        Some(sd::Event::ImageData(data)) => digests[1].update(data),
+                        #[cfg(feature = "ancillary")]
+                        Some(sd::Event::Extension(_)) => digests[1].update(b"extension"),
+                        Some(sd::Event::Warning(_)) => digests[1].update(b"warning"),
+                        Some(sd::Event::End) => digests[1].update(b"end"),
+                        None => {}
+                    }
+                    while let Some(e) = sd_event {
+                        let (leftover, mut i_event) = inflater.update(e)?;
+                        if let Some(inflater::Event::ImageData(data)) = &i_event {
+                            digests[2].update(data);
+                        }
+                        while let Some(e) = i_event {
+                            let (leftover, u_event) = unfilterer.update(e)?;
+                            if let Some(unfilter::Event::Row(row)) = u_event {
+                                digests[3].update(row);
+                            }
+                            i_event = leftover;
+                        }
+                        sd_event = leftover;
+                    }
+                    dc_event = leftover;
+                }
+                Ok::<_, Error>(())
+            })?;
+        }
+        dechunker.eof()?;
+        sd.eof()?;
+
+        let [dechunker, stream_decoder, inflater, unfilterer] = digests.map(|d| d.finish());
+        Ok(StageDigests {
+            dechunker,
+            stream_decoder,
+            inflater,
+            unfilterer,
+        })
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn split_points() {
+            let data = [1, 2, 3, 4, 5];
+            let mut pieces = split(&data, [3, 0, 1, 3, 9]);
+            assert_eq!(pieces.next(), Some(&data[..1]));
+            assert_eq!(pieces.next(), Some(&data[1..3]));
+            assert_eq!(pieces.next(), Some(&data[3..]));
+            assert_eq!(pieces.next(), None);
         }
     }
 }
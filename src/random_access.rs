@@ -0,0 +1,211 @@
+//! Decoding any range of rows again after a first pass, for host-side viewers scrolling through
+//! images too large to keep decoded in memory.
+//!
+//! The first pass keeps a copy of the decoder every few rows. A range of rows is then decoded
+//! from the last copy before it, so only the compressed file has to stay in memory.
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use crate::decoder::{Decoder, DecoderConfig};
+use crate::stream_decoder::ImageHeader;
+use crate::unfilter;
+use crate::Error;
+#[cfg(feature = "palette")]
+use crate::Palette;
+
+/// Input passed to the decoder at once. Checkpoints are taken between pieces, so this is how far
+/// they can be from the requested interval.
+const PIECE_SIZE: usize = 256;
+
+/// Decoder state from which the rest of the file can be decoded.
+#[derive(Clone)]
+struct Checkpoint<const PALETTE_SIZE: usize, const BUFFER_SIZE: usize, const MAX_SCANLINE: usize> {
+    decoder: Box<Decoder<PALETTE_SIZE, BUFFER_SIZE, MAX_SCANLINE>>,
+    /// Position in the file
+    offset: usize,
+    /// Rows produced before `offset`
+    row: u32,
+}
+
+/// Produces any range of rows of a PNG file held in memory, decoding only from the nearest
+/// checkpoint before it.
+///
+/// Each checkpoint is a copy of the [`Decoder`], so they cost about
+/// [`Decoder::MEMORY_FOOTPRINT`] each. Rows are counted in the order the Unfilterer produces
+/// them, i.e. pass by pass for interlaced images.
+pub struct RandomAccessDecoder<
+    'png,
+    const PALETTE_SIZE: usize = 256,
+    const BUFFER_SIZE: usize = 1024,
+    const MAX_SCANLINE: usize = 1024,
+> {
+    png: &'png [u8],
+    header: ImageHeader,
+    rows: u32,
+    checkpoints: Vec<Checkpoint<PALETTE_SIZE, BUFFER_SIZE, MAX_SCANLINE>>,
+    #[cfg(feature = "palette")]
+    palette: Palette<PALETTE_SIZE>,
+}
+
+impl<'png, const PALETTE_SIZE: usize, const BUFFER_SIZE: usize, const MAX_SCANLINE: usize>
+    RandomAccessDecoder<'png, PALETTE_SIZE, BUFFER_SIZE, MAX_SCANLINE>
+{
+    /// Decodes the whole file, taking a checkpoint every `interval` rows (or a little later).
+    /// Fails like [`Decoder::finish`] if the file is incomplete.
+    pub fn new(png: &'png [u8], config: DecoderConfig, interval: u32) -> Result<Self, Error> {
+        let interval = interval.max(1);
+        let mut decoder: Box<Decoder<PALETTE_SIZE, BUFFER_SIZE, MAX_SCANLINE>> =
+            Box::new(config.build());
+        let mut checkpoints: Vec<Checkpoint<PALETTE_SIZE, BUFFER_SIZE, MAX_SCANLINE>> = Vec::new();
+        let mut header = None;
+        let mut rows = 0;
+
+        for (i, piece) in png.chunks(PIECE_SIZE).enumerate() {
+            let due = checkpoints
+                .last()
+                .is_none_or(|c| rows >= c.row.saturating_add(interval));
+            if due {
+                checkpoints.push(Checkpoint {
+                    decoder: decoder.clone(),
+                    offset: i * PIECE_SIZE,
+                    row: rows,
+                });
+            }
+            decoder.update_all(piece, |e, _| {
+                match e {
+                    unfilter::Event::ImageHeader(h) => header = Some(h),
+                    unfilter::Event::Row(_) => rows += 1,
+                    _ => {}
+                }
+                Ok::<_, Error>(())
+            })?;
+        }
+        decoder.finish()?;
+
+        Ok(Self {
+            png,
+            header: header.ok_or(Error::NoImageHeader)?,
+            rows,
+            checkpoints,
+            #[cfg(feature = "palette")]
+            palette: decoder.palette().clone(),
+        })
+    }
+
+    pub fn header(&self) -> &ImageHeader {
+        &self.header
+    }
+
+    /// Number of rows in the file, which is the image height unless it's interlaced.
+    pub fn rows(&self) -> u32 {
+        self.rows
+    }
+
+    #[cfg(feature = "palette")]
+    pub fn palette(&self) -> &Palette<PALETTE_SIZE> {
+        &self.palette
+    }
+
+    /// Number of checkpoints taken by [`new`](Self::new).
+    pub fn checkpoints(&self) -> usize {
+        self.checkpoints.len()
+    }
+
+    /// Calls `f` with the index and unfiltered data of each row in `range`. Rows past the end
+    /// of the image are left out.
+    pub fn decode_rows<E: From<Error>>(
+        &self,
+        range: Range<u32>,
+        mut f: impl FnMut(u32, &[u8]) -> Result<(), E>,
+    ) -> Result<(), E> {
+        let i = self
+            .checkpoints
+            .partition_point(|c| c.row <= range.start)
+            .saturating_sub(1);
+        let Some(checkpoint) = self.checkpoints.get(i) else {
+            return Ok(());
+        };
+        let mut decoder = checkpoint.decoder.clone();
+        let mut y = checkpoint.row;
+
+        let rest = self.png.get(checkpoint.offset..).unwrap_or_default();
+        for piece in rest.chunks(PIECE_SIZE) {
+            if y >= range.end {
+                break;
+            }
+            decoder.update_all(piece, |e, _| {
+                if let unfilter::Event::Row(row) = e {
+                    if range.contains(&y) {
+                        f(y, row)?;
+                    }
+                    y += 1;
+                }
+                Ok::<_, E>(())
+            })?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    extern crate std;
+
+    use std::vec;
+
+    const PNGSUITE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/pngdump/pngsuite");
+
+    fn decode_rows(decoder: &RandomAccessDecoder, range: Range<u32>) -> Vec<(u32, Vec<u8>)> {
+        let mut rows = Vec::new();
+        decoder
+            .decode_rows(range, |y, row| {
+                rows.push((y, row.to_vec()));
+                Ok::<_, Error>(())
+            })
+            .unwrap();
+        rows
+    }
+
+    #[test]
+    fn any_range() {
+        let png = std::fs::read(std::format!("{PNGSUITE}/basn6a16.png")).unwrap();
+        let all = RandomAccessDecoder::<0, 1, 257>::new(&png, DecoderConfig::new(), 32).unwrap();
+        let decoder = RandomAccessDecoder::new(&png, DecoderConfig::new(), 4).unwrap();
+        assert_eq!((decoder.header().height, decoder.rows()), (32, 32));
+        assert!(decoder.checkpoints() > 2);
+
+        let mut expected = Vec::new();
+        all.decode_rows(0..32, |y, row| {
+            expected.push((y, row.to_vec()));
+            Ok::<_, Error>(())
+        })
+        .unwrap();
+        assert_eq!(expected.len(), 32);
+        for start in 0..32 {
+            for end in start..34 {
+                let end_row = end.min(32) as usize;
+                assert_eq!(
+                    decode_rows(&decoder, start..end),
+                    expected[start as usize..end_row.max(start as usize)],
+                    "{start}..{end}"
+                );
+            }
+        }
+        assert_eq!(decode_rows(&decoder, 40..50), vec![]);
+    }
+
+    #[test]
+    fn truncated() {
+        let png = std::fs::read(std::format!("{PNGSUITE}/basn0g08.png")).unwrap();
+        assert_eq!(
+            RandomAccessDecoder::<256>::new(&png[..png.len() / 2], DecoderConfig::new(), 4).err(),
+            Some(Error::UnfinishedChunk)
+        );
+    }
+}
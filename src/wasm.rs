@@ -0,0 +1,284 @@
+//! wasm-bindgen bindings for decoding in the browser. Bytes are pushed as they arrive, e.g.
+//! from a `fetch()` body, and each row is handed to a callback as soon as it's decoded, already
+//! converted to RGBA for `ImageData`. See `examples/wasm` for a page drawing into a canvas.
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use js_sys::{Function, Uint8ClampedArray};
+use wasm_bindgen::prelude::*;
+
+use crate::dechunker::Dechunker;
+use crate::inflater::Inflater;
+use crate::stream_decoder::{ImageHeader, StreamDecoder};
+use crate::unfilter::{self, Unfilterer};
+use crate::{Error, Palette};
+
+/// Longest supported scanline in bytes, including the filter type byte. Enough for 8192 pixels
+/// of 16-bit RGBA.
+const MAX_SCANLINE: usize = 8192 * 8 + 1;
+
+struct Pipeline {
+    dechunker: Dechunker,
+    stream_decoder: StreamDecoder,
+    inflater: Inflater<4096>,
+    unfilterer: Unfilterer<MAX_SCANLINE>,
+}
+
+/// Why the pipeline stopped.
+enum Stop {
+    Error(Error),
+    Callback(JsValue),
+}
+
+impl From<Error> for Stop {
+    fn from(e: Error) -> Self {
+        Stop::Error(e)
+    }
+}
+
+impl From<Stop> for JsValue {
+    fn from(stop: Stop) -> Self {
+        match stop {
+            Stop::Error(e) => JsError::new(&alloc::format!("{e}")).into(),
+            Stop::Callback(e) => e,
+        }
+    }
+}
+
+/// Progressive PNG decoder.
+#[wasm_bindgen]
+pub struct Decoder {
+    pipeline: Box<Pipeline>,
+    on_header: Function,
+    on_row: Function,
+    header: Option<ImageHeader>,
+    y: u32,
+    rgba: Vec<u8>,
+}
+
+#[wasm_bindgen]
+impl Decoder {
+    /// `on_header(width, height)` is called once the size is known, then `on_row(y, rgba)` for
+    /// every row. `rgba` is only valid during the call.
+    #[wasm_bindgen(constructor)]
+    pub fn new(on_header: Function, on_row: Function) -> Decoder {
+        Decoder {
+            pipeline: Box::new(Pipeline {
+                dechunker: Dechunker::new(),
+                stream_decoder: StreamDecoder::new(),
+                inflater: Inflater::new(),
+                unfilterer: Unfilterer::new(),
+            }),
+            on_header,
+            on_row,
+            header: None,
+            y: 0,
+            rgba: Vec::new(),
+        }
+    }
+
+    /// Decodes the next part of the file. Throws on invalid data, and passes on exceptions
+    /// thrown by the callbacks.
+    pub fn push(&mut self, bytes: &[u8]) -> Result<(), JsValue> {
+        let Self {
+            pipeline,
+            on_header,
+            on_row,
+            header,
+            y,
+            rgba,
+        } = self;
+        let Pipeline {
+            dechunker,
+            stream_decoder,
+            inflater,
+            unfilterer,
+        } = &mut **pipeline;
+        dechunker.update_all(bytes, |e| {
+            let mut dc_event = Some(e);
+            while let Some(e) = dc_event {
+                let (leftover, mut sd_event) = stream_decoder.update(e)?;
+                while let Some(e) = sd_event {
+                    let (leftover, mut i_event) = inflater.update(e)?;
+                    while let Some(e) = i_event {
+                        let (leftover, u_event) = unfilterer.update(e)?;
+                        match u_event {
+                            Some(unfilter::Event::ImageHeader(h)) => {
+                                *header = Some(h);
+                                *y = 0;
+                                let len = (h.width as usize).checked_mul(4);
+                                *rgba = vec![0; len.ok_or(Error::ArithmeticOverflow)?];
+                                on_header
+                                    .call2(&JsValue::NULL, &h.width.into(), &h.height.into())
+                                    .map_err(Stop::Callback)?;
+                            }
+                            Some(unfilter::Event::Row(row)) => {
+                                let h = header.as_ref().ok_or(Error::NoImageHeader)?;
+                                to_rgba(h, stream_decoder.palette(), row, rgba)?;
+                                let array = Uint8ClampedArray::from(&rgba[..]);
+                                on_row
+                                    .call2(&JsValue::NULL, &(*y).into(), &array)
+                                    .map_err(Stop::Callback)?;
+                                *y += 1;
+                            }
+                            #[cfg(feature = "ancillary")]
+                            Some(unfilter::Event::Extension(_)) => {}
+                            Some(
+                                unfilter::Event::ImageInfo(_)
+                                | unfilter::Event::Warning(_)
+                                | unfilter::Event::End,
+                            )
+                            | None => {}
+                        }
+                        i_event = leftover;
+                    }
+                    sd_event = leftover;
+                }
+                dc_event = leftover;
+            }
+            Ok::<_, Stop>(())
+        })?;
+        Ok(())
+    }
+
+    /// Throws if the file ended in the middle of a chunk or before IEND, if the deflate stream
+    /// didn't end, or if the number of rows doesn't match the header.
+    pub fn finish(&self) -> Result<(), JsValue> {
+        let Pipeline {
+            dechunker,
+            stream_decoder,
+            inflater,
+            unfilterer,
+        } = &*self.pipeline;
+        dechunker
+            .eof()
+            .and_then(|()| stream_decoder.eof())
+            .and_then(|()| inflater.eof())
+            .and_then(|()| unfilterer.eof())
+            .map_err(Stop::Error)?;
+        Ok(())
+    }
+}
+
+/// Converts an unfiltered row of any colour type and bit depth to 8-bit RGBA.
+fn to_rgba(
+    header: &ImageHeader,
+    palette: &Palette,
+    row: &[u8],
+    out: &mut [u8],
+) -> Result<(), Error> {
+    let channels = header.channels().ok_or(Error::InvalidColourType)? as usize;
+    if !header.bit_depth_allowed() {
+        return Err(Error::InvalidBitDepth);
+    }
+    let bit_depth = header.bit_depth as usize;
+    let max = ((1u32 << bit_depth.min(8)) - 1) as u8;
+    let sample = |i: usize| -> u8 {
+        match bit_depth {
+            // High byte is precise enough for display
+            16 => row.get(i * 2).copied().unwrap_or(0),
+            _ => {
+                let bit = i * bit_depth;
+                let byte = row.get(bit / 8).copied().unwrap_or(0);
+                (byte >> (8 - bit_depth - bit % 8)) & max
+            }
+        }
+    };
+    // Scales a sample of less than 8 bits to the full range
+    let scale = |s: u8| (s as u32 * 255 / max as u32) as u8;
+    for (x, pixel) in out.chunks_exact_mut(4).enumerate() {
+        let i = x * channels;
+        let rgba = match header.colour_type {
+            0 => {
+                let v = scale(sample(i));
+                [v, v, v, 0xff]
+            }
+            2 => [
+                scale(sample(i)),
+                scale(sample(i + 1)),
+                scale(sample(i + 2)),
+                0xff,
+            ],
+            3 => palette.color_at_rgba(sample(i)),
+            4 => {
+                let v = scale(sample(i));
+                [v, v, v, scale(sample(i + 1))]
+            }
+            _ => [
+                scale(sample(i)),
+                scale(sample(i + 1)),
+                scale(sample(i + 2)),
+                scale(sample(i + 3)),
+            ],
+        };
+        pixel.copy_from_slice(&rgba);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(colour_type: u8, bit_depth: u8, width: u32) -> ImageHeader {
+        ImageHeader {
+            width,
+            height: 1,
+            bit_depth,
+            colour_type,
+            compression_method: 0,
+            filter_method: 0,
+            interlace_method: 0,
+        }
+    }
+
+    fn convert(header: ImageHeader, row: &[u8]) -> Vec<u8> {
+        let mut out = vec![0; header.width as usize * 4];
+        to_rgba(&header, &Palette::new(), row, &mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn converts_to_rgba() {
+        assert_eq!(
+            convert(header(0, 1, 3), &[0b101_00000]),
+            [255, 255, 255, 255, 0, 0, 0, 255, 255, 255, 255, 255]
+        );
+        assert_eq!(convert(header(0, 4, 1), &[0x50]), [85, 85, 85, 255]);
+        assert_eq!(
+            convert(header(2, 16, 1), &[1, 2, 3, 4, 5, 6]),
+            [1, 3, 5, 255]
+        );
+        assert_eq!(convert(header(4, 8, 1), &[7, 8]), [7, 7, 7, 8]);
+        assert_eq!(convert(header(6, 8, 1), &[1, 2, 3, 4]), [1, 2, 3, 4]);
+        // Empty palette: transparent black
+        assert_eq!(convert(header(3, 8, 1), &[0]), [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn invalid_header() {
+        let mut out = [0; 4];
+        for (colour_type, bit_depth, error) in [
+            (0, 0, Error::InvalidBitDepth),
+            (0, 3, Error::InvalidBitDepth),
+            (0, 9, Error::InvalidBitDepth),
+            (2, 4, Error::InvalidBitDepth),
+            (1, 8, Error::InvalidColourType),
+        ] {
+            assert_eq!(
+                to_rgba(
+                    &header(colour_type, bit_depth, 1),
+                    &Palette::new(),
+                    &[0; 8],
+                    &mut out
+                ),
+                Err(error),
+                "colour type {colour_type}, bit depth {bit_depth}"
+            );
+        }
+    }
+}
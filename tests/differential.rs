@@ -0,0 +1,153 @@
+//! Decodes sample images with both this crate and the `png` crate and compares the header,
+//! palette and pixels. Uses the PngSuite images, or the PNG files in the directory named by
+//! `PNG_SAMPLES`.
+//!
+//! `cargo test --features differential --test differential`
+
+use std::path::{Path, PathBuf};
+
+use incremental_png::{
+    dechunker::Dechunker,
+    inflater::Inflater,
+    stream_decoder::{ImageHeader, StreamDecoder},
+    unfilter::{self, Unfilterer},
+    Error,
+};
+
+/// What this crate decoded
+struct Decoded {
+    header: ImageHeader,
+    /// RGBA palette entries, alpha from tRNS
+    palette: Vec<[u8; 4]>,
+    pixels: Vec<u8>,
+}
+
+fn decode(data: &[u8]) -> Result<Decoded, Error> {
+    let mut dechunker = Dechunker::new();
+    let mut sd = StreamDecoder::new();
+    let mut inflater = Inflater::<1024>::new();
+    let mut unfilterer = Box::new(Unfilterer::<{ 1 << 16 }>::new());
+    let mut header = None;
+    let mut pixels = vec![];
+
+    dechunker.update_all(data, |e| {
+        let mut dc_event = Some(e);
+        while let Some(e) = dc_event {
+            let (leftover, mut sd_event) = sd.update(e)?;
+            while let Some(e) = sd_event {
+                let (leftover, mut i_event) = inflater.update(e)?;
+                while let Some(e) = i_event {
+                    let (leftover, u_event) = unfilterer.update(e)?;
+                    match u_event {
+                        Some(unfilter::Event::ImageHeader(h)) => header = Some(h),
+                        Some(unfilter::Event::Row(row)) => pixels.extend_from_slice(row),
+                        _ => {}
+                    }
+                    i_event = leftover;
+                }
+                sd_event = leftover;
+            }
+            dc_event = leftover;
+        }
+        Ok::<_, Error>(())
+    })?;
+    dechunker.eof()?;
+    sd.eof()?;
+
+    let palette = sd.palette();
+    Ok(Decoded {
+        header: header.ok_or(Error::NoImageHeader)?,
+        palette: (0..palette.len())
+            .map(|i| palette.color_at_rgba(i as u8))
+            .collect(),
+        pixels,
+    })
+}
+
+fn samples() -> Vec<PathBuf> {
+    let dir = match std::env::var_os("PNG_SAMPLES") {
+        Some(dir) => PathBuf::from(dir),
+        None => Path::new(env!("CARGO_MANIFEST_DIR")).join("pngdump/pngsuite"),
+    };
+    let mut files: Vec<_> = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "png"))
+        .collect();
+    files.sort();
+    files
+}
+
+/// Compares the results for one file. Returns false if it was skipped.
+fn compare(path: &Path) -> bool {
+    let data = std::fs::read(path).unwrap();
+
+    let mut decoder = png::Decoder::new(std::io::Cursor::new(&data));
+    decoder.set_transformations(png::Transformations::IDENTITY);
+    let Ok(mut reader) = decoder.read_info() else {
+        return false;
+    };
+    let mut expected = vec![0; reader.output_buffer_size().unwrap()];
+    let Ok(frame) = reader.next_frame(&mut expected) else {
+        return false;
+    };
+    expected.truncate(frame.buffer_size());
+    let info = reader.info();
+
+    let decoded = match decode(&data) {
+        Ok(decoded) => decoded,
+        Err(Error::InterlaceNotSupported) => return false,
+        Err(e) => panic!("{}: {:?}", path.display(), e),
+    };
+
+    let header = decoded.header;
+    assert_eq!(
+        (header.width, header.height),
+        (info.width, info.height),
+        "{}: size",
+        path.display()
+    );
+    assert_eq!(
+        (header.colour_type, header.bit_depth),
+        (info.color_type as u8, info.bit_depth as u8),
+        "{}: format",
+        path.display()
+    );
+
+    let mut palette = vec![];
+    if let Some(rgb) = &info.palette {
+        let alpha = info.trns.as_deref().unwrap_or_default();
+        for (i, c) in rgb.chunks_exact(3).enumerate() {
+            palette.push([c[0], c[1], c[2], alpha.get(i).copied().unwrap_or(255)]);
+        }
+    }
+    // The png crate also keeps the suggested palette of true colour images
+    if header.colour_type == 3 {
+        assert_eq!(decoded.palette, palette, "{}: palette", path.display());
+    }
+
+    let row_len = frame.line_size;
+    for (y, (row, expected_row)) in decoded
+        .pixels
+        .chunks(row_len)
+        .zip(expected.chunks(row_len))
+        .enumerate()
+    {
+        if let Some(x) = row.iter().zip(expected_row).position(|(a, b)| a != b) {
+            panic!("{}: row {} differs at byte {}", path.display(), y, x);
+        }
+    }
+    assert_eq!(
+        decoded.pixels.len(),
+        expected.len(),
+        "{}: size of pixel data",
+        path.display()
+    );
+    true
+}
+
+#[test]
+fn same_as_png_crate() {
+    let compared = samples().iter().filter(|path| compare(path)).count();
+    assert!(compared > 0, "no images compared");
+}
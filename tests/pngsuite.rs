@@ -0,0 +1,782 @@
+//! Runs the whole decoder over the PngSuite images in `pngdump/pngsuite`.
+//!
+//! <http://www.schaik.com/pngsuite/>
+
+use std::path::Path;
+
+use incremental_png::{
+    checksum::{self, PixelChecksum},
+    crc32::Crc32,
+    dechunker::{self, ChunkType, Dechunker},
+    decoder::{Decoder, DecoderConfig},
+    inflater::Inflater,
+    stream_decoder::StreamDecoder,
+    unfilter::{self, Unfilterer},
+    Error, Strictness, Warning,
+};
+
+/// CRC-32 of the unfiltered rows of each non-interlaced image, concatenated. Checked against
+/// the `png` crate.
+const EXPECTED: &[(&str, u32)] = &[
+    ("PngSuite.png", 0xf2a8c0cf),
+    ("basn0g01.png", 0xb71a0667),
+    ("basn0g02.png", 0xc429db1d),
+    ("basn0g04.png", 0x8089a6e9),
+    ("basn0g08.png", 0x784b4a4e),
+    ("basn0g16.png", 0x9362f0f0),
+    ("basn2c08.png", 0x7855b9bf),
+    ("basn2c16.png", 0xc278125a),
+    ("basn3p01.png", 0xb3189a91),
+    ("basn3p02.png", 0xe56b2b96),
+    ("basn3p04.png", 0x31be6049),
+    ("basn3p08.png", 0xf6ed8aa6),
+    ("basn4a08.png", 0xb076606c),
+    ("basn4a16.png", 0x5264d303),
+    ("basn6a08.png", 0xa74df32c),
+    ("basn6a16.png", 0x632e0a2a),
+    ("bgan6a08.png", 0xa74df32c),
+    ("bgan6a16.png", 0x632e0a2a),
+    ("bgbn4a08.png", 0xb076606c),
+    ("bggn4a16.png", 0x5264d303),
+    ("bgwn6a08.png", 0xa74df32c),
+    ("bgyn6a16.png", 0x632e0a2a),
+    ("ccwn2c08.png", 0x61b69e8e),
+    ("ccwn3p08.png", 0x1eef6e54),
+    ("cdfn2c08.png", 0x99af40a3),
+    ("cdhn2c08.png", 0x84a4ef40),
+    ("cdsn2c08.png", 0x82b26daf),
+    ("cdun2c08.png", 0xee50e3ca),
+    ("ch1n3p04.png", 0x31be6049),
+    ("ch2n3p08.png", 0xf6ed8aa6),
+    ("cm0n0g04.png", 0x7dbd9bfd),
+    ("cm7n0g04.png", 0x7dbd9bfd),
+    ("cm9n0g04.png", 0x7dbd9bfd),
+    ("cs3n2c16.png", 0x2b8f5329),
+    ("cs3n3p08.png", 0x512abb0c),
+    ("cs5n2c08.png", 0x1b16d169),
+    ("cs5n3p08.png", 0xaca0fc47),
+    ("cs8n2c08.png", 0x7306351c),
+    ("cs8n3p08.png", 0x011f5755),
+    ("ct0n0g04.png", 0x7dbd9bfd),
+    ("ct1n0g04.png", 0x7dbd9bfd),
+    ("cten0g04.png", 0xb1dd1102),
+    ("ctfn0g04.png", 0x1867e3d8),
+    ("ctgn0g04.png", 0xe3048287),
+    ("cthn0g04.png", 0x4f92e140),
+    ("ctjn0g04.png", 0xf7a0086d),
+    ("ctzn0g04.png", 0x7dbd9bfd),
+    ("exif2c08.png", 0x1a5022ef),
+    ("f00n0g08.png", 0x1f18265f),
+    ("f00n2c08.png", 0x3f1d66ad),
+    ("f01n0g08.png", 0x1868217f),
+    ("f01n2c08.png", 0x11c1b27e),
+    ("f02n0g08.png", 0x79b9c9de),
+    ("f02n2c08.png", 0x7f1ca785),
+    ("f03n0g08.png", 0xa373c644),
+    ("f03n2c08.png", 0x31645d89),
+    ("f04n0g08.png", 0xb8006228),
+    ("f04n2c08.png", 0x77056a6f),
+    ("f99n0g04.png", 0xe94e1d1a),
+    ("g03n0g16.png", 0x1c3c33ca),
+    ("g03n2c08.png", 0x3633021f),
+    ("g03n3p04.png", 0x35ad5216),
+    ("g04n0g16.png", 0x3f452561),
+    ("g04n2c08.png", 0xae824f12),
+    ("g04n3p04.png", 0x9c9db9c3),
+    ("g05n0g16.png", 0xdea78b6a),
+    ("g05n2c08.png", 0x3c168b7d),
+    ("g05n3p04.png", 0xdb86955f),
+    ("g07n0g16.png", 0x1f800abd),
+    ("g07n2c08.png", 0x6d9b8873),
+    ("g07n3p04.png", 0x56af4531),
+    ("g10n0g16.png", 0xc787de88),
+    ("g10n2c08.png", 0xfa142827),
+    ("g10n3p04.png", 0x23d260ed),
+    ("g25n0g16.png", 0x55f41280),
+    ("g25n2c08.png", 0xe5914131),
+    ("g25n3p04.png", 0x1023a9bf),
+    ("oi1n0g16.png", 0x9362f0f0),
+    ("oi1n2c16.png", 0xc278125a),
+    ("oi2n0g16.png", 0x9362f0f0),
+    ("oi2n2c16.png", 0xc278125a),
+    ("oi4n0g16.png", 0x9362f0f0),
+    ("oi4n2c16.png", 0xc278125a),
+    ("oi9n0g16.png", 0x9362f0f0),
+    ("oi9n2c16.png", 0xc278125a),
+    ("pp0n2c16.png", 0xc278125a),
+    ("pp0n6a08.png", 0x0ee05c61),
+    ("ps1n0g08.png", 0x784b4a4e),
+    ("ps1n2c16.png", 0xc278125a),
+    ("ps2n0g08.png", 0x784b4a4e),
+    ("ps2n2c16.png", 0xc278125a),
+    ("s01n3p01.png", 0xd202ef8d),
+    ("s02n3p01.png", 0x41d912ff),
+    ("s03n3p01.png", 0x0f389617),
+    ("s04n3p01.png", 0x96cd094d),
+    ("s05n3p02.png", 0x61f40a67),
+    ("s06n3p02.png", 0x039d5670),
+    ("s07n3p02.png", 0xb5fe8bc8),
+    ("s08n3p02.png", 0x392c79a2),
+    ("s09n3p02.png", 0x1b3526af),
+    ("s32n3p04.png", 0x5ac833e7),
+    ("s33n3p04.png", 0x5702f399),
+    ("s34n3p04.png", 0x73583830),
+    ("s35n3p04.png", 0x64380795),
+    ("s36n3p04.png", 0x1f02eddb),
+    ("s37n3p04.png", 0x04604f43),
+    ("s38n3p04.png", 0x799f0c41),
+    ("s39n3p04.png", 0xb4c01403),
+    ("s40n3p04.png", 0xa2d933e9),
+    ("tbbn0g04.png", 0x5b672029),
+    ("tbbn2c16.png", 0x588264c2),
+    ("tbbn3p08.png", 0x2111c7b4),
+    ("tbgn2c16.png", 0x588264c2),
+    ("tbgn3p08.png", 0x2111c7b4),
+    ("tbrn2c08.png", 0xe519cff0),
+    ("tbwn0g16.png", 0xc2013edc),
+    ("tbwn3p08.png", 0x2111c7b4),
+    ("tbyn3p08.png", 0x2111c7b4),
+    ("tm3n3p02.png", 0xe837b5c5),
+    ("tp0n0g08.png", 0xc3dda42e),
+    ("tp0n2c08.png", 0xb426b350),
+    ("tp0n3p08.png", 0xe242eb8a),
+    ("tp1n3p08.png", 0x2111c7b4),
+    ("z00n2c08.png", 0xf8f7d651),
+    ("z03n2c08.png", 0xf8f7d651),
+    ("z06n2c08.png", 0xf8f7d651),
+    ("z09n2c08.png", 0xf8f7d651),
+];
+
+/// What decoding a file produced: the CRC-32 of its rows, or the first problem found.
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    Pixels(u32),
+    Error(Error),
+    BadCrc,
+    RowCount { expected: u32, actual: u32 },
+}
+
+/// Feeds the file through every stage, a few bytes at a time, also checking chunk CRCs.
+fn decode(data: &[u8]) -> Outcome {
+    let mut dechunker = Dechunker::new().with_crc_events();
+    let mut sd = StreamDecoder::new();
+    let mut inflater = Inflater::<1024>::new();
+    let mut unfilterer = Unfilterer::<1024>::new();
+    let mut chunk_crc = Crc32::new();
+    let mut pixels = Crc32::new();
+    let mut bad_crc = false;
+    let mut height = 0;
+    let mut rows = 0;
+
+    let mut run = || -> Result<(), Error> {
+        for piece in data.chunks(7) {
+            dechunker.update_all(piece, |e| {
+                match &e {
+                    dechunker::Event::BeginChunk(h) => {
+                        chunk_crc = Crc32::new();
+                        chunk_crc.update(&h.type_.0);
+                    }
+                    dechunker::Event::Data(data) => chunk_crc.update(data),
+                    dechunker::Event::Crc(stored) => {
+                        bad_crc |= *stored != chunk_crc.clone().finish();
+                    }
+                    dechunker::Event::EndChunk | dechunker::Event::Resync(_) => {}
+                }
+                let mut dc_event = Some(e);
+                while let Some(e) = dc_event {
+                    let (leftover, mut sd_event) = sd.update(e)?;
+                    while let Some(e) = sd_event {
+                        let (leftover, mut i_event) = inflater.update(e)?;
+                        while let Some(e) = i_event {
+                            let (leftover, u_event) = unfilterer.update(e)?;
+                            match u_event {
+                                Some(unfilter::Event::ImageHeader(h)) => height = h.height,
+                                Some(unfilter::Event::Row(row)) => {
+                                    pixels.update(row);
+                                    rows += 1;
+                                }
+                                _ => {}
+                            }
+                            i_event = leftover;
+                        }
+                        sd_event = leftover;
+                    }
+                    dc_event = leftover;
+                }
+                Ok::<_, Error>(())
+            })?;
+        }
+        dechunker.eof()?;
+        sd.eof()
+    };
+
+    if let Err(e) = run() {
+        return Outcome::Error(e);
+    }
+    if bad_crc {
+        return Outcome::BadCrc;
+    }
+    if rows != height {
+        return Outcome::RowCount {
+            expected: height,
+            actual: rows,
+        };
+    }
+    Outcome::Pixels(pixels.finish())
+}
+
+fn suite() -> Vec<(String, Vec<u8>)> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("pngdump/pngsuite");
+    let mut files: Vec<_> = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "png"))
+        .map(|path| {
+            let name = path.file_name().unwrap().to_str().unwrap().to_owned();
+            (name, std::fs::read(&path).unwrap())
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+/// Interlacing is the fourth character of the name, `i` or `n`.
+fn is_interlaced(name: &str) -> bool {
+    name.as_bytes()[3] == b'i'
+}
+
+#[test]
+fn valid_images() {
+    let mut checked = 0;
+    for (name, data) in suite() {
+        if name.starts_with('x') || is_interlaced(&name) {
+            continue;
+        }
+        let expected = EXPECTED
+            .iter()
+            .find(|(n, _)| *n == name)
+            .unwrap_or_else(|| panic!("no reference for {}", name));
+        assert_eq!(decode(&data), Outcome::Pixels(expected.1), "{}", name);
+        checked += 1;
+    }
+    assert_eq!(checked, EXPECTED.len());
+}
+
+#[test]
+fn interlaced_images() {
+    for (name, data) in suite() {
+        if !name.starts_with('x') && is_interlaced(&name) {
+            assert_eq!(
+                decode(&data),
+                Outcome::Error(Error::InterlaceNotSupported),
+                "{}",
+                name
+            );
+        }
+    }
+}
+
+#[test]
+fn corrupt_images() {
+    for (name, data) in suite() {
+        if name.starts_with('x') {
+            let outcome = decode(&data);
+            assert!(!matches!(outcome, Outcome::Pixels(_)), "{}", name);
+        }
+    }
+}
+
+/// Decodes with the composed [`Decoder`], verifying CRCs.
+fn decode_with_decoder(data: &[u8]) -> Result<u32, Error> {
+    // The Inflater's buffer is bypassed
+    let mut decoder: Decoder<256, 1> = DecoderConfig::new().verify_crc(true).build();
+    let mut pixels = Crc32::new();
+    for piece in data.chunks(7) {
+        decoder.update_all(piece, |e, _| {
+            if let unfilter::Event::Row(row) = e {
+                pixels.update(row);
+            }
+            Ok::<_, Error>(())
+        })?;
+    }
+    decoder.finish()?;
+    Ok(pixels.finish())
+}
+
+#[test]
+fn configured_decoder() {
+    for (name, data) in suite() {
+        if name.starts_with('x') || is_interlaced(&name) {
+            continue;
+        }
+        let expected = EXPECTED.iter().find(|(n, _)| *n == name).unwrap();
+        assert_eq!(decode_with_decoder(&data), Ok(expected.1), "{}", name);
+    }
+    for name in ["xcsn0g01.png", "xhdn0g08.png"] {
+        let data = std::fs::read(
+            Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("pngdump/pngsuite")
+                .join(name),
+        )
+        .unwrap();
+        assert_eq!(
+            decode_with_decoder(&data),
+            Err(Error::ChecksumMismatch),
+            "{}",
+            name
+        );
+    }
+}
+
+#[test]
+fn pixel_checksum() {
+    for (name, data) in suite() {
+        if name.starts_with('x') || is_interlaced(&name) {
+            continue;
+        }
+        let mut decoder: Decoder<256, 1> = DecoderConfig::new().build();
+        let mut stage = PixelChecksum::<Crc32>::new();
+        let mut reported = None;
+        decoder
+            .update_all(&data, |e, _| {
+                let mut event = Some(e);
+                while let Some(e) = event {
+                    let (leftover, output) = stage.update(e)?;
+                    if let Some(checksum::Event::Checksum(c)) = output {
+                        reported = Some(c);
+                    }
+                    event = leftover;
+                }
+                Ok::<_, Error>(())
+            })
+            .unwrap();
+        let expected = EXPECTED.iter().find(|(n, _)| *n == name).unwrap();
+        assert_eq!(reported, Some(expected.1 as u64), "{}", name);
+    }
+}
+
+#[test]
+fn decoder_finish() {
+    let data =
+        std::fs::read(Path::new(env!("CARGO_MANIFEST_DIR")).join("pngdump/pngsuite/basn0g08.png"))
+            .unwrap();
+    assert_eq!(
+        decode_with_decoder(&data[..data.len() - 3]),
+        Err(Error::UnfinishedChunk)
+    );
+    // Without IEND
+    assert_eq!(
+        decode_with_decoder(&data[..data.len() - 12]),
+        Err(Error::MissingEndChunk)
+    );
+
+    // One row more in the header than in the image data. IHDR starts at offset 8.
+    let mut taller = data.clone();
+    taller[8 + 8 + 7] += 1;
+    let mut crc = Crc32::new();
+    crc.update(&taller[12..29]);
+    taller[29..33].copy_from_slice(&crc.finish().to_be_bytes());
+    // Noticed at the end of the deflate stream already
+    assert_eq!(
+        decode_with_decoder(&taller),
+        Err(Error::TruncatedImageData {
+            expected: 33 * 33,
+            got: 32 * 33
+        })
+    );
+}
+
+#[test]
+fn lenient_crc() {
+    let data =
+        std::fs::read(Path::new(env!("CARGO_MANIFEST_DIR")).join("pngdump/pngsuite/ct1n0g04.png"))
+            .unwrap();
+    let decode = |data: &[u8], config: DecoderConfig| {
+        let mut decoder: Decoder<256, 1> = config.verify_crc(true).build();
+        let mut pixels = Crc32::new();
+        decoder.update_all(data, |e, _| {
+            if let unfilter::Event::Row(row) = e {
+                pixels.update(row);
+            }
+            Ok::<_, Error>(())
+        })?;
+        decoder.finish()?;
+        Ok::<_, Error>((pixels.finish(), decoder.skipped_chunks()))
+    };
+    let lenient = DecoderConfig::new().strictness(Strictness::Lenient);
+    let (expected, skipped) = decode(&data, lenient).unwrap();
+    assert_eq!(skipped, 0);
+
+    // A corrupt tEXt chunk is skipped
+    let mut corrupt = data.clone();
+    let text = corrupt.windows(4).position(|w| w == b"tEXt").unwrap();
+    corrupt[text + 4] ^= 1;
+    assert_eq!(decode(&corrupt, lenient), Ok((expected, 1)));
+    assert_eq!(
+        decode(&corrupt, DecoderConfig::new()),
+        Err(Error::ChecksumMismatch)
+    );
+
+    // A bad CRC on IDAT isn't
+    let mut corrupt = data.clone();
+    let idat = corrupt.windows(4).position(|w| w == b"IDAT").unwrap();
+    let len = u32::from_be_bytes(corrupt[idat - 4..idat].try_into().unwrap()) as usize;
+    corrupt[idat + 4 + len] ^= 1;
+    assert_eq!(decode(&corrupt, lenient), Err(Error::ChecksumMismatch));
+}
+
+#[test]
+fn row_step() {
+    let data =
+        std::fs::read(Path::new(env!("CARGO_MANIFEST_DIR")).join("pngdump/pngsuite/basn2c08.png"))
+            .unwrap();
+    let rows = |config: DecoderConfig| {
+        let mut decoder: Decoder<256, 1, 512> = config.build();
+        let mut rows = Vec::new();
+        decoder
+            .update_all(&data, |e, _| {
+                if let unfilter::Event::Row(row) = e {
+                    rows.push(row.to_vec());
+                }
+                Ok::<_, Error>(())
+            })
+            .unwrap();
+        decoder.finish().unwrap();
+        rows
+    };
+    let all = rows(DecoderConfig::new());
+    let every_fourth = rows(DecoderConfig::new().row_step(4));
+    assert_eq!(all.len(), 32);
+    assert_eq!(every_fourth, all.into_iter().step_by(4).collect::<Vec<_>>());
+}
+
+#[test]
+fn adam7_thumbnail() {
+    let read = |name: &str| {
+        std::fs::read(
+            Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("pngdump/pngsuite")
+                .join(name),
+        )
+        .unwrap()
+    };
+    let rows = |data: &[u8], config: DecoderConfig| {
+        let mut decoder: Decoder<256, 1, 512> = config.build();
+        let mut rows = Vec::new();
+        decoder
+            .update_all(data, |e, _| {
+                if let unfilter::Event::Row(row) = e {
+                    rows.push(row.to_vec());
+                }
+                Ok::<_, Error>(())
+            })
+            .unwrap();
+        decoder.finish().unwrap();
+        rows
+    };
+
+    // Pass 1 of the interlaced image has every 8th pixel of every 8th row, 4x4 RGB
+    let thumbnail = rows(
+        &read("basi2c08.png"),
+        DecoderConfig::new().adam7_pass_limit(1),
+    );
+    let full = rows(&read("basn2c08.png"), DecoderConfig::new());
+    let expected: Vec<Vec<u8>> = full
+        .iter()
+        .step_by(8)
+        .map(|row| row.chunks(3).step_by(8).flatten().copied().collect())
+        .collect();
+    assert_eq!(thumbnail, expected);
+
+    // All the passes
+    let passes = rows(
+        &read("basi2c08.png"),
+        DecoderConfig::new().adam7_pass_limit(7),
+    );
+    assert_eq!(passes.len(), 4 + 4 + 4 + 8 + 8 + 16 + 16);
+}
+
+#[test]
+fn deinterlaced_pixels() {
+    let pixels = |name: &str| {
+        let data = std::fs::read(
+            Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("pngdump/pngsuite")
+                .join(name),
+        )
+        .unwrap();
+        let config = DecoderConfig::new().adam7_pass_limit(7);
+        let mut decoder: Decoder<256, 1, 512> = config.build();
+        let mut tracker = config.row_tracker();
+        let mut pixels = Vec::new();
+        decoder
+            .update_all(&data, |e, _| {
+                if let Some(row) = tracker.pixels(&e) {
+                    pixels.extend(row.map(|(x, y, samples)| (y, x, samples.to_vec())));
+                }
+                Ok::<_, Error>(())
+            })
+            .unwrap();
+        decoder.finish().unwrap();
+        pixels.sort();
+        pixels
+    };
+    for (interlaced, plain) in [
+        ("basi0g01.png", "basn0g01.png"),
+        ("basi3p04.png", "basn3p04.png"),
+        ("basi2c16.png", "basn2c16.png"),
+    ] {
+        let expected = pixels(plain);
+        assert_eq!(expected.len(), 32 * 32, "{}", plain);
+        assert_eq!(pixels(interlaced), expected, "{}", interlaced);
+    }
+}
+
+/// basn6a08 converted to Apple's CgBI variant: a CgBI chunk first, BGRA pixels and raw deflate.
+fn cgbi_image() -> (Vec<u8>, Vec<u8>) {
+    let data =
+        std::fs::read(Path::new(env!("CARGO_MANIFEST_DIR")).join("pngdump/pngsuite/basn6a08.png"))
+            .unwrap();
+    let mut chunks = Vec::new();
+    let mut pos = 8;
+    while pos < data.len() {
+        let len = u32::from_be_bytes(data[pos..pos + 4].try_into().unwrap()) as usize;
+        chunks.push((&data[pos + 4..pos + 8], &data[pos + 8..pos + 8 + len]));
+        pos += 12 + len;
+    }
+    let mut image_data = Vec::new();
+    for (_, data) in chunks.iter().filter(|(t, _)| *t == b"IDAT") {
+        image_data.extend_from_slice(data);
+    }
+    let mut scanlines = miniz_oxide::inflate::decompress_to_vec_zlib(&image_data).unwrap();
+    for scanline in scanlines.chunks_mut(1 + 32 * 4) {
+        for pixel in scanline[1..].chunks_mut(4) {
+            pixel.swap(0, 2);
+        }
+    }
+
+    let mut cgbi = data[..8].to_vec();
+    let idat = miniz_oxide::deflate::compress_to_vec(&scanlines, 6);
+    let ihdr = chunks[0].1;
+    for (type_, data) in [
+        (b"CgBI", &[0x50, 0, 0x20, 6][..]),
+        (b"IHDR", ihdr),
+        (b"IDAT", &idat),
+        (b"IEND", &[]),
+    ] {
+        cgbi.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        let mut crc = Crc32::new();
+        crc.update(type_);
+        crc.update(data);
+        cgbi.extend_from_slice(type_);
+        cgbi.extend_from_slice(data);
+        cgbi.extend_from_slice(&crc.finish().to_be_bytes());
+    }
+    (data, cgbi)
+}
+
+#[test]
+fn cgbi() {
+    let (png, cgbi) = cgbi_image();
+    let decode = |data: &[u8], config: DecoderConfig| {
+        let mut decoder: Decoder<256, 1> = config.verify_crc(true).build();
+        let mut rows = Vec::new();
+        for piece in data.chunks(7) {
+            decoder.update_all(piece, |e, _| {
+                if let unfilter::Event::Row(row) = e {
+                    rows.extend_from_slice(row);
+                }
+                Ok::<_, Error>(())
+            })?;
+        }
+        decoder.finish()?;
+        Ok::<_, Error>(rows)
+    };
+    let expected = decode(&png, DecoderConfig::new()).unwrap();
+    assert_eq!(
+        decode(&cgbi, DecoderConfig::new().cgbi(true)),
+        Ok(expected.clone())
+    );
+    // Regular files are unaffected
+    assert_eq!(decode(&png, DecoderConfig::new().cgbi(true)), Ok(expected));
+    assert_eq!(
+        decode(&cgbi, DecoderConfig::new()),
+        Err(Error::InvalidDeflateStream)
+    );
+
+    let mut stream_decoder = StreamDecoder::new();
+    Dechunker::new()
+        .update_all(&cgbi, |e| stream_decoder.update(e).map(|_| ()))
+        .unwrap();
+    assert!(stream_decoder.is_cgbi());
+}
+
+#[test]
+fn crc_warnings() {
+    let data =
+        std::fs::read(Path::new(env!("CARGO_MANIFEST_DIR")).join("pngdump/pngsuite/ct1n0g04.png"))
+            .unwrap();
+    let decode = |data: &[u8]| {
+        let mut decoder: Decoder<256, 1> = DecoderConfig::new().crc_warnings(true).build();
+        let mut pixels = Crc32::new();
+        let mut warnings = Vec::new();
+        decoder.update_all(data, |e, _| {
+            match e {
+                unfilter::Event::Row(row) => pixels.update(row),
+                unfilter::Event::Warning(w) => warnings.push(w),
+                _ => {}
+            }
+            Ok::<_, Error>(())
+        })?;
+        decoder.finish()?;
+        Ok::<_, Error>((pixels.finish(), warnings))
+    };
+    let (expected, warnings) = decode(&data).unwrap();
+    assert_eq!(warnings, []);
+
+    // Both a text chunk and IDAT with a bad CRC are decoded anyway
+    let mut corrupt = data.clone();
+    let text = corrupt.windows(4).position(|w| w == b"tEXt").unwrap();
+    let idat = corrupt.windows(4).position(|w| w == b"IDAT").unwrap();
+    let len = u32::from_be_bytes(corrupt[idat - 4..idat].try_into().unwrap()) as usize;
+    let stored = u32::from_be_bytes(corrupt[idat + 4 + len..idat + 8 + len].try_into().unwrap());
+    corrupt[text + 4] ^= 1;
+    corrupt[idat + 4 + len] ^= 1;
+    let (pixels, warnings) = decode(&corrupt).unwrap();
+    assert_eq!(pixels, expected);
+    let types: Vec<_> = warnings
+        .iter()
+        .filter_map(|w| match w {
+            Warning::CrcMismatch { chunk_type, .. } => Some(chunk_type.to_string()),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(types, ["tEXt", "IDAT"]);
+    assert_eq!(
+        warnings[1],
+        Warning::CrcMismatch {
+            chunk_type: ChunkType(*b"IDAT"),
+            stored: stored ^ 0x0100_0000,
+            computed: stored,
+        }
+    );
+}
+
+#[test]
+fn warning_events() {
+    let data =
+        std::fs::read(Path::new(env!("CARGO_MANIFEST_DIR")).join("pngdump/pngsuite/ct1n0g04.png"))
+            .unwrap();
+    // An unknown ancillary chunk before IDAT, and a corrupt tEXt chunk
+    let mut modified = data.clone();
+    let idat = modified.windows(4).position(|w| w == b"IDAT").unwrap() - 4;
+    let mut crc = Crc32::new();
+    crc.update(b"quUx");
+    let unknown = [&[0, 0, 0, 0][..], b"quUx", &crc.finish().to_be_bytes()].concat();
+    modified.splice(idat..idat, unknown);
+    let text = modified.windows(4).position(|w| w == b"tEXt").unwrap();
+    modified[text + 4] ^= 1;
+
+    let config = DecoderConfig::new()
+        .verify_crc(true)
+        .strictness(Strictness::Lenient);
+    let decode = |config: DecoderConfig| {
+        let mut decoder: Decoder<256, 1> = config.build();
+        let mut warnings = Vec::new();
+        decoder
+            .update_all(&modified, |e, _| {
+                if let unfilter::Event::Warning(w) = e {
+                    warnings.push(w);
+                }
+                Ok::<_, Error>(())
+            })
+            .unwrap();
+        decoder.finish().unwrap();
+        warnings
+    };
+    let warnings = decode(config.warnings(true));
+    assert!(matches!(
+        warnings[..],
+        [
+            Warning::CrcMismatch { chunk_type: t, .. },
+            Warning::UnknownChunk(u),
+        ] if t == ChunkType(*b"tEXt") && u == ChunkType(*b"quUx")
+    ));
+    assert_eq!(decode(config), []);
+}
+
+#[test]
+#[cfg(all(feature = "palette", feature = "ancillary"))]
+fn image_info() {
+    use incremental_png::stream_decoder::ColourKey;
+
+    let info = |name: &str| {
+        let data = std::fs::read(
+            Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("pngdump/pngsuite")
+                .join(name),
+        )
+        .unwrap();
+        let mut decoder: Decoder<256, 1> = DecoderConfig::new().build();
+        let mut infos = Vec::new();
+        decoder
+            .update_all(&data, |e, _| {
+                if let unfilter::Event::ImageInfo(info) = e {
+                    infos.push(info);
+                }
+                Ok::<_, Error>(())
+            })
+            .unwrap();
+        // Only at the first IDAT
+        assert_eq!(infos.len(), 1, "{name}");
+        infos[0]
+    };
+
+    assert_eq!(info("g03n2c08.png").ancillary.gamma, Some(35000));
+    let rgb = info("tbrn2c08.png");
+    assert_eq!(rgb.header.colour_type, 2);
+    assert_eq!(
+        rgb.ancillary.colour_key,
+        Some(ColourKey::Rgb(0xff, 0xff, 0xff))
+    );
+    assert_eq!(
+        info("tbbn0g04.png").ancillary.colour_key,
+        Some(ColourKey::Grey(15))
+    );
+    let indexed = info("tbbn3p08.png");
+    assert_eq!((indexed.palette_len, indexed.palette_alpha_len), (246, 1));
+    assert_eq!(indexed.ancillary.colour_key, None);
+}
+
+#[test]
+fn trailing_image_data() {
+    let data =
+        std::fs::read(Path::new(env!("CARGO_MANIFEST_DIR")).join("pngdump/pngsuite/basn0g01.png"))
+            .unwrap();
+    // Four bytes after the end of the zlib stream, inside the only IDAT chunk
+    let idat = data.windows(4).position(|w| w == b"IDAT").unwrap() - 4;
+    let len = u32::from_be_bytes(data[idat..idat + 4].try_into().unwrap()) as usize;
+    let mut chunk = data[idat + 4..idat + 8 + len].to_vec();
+    chunk.extend_from_slice(&[1, 2, 3, 4]);
+    let mut crc = Crc32::new();
+    crc.update(&chunk);
+    let mut modified = data[..idat].to_vec();
+    modified.extend_from_slice(&(len as u32 + 4).to_be_bytes());
+    modified.extend_from_slice(&chunk);
+    modified.extend_from_slice(&crc.finish().to_be_bytes());
+    modified.extend_from_slice(&data[idat + 12 + len..]);
+
+    assert_eq!(
+        decode(&modified),
+        Outcome::Error(Error::TrailingCompressedData)
+    );
+    assert_eq!(
+        decode_with_decoder(&modified),
+        Err(Error::TrailingCompressedData)
+    );
+    let mut decoder: Decoder<256, 1> = DecoderConfig::new().build();
+    assert_eq!(
+        decoder.update_all(&modified, |_, _| Ok::<_, Error>(())),
+        Err(Error::TrailingCompressedData)
+    );
+}
@@ -0,0 +1,32 @@
+//! Feeding an image in differently sized pieces must produce the same output from every stage.
+
+use incremental_png::testing::{assert_split_invariant, decode_digests};
+use proptest::prelude::*;
+
+const IMAGES: &[&[u8]] = &[
+    include_bytes!("../pngdump/pngsuite/basn0g01.png"),
+    include_bytes!("../pngdump/pngsuite/basn2c16.png"),
+    include_bytes!("../pngdump/pngsuite/basn3p04.png"),
+    include_bytes!("../pngdump/pngsuite/basn6a08.png"),
+    include_bytes!("../pngdump/pngsuite/f04n2c08.png"),
+    include_bytes!("../pngdump/pngsuite/z09n2c08.png"),
+];
+
+proptest! {
+    #[test]
+    fn same_output_for_any_split(
+        image in 0..IMAGES.len(),
+        points in prop::array::uniform8(0..5000usize),
+    ) {
+        let data = IMAGES[image];
+        assert_split_invariant(data, points, |pieces| decode_digests(pieces).unwrap());
+    }
+
+    #[test]
+    fn same_output_for_byte_by_byte(image in 0..IMAGES.len()) {
+        let data = IMAGES[image];
+        let whole = decode_digests(&mut core::iter::once(data)).unwrap();
+        let bytes = decode_digests(&mut data.chunks(1)).unwrap();
+        prop_assert_eq!(whole, bytes);
+    }
+}